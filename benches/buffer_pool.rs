@@ -0,0 +1,85 @@
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, Criterion};
+use kv::{BufferPool, BufferPoolConfig, CommandRequest, CompressorType, FrameCoder};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+// 统计内存分配次数的 allocator
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ROUNDS: usize = 1000;
+
+// 和连接上一样复用读写缓存，只比较压缩和解压时的临时 buffer
+fn round_trip(cmd: &CommandRequest, buf: &mut BytesMut, pool: &mut BufferPool) -> CommandRequest {
+    buf.clear();
+    cmd.encode_frame_pooled(2, buf, CompressorType::LZ4, None, pool)
+        .unwrap();
+    CommandRequest::decode_frame_pooled(2, buf, pool).unwrap()
+}
+
+fn allocations_per_request(cmd: &CommandRequest, mut pool: BufferPool) -> f64 {
+    let mut buf = BytesMut::new();
+    // 第一次需要分配读写缓存和 pool 中的 buffer
+    round_trip(cmd, &mut buf, &mut pool);
+    let start = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..ROUNDS {
+        round_trip(cmd, &mut buf, &mut pool);
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - start) as f64 / ROUNDS as f64
+}
+
+fn buffer_pool(c: &mut Criterion) {
+    let cmd = CommandRequest::new_hset("table", "key", Bytes::from(vec![1u8; 64 * 1024]));
+    let pools = [
+        ("pooled", BufferPoolConfig::default()),
+        (
+            "disabled",
+            BufferPoolConfig {
+                max_buffers: 0,
+                max_buffer_size: 0,
+            },
+        ),
+    ];
+
+    let mut group = c.benchmark_group("buffer_pool");
+    for (name, config) in pools {
+        println!(
+            "{name}: {:.2} allocations per request",
+            allocations_per_request(&cmd, BufferPool::new(config))
+        );
+
+        let mut pool = BufferPool::new(config);
+        let mut buf = BytesMut::new();
+        group.bench_function(name, |b| b.iter(|| round_trip(&cmd, &mut buf, &mut pool)));
+    }
+    group.finish();
+}
+
+criterion_group! {name = benches;
+config = Criterion::default().measurement_time(Duration::new(5, 0));
+targets = buffer_pool}
+criterion_main!(benches);
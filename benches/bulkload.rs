@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use kv::{CommandRequest, Kvpair, MemTable, ProstClientStream, ProstServerStream, Service};
+use std::time::Duration;
+use tokio::{io::duplex, runtime::Builder};
+
+// 通过内存中的 duplex 连接 server 和 client，只衡量协议和存储的开销
+fn connect(service: Service) -> ProstClientStream<tokio::io::DuplexStream> {
+    let (client, server) = duplex(64 * 1024);
+    let stream = ProstServerStream::new(server, service);
+    tokio::spawn(stream.process());
+    ProstClientStream::new(client)
+}
+
+fn pairs(n: usize) -> impl Iterator<Item = Kvpair> {
+    (0..n).map(|i| Kvpair::new(format!("key{i}"), format!("value{i}")))
+}
+
+fn bulkload(c: &mut Criterion) {
+    let runtime = Builder::new_multi_thread()
+        .worker_threads(4)
+        .thread_name("bulkload")
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("load");
+    for n in [1000, 10000] {
+        group.bench_with_input(BenchmarkId::new("hset", n), &n, |b, &n| {
+            b.to_async(&runtime).iter(|| async move {
+                let mut client = connect(Service::new(MemTable::new()));
+                for pair in pairs(n) {
+                    let cmd = CommandRequest::new_hset("table", pair.key, pair.value.unwrap());
+                    client.execute_unary(&cmd).await.unwrap();
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("bulk_load", n), &n, |b, &n| {
+            b.to_async(&runtime).iter(|| async move {
+                let mut client = connect(Service::new(MemTable::new()));
+                client.bulk_load("table", pairs(n)).await.unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {name = benches;
+config = Criterion::default().measurement_time(Duration::new(10, 0))
+.sample_size(10);
+targets = bulkload}
+criterion_main!(benches);
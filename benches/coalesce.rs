@@ -0,0 +1,49 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use kv::{CoalesceConfig, CoalescedStore, RocksDB, SledDb, Storage};
+use std::time::Duration;
+
+const CONFIG: CoalesceConfig = CoalesceConfig {
+    window: 10,
+    max_ops: 1024,
+};
+
+fn write(store: &impl Storage, n: usize) {
+    for i in 0..n {
+        store
+            .set("table", format!("key{i}"), format!("value{i}"))
+            .unwrap();
+    }
+}
+
+// 比较逐个写入和合并写入的吞吐
+fn coalesce(c: &mut Criterion) {
+    let n = 10000;
+    let mut group = c.benchmark_group("write");
+
+    let dir = tempfile::tempdir().unwrap();
+    let sled = SledDb::new(dir.path().join("sled"));
+    group.bench_with_input(BenchmarkId::new("sled", n), &n, |b, &n| {
+        b.iter(|| write(&sled, n))
+    });
+    let sled = CoalescedStore::new(SledDb::new(dir.path().join("sled_coalesced")), CONFIG);
+    group.bench_with_input(BenchmarkId::new("sled_coalesced", n), &n, |b, &n| {
+        b.iter(|| write(&sled, n))
+    });
+
+    let rocksdb = RocksDB::new(dir.path().join("rocksdb"));
+    group.bench_with_input(BenchmarkId::new("rocksdb", n), &n, |b, &n| {
+        b.iter(|| write(&rocksdb, n))
+    });
+    let rocksdb = CoalescedStore::new(RocksDB::new(dir.path().join("rocksdb_coalesced")), CONFIG);
+    group.bench_with_input(BenchmarkId::new("rocksdb_coalesced", n), &n, |b, &n| {
+        b.iter(|| write(&rocksdb, n))
+    });
+
+    group.finish();
+}
+
+criterion_group! {name = benches;
+config = Criterion::default().measurement_time(Duration::new(10, 0))
+.sample_size(10);
+targets = coalesce}
+criterion_main!(benches);
@@ -0,0 +1,78 @@
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::{executor::block_on, StreamExt};
+use kv::{
+    try_decode_frame, CommandRequest, CommandResponse, CompressorType, FrameCoder, MemTable,
+    Service,
+};
+use prost::Message;
+use std::time::Duration;
+
+const COMPRESSORS: [CompressorType; 4] = [
+    CompressorType::None,
+    CompressorType::GZIP,
+    CompressorType::LZ4,
+    CompressorType::ZSTD,
+];
+
+// 小于压缩阈值的 frame 不会被压缩，无论选择哪种压缩算法都走同一条路径；
+// 大的 frame 使用可压缩的数据，比较各个压缩算法的开销
+fn frame(c: &mut Criterion) {
+    let small = CommandRequest::new_hset("table", "key", "value");
+    let large = CommandRequest::new_hset("table", "key", Bytes::from(vec![1u8; 64 * 1024]));
+
+    for (name, cmd) in [("small", small), ("large", large)] {
+        let mut group = c.benchmark_group(format!("frame_{name}"));
+        group.throughput(Throughput::Bytes(cmd.encoded_len() as u64));
+        for compressor in COMPRESSORS {
+            let id = format!("{compressor:?}");
+            let mut buf = BytesMut::new();
+            group.bench_function(BenchmarkId::new("encode", &id), |b| {
+                b.iter(|| {
+                    buf.clear();
+                    cmd.encode_frame_with_compressor(&mut buf, compressor)
+                        .unwrap();
+                })
+            });
+
+            // 不压缩的 payload 直接借用输入，decode 时不需要拷贝
+            let mut encoded = BytesMut::new();
+            cmd.encode_frame_with_compressor(&mut encoded, compressor)
+                .unwrap();
+            group.bench_function(BenchmarkId::new("decode", &id), |b| {
+                b.iter(|| {
+                    let frame = try_decode_frame(&encoded).unwrap();
+                    frame.message::<CommandRequest>().unwrap()
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+// 一个小的请求在进程内的完整路径：encode 请求、decode 请求、执行、encode 响应、decode 响应
+fn round_trip(service: &Service, cmd: &CommandRequest, buf: &mut BytesMut) -> CommandResponse {
+    buf.clear();
+    cmd.encode_frame(buf).unwrap();
+    let cmd = CommandRequest::decode_frame(buf).unwrap();
+    let res = block_on(service.execute(cmd).next()).unwrap();
+    res.encode_frame(buf).unwrap();
+    CommandResponse::decode_frame(buf).unwrap()
+}
+
+fn small_request(c: &mut Criterion) {
+    let service = Service::new(MemTable::new());
+    let hset = CommandRequest::new_hset("table", "key", "value");
+    let hget = CommandRequest::new_hget("table", "key");
+    let mut buf = BytesMut::new();
+
+    let mut group = c.benchmark_group("small_request");
+    group.bench_function("hset", |b| b.iter(|| round_trip(&service, &hset, &mut buf)));
+    group.bench_function("hget", |b| b.iter(|| round_trip(&service, &hget, &mut buf)));
+    group.finish();
+}
+
+criterion_group! {name = benches;
+config = Criterion::default().measurement_time(Duration::new(5, 0));
+targets = frame, small_request}
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion};
+use kv::{MemTable, SledDb, Storage};
+use std::time::Duration;
+
+const VALUE_SIZE: usize = 1024 * 1024;
+
+// 反复读取同一个 1MB 的 binary value。
+// MemTable 返回的 Bytes 和存储共享内存，每次读取只增加引用计数，不随 value 大小增长；
+// SledDb 每次读取都需要把数据 decode 成新的 Value，会拷贝整个 value
+fn large_value(c: &mut Criterion) {
+    let data = Bytes::from(vec![1u8; VALUE_SIZE]);
+
+    let memtable = MemTable::new();
+    memtable.set("table", "key", data.clone()).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let sled = SledDb::new(dir.path());
+    sled.set("table", "key", data).unwrap();
+
+    let mut group = c.benchmark_group("get_1mb");
+    group.bench_function("memtable", |b| {
+        b.iter(|| memtable.get("table", "key").unwrap())
+    });
+    group.bench_function("sled", |b| b.iter(|| sled.get("table", "key").unwrap()));
+    group.finish();
+}
+
+criterion_group! {name = benches;
+config = Criterion::default().measurement_time(Duration::new(10, 0))
+.sample_size(10);
+targets = large_value}
+criterion_main!(benches);
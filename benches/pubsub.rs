@@ -32,7 +32,7 @@ async fn connect() -> Result<YamuxConn<TlsStream<TcpStream>>> {
     let mut config: ClientConfig = toml::from_str(TLS_CLIENT_CONFIG)?;
     config.general.addr = addr.into();
 
-    Ok(start_yamux_client_with_tls_config(&config).await?)
+    start_yamux_client_with_tls_config(&config).await
 }
 
 async fn start_subscribers(topic: &'static str) -> Result<()> {
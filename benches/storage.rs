@@ -0,0 +1,194 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kv::{AnyStore, Storage, StorageConfig};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+const TABLE: &str = "table";
+// 每次 scan 读取的 kv pair 数
+const SCAN_LEN: usize = 10;
+// 每轮执行的操作数
+const OPS: usize = 1000;
+// 统计延迟时执行的轮数
+const LATENCY_ROUNDS: usize = 100;
+const DEFAULT_MIX: &str = "get=70,set=20,del=5,scan=5";
+
+#[derive(Clone, Copy)]
+enum Op {
+    Get,
+    Set,
+    Del,
+    Scan,
+}
+
+/// 负载的参数，可以用环境变量覆盖：
+///
+/// - KV_BENCH_KEYS：预先写入的 key 的数量，默认 10000
+/// - KV_BENCH_KEY_SIZE / KV_BENCH_VALUE_SIZE：key 和 value 的字节数，默认 16 和 128
+/// - KV_BENCH_MIX：get/set/del/scan 的比例，默认是 DEFAULT_MIX
+struct Workload {
+    keys: usize,
+    key_size: usize,
+    value_size: usize,
+    // 每种操作的权重
+    mix: Vec<(Op, u32)>,
+}
+
+impl Workload {
+    fn from_env() -> Self {
+        let var = |name: &str, default: usize| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        let mix = env::var("KV_BENCH_MIX").unwrap_or_else(|_| DEFAULT_MIX.into());
+        Self {
+            keys: var("KV_BENCH_KEYS", 10000),
+            key_size: var("KV_BENCH_KEY_SIZE", 16),
+            value_size: var("KV_BENCH_VALUE_SIZE", 128),
+            mix: parse_mix(&mix),
+        }
+    }
+
+    // 固定长度的 key，保持数字的顺序，scan 可以按范围读取
+    fn key(&self, i: usize) -> String {
+        format!("k{i:0width$}", width = self.key_size.saturating_sub(1))
+    }
+
+    fn value(&self) -> String {
+        "v".repeat(self.value_size)
+    }
+
+    // 预先生成操作序列，随机数的开销不计入测量的时间
+    fn ops(&self, seed: u64) -> Vec<(Op, usize)> {
+        let total: u32 = self.mix.iter().map(|(_, w)| w).sum();
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..OPS)
+            .map(|_| {
+                let mut n = rng.gen_range(0..total);
+                let op = self
+                    .mix
+                    .iter()
+                    .find(|(_, w)| {
+                        let hit = n < *w;
+                        n = n.saturating_sub(*w);
+                        hit
+                    })
+                    .map(|(op, _)| *op)
+                    .unwrap();
+                (op, rng.gen_range(0..self.keys))
+            })
+            .collect()
+    }
+
+    fn prepare(&self, store: &impl Storage) {
+        let value = self.value();
+        for i in 0..self.keys {
+            store.put(TABLE, self.key(i), value.as_str()).unwrap();
+        }
+    }
+
+    fn execute(&self, store: &impl Storage, op: Op, i: usize, value: &str) {
+        let key = self.key(i);
+        match op {
+            Op::Get => {
+                store.get(TABLE, &key).unwrap();
+            }
+            Op::Set => {
+                store.put(TABLE, key, value).unwrap();
+            }
+            Op::Del => {
+                store.remove(TABLE, &key).unwrap();
+            }
+            Op::Scan => {
+                let end = self.key(i + SCAN_LEN);
+                store.get_range(TABLE, &key, &end).unwrap().count();
+            }
+        }
+    }
+}
+
+fn parse_mix(mix: &str) -> Vec<(Op, u32)> {
+    let mix: Vec<_> = mix
+        .split(',')
+        .map(|item| {
+            let (name, weight) = item.trim().split_once('=').expect("expect <op>=<weight>");
+            let op = match name {
+                "get" => Op::Get,
+                "set" => Op::Set,
+                "del" => Op::Del,
+                "scan" => Op::Scan,
+                _ => panic!("unknown op {name}, expect get/set/del/scan"),
+            };
+            (op, weight.parse().expect("weight should be an integer"))
+        })
+        .collect();
+    assert!(mix.iter().any(|(_, w)| *w > 0), "mix is empty");
+    mix
+}
+
+// 所有存储使用和服务器相同的创建方式
+fn backends(dir: &tempfile::TempDir) -> Vec<(&'static str, AnyStore)> {
+    let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+    vec![
+        ("memtable", AnyStore::new(&StorageConfig::MemTable)),
+        ("sled", AnyStore::new(&StorageConfig::Sledb(path("sled")))),
+        (
+            "rocksdb",
+            AnyStore::new(&StorageConfig::Rocksdb(path("rocksdb"))),
+        ),
+    ]
+}
+
+// 由 criterion 测量吞吐，之后再单独执行 LATENCY_ROUNDS 轮，输出单个操作延迟的百分位数
+fn mixed_workload(c: &mut Criterion) {
+    let workload = Workload::from_env();
+    let ops = workload.ops(42);
+    let value = workload.value();
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut group = c.benchmark_group("mixed_workload");
+    group.throughput(Throughput::Elements(OPS as u64));
+    for (name, store) in backends(&dir) {
+        workload.prepare(&store);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                for &(op, i) in &ops {
+                    workload.execute(&store, op, i, &value);
+                }
+            })
+        });
+
+        let mut latencies = Vec::with_capacity(OPS * LATENCY_ROUNDS);
+        for _ in 0..LATENCY_ROUNDS {
+            for &(op, i) in &ops {
+                let start = Instant::now();
+                workload.execute(&store, op, i, &value);
+                latencies.push(start.elapsed());
+            }
+        }
+        print_percentiles(name, &mut latencies);
+    }
+    group.finish();
+}
+
+fn print_percentiles(name: &str, latencies: &mut [Duration]) {
+    latencies.sort_unstable();
+    let at = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+    println!(
+        "{name}: p50 {:?}, p99 {:?}, p999 {:?}, max {:?}",
+        at(0.5),
+        at(0.99),
+        at(0.999),
+        latencies[latencies.len() - 1]
+    );
+}
+
+criterion_group! {name = benches;
+config = Criterion::default().measurement_time(Duration::new(10, 0))
+.sample_size(10);
+targets = mixed_workload}
+criterion_main!(benches);
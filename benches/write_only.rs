@@ -0,0 +1,42 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use kv::{RocksDB, Storage};
+use std::time::Duration;
+
+// 覆盖写已经存在的 key，set 需要先读出旧值，put 不需要
+fn write_only(c: &mut Criterion) {
+    let n = 10000;
+    let dir = tempfile::tempdir().unwrap();
+    let store = RocksDB::new(dir.path().join("rocksdb"));
+    for i in 0..n {
+        store
+            .set("table", format!("key{i}"), format!("value{i}"))
+            .unwrap();
+    }
+
+    let mut group = c.benchmark_group("rocksdb_overwrite");
+    group.bench_with_input(BenchmarkId::new("get_old", n), &n, |b, &n| {
+        b.iter(|| {
+            for i in 0..n {
+                store
+                    .set("table", format!("key{i}"), format!("value{i}"))
+                    .unwrap();
+            }
+        })
+    });
+    group.bench_with_input(BenchmarkId::new("write_only", n), &n, |b, &n| {
+        b.iter(|| {
+            for i in 0..n {
+                store
+                    .put("table", format!("key{i}"), format!("value{i}"))
+                    .unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group! {name = benches;
+config = Criterion::default().measurement_time(Duration::new(10, 0))
+.sample_size(10);
+targets = write_only}
+criterion_main!(benches);
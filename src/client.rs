@@ -1,8 +1,9 @@
 use anyhow::Result;
 use futures::StreamExt;
 use kv::{
-    start_quic_client_with_config, start_yamux_client_with_tls_config, AppStream, ClientConfig,
-    CommandRequest, NetworkType, QUIC_CLIENT_CONFIG,
+    keyspace_topic, log_topic, start_quic_client_with_config, start_yamux_client_with_tls_config,
+    AppStream, ClientConfig, CommandRequest, CompressionConfig, Kvpair, NetworkType,
+    QUIC_CLIENT_CONFIG,
 };
 use rustyline::{error::ReadlineError, DefaultEditor};
 use std::collections::HashMap;
@@ -17,12 +18,14 @@ async fn main() -> Result<()> {
     match config.general.network {
         NetworkType::Tcp => {
             let conn = start_yamux_client_with_tls_config(&config).await?;
-            process(conn).await?;
+            process(conn, config.general.compression).await?;
         }
         NetworkType::Quic => {
             let conn = start_quic_client_with_config(&config).await?;
-            process(conn).await?;
+            process(conn, config.general.compression).await?;
         }
+        NetworkType::Unix => anyhow::bail!("kvc does not support unix socket yet"),
+        NetworkType::TcpQuic => anyhow::bail!("tcp_quic is only for servers, use tcp or quic"),
     }
 
     println!("Done!");
@@ -30,12 +33,12 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn process<S, T>(mut conn: S) -> Result<()>
+async fn process<S, T>(mut conn: S, compression: CompressionConfig) -> Result<()>
 where
     S: AppStream<InnerStream = T>,
     T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    let mut client = conn.open_stream().await?;
+    let mut client = conn.open_stream().await?.with_compression(compression);
     let mut editor = DefaultEditor::new()?;
     if editor.load_history("history.txt").is_ok() {
         println!("History is loaded.");
@@ -67,6 +70,17 @@ where
                         let data = client.execute_unary(&cmd).await?;
                         println!("{data}");
                     }
+                    "mtget" => {
+                        if args.len() < 3 || args.len() % 2 == 0 {
+                            println!("Usage: MTGET <table> <key> [<table> <key> ...]");
+                            continue;
+                        }
+
+                        let requests = args[1..].chunks(2).map(|v| (v[0], v[1])).collect();
+                        let cmd = CommandRequest::new_multi_table_get(requests);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
                     "set" => {
                         if args.len() < 3 {
                             println!("Usage: SET <key> <value>");
@@ -77,6 +91,71 @@ where
                         let data = client.execute_unary(&cmd).await?;
                         println!("{data}");
                     }
+                    "setcas" => {
+                        let Some(expected) = args.get(3).and_then(|v| v.parse().ok()) else {
+                            println!("Usage: SETCAS <key> <value> <expected_version>");
+                            continue;
+                        };
+
+                        let cmd = CommandRequest::new_hset_cas(table, args[1], args[2], expected);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "msetnx" => {
+                        if args.len() < 3 || args.len() % 2 == 0 {
+                            println!("Usage: MSETNX <key> <value> [<key> <value>...]");
+                            continue;
+                        }
+
+                        let pairs: Vec<_> = args[1..]
+                            .chunks(2)
+                            .map(|kv| Kvpair::new(kv[0], kv[1]))
+                            .collect();
+                        let cmd = CommandRequest::new_hmsetnx(table, pairs);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "getset" => {
+                        if args.len() < 3 {
+                            println!("Usage: GETSET <key> <value>");
+                            continue;
+                        }
+
+                        let cmd = CommandRequest::new_hgetset(table, args[1], args[2]);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "getchunked" => {
+                        if args.len() < 2 {
+                            println!("Usage: GETCHUNKED <key>");
+                            continue;
+                        }
+
+                        // 在单独的 stream 上分段读取，不影响当前 stream 上的命令
+                        let client = conn.open_stream().await?.with_compression(compression);
+                        let data = client.hget_chunked(table, args[1], 0).await?;
+                        println!("{} bytes", data.len());
+                    }
+                    "getifnewer" => {
+                        let Some(since) = args.get(2).and_then(|v| v.parse().ok()) else {
+                            println!("Usage: GETIFNEWER <key> <since_version>");
+                            continue;
+                        };
+
+                        let cmd = CommandRequest::new_hget_if_newer(table, args[1], since);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "getfield" => {
+                        if args.len() < 3 {
+                            println!("Usage: GETFIELD <key> <field>");
+                            continue;
+                        }
+
+                        let cmd = CommandRequest::new_hgetfield(table, args[1], args[2]);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
                     "del" => {
                         if args.len() < 2 {
                             println!("Usage: DEL <key>");
@@ -87,6 +166,59 @@ where
                         let data = client.execute_unary(&cmd).await?;
                         println!("{data}");
                     }
+                    "getdel" => {
+                        if args.len() < 2 {
+                            println!("Usage: GETDEL <key>");
+                            continue;
+                        }
+
+                        let cmd = CommandRequest::new_hgetdel(table, args[1]);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "create" => {
+                        if args.len() < 2 {
+                            println!("Usage: CREATE <table>");
+                            continue;
+                        }
+
+                        let cmd = CommandRequest::new_create_table(args[1]);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "drop" => {
+                        if args.len() < 2 {
+                            println!("Usage: DROP <table>");
+                            continue;
+                        }
+
+                        let cmd = CommandRequest::new_drop_table(args[1]);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "rename" => {
+                        if args.len() < 3 {
+                            println!("Usage: RENAME <from> <to> [overwrite]");
+                            continue;
+                        }
+                        let overwrite = args
+                            .get(3)
+                            .is_some_and(|v| v.eq_ignore_ascii_case("overwrite"));
+
+                        let cmd = CommandRequest::new_rename_table(args[1], args[2], overwrite);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "movenx" => {
+                        if args.len() < 4 {
+                            println!("Usage: MOVENX <key> <dst_table> <dst_key>");
+                            continue;
+                        }
+
+                        let cmd = CommandRequest::new_move_nx(table, args[1], args[2], args[3]);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
                     "exist" => {
                         if args.len() < 2 {
                             println!("Usage: EXIST <key>");
@@ -97,6 +229,115 @@ where
                         let data = client.execute_unary(&cmd).await?;
                         println!("{data}");
                     }
+                    "expire" => {
+                        let Some(ttl) = args.get(2).and_then(|v| v.parse().ok()) else {
+                            println!("Usage: EXPIRE <key> <seconds>");
+                            continue;
+                        };
+
+                        let cmd = CommandRequest::new_hexpire(table, args[1], ttl);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "persist" => {
+                        if args.len() < 2 {
+                            println!("Usage: PERSIST <key>");
+                            continue;
+                        }
+
+                        let cmd = CommandRequest::new_hpersist(table, args[1]);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "ttl" => {
+                        if args.len() < 2 {
+                            println!("Usage: TTL <key>");
+                            continue;
+                        }
+
+                        let cmd = CommandRequest::new_httl(table, args[1]);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "incrbyfloat" => {
+                        let Some(delta) = args.get(2).and_then(|v| v.parse().ok()) else {
+                            println!("Usage: INCRBYFLOAT <key> <delta>");
+                            continue;
+                        };
+
+                        let cmd = CommandRequest::new_hincrbyfloat(table, args[1], delta);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "incrinit" => {
+                        let initial = args.get(2).and_then(|v| v.parse().ok());
+                        let delta = args.get(3).and_then(|v| v.parse().ok());
+                        let (Some(initial), Some(delta)) = (initial, delta) else {
+                            println!("Usage: INCRINIT <key> <initial> <delta>");
+                            continue;
+                        };
+
+                        let cmd = CommandRequest::new_hincr_init(table, args[1], initial, delta);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "increx" => {
+                        let delta = args.get(2).and_then(|v| v.parse().ok());
+                        let ttl = args.get(3).and_then(|v| v.parse().ok());
+                        let (Some(delta), Some(ttl)) = (delta, ttl) else {
+                            println!("Usage: INCREX <key> <delta> <ttl> [refresh]");
+                            continue;
+                        };
+                        let refresh = args
+                            .get(4)
+                            .is_some_and(|v| v.eq_ignore_ascii_case("refresh"));
+
+                        let cmd = CommandRequest::new_hincr_ex(table, args[1], delta, ttl, refresh);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "sizeof" => {
+                        let key = args.get(1).map(|k| k.to_string());
+                        let cmd = CommandRequest::new_sizeof(table, key);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "compact" => {
+                        let table = args.get(1).map(|t| t.to_string());
+                        let cmd = CommandRequest::new_compact(table);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "scan" => {
+                        // 在单独的 stream 上遍历，不影响当前 stream 上的命令
+                        let cmd = CommandRequest::new_scan_stream(table, 0);
+                        let client = conn.open_stream().await?.with_compression(compression);
+                        let mut stream = client.execute_streaming(&cmd).await?;
+                        let mut count = 0;
+                        while let Some(res) = stream.next().await {
+                            let res = res?;
+                            for pair in &res.pairs {
+                                println!("{pair}");
+                            }
+                            count += res.pairs.len();
+                        }
+                        println!("{count} pairs");
+                    }
+                    "stats" => {
+                        let cmd = CommandRequest::new_stats();
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "diagnostics" => {
+                        let cmd = CommandRequest::new_diagnostics();
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
+                    "mysubscriptions" => {
+                        let cmd = CommandRequest::new_my_subscriptions();
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
                     "select" => {
                         if args.len() < 2 {
                             println!("Usage: SELECT <table>");
@@ -109,12 +350,18 @@ where
                     // chat command
                     "subscribe" => {
                         if args.len() < 2 {
-                            println!("Usage: SUBSCRIBE <topic>");
+                            println!("Usage: SUBSCRIBE <topic> [throttle_ms] [sample_every]");
                             continue;
                         }
 
-                        let cmd = CommandRequest::new_subscribe(args[1]);
-                        let client = conn.open_stream().await?;
+                        let throttle_ms = args.get(2).and_then(|v| v.parse().ok()).unwrap_or(0);
+                        let sample_every = args.get(3).and_then(|v| v.parse().ok()).unwrap_or(0);
+                        let cmd = CommandRequest::new_subscribe_throttled(
+                            args[1],
+                            throttle_ms,
+                            sample_every,
+                        );
+                        let client = conn.open_stream().await?.with_compression(compression);
                         let mut stream = client.execute_streaming(&cmd).await.unwrap();
                         topic_map.insert(args[1].to_owned(), stream.id);
                         tokio::spawn(async move {
@@ -123,6 +370,43 @@ where
                             }
                         });
                     }
+                    "logs" => {
+                        let level = args.get(1).copied().unwrap_or_default();
+                        let topic = match log_topic(level) {
+                            Ok(topic) => topic,
+                            Err(e) => {
+                                println!("{e}");
+                                continue;
+                            }
+                        };
+                        let cmd = CommandRequest::new_subscribe_logs(level);
+                        let client = conn.open_stream().await?.with_compression(compression);
+                        let mut stream = client.execute_streaming(&cmd).await.unwrap();
+                        // 和普通的订阅一样可以用 UNSUBSCRIBE __logs@<level> 取消
+                        topic_map.insert(topic, stream.id);
+                        tokio::spawn(async move {
+                            while let Some(Ok(data)) = stream.next().await {
+                                println!("{data:?}");
+                            }
+                        });
+                    }
+                    "watch" => {
+                        if args.len() < 2 {
+                            println!("Usage: WATCH <key>");
+                            continue;
+                        }
+
+                        let cmd = CommandRequest::new_watch_key(table.as_str(), args[1]);
+                        let client = conn.open_stream().await?.with_compression(compression);
+                        let mut stream = client.execute_streaming(&cmd).await.unwrap();
+                        // 可以用 UNSUBSCRIBE __keyspace@<table> 取消
+                        topic_map.insert(keyspace_topic(&table), stream.id);
+                        tokio::spawn(async move {
+                            while let Some(Ok(data)) = stream.next().await {
+                                println!("{data:?}");
+                            }
+                        });
+                    }
                     "unsubscribe" => {
                         if args.len() < 2 {
                             println!("Usage: UNSUBSCRIBE <topic>");
@@ -148,6 +432,18 @@ where
                         let data = client.execute_unary(&cmd).await?;
                         println!("{data}");
                     }
+                    "mpublish" => {
+                        if args.len() < 3 {
+                            println!("Usage: MPUBLISH <topic>... <value>");
+                            continue;
+                        }
+
+                        let (value, topics) = args[1..].split_last().unwrap();
+                        let cmd =
+                            CommandRequest::new_mpublish(topics.to_vec(), vec![(*value).into()]);
+                        let data = client.execute_unary(&cmd).await?;
+                        println!("{data}");
+                    }
 
                     "quit" | "exit" => {
                         println!("Exiting...");
@@ -1,7 +1,9 @@
-use crate::KvError;
+use crate::{
+    command_request::RequestData, CommandRequest, CompressorType, KvError, Sizeof, ValueCodecType,
+};
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
-use std::{fs, str::FromStr};
+use std::{fs, str::FromStr, time::Duration};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ServerConfig {
@@ -9,6 +11,51 @@ pub struct ServerConfig {
     pub storage: StorageConfig,
     pub security: ServerSecurityProtocol,
     pub log: LogConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// 读写不存在的 table 时是否自动创建，为 false 时需要先用 CreateTable 创建 table
+    #[serde(default = "default_auto_create_tables")]
+    pub auto_create_tables: bool,
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+    /// 磁盘存储的写入合并，None 表示每次写入都直接写入存储
+    #[serde(default)]
+    pub write_coalescing: Option<CoalesceConfig>,
+    /// 按 table 名选择存储，不匹配任何 pattern 的 table 使用 storage
+    #[serde(default)]
+    pub table_storage: Vec<TableStorageConfig>,
+    /// 是否允许客户端执行管理命令（如 COMPACT），默认不允许
+    #[serde(default)]
+    pub admin_commands: bool,
+    /// 写命令成功后是否向 table 的 keyspace topic 发布通知，见 keyspace_topic
+    #[serde(default)]
+    pub keyspace_notifications: bool,
+    /// 是否在内存中记录每个 key 的版本号，HgetIfNewer 需要它。开启后每个写入过的 key 都会占用内存
+    #[serde(default)]
+    pub key_versioning: bool,
+    /// 每个主题缓存最近发布的多少条数据，用于 Subscribe 的 from_seq 重放，0 表示不缓存。
+    /// 重放的数据要能放进新订阅的 channel，超过上限（127）时按上限处理
+    #[serde(default)]
+    pub topic_history_size: usize,
+    /// 通过 mDNS 公布服务器时使用的实例名，None 表示不公布，需要开启 mdns feature
+    #[serde(default)]
+    pub mdns: Option<String>,
+    /// 磁盘存储中 value 的编码，打开已有的数据库时必须和写入时一致
+    #[serde(default)]
+    pub value_codec: ValueCodecType,
+    /// 磁盘存储连续出错时的熔断，None 表示不熔断
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// 磁盘存储的读缓存，None 表示不缓存
+    #[serde(default)]
+    pub read_cache: Option<ReadCacheConfig>,
+    /// 按 table 名压缩磁盘存储中的 value，不匹配任何 pattern 的 table 不压缩。MemTable 不压缩
+    #[serde(default)]
+    pub table_compression: Vec<TableCompressionConfig>,
+}
+
+fn default_auto_create_tables() -> bool {
+    true
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -20,13 +67,13 @@ pub struct ClientConfig {
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum ServerSecurityProtocol {
     Tls(ServerTlsConfig),
-    Noise,
+    Noise(NoiseConfig),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum ClientSecurityProtocol {
     Tls(ClientTlsConfig),
-    Noise,
+    Noise(NoiseConfig),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -36,6 +83,9 @@ pub struct LogConfig {
     pub log_level: String,
     pub path: String,
     pub rotation: RotationConfig,
+    /// 启用 tokio-console，需要编译时开启 tokio-console feature
+    #[serde(default)]
+    pub tokio_console: bool,
 }
 
 impl Default for LogConfig {
@@ -46,50 +96,340 @@ impl Default for LogConfig {
             log_level: "info".to_string(),
             path: "/tmp/kv-log".into(),
             rotation: RotationConfig::Daily,
+            tokio_console: false,
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, ValueEnum)]
+/// 存储配额，None 表示不限制
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct LimitsConfig {
+    /// 最多能有多少个 table
+    #[serde(default)]
+    pub max_tables: Option<usize>,
+    /// 每个 table 最多能有多少个 key
+    #[serde(default)]
+    pub max_keys_per_table: Option<usize>,
+    /// HMGET/HMSET/HMSETNX/HMDEL/HMEXIST 和 BulkDel 的每一批最多能操作多少个 key
+    #[serde(default)]
+    pub max_keys_per_command: Option<usize>,
+}
+
+/// 写入合并的配置，见 CoalescedStore
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CoalesceConfig {
+    /// 写入在缓冲区中最多停留多久（毫秒）
+    pub window: u64,
+    /// 缓冲的写入达到这个数量时立即写入存储
+    pub max_ops: usize,
+}
+
+/// 读缓存的配置，见 CachedStore
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ReadCacheConfig {
+    /// 最多缓存多少个 key
+    pub capacity: usize,
+}
+
+/// 一组 table 的 value 压缩，见 ValueCompression
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TableCompressionConfig {
+    /// table 名，以 * 结尾时按前缀匹配
+    pub pattern: String,
+    /// 为 none 时不压缩，可以让部分 table 不使用后面更宽泛的 pattern
+    pub compressor: CompressorType,
+    /// 压缩级别，None 时使用算法的默认级别
+    #[serde(default)]
+    pub level: Option<i32>,
+}
+
+/// 熔断的配置，见 CircuitBreakerStore
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// 存储连续出错多少次后熔断
+    pub failure_threshold: u32,
+    /// 熔断后多久（毫秒）再尝试访问存储
+    pub cooldown: u64,
+}
+
+/// 服务端 tokio runtime 的线程配置，None 表示使用 tokio 的默认值
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct RuntimeConfig {
+    /// 处理网络和压缩的 worker 线程数，默认为 CPU 核数
+    #[serde(default)]
+    pub worker_threads: Option<usize>,
+    /// 执行同步 storage 调用的 blocking 线程数上限，默认为 512
+    #[serde(default)]
+    pub max_blocking_threads: Option<usize>,
+}
+
+impl RuntimeConfig {
+    /// 根据配置创建多线程的 tokio runtime
+    pub fn build(&self) -> std::io::Result<tokio::runtime::Runtime> {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.enable_all();
+        if let Some(n) = self.worker_threads {
+            builder.worker_threads(n);
+        }
+        if let Some(n) = self.max_blocking_threads {
+            builder.max_blocking_threads(n);
+        }
+        builder.build()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, ValueEnum, Default)]
 pub enum RotationConfig {
     Hourly,
+    #[default]
     Daily,
     Never,
 }
 
-impl Default for RotationConfig {
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct GeneralConfig {
+    pub addr: String,
+    #[serde(default)]
+    pub network: NetworkType,
+    /// 连接空闲超时（秒），在这段时间内没有收到任何命令就关闭连接，None 表示不超时
+    /// 目前只对 yamux 连接生效
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    /// 服务端执行单个命令的超时
+    #[serde(default)]
+    pub command_timeout: CommandTimeout,
+    /// 除 addr 之外额外监听的地址，所有 listener 共享同一个 service
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    /// 发送 frame 时的压缩配置
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// 每个 stream 同时执行的命令数，达到上限后暂停读取新的命令，直到有响应发送出去。
+    /// 大于 1 时同一个 stream 上的命令可能并发执行，但响应总是按命令的顺序返回
+    #[serde(default = "default_max_in_flight")]
+    pub max_in_flight: usize,
+    /// 每个 stream 的优先级队列的长度，执行的命令达到 max_in_flight 后继续读取的命令数，
+    /// 这些命令按 CommandRequest 的 priority 从高到低执行。0 表示按读取的顺序执行
+    #[serde(default)]
+    pub priority_queue: usize,
+    /// 每个连接同时进行的订阅数的上限，超过时 SUBSCRIBE 返回 429，None 表示不限制
+    #[serde(default)]
+    pub max_subscriptions: Option<usize>,
+    /// 每个 stream 上复用的 frame 临时 buffer
+    #[serde(default)]
+    pub buffer_pool: BufferPoolConfig,
+    /// TCP listener 等待 accept 的连接队列的长度，None 时使用默认值 1024。
+    /// Unix socket 和 QUIC listener 不使用这个配置
+    #[serde(default)]
+    pub listen_backlog: Option<u32>,
+    /// 建立连接后完成 TLS/Noise 握手的超时（秒），超时后关闭连接，0 表示不超时
+    #[serde(default = "default_handshake_timeout")]
+    pub handshake_timeout: u64,
+    /// 每个 listener 接受新连接的速率限制，None 表示不限制
+    #[serde(default)]
+    pub accept_rate: Option<AcceptRateConfig>,
+    /// 同时处理的连接数的上限，所有 listener 共享，None 表示不限制。
+    /// 达到上限后新连接完成握手，对第一个命令返回 503 后关闭；QUIC 连接直接以错误码关闭
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+}
+
+/// 接受新连接的速率限制（令牌桶）：每秒最多 accept per_second 个连接，空闲之后最多连续 accept
+/// burst 个。超过速率时暂停 accept，新连接在 listen backlog 中等待，不会先进行 TLS/Noise 握手。
+/// QUIC 的握手在 accept 之前由 s2n-quic 完成，只能限制处理新连接的速率
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AcceptRateConfig {
+    pub per_second: u32,
+    /// None 时等于 per_second
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+/// 默认的握手超时（秒）
+pub const DEFAULT_HANDSHAKE_TIMEOUT: u64 = 10;
+
+fn default_max_in_flight() -> usize {
+    1
+}
+
+fn default_handshake_timeout() -> u64 {
+    DEFAULT_HANDSHAKE_TIMEOUT
+}
+
+/// 发送 frame 时使用的压缩算法和各个算法的压缩级别，payload 不超过 1432 字节时不压缩。
+/// 接收时根据 frame 头解压，不需要和对端的配置一致
+///
+/// 压缩级别越高压缩率越高、速度越慢，None 表示使用算法的默认级别，超出范围的级别会被截断到范围内
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CompressionConfig {
+    #[serde(default = "default_compressor")]
+    pub algorithm: CompressorType,
+    /// gzip 的级别 0..=9，默认 6
+    #[serde(default)]
+    pub gzip_level: Option<i32>,
+    /// lz4 的级别 0..=12，默认 0，3 以上使用 LZ4 HC
+    #[serde(default)]
+    pub lz4_level: Option<i32>,
+    /// zstd 的级别 1..=22，默认 3
+    #[serde(default)]
+    pub zstd_level: Option<i32>,
+}
+
+fn default_compressor() -> CompressorType {
+    CompressorType::GZIP
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: default_compressor(),
+            gzip_level: None,
+            lz4_level: None,
+            zstd_level: None,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// 当前算法的压缩级别
+    pub fn level(&self) -> Option<i32> {
+        match self.algorithm {
+            CompressorType::GZIP => self.gzip_level,
+            CompressorType::LZ4 => self.lz4_level,
+            CompressorType::ZSTD => self.zstd_level,
+            CompressorType::None => None,
+        }
+    }
+}
+
+/// frame 编解码时临时 buffer 的复用，见 BufferPool
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BufferPoolConfig {
+    /// 每个 stream 最多缓存多少个 buffer，0 表示不复用
+    #[serde(default = "default_pool_buffers")]
+    pub max_buffers: usize,
+    /// 容量超过这个大小（字节）的 buffer 用完后直接释放
+    #[serde(default = "default_pool_buffer_size")]
+    pub max_buffer_size: usize,
+}
+
+// 压缩和解压各用一个
+fn default_pool_buffers() -> usize {
+    2
+}
+
+fn default_pool_buffer_size() -> usize {
+    1024 * 1024
+}
+
+impl Default for BufferPoolConfig {
     fn default() -> Self {
-        RotationConfig::Daily
+        Self {
+            max_buffers: default_pool_buffers(),
+            max_buffer_size: default_pool_buffer_size(),
+        }
     }
 }
 
+/// 服务端的一个监听地址
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
-pub struct GeneralConfig {
+pub struct ListenerConfig {
+    /// 监听的地址，network 为 unix 时是 socket 文件的路径
     pub addr: String,
     #[serde(default)]
     pub network: NetworkType,
+    pub security: ServerSecurityProtocol,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+/// 服务端执行命令的超时（毫秒），按命令类型分别配置，None 表示不超时
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct CommandTimeout {
+    /// HGET/HGETFIELD/HMGET/HEXIST/HMEXIST
+    #[serde(default)]
+    pub read: Option<u64>,
+    /// HSET/HMSET/HMSETNX/HDEL/HMDEL
+    #[serde(default)]
+    pub write: Option<u64>,
+    /// HGETALL/不指定 key 的 SIZEOF/STATS/DIAGNOSTICS
+    #[serde(default)]
+    pub scan: Option<u64>,
+}
+
+impl CommandTimeout {
+    /// 获取命令对应的超时，pub/sub 命令的 stream 是长期存在的，不设超时
+    pub fn get(&self, cmd: &CommandRequest) -> Option<Duration> {
+        let ms = match cmd.request_data.as_ref()? {
+            RequestData::Hget(_)
+            | RequestData::Hgetfield(_)
+            | RequestData::HgetIfNewer(_)
+            | RequestData::Hmget(_)
+            | RequestData::MultiTableGet(_)
+            | RequestData::Hexist(_)
+            | RequestData::Hmexist(_)
+            | RequestData::Httl(_)
+            | RequestData::Sizeof(Sizeof { key: Some(_), .. }) => self.read,
+            RequestData::Hset(_)
+            | RequestData::HsetCas(_)
+            | RequestData::Hgetset(_)
+            | RequestData::Hincrbyfloat(_)
+            | RequestData::HincrEx(_)
+            | RequestData::HincrInit(_)
+            | RequestData::Hmset(_)
+            | RequestData::Hmsetnx(_)
+            | RequestData::Hdel(_)
+            | RequestData::Hgetdel(_)
+            | RequestData::Hmdel(_)
+            | RequestData::CreateTable(_)
+            | RequestData::DropTable(_)
+            | RequestData::RenameTable(_)
+            | RequestData::MoveNx(_)
+            | RequestData::Hexpire(_)
+            | RequestData::Hpersist(_) => self.write,
+            RequestData::Hgetall(_)
+            | RequestData::Sizeof(_)
+            | RequestData::Stats(_)
+            | RequestData::Diagnostics(_) => self.scan,
+            // 压缩可能需要很长时间，不设超时；ScanStream 和 HgetChunked 的耗时取决于客户端读取的速度
+            RequestData::Compact(_) | RequestData::ScanStream(_) | RequestData::HgetChunked(_) => {
+                None
+            }
+            _ => None,
+        };
+        ms.map(Duration::from_millis)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum NetworkType {
+    #[default]
     Tcp,
     Quic,
+    Unix,
+    /// 只用于服务端：在同一个端口上同时监听 TCP（TLS + yamux）和 UDP（QUIC），使用同一份 TLS 证书。
+    ///
+    /// TCP 和 UDP 的端口是相互独立的，Linux、macOS 和 Windows 都允许不同协议的 socket bind 同一个端口，
+    /// 不需要 SO_REUSEPORT，但这个端口的 TCP 和 UDP 都不能被其他进程占用，防火墙也需要同时放行两者。
+    /// 端口为 0 时 QUIC 使用 TCP 分配到的端口。由 systemd 传入 socket 时依次使用一个 TCP 和一个 UDP socket
+    TcpQuic,
 }
 
-impl Default for NetworkType {
-    fn default() -> Self {
-        NetworkType::Tcp
-    }
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
 pub enum StorageConfig {
+    #[default]
     MemTable,
     Sledb(String),
     Rocksdb(String),
 }
 
+/// 一组 table 使用的存储
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TableStorageConfig {
+    /// table 名，以 * 结尾时按前缀匹配，如 "cache_*"
+    pub pattern: String,
+    pub storage: StorageConfig,
+}
+
 impl FromStr for StorageConfig {
     type Err = String;
 
@@ -103,24 +443,59 @@ impl FromStr for StorageConfig {
     }
 }
 
-impl Default for StorageConfig {
-    fn default() -> Self {
-        StorageConfig::MemTable
-    }
-}
-
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ServerTlsConfig {
     pub cert: String,
     pub key: String,
+    /// key 是加密的私钥时用于解密的口令，目前只对 TCP 上的 TLS 生效
+    #[serde(default)]
+    pub key_passphrase: Option<String>,
     pub ca: Option<String>,
+    /// 允许的协议版本和加密套件，目前只对 TCP 上的 TLS 生效
+    #[serde(flatten)]
+    pub policy: TlsPolicyConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ClientTlsConfig {
     pub domain: String,
     pub identity: Option<(String, String)>,
+    /// identity 中的 key 是加密的私钥时用于解密的口令，目前只对 TCP 上的 TLS 生效
+    #[serde(default)]
+    pub key_passphrase: Option<String>,
     pub ca: Option<String>,
+    /// 允许的协议版本和加密套件，目前只对 TCP 上的 TLS 生效
+    #[serde(flatten)]
+    pub policy: TlsPolicyConfig,
+}
+
+/// Noise 握手的配置，两端的 pattern 必须一致。
+/// 密钥都是 hex 编码的，可以用 NoiseBuilder::generate_keypair 生成
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct NoiseConfig {
+    /// 使用的 pattern，None 表示 DEFAULT_NOISE_PATTERN
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// 本地的静态私钥，XX、IK 等 pattern 需要
+    #[serde(default)]
+    pub static_key: Option<String>,
+    /// 只信任这些对端的静态公钥，为空时不校验
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+    /// 客户端预先知道的服务端静态公钥，IK 等 pattern 需要，服务端的身份必须与之一致
+    #[serde(default)]
+    pub remote_key: Option<String>,
+}
+
+/// TLS 握手允许的协议版本和加密套件，为空时使用 rustls 的默认值
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct TlsPolicyConfig {
+    /// 允许的协议版本，"1.2" 或 "1.3"
+    #[serde(default)]
+    pub versions: Vec<String>,
+    /// 允许的加密套件，使用 rustls 中的名字，如 "TLS13_AES_256_GCM_SHA384"
+    #[serde(default)]
+    pub cipher_suites: Vec<String>,
 }
 
 impl ServerConfig {
@@ -129,6 +504,48 @@ impl ServerConfig {
         let config: Self = toml::from_str(&config)?;
         Ok(config)
     }
+
+    /// 所有需要监听的地址，第一个是 general.addr
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        let primary = ListenerConfig {
+            addr: self.general.addr.clone(),
+            network: self.general.network.clone(),
+            security: self.security.clone(),
+        };
+        std::iter::once(primary)
+            .chain(self.general.listeners.iter().cloned())
+            .collect()
+    }
+
+    /// 去掉私钥和口令之后的配置，可以安全地输出到日志或诊断信息中
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        config.security.redact();
+        for listener in &mut config.general.listeners {
+            listener.security.redact();
+        }
+        config
+    }
+}
+
+const REDACTED: &str = "<redacted>";
+
+impl ServerSecurityProtocol {
+    fn redact(&mut self) {
+        match self {
+            ServerSecurityProtocol::Tls(tls) => {
+                tls.key = REDACTED.into();
+                if let Some(passphrase) = tls.key_passphrase.as_mut() {
+                    *passphrase = REDACTED.into();
+                }
+            }
+            ServerSecurityProtocol::Noise(noise) => {
+                if let Some(key) = noise.static_key.as_mut() {
+                    *key = REDACTED.into();
+                }
+            }
+        }
+    }
 }
 
 impl ClientConfig {
@@ -151,6 +568,45 @@ mod tests {
         assert!(result.is_ok())
     }
 
+    #[test]
+    fn redacted_config_should_not_contain_private_keys() {
+        let mut config: ServerConfig = toml::from_str(TLS_SERVER_CONFIG).unwrap();
+        let ServerSecurityProtocol::Tls(tls) = &mut config.security else {
+            panic!("expect tls config");
+        };
+        tls.key_passphrase = Some("s3cret-passphrase".into());
+        let tls = tls.clone();
+        config.general.listeners.push(ListenerConfig {
+            addr: "127.0.0.1:0".into(),
+            network: NetworkType::Tcp,
+            security: ServerSecurityProtocol::Tls(tls.clone()),
+        });
+
+        let redacted = config.redacted();
+        for security in [&redacted.security, &redacted.general.listeners[0].security] {
+            let ServerSecurityProtocol::Tls(redacted_tls) = security else {
+                panic!("expect tls config");
+            };
+            assert_eq!(redacted_tls.key, REDACTED);
+            assert_eq!(redacted_tls.key_passphrase.as_deref(), Some(REDACTED));
+            // 证书不是敏感信息
+            assert_eq!(redacted_tls.cert, tls.cert);
+        }
+        let redacted = toml::to_string(&redacted).unwrap();
+        assert!(!redacted.contains("PRIVATE KEY"));
+        assert!(!redacted.contains("s3cret-passphrase"));
+    }
+
+    #[test]
+    fn runtime_config_should_build_runtime() {
+        let config = RuntimeConfig {
+            worker_threads: Some(2),
+            max_blocking_threads: Some(4),
+        };
+        let rt = config.build().unwrap();
+        assert_eq!(rt.block_on(async { 1 + 1 }), 2);
+    }
+
     #[test]
     fn client_config_should_be_loaded() {
         let result: Result<ClientConfig, toml::de::Error> = toml::from_str(TLS_CLIENT_CONFIG);
@@ -1,3 +1,4 @@
+use http::StatusCode;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -30,11 +31,11 @@ pub enum KvError {
     RocksDBError(#[from] rocksdb::Error),
     #[error("I/O error")]
     IoError(#[from] std::io::Error),
-    #[error("tls error")]
+    #[error("tls error: {0}")]
     TlsError(#[from] tokio_rustls::rustls::Error),
-    #[error("noise error")]
-    NoiseError(#[from] snow::Error),
-    #[error("Yamux Connection error")]
+    #[error("noise error: {0}")]
+    NoiseError(String),
+    #[error("Yamux connection error: {0}")]
     YamuxConnectionError(#[from] yamux::ConnectionError),
     #[error("Quic Connection error")]
     QuicConnectionError(#[from] s2n_quic::connection::Error),
@@ -43,4 +44,255 @@ pub enum KvError {
 
     #[error("Internal error: {0}")]
     Internal(String),
+    #[error("Command timed out after {0:?}")]
+    Timeout(std::time::Duration),
+    #[error("Insufficient storage: {0}")]
+    InsufficientStorage(String),
+    #[error("Unsupported protocol version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+    #[error("Server is full: at most {0} connections")]
+    ServerFull(usize),
+    #[error("Version conflict: expected {expected}, current {current}")]
+    VersionConflict { expected: u64, current: u64 },
+}
+
+impl KvError {
+    /// 获取错误码，客户端可以根据错误码区分错误类型，而不需要匹配错误信息
+    ///
+    /// | 错误码 | 错误类型 |
+    /// | --- | --- |
+    /// | 0 | 成功 |
+    /// | 1 | NotFound |
+    /// | 2 | FrameError |
+    /// | 3 | InvalidCommand |
+    /// | 4 | ConvertError |
+    /// | 5 | StorageError |
+    /// | 6 | CertifcateParseError |
+    /// | 7 | EncodeError |
+    /// | 8 | DecodeError |
+    /// | 9 | SeldError |
+    /// | 10 | RocksDBError |
+    /// | 11 | IoError |
+    /// | 12 | TlsError |
+    /// | 13 | NoiseError |
+    /// | 14 | YamuxConnectionError |
+    /// | 15 | QuicConnectionError |
+    /// | 16 | ConfigError |
+    /// | 17 | Internal |
+    /// | 18 | Timeout |
+    /// | 19 | InsufficientStorage |
+    /// | 20 | UnsupportedVersion |
+    /// | 21 | PermissionDenied |
+    /// | 22 | TooManyRequests |
+    /// | 23 | ServiceUnavailable |
+    /// | 24 | ServerFull |
+    /// | 25 | VersionConflict |
+    pub fn code(&self) -> u32 {
+        match self {
+            KvError::NotFound(_) => 1,
+            KvError::FrameError => 2,
+            KvError::InvalidCommand(_) => 3,
+            KvError::ConvertError(..) => 4,
+            KvError::StorageError { .. } => 5,
+            KvError::CertifcateParseError(..) => 6,
+            KvError::EncodeError(_) => 7,
+            KvError::DecodeError(_) => 8,
+            KvError::SeldError(_) => 9,
+            KvError::RocksDBError(_) => 10,
+            KvError::IoError(_) => 11,
+            KvError::TlsError(_) => 12,
+            KvError::NoiseError(_) => 13,
+            KvError::YamuxConnectionError(_) => 14,
+            KvError::QuicConnectionError(_) => 15,
+            KvError::ConfigError(_) => 16,
+            KvError::Internal(_) => 17,
+            KvError::Timeout(_) => 18,
+            KvError::InsufficientStorage(_) => 19,
+            KvError::UnsupportedVersion(_) => 20,
+            KvError::PermissionDenied(_) => 21,
+            KvError::TooManyRequests(_) => 22,
+            KvError::ServiceUnavailable(_) => 23,
+            KvError::ServerFull(_) => 24,
+            KvError::VersionConflict { .. } => 25,
+        }
+    }
+}
+
+impl From<snow::Error> for KvError {
+    fn from(e: snow::Error) -> Self {
+        KvError::NoiseError(e.to_string())
+    }
+}
+
+impl KvError {
+    /// 是否是连接相关的暂时性错误，这类错误重新连接后可能恢复
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            KvError::IoError(_)
+                | KvError::YamuxConnectionError(_)
+                | KvError::QuicConnectionError(_)
+        )
+    }
+
+    /// 错误在 CommandResponse 中的状态码：4xx 表示请求本身有问题，原样重试也不会成功；
+    /// 5xx 表示服务器或者存储出错
+    pub fn status(&self) -> StatusCode {
+        match self {
+            KvError::NotFound(_) => StatusCode::NOT_FOUND,
+            KvError::InvalidCommand(_)
+            | KvError::ConvertError(..)
+            | KvError::UnsupportedVersion(_) => StatusCode::BAD_REQUEST,
+            KvError::FrameError => StatusCode::PAYLOAD_TOO_LARGE,
+            KvError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            KvError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            KvError::VersionConflict { .. } => StatusCode::CONFLICT,
+            // 存储和连接的错误通常是暂时的
+            KvError::StorageError { .. }
+            | KvError::SeldError(_)
+            | KvError::RocksDBError(_)
+            | KvError::IoError(_)
+            | KvError::YamuxConnectionError(_)
+            | KvError::QuicConnectionError(_)
+            | KvError::ServiceUnavailable(_)
+            | KvError::ServerFull(_) => StatusCode::SERVICE_UNAVAILABLE,
+            KvError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            KvError::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            KvError::CertifcateParseError(..)
+            | KvError::EncodeError(_)
+            | KvError::DecodeError(_)
+            | KvError::TlsError(_)
+            | KvError::NoiseError(_)
+            | KvError::ConfigError(_)
+            | KvError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// 稍后重试同样的请求是否可能成功，见 is_retryable_status
+    pub fn is_retryable(&self) -> bool {
+        is_retryable_status(self.status().as_u16() as u32)
+    }
+}
+
+/// 限流（429）、存储或服务暂时不可用（503）和超时（504）可以重试，其他错误重试也会得到同样的结果。
+/// 重试写命令可能使它被执行多次，调用者需要自己判断命令是否幂等
+pub fn is_retryable_status(status: u32) -> bool {
+    [
+        StatusCode::TOO_MANY_REQUESTS,
+        StatusCode::SERVICE_UNAVAILABLE,
+        StatusCode::GATEWAY_TIMEOUT,
+    ]
+    .iter()
+    .any(|s| s.as_u16() as u32 == status)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::{CommandResponse, Value};
+
+    #[test]
+    fn error_code_should_match_variant() {
+        let errors = [
+            (KvError::NotFound("key".into()), 1),
+            (KvError::FrameError, 2),
+            (KvError::InvalidCommand("cmd".into()), 3),
+            (KvError::ConvertError("value".into(), "Integer"), 4),
+            (
+                KvError::StorageError {
+                    command: "get",
+                    table: "table".into(),
+                    key: "key".into(),
+                    error: "error".into(),
+                },
+                5,
+            ),
+            (KvError::CertifcateParseError("server", "cert"), 6),
+            (Value::decode(&[0xff][..]).unwrap_err().into(), 8),
+            (sled::Error::Unsupported("sled".into()).into(), 9),
+            (std::io::Error::other("io").into(), 11),
+            (
+                tokio_rustls::rustls::Error::General("tls".into()).into(),
+                12,
+            ),
+            (snow::Error::Decrypt.into(), 13),
+            (yamux::ConnectionError::Closed.into(), 14),
+            (toml::from_str::<toml::Table>("=").unwrap_err().into(), 16),
+            (KvError::Internal("internal".into()), 17),
+            (KvError::Timeout(std::time::Duration::from_secs(1)), 18),
+            (KvError::InsufficientStorage("table".into()), 19),
+            (KvError::UnsupportedVersion(2), 20),
+            (KvError::PermissionDenied("compact".into()), 21),
+            (KvError::TooManyRequests("subscribe".into()), 22),
+            (KvError::ServiceUnavailable("sledb".into()), 23),
+            (KvError::ServerFull(1), 24),
+            (
+                KvError::VersionConflict {
+                    expected: 1,
+                    current: 2,
+                },
+                25,
+            ),
+        ];
+
+        for (err, code) in errors {
+            assert_eq!(err.code(), code);
+            let res: CommandResponse = err.into();
+            assert_eq!(res.error_code, code);
+        }
+    }
+
+    #[test]
+    fn client_errors_should_not_be_retryable() {
+        let errors = [
+            (KvError::ConvertError("value".into(), "Integer"), 400, false),
+            (KvError::InvalidCommand("cmd".into()), 400, false),
+            (KvError::NotFound("key".into()), 404, false),
+            (KvError::FrameError, 413, false),
+            (KvError::PermissionDenied("compact".into()), 403, false),
+            (KvError::TooManyRequests("subscribe".into()), 429, true),
+            (
+                KvError::StorageError {
+                    command: "get",
+                    table: "table".into(),
+                    key: "key".into(),
+                    error: "error".into(),
+                },
+                503,
+                true,
+            ),
+            (sled::Error::Unsupported("sled".into()).into(), 503, true),
+            (std::io::Error::other("io").into(), 503, true),
+            (
+                KvError::Timeout(std::time::Duration::from_secs(1)),
+                504,
+                true,
+            ),
+            (KvError::InsufficientStorage("table".into()), 507, false),
+            (KvError::Internal("internal".into()), 500, false),
+        ];
+
+        for (err, status, retryable) in errors {
+            assert_eq!(err.is_retryable(), retryable, "{err}");
+            let res: CommandResponse = err.into();
+            assert_eq!(res.status, status);
+            assert_eq!(res.is_retryable(), retryable);
+        }
+        assert!(!CommandResponse::ok().is_retryable());
+    }
+
+    #[test]
+    fn ok_response_should_have_no_error_code() {
+        let res: CommandResponse = Value::from("hello").into();
+        assert_eq!(res.error_code, 0);
+        assert_eq!(CommandResponse::ok().error_code, 0);
+    }
 }
@@ -4,6 +4,8 @@ mod network;
 mod pb;
 mod service;
 mod storage;
+#[cfg(any(test, feature = "testing"))]
+pub mod test_support;
 
 pub use config::*;
 pub use error::*;
@@ -14,86 +16,439 @@ pub use storage::*;
 
 use ::anyhow::Result;
 use anyhow::anyhow;
-use s2n_quic::{client::Connect, Client, Server};
-use std::{net::SocketAddr, str::FromStr};
-use tokio::net::{TcpListener, TcpStream};
+use futures::future;
+use std::{
+    env,
+    fmt::Display,
+    future::Future,
+    net::{self, SocketAddr},
+    os::fd::{FromRawFd, OwnedFd},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, UnixListener},
+    signal,
+    task::{AbortHandle, JoinHandle, JoinSet},
+    time,
+};
 use tokio_rustls::client;
 use tokio_util::compat::FuturesAsyncReadCompatExt;
-use tracing::{info, instrument, span};
-
-pub const QUIC_SERVER_CONFIG: &'static str = include_str!("../fixtures/quic/server.conf");
-pub const QUIC_CLIENT_CONFIG: &'static str = include_str!("../fixtures/quic/client.conf");
-pub const QUIC_CA_CERT: &'static str = include_str!("../fixtures/quic/ca.cert");
-pub const QUIC_CLIENT_CERT: &'static str = include_str!("../fixtures/quic/client.cert");
-pub const QUIC_CLIENT_KEY: &'static str = include_str!("../fixtures/quic/client.key");
-pub const QUIC_SERVER_CERT: &'static str = include_str!("../fixtures/quic/server.cert");
-pub const QUIC_SERVER_KEY: &'static str = include_str!("../fixtures/quic/server.key");
-
-pub const NOISE_SERVER_CONFIG: &'static str = include_str!("../fixtures/noise/server.conf");
-pub const NOISE_CLIENT_CONFIG: &'static str = include_str!("../fixtures/noise/client.conf");
-
-pub const TLS_SERVER_CONFIG: &'static str = include_str!("../fixtures/tls/server.conf");
-pub const TLS_CLIENT_CONFIG: &'static str = include_str!("../fixtures/tls/client.conf");
-pub const TLS_CA_CERT: &'static str = include_str!("../fixtures/tls/ca.cert");
-pub const TLS_CLIENT_CERT: &'static str = include_str!("../fixtures/tls/client.cert");
-pub const TLS_CLIENT_KEY: &'static str = include_str!("../fixtures/tls/client.key");
-pub const TLS_SERVER_CERT: &'static str = include_str!("../fixtures/tls/server.cert");
-pub const TLS_SERVER_KEY: &'static str = include_str!("../fixtures/tls/server.key");
-
-// 通过配置创建 KV 服务器
+use tracing::{info, instrument, warn};
+
+pub const QUIC_SERVER_CONFIG: &str = include_str!("../fixtures/quic/server.conf");
+pub const QUIC_CLIENT_CONFIG: &str = include_str!("../fixtures/quic/client.conf");
+pub const QUIC_CA_CERT: &str = include_str!("../fixtures/quic/ca.cert");
+pub const QUIC_CLIENT_CERT: &str = include_str!("../fixtures/quic/client.cert");
+pub const QUIC_CLIENT_KEY: &str = include_str!("../fixtures/quic/client.key");
+pub const QUIC_SERVER_CERT: &str = include_str!("../fixtures/quic/server.cert");
+pub const QUIC_SERVER_KEY: &str = include_str!("../fixtures/quic/server.key");
+
+pub const NOISE_SERVER_CONFIG: &str = include_str!("../fixtures/noise/server.conf");
+pub const NOISE_CLIENT_CONFIG: &str = include_str!("../fixtures/noise/client.conf");
+
+pub const TLS_SERVER_CONFIG: &str = include_str!("../fixtures/tls/server.conf");
+pub const TLS_CLIENT_CONFIG: &str = include_str!("../fixtures/tls/client.conf");
+pub const TLS_CA_CERT: &str = include_str!("../fixtures/tls/ca.cert");
+pub const TLS_CLIENT_CERT: &str = include_str!("../fixtures/tls/client.cert");
+pub const TLS_CLIENT_KEY: &str = include_str!("../fixtures/tls/client.key");
+pub const TLS_SERVER_CERT: &str = include_str!("../fixtures/tls/server.cert");
+pub const TLS_SERVER_KEY: &str = include_str!("../fixtures/tls/server.key");
+
+// tokio 只在 tokio_unstable 下支持给 task 命名和 tokio-console 需要的 instrumentation
+#[cfg(all(feature = "tokio-console", not(tokio_unstable)))]
+compile_error!("the tokio-console feature requires RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+/// 启动一个 tokio task，开启 tokio-console feature 时 task 带上名字，方便在 tokio-console 中区分
+#[track_caller]
+pub(crate) fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(feature = "tokio-console", tokio_unstable))]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("failed to spawn task");
+
+    #[cfg(not(all(feature = "tokio-console", tokio_unstable)))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// 和 spawn_named 一样，但 task 由 JoinSet 管理
+#[track_caller]
+pub(crate) fn spawn_named_in<F>(
+    tasks: &mut JoinSet<F::Output>,
+    name: &str,
+    future: F,
+) -> AbortHandle
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(feature = "tokio-console", tokio_unstable))]
+    return tasks
+        .build_task()
+        .name(name)
+        .spawn(future)
+        .expect("failed to spawn task");
+
+    #[cfg(not(all(feature = "tokio-console", tokio_unstable)))]
+    {
+        let _ = name;
+        tasks.spawn(future)
+    }
+}
+
+/// 收到关闭信号后，等待正在投递的 publish 的最长时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 连接数达到上限时，服务器关闭 QUIC 连接使用的应用错误码
+pub const QUIC_SERVER_FULL_CODE: u32 = 0x503;
+
+/// 连接数达到上限时，等待被拒绝的连接发送第一个命令的最长时间
+const REJECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// 通过配置创建 KV 服务器，收到 ctrl-c 后优雅退出
 #[instrument(name = "start_server_with_config", skip_all)]
 pub async fn start_server_with_config(config: &ServerConfig) -> Result<()> {
-    let addr = &config.general.addr;
-    match &config.security {
-        ServerSecurityProtocol::Tls(tls_config) => match config.general.network {
-            NetworkType::Tcp => {
-                let acceptor = TlsServerAcceptor::new(
-                    &tls_config.cert,
-                    &tls_config.key,
-                    tls_config.ca.as_deref(),
-                )?;
-
-                match &config.storage {
-                    StorageConfig::MemTable => {
-                        start_yamux_server(addr, MemTable::new(), acceptor).await?
-                    }
-                    StorageConfig::Sledb(path) => {
-                        start_yamux_server(addr, SledDb::new(path), acceptor).await?
-                    }
-                    StorageConfig::Rocksdb(path) => {
-                        start_yamux_server(addr, RocksDB::new(path), acceptor).await?
-                    }
-                };
-            }
-            NetworkType::Quic => {
-                match &config.storage {
-                    StorageConfig::MemTable => {
-                        start_quic_server(addr, MemTable::new(), tls_config).await?
-                    }
-                    StorageConfig::Sledb(path) => {
-                        start_quic_server(addr, SledDb::new(path), tls_config).await?
-                    }
-                    StorageConfig::Rocksdb(path) => {
-                        start_quic_server(addr, RocksDB::new(path), tls_config).await?
-                    }
-                };
-            }
+    let shutdown = async {
+        if let Err(e) = signal::ctrl_c().await {
+            // 无法监听信号时一直运行
+            warn!("Failed to listen for ctrl-c: {e}");
+            future::pending::<()>().await;
+        }
+    };
+    start_server_with_shutdown(config, shutdown).await
+}
+
+/// 通过配置创建 KV 服务器，shutdown 完成后停止监听，等待正在投递的 publish 发送完再返回
+pub async fn start_server_with_shutdown(
+    config: &ServerConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    start_server_with_registrar(config, config_registrar(config)?, shutdown).await
+}
+
+/// 和 start_server_with_shutdown 一样，但每个 listener bind 之后用 registrar 注册，
+/// 关闭时注销，不使用配置中的 mdns
+pub async fn start_server_with_registrar(
+    config: &ServerConfig,
+    registrar: Arc<dyn ServiceRegistrar>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let registry = Arc::new(Registry::new(registrar));
+    if !config.table_storage.is_empty() {
+        let store = RoutingStore::new(&config.storage, &config.table_storage)
+            .with_codec(config.value_codec)
+            .with_compression(ValueCompression::new(&config.table_compression));
+        return match store.is_blocking() {
+            true => start_disk_listeners(config, store, registry, shutdown).await,
+            false => start_listeners(config, store, registry, shutdown).await,
+        };
+    }
+    match &config.storage {
+        StorageConfig::MemTable => {
+            start_listeners(config, MemTable::new(), registry, shutdown).await
+        }
+        StorageConfig::Sledb(path) => {
+            let store = SledDb::new(path)
+                .with_codec(config.value_codec)
+                .with_compression(ValueCompression::new(&config.table_compression));
+            start_disk_listeners(config, store, registry, shutdown).await
+        }
+        StorageConfig::Rocksdb(path) => {
+            let store = RocksDB::new(path)
+                .with_codec(config.value_codec)
+                .with_compression(ValueCompression::new(&config.table_compression));
+            start_disk_listeners(config, store, registry, shutdown).await
+        }
+    }
+}
+
+// 配置了 mdns 时通过 mDNS 公布服务器，需要开启 mdns feature
+fn config_registrar(config: &ServerConfig) -> Result<Arc<dyn ServiceRegistrar>> {
+    match &config.mdns {
+        #[cfg(feature = "mdns")]
+        Some(instance) => Ok(Arc::new(MdnsRegistrar::new(instance.as_str())?)),
+        #[cfg(not(feature = "mdns"))]
+        Some(_) => {
+            warn!("mdns is configured but the mdns feature is not enabled");
+            Ok(Arc::new(NoopRegistrar))
+        }
+        None => Ok(Arc::new(NoopRegistrar)),
+    }
+}
+
+// 磁盘存储可以配置写入合并
+async fn start_disk_listeners<Store: Storage>(
+    config: &ServerConfig,
+    store: Store,
+    registry: Arc<Registry>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    match config.write_coalescing {
+        Some(coalesce) => {
+            let store = CoalescedStore::new(store, coalesce);
+            start_cached_listeners(config, store, registry, shutdown).await
+        }
+        None => start_cached_listeners(config, store, registry, shutdown).await,
+    }
+}
+
+// 配置了读缓存时在写入合并之上包装 CachedStore
+async fn start_cached_listeners<Store: Storage>(
+    config: &ServerConfig,
+    store: Store,
+    registry: Arc<Registry>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    match config.read_cache {
+        Some(cache) => {
+            let store = CachedStore::new(store, cache);
+            start_guarded_listeners(config, store, registry, shutdown).await
+        }
+        None => start_guarded_listeners(config, store, registry, shutdown).await,
+    }
+}
+
+// 配置了熔断时在最外层包装 CircuitBreakerStore，缓冲区写入失败也会被计数
+async fn start_guarded_listeners<Store: Storage>(
+    config: &ServerConfig,
+    store: Store,
+    registry: Arc<Registry>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    match config.circuit_breaker {
+        Some(breaker) => {
+            let store = CircuitBreakerStore::new(store, breaker);
+            start_listeners(config, store, registry, shutdown).await
+        }
+        None => start_listeners(config, store, registry, shutdown).await,
+    }
+}
+
+// 为每个 listener 启动一个 accept loop，所有 listener 共享同一个 store 和 broadcaster
+async fn start_listeners<Store: Storage>(
+    config: &ServerConfig,
+    store: Store,
+    registry: Arc<Registry>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let service: Service<Store> = ServiceInner::new(store)
+        .limits(config.limits)
+        .auto_create_tables(config.auto_create_tables)
+        .admin_commands(config.admin_commands)
+        .keyspace_notifications(config.keyspace_notifications)
+        .key_versioning(config.key_versioning)
+        .broadcaster(Broadcaster::default().history_size(config.topic_history_size))
+        .max_connections(config.general.max_connections)
+        .compression(config.general.compression)
+        .server_config(config)
+        .log_bridge(LogBridge::global())
+        .into();
+    let settings = ConnSettings {
+        idle_timeout: config.general.idle_timeout.map(Duration::from_secs),
+        timeout: config.general.command_timeout,
+        max_in_flight: config.general.max_in_flight,
+        priority_queue: config.general.priority_queue,
+        max_subscriptions: config.general.max_subscriptions,
+        buffer_pool: config.general.buffer_pool,
+        handshake_timeout: match config.general.handshake_timeout {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
         },
-        ServerSecurityProtocol::Noise => {
-            let acceptor = NoiseBuilder::new();
-            match &config.storage {
-                StorageConfig::MemTable => {
-                    start_yamux_server(addr, MemTable::new(), acceptor).await?
+        accept_rate: config.general.accept_rate,
+    };
+    let backlog = config
+        .general
+        .listen_backlog
+        .unwrap_or(DEFAULT_LISTEN_BACKLOG);
+
+    // 由 systemd socket activation 启动时，按顺序使用传入的 socket 代替配置中的 listener 自己 bind
+    let mut fds = systemd_listen_fds().into_iter();
+
+    // 每个 accept loop 是一个单独的 task
+    let mut handles = vec![];
+    let listeners = config.listeners().into_iter().map(|listener| {
+        let fd = fds.next();
+        // 同时监听 TCP 和 QUIC 的 listener 需要第二个 socket
+        let udp_fd = match listener.network {
+            NetworkType::TcpQuic => fds.next(),
+            _ => None,
+        };
+        let service = service.clone();
+        let registry = registry.clone();
+        let name = format!("accept {}", listener.addr);
+        let handle = spawn_named(&name, async move {
+            let addr = &listener.addr;
+            match (&listener.security, &listener.network) {
+                (ServerSecurityProtocol::Tls(tls_config), NetworkType::Quic) => {
+                    let transport = QuicTransport::server(tls_config.clone());
+                    listen(transport, &listener, fd, service, settings, &registry).await
                 }
-                StorageConfig::Sledb(path) => {
-                    start_yamux_server(addr, SledDb::new(path), acceptor).await?
+                (ServerSecurityProtocol::Tls(tls_config), network) => {
+                    let acceptor = TlsServerAcceptor::new_with_policy(
+                        &tls_config.cert,
+                        &tls_config.key,
+                        tls_config.key_passphrase.as_deref(),
+                        tls_config.ca.as_deref(),
+                        &tls_config.policy,
+                    )?;
+                    match network {
+                        NetworkType::Unix => {
+                            let transport = TlsTransport::<UnixListener>::server(acceptor);
+                            listen(transport, &listener, fd, service, settings, &registry).await
+                        }
+                        NetworkType::TcpQuic => {
+                            let tcp =
+                                TlsTransport::<TcpListener>::server(acceptor).with_backlog(backlog);
+                            let quic = QuicTransport::server(tls_config.clone());
+                            let fds = (fd, udp_fd);
+                            listen_tcp_quic(tcp, quic, &listener, fds, service, settings, &registry)
+                                .await
+                        }
+                        _ => {
+                            let transport =
+                                TlsTransport::<TcpListener>::server(acceptor).with_backlog(backlog);
+                            listen(transport, &listener, fd, service, settings, &registry).await
+                        }
+                    }
                 }
-                StorageConfig::Rocksdb(path) => {
-                    start_yamux_server(addr, RocksDB::new(path), acceptor).await?
+                (ServerSecurityProtocol::Noise(_), NetworkType::Quic | NetworkType::TcpQuic) => {
+                    Err(anyhow!("QUIC listener {addr} requires TLS"))
+                }
+                (ServerSecurityProtocol::Noise(noise), network) => {
+                    let builder = NoiseBuilder::from_config(noise)?;
+                    match network {
+                        NetworkType::Unix => {
+                            let transport = NoiseTransport::<UnixListener>::server(builder);
+                            listen(transport, &listener, fd, service, settings, &registry).await
+                        }
+                        _ => {
+                            let transport = NoiseTransport::<TcpListener>::server(builder)
+                                .with_backlog(backlog);
+                            listen(transport, &listener, fd, service, settings, &registry).await
+                        }
+                    }
                 }
             }
+        });
+        handles.push(handle.abort_handle());
+        async move { handle.await? }
+    });
+    let listeners: Vec<_> = listeners.collect();
+    if fds.len() > 0 {
+        warn!("{} sockets passed by systemd are not used", fds.len());
+    }
+
+    let res = tokio::select! {
+        res = future::try_join_all(listeners) => res.map(|_| ()),
+        _ = shutdown => {
+            info!("Shutting down");
+            Ok(())
         }
+    };
+
+    // 先停止 accept 并注销服务，再等待 broadcaster 把数据发送完并关闭所有订阅
+    for handle in handles {
+        handle.abort();
     }
+    registry.deregister_all();
+    res?;
+    if !service.broadcaster().shutdown(SHUTDOWN_TIMEOUT).await {
+        warn!("Some publishes are not delivered before shutdown");
+    }
+
+    Ok(())
+}
+
+// systemd socket activation 传入的 fd，从 3 开始连续 LISTEN_FDS 个，
+// LISTEN_PID 不是当前进程时说明环境变量是从父进程继承来的，忽略它们
+fn systemd_listen_fds() -> Vec<OwnedFd> {
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok());
+    if pid != Some(std::process::id()) {
+        return vec![];
+    }
+    let n = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok())
+        .unwrap_or(0);
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + n)
+        // SAFETY: systemd 保证这些 fd 已经打开并且只交给当前进程，每个 fd 只在这里转换一次
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .collect()
+}
+
+// 在 transport 上监听 listener 的地址（或者使用传入的 fd），bind 之后注册服务，
+// 直到 accept 遇到无法恢复的错误
+async fn listen<T: Transport, Store: Storage>(
+    transport: T,
+    config: &ListenerConfig,
+    fd: Option<OwnedFd>,
+    service: Service<Store>,
+    settings: ConnSettings,
+    registry: &Registry,
+) -> Result<()> {
+    let listener = match fd {
+        Some(fd) => transport.adopt(fd)?,
+        None => transport.bind(&config.addr).await?,
+    };
+    info!("Start listening on {}", config.addr);
+    if let Some(addr) = listener.local_addr() {
+        registry.register(ServiceEndpoint::new(addr, config));
+    }
+    Ok(serve(listener, service, settings).await?)
+}
+
+// 在同一个端口上监听 TCP 和 QUIC，两个 listener 共享 service，任意一个遇到无法恢复的错误时返回。
+// 先 bind TCP，端口为 0 时 QUIC 使用 TCP 分配到的端口
+async fn listen_tcp_quic<Store: Storage>(
+    tcp: TlsTransport<TcpListener>,
+    quic: QuicTransport,
+    config: &ListenerConfig,
+    (tcp_fd, udp_fd): (Option<OwnedFd>, Option<OwnedFd>),
+    service: Service<Store>,
+    settings: ConnSettings,
+    registry: &Registry,
+) -> Result<()> {
+    let tcp_listener = match tcp_fd {
+        Some(fd) => tcp.adopt(fd)?,
+        None => tcp.bind(&config.addr).await?,
+    };
+    let quic_listener = match (udp_fd, tcp_listener.local_addr()) {
+        (Some(fd), _) => quic.adopt(fd)?,
+        (None, Some(addr)) => quic.bind(&addr.to_string()).await?,
+        (None, None) => quic.bind(&config.addr).await?,
+    };
+    info!("Start listening on {} (TCP and QUIC)", config.addr);
+    let endpoints = [
+        (NetworkType::Tcp, tcp_listener.local_addr()),
+        (NetworkType::Quic, quic_listener.local_addr()),
+    ];
+    for (network, addr) in endpoints {
+        let config = ListenerConfig {
+            network,
+            ..config.clone()
+        };
+        if let Some(addr) = addr {
+            registry.register(ServiceEndpoint::new(addr, &config));
+        }
+    }
+    tokio::try_join!(
+        serve(tcp_listener, service.clone(), settings),
+        serve(quic_listener, service, settings),
+    )?;
     Ok(())
 }
 
@@ -101,15 +456,17 @@ pub async fn start_server_with_config(config: &ServerConfig) -> Result<()> {
 pub async fn start_yamux_client_with_tls_config(
     config: &ClientConfig,
 ) -> Result<YamuxConn<client::TlsStream<TcpStream>>> {
-    let addr = &config.general.addr;
     if let ClientSecurityProtocol::Tls(tls) = &config.security {
         let identity = tls.identity.as_ref().map(|(c, k)| (c.as_str(), k.as_str()));
-        let connector = TlsClientConnector::new(&tls.domain, identity, tls.ca.as_deref())?;
-        let stream = TcpStream::connect(addr).await?;
-        let stream = connector.connect(stream).await?;
-
-        // 打开一个 stream
-        Ok(YamuxConn::new_client(stream, None))
+        let connector = TlsClientConnector::new_with_policy(
+            &tls.domain,
+            identity,
+            tls.key_passphrase.as_deref(),
+            tls.ca.as_deref(),
+            &tls.policy,
+        )?;
+        let transport = TlsTransport::<TcpListener>::client(connector);
+        Ok(transport.connect(&config.general.addr).await?)
     } else {
         Err(anyhow!("client security protocol is not matched"))
     }
@@ -119,13 +476,9 @@ pub async fn start_yamux_client_with_tls_config(
 pub async fn start_yamux_client_with_noise_config(
     config: &ClientConfig,
 ) -> Result<YamuxConn<NoiseInitiator<TcpStream>>> {
-    let addr = &config.general.addr;
-    if let ClientSecurityProtocol::Noise = &config.security {
-        let stream = TcpStream::connect(addr).await?;
-        let stream = NoiseBuilder::new().connect(stream).await?;
-
-        // 打开一个 stream
-        Ok(YamuxConn::new_client(stream, None))
+    if let ClientSecurityProtocol::Noise(noise) = &config.security {
+        let transport = NoiseTransport::<TcpListener>::client(NoiseBuilder::from_config(noise)?);
+        Ok(transport.connect(&config.general.addr).await?)
     } else {
         Err(anyhow!("client security protocol is not matched"))
     }
@@ -133,21 +486,9 @@ pub async fn start_yamux_client_with_noise_config(
 
 #[instrument(name = "start_quic_client_with_config", skip_all)]
 pub async fn start_quic_client_with_config(config: &ClientConfig) -> Result<QuicConn> {
-    let addr = SocketAddr::from_str(&config.general.addr)?;
     if let ClientSecurityProtocol::Tls(tls) = &config.security {
-        let client = Client::builder()
-            .with_tls(tls.ca.as_ref().unwrap().as_str())?
-            .with_io("0.0.0.0:0")?
-            .start()
-            .map_err(|e| anyhow!("Failed to start client. Error: {e}"))?;
-
-        // "Server Name Indication" (SNI) 在生成时证书设置，以绑定证书与特定主机，用于防止中间人攻击
-        let connect = Connect::new(addr).with_server_name("kvserver.acme.inc");
-        let mut conn = client.connect(connect).await?;
-
-        conn.keep_alive(true)?;
-
-        Ok(QuicConn::new(conn))
+        let transport = QuicTransport::client(tls.clone());
+        Ok(transport.connect(&config.general.addr).await?)
     } else {
         Err(anyhow!("client security protocol is not matched"))
     }
@@ -155,73 +496,221 @@ pub async fn start_quic_client_with_config(config: &ClientConfig) -> Result<Quic
 
 pub async fn start_quic_server<Store: Storage>(
     addr: &str,
-    store: Store,
+    service: Service<Store>,
     tls_config: &ServerTlsConfig,
+    timeout: CommandTimeout,
 ) -> Result<()> {
-    let service: Service<Store> = ServiceInner::new(store).into();
-    let mut listener = Server::builder()
-        .with_tls((tls_config.cert.as_str(), tls_config.key.as_str()))?
-        .with_io(addr)?
-        .start()
-        .map_err(|e| anyhow::anyhow!("Failed to start server. Error: {}", e))?;
-
-    info!("Start listening on {addr}");
-
-    loop {
-        let root = span!(tracing::Level::INFO, "server_process");
-        let _enter = root.enter();
-
-        if let Some(mut conn) = listener.accept().await {
-            info!("Client {} connected", conn.remote_addr()?);
-            let svc = service.clone();
-
-            tokio::spawn(async move {
-                while let Ok(Some(stream)) = conn.accept_bidirectional_stream().await {
-                    info!(
-                        "Accepted stream from {}",
-                        stream.connection().remote_addr()?
-                    );
-
-                    let svc = svc.clone();
-                    tokio::spawn(async move {
-                        let stream = ProstServerStream::new(stream, svc);
-                        stream.process().await.unwrap();
-                    });
-                }
-                Ok::<(), anyhow::Error>(())
-            });
-        }
-    }
+    let transport = QuicTransport::server(tls_config.clone());
+    let listener = ListenerConfig {
+        addr: addr.to_string(),
+        network: NetworkType::Quic,
+        security: ServerSecurityProtocol::Tls(tls_config.clone()),
+    };
+    let settings = ConnSettings {
+        timeout,
+        ..Default::default()
+    };
+    listen(
+        transport,
+        &listener,
+        None,
+        service,
+        settings,
+        &Registry::default(),
+    )
+    .await
 }
 
-async fn start_yamux_server<Store, Acceptor>(
-    addr: &str,
+/// 和 start_quic_server 一样，但是使用调用者已经 bind 好的 UDP socket
+pub async fn start_quic_server_with_socket<Store: Storage>(
+    socket: net::UdpSocket,
+    service: Service<Store>,
+    tls_config: &ServerTlsConfig,
+    timeout: CommandTimeout,
+) -> Result<()> {
+    let listener = QuicTransport::server(tls_config.clone()).listen_on(socket)?;
+    info!("Start listening on {:?}", listener.local_addr());
+    let settings = ConnSettings {
+        timeout,
+        ..Default::default()
+    };
+    Ok(serve(listener, service, settings).await?)
+}
+
+/// 在调用者已经 bind 好的 TcpListener 上用 TLS + yamux 运行服务，
+/// 比如端口由测试分配，或者 socket 由其他进程传入
+pub async fn start_yamux_server_with_listener<Store: Storage>(
+    listener: TcpListener,
     store: Store,
-    acceptor: Acceptor,
-) -> Result<()>
+    acceptor: TlsServerAcceptor,
+) -> Result<()> {
+    let listener = TlsTransport::<TcpListener>::server(acceptor).listen_on(listener)?;
+    info!("Start listening on {:?}", listener.local_addr());
+    let service = ServiceInner::new(store).into();
+    Ok(serve(listener, service, ConnSettings::default()).await?)
+}
+
+/// 在 stdin/stdout 上运行 service，用于作为子进程嵌入到其他程序中，不需要网络：
+/// 从 stdin 读取命令的 frame，向 stdout 写入响应，stdin 关闭后处理完已读取的命令再返回。
+///
+/// 日志等输出不能再写到 stdout，否则会破坏 frame
+pub async fn start_stdio_server<Store: Storage>(store: Store) -> Result<()> {
+    let service = ServiceInner::new(store).into();
+    serve_stdio(io::stdin(), io::stdout(), service).await
+}
+
+/// 在一对 reader/writer 上运行 service，和 start_stdio_server 一样只有一个 stream
+pub async fn serve_stdio<R, W, Store>(reader: R, writer: W, service: Service<Store>) -> Result<()>
 where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
     Store: Storage,
-    Acceptor: SecureStreamAccept<tokio::net::TcpStream> + Clone + Send + 'static,
 {
-    let service: Service<Store> = ServiceInner::new(store).into();
-    let listener = TcpListener::bind(addr).await?;
-    info!("Start listening on {addr}");
-    loop {
-        let acceptor = acceptor.clone();
-        let (stream, addr) = listener.accept().await?;
-        info!("Client {addr:?} connected");
-
-        let svc = service.clone();
-        tokio::spawn(async move {
-            let stream = acceptor.accept(stream).await.unwrap();
-            YamuxConn::new_server(stream, None, move |stream| {
-                let svc = svc.clone();
-                async move {
-                    let stream = ProstServerStream::new(stream.compat(), svc.clone());
-                    stream.process().await.unwrap();
-                    Ok(())
-                }
-            })
+    let stream = ProstServerStream::new(io::join(reader, writer), service);
+    stream.process().await?;
+    Ok(())
+}
+
+// 处理一个 QUIC 连接，每个 stream 在单独的 task 中处理
+async fn serve_quic_conn<Store: Storage>(
+    mut conn: s2n_quic::Connection,
+    svc: Service<Store>,
+    settings: ConnSettings,
+) {
+    // s2n-quic 的连接上拿不到客户端证书
+    let conn_info = ConnectionInfo {
+        remote_addr: conn.remote_addr().ok(),
+        peer_identity: None,
+    };
+    // QUIC 可以直接用应用错误码告诉客户端关闭的原因
+    if let Err(e) = svc.connected(&conn_info) {
+        warn!("Reject connection from {:?}: {e}", conn_info.remote_addr);
+        conn.close(QUIC_SERVER_FULL_CODE.into());
+        return;
+    }
+    let quota = Arc::new(SubscriptionQuota::new(settings.max_subscriptions));
+
+    while let Ok(Some(stream)) = conn.accept_bidirectional_stream().await {
+        info!("Accepted stream from {:?}", conn_info.remote_addr);
+
+        let svc = svc.clone();
+        let quota = quota.clone();
+        let remote_addr = conn_info.remote_addr;
+        let name = format!("quic stream {remote_addr:?}");
+        spawn_named(&name, async move {
+            let stream = ProstServerStream::new(stream, svc)
+                .with_timeout(settings.timeout)
+                .with_max_in_flight(settings.max_in_flight)
+                .with_priority_queue(settings.priority_queue)
+                .with_subscription_quota(quota)
+                .with_buffer_pool(settings.buffer_pool)
+                .with_peer_addr(remote_addr);
+            stream.process().await.unwrap();
         });
     }
+    svc.disconnected(&conn_info);
+}
+
+// 在一个已经 accept 的连接上运行 yamux，连接空闲超时后关闭连接
+async fn serve_yamux_conn<S, Store, Acceptor>(
+    stream: S,
+    addr: impl Display,
+    remote_addr: Option<SocketAddr>,
+    acceptor: Acceptor,
+    svc: Service<Store>,
+    settings: ConnSettings,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+    Store: Storage,
+    Acceptor: SecureStreamAccept<S>,
+    Acceptor::InnerStream: PeerIdentity + 'static,
+{
+    // 握手失败或者超时只关闭这个连接，避免不完成握手的连接一直占用资源
+    let accept = acceptor.accept(stream);
+    let res = match settings.handshake_timeout {
+        Some(timeout) => time::timeout(timeout, accept)
+            .await
+            .unwrap_or(Err(KvError::Timeout(timeout))),
+        None => accept.await,
+    };
+    let stream = match res {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!("Handshake with {addr} failed: {e}");
+            return;
+        }
+    };
+    let conn_info = ConnectionInfo {
+        remote_addr,
+        peer_identity: stream.peer_identity(),
+    };
+    if let Err(e) = svc.connected(&conn_info) {
+        warn!("Reject connection from {addr}: {e}");
+        reject_yamux_conn(stream, svc, e.into()).await;
+        return;
+    }
+
+    let activity = Arc::new(tokio::sync::Notify::new());
+    let activity_cloned = activity.clone();
+    let svc_cloned = svc.clone();
+    // 连接上的所有 stream 共享订阅数的上限
+    let quota = Arc::new(SubscriptionQuota::new(settings.max_subscriptions));
+    let mut conn = YamuxConn::new_server(stream, None, move |stream| {
+        let svc = svc_cloned.clone();
+        let activity = activity_cloned.clone();
+        let quota = quota.clone();
+        async move {
+            let stream = ProstServerStream::new(stream.compat(), svc.clone())
+                .with_activity(activity)
+                .with_timeout(settings.timeout)
+                .with_max_in_flight(settings.max_in_flight)
+                .with_priority_queue(settings.priority_queue)
+                .with_subscription_quota(quota)
+                .with_buffer_pool(settings.buffer_pool)
+                .with_peer_addr(remote_addr);
+            stream.process().await.unwrap();
+            Ok(())
+        }
+    });
+
+    // 等待连接关闭，连接空闲超时后主动关闭连接
+    let idle = async {
+        match settings.idle_timeout {
+            Some(idle_timeout) => {
+                wait_idle(&activity, idle_timeout).await;
+                idle_timeout
+            }
+            None => future::pending().await,
+        }
+    };
+    let idle_timeout = tokio::select! {
+        _ = conn.closed() => None,
+        idle_timeout = idle => Some(idle_timeout),
+    };
+    if let Some(idle_timeout) = idle_timeout {
+        info!("Client {addr} is idle for {idle_timeout:?}, closing");
+        conn.close();
+    }
+    svc.disconnected(&conn_info);
+}
+
+// 握手已经完成，对客户端每个 stream 的第一个命令返回 res，直到客户端关闭连接或者超时。
+// 立即关闭连接可能丢弃还没有发送出去的响应
+async fn reject_yamux_conn<S, Store>(stream: S, svc: Service<Store>, res: CommandResponse)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    Store: Storage,
+{
+    let mut conn = YamuxConn::new_server(stream, None, move |stream| {
+        let stream = ProstServerStream::new(stream.compat(), svc.clone());
+        let res = res.clone();
+        async move {
+            // 发送失败说明对端已经断开，不需要处理
+            let _ = stream.reject(res).await;
+            Ok(())
+        }
+    });
+    if time::timeout(REJECT_TIMEOUT, conn.closed()).await.is_err() {
+        conn.close();
+    }
 }
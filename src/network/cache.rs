@@ -0,0 +1,321 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use lru::LruCache;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task::JoinHandle,
+};
+use tracing::warn;
+
+use crate::{
+    keyspace_event, keyspace_topic, spawn_named, AppStream, CommandRequest, CommandResponse,
+    KvError, ProstClientStream, Value,
+};
+
+/// 带本地缓存的客户端：HGET 的结果缓存在本地，通过订阅 table 的 keyspace 通知让缓存失效。
+///
+/// 需要服务器开启 keyspace_notifications。subscriber 消费太慢时通知可能被丢弃，
+/// key 过期被删除时也不会有通知，所以对一致性有要求时应该设置 ttl
+pub struct CachingClient<C: AppStream> {
+    conn: C,
+    stream: Option<ProstClientStream<C::InnerStream>>,
+    ttl: Option<Duration>,
+    state: Arc<Mutex<CacheState>>,
+    // table -> 接收这个 table 的 keyspace 通知的 task
+    watchers: HashMap<String, JoinHandle<()>>,
+}
+
+struct CacheState {
+    entries: LruCache<(String, String), CacheEntry>,
+    // 每个 table 失效的次数，读取期间 table 失效过时，读到的结果不写入缓存
+    generations: HashMap<String, u64>,
+}
+
+struct CacheEntry {
+    // None 表示 key 不存在
+    value: Option<Value>,
+    expires_at: Option<Instant>,
+}
+
+impl CacheState {
+    fn generation(&self, table: &str) -> u64 {
+        self.generations.get(table).copied().unwrap_or_default()
+    }
+
+    // keys 为空时整个 table 失效
+    fn invalidate(&mut self, table: &str, keys: &[String]) {
+        *self.generations.entry(table.to_string()).or_default() += 1;
+        if keys.is_empty() {
+            let stale: Vec<_> = self
+                .entries
+                .iter()
+                .filter(|((t, _), _)| t == table)
+                .map(|(k, _)| k.clone())
+                .collect();
+            for k in stale {
+                self.entries.pop(&k);
+            }
+        } else {
+            for key in keys {
+                self.entries.pop(&(table.to_string(), key.clone()));
+            }
+        }
+    }
+}
+
+impl CacheEntry {
+    fn is_fresh(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => Instant::now() < expires_at,
+            None => true,
+        }
+    }
+}
+
+impl<C> CachingClient<C>
+where
+    C: AppStream,
+    C::InnerStream: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// capacity 是最多缓存的 key 的数量，超出时淘汰最久没有读取的 key
+    pub fn new(conn: C, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        let state = CacheState {
+            entries: LruCache::new(capacity),
+            generations: HashMap::new(),
+        };
+        Self {
+            conn,
+            stream: None,
+            ttl: None,
+            state: Arc::new(Mutex::new(state)),
+            watchers: HashMap::new(),
+        }
+    }
+
+    /// 缓存的结果在 ttl 之后失效，即使没有收到通知
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// 读取 key 的值，key 不存在时返回 None。命中缓存时不访问服务器
+    pub async fn get(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.watch(table).await?;
+
+        let cache_key = (table.to_string(), key.to_string());
+        let generation = {
+            let mut state = self.state.lock().unwrap();
+            match state.entries.get(&cache_key) {
+                Some(entry) if entry.is_fresh() => return Ok(entry.value.clone()),
+                Some(_) => {
+                    state.entries.pop(&cache_key);
+                }
+                None => {}
+            }
+            state.generation(table)
+        };
+
+        let res = self
+            .execute_unary(&CommandRequest::new_hget(table, key))
+            .await?;
+        let value = match res.status {
+            200 => res.values.into_iter().next(),
+            404 => None,
+            _ => return Err(response_error(&res)),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state.generation(table) == generation {
+            let entry = CacheEntry {
+                value: value.clone(),
+                expires_at: self.ttl.map(|ttl| Instant::now() + ttl),
+            };
+            state.entries.put(cache_key, entry);
+        }
+        Ok(value)
+    }
+
+    /// 执行命令，写命令成功后立即让本地缓存失效，不用等待服务器的通知
+    pub async fn execute_unary(
+        &mut self,
+        cmd: &CommandRequest,
+    ) -> Result<CommandResponse, KvError> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => self.stream.insert(self.conn.open_stream().await?),
+        };
+        let res = stream.execute_unary(cmd).await;
+        match &res {
+            Ok(res) if res.status == 200 => {
+                for (table, _, keys) in keyspace_event(cmd) {
+                    let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+                    self.state.lock().unwrap().invalidate(table, &keys);
+                }
+            }
+            Ok(_) => {}
+            // 出错的 stream 不再可用，下次重新打开
+            Err(_) => self.stream = None,
+        }
+        res
+    }
+
+    // 订阅 table 的 keyspace 通知，已经订阅时什么都不做
+    async fn watch(&mut self, table: &str) -> Result<(), KvError> {
+        if self.watchers.get(table).is_some_and(|h| !h.is_finished()) {
+            return Ok(());
+        }
+
+        let stream = self.conn.open_stream().await?;
+        let cmd = CommandRequest::new_subscribe(keyspace_topic(table));
+        let mut notifications = stream.execute_streaming(&cmd).await?;
+        // 没有订阅期间缓存的结果可能已经过期
+        self.state.lock().unwrap().invalidate(table, &[]);
+
+        let state = self.state.clone();
+        let name = table.to_string();
+        let handle = spawn_named(&format!("keyspace {table}"), async move {
+            while let Some(Ok(res)) = notifications.next().await {
+                // 第一个 value 是事件名，之后是被修改的 key
+                let keys: Vec<String> = res
+                    .values
+                    .into_iter()
+                    .skip(1)
+                    .filter_map(|v| v.try_into().ok())
+                    .collect();
+                state.lock().unwrap().invalidate(&name, &keys);
+            }
+            // 收不到通知之后缓存不再可靠，下次读取时重新订阅
+            warn!("Keyspace notifications of table {name} stopped");
+            state.lock().unwrap().invalidate(&name, &[]);
+        });
+        self.watchers.insert(table.to_string(), handle);
+        Ok(())
+    }
+}
+
+impl<C: AppStream> Drop for CachingClient<C> {
+    fn drop(&mut self) {
+        for handle in self.watchers.values() {
+            handle.abort();
+        }
+    }
+}
+
+fn response_error(res: &CommandResponse) -> KvError {
+    KvError::Internal(format!(
+        "Command failed with status {}: {}",
+        res.status, res.message
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::*;
+    use crate::{command_request::RequestData, MemTable, ProstServerStream, Service, ServiceInner};
+
+    static HGETS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_hget(cmd: &CommandRequest) {
+        if let Some(RequestData::Hget(_)) = cmd.request_data {
+            HGETS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // 每次打开 stream 时在内存中连接到同一个 service
+    struct LocalConn(Service);
+
+    impl AppStream for LocalConn {
+        type InnerStream = DuplexStream;
+
+        async fn open_stream(&mut self) -> Result<ProstClientStream<DuplexStream>, KvError> {
+            let (client, server) = duplex(4096);
+            let stream = ProstServerStream::new(server, self.0.clone());
+            tokio::spawn(stream.process());
+            Ok(ProstClientStream::new(client))
+        }
+    }
+
+    fn service() -> Service {
+        ServiceInner::new(MemTable::new())
+            .keyspace_notifications(true)
+            .into()
+    }
+
+    #[tokio::test]
+    async fn server_write_should_invalidate_cache() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .keyspace_notifications(true)
+            .fn_received(count_hget)
+            .into();
+        let mut writer = LocalConn(service.clone()).open_stream().await.unwrap();
+        let mut client = CachingClient::new(LocalConn(service), 16);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1");
+        writer.execute_unary(&cmd).await.unwrap();
+        assert_eq!(client.get("t1", "k1").await.unwrap(), Some("v1".into()));
+
+        // 第二次读取命中缓存，不访问服务器
+        let hgets = HGETS.load(Ordering::SeqCst);
+        assert_eq!(client.get("t1", "k1").await.unwrap(), Some("v1".into()));
+        assert_eq!(HGETS.load(Ordering::SeqCst), hgets);
+
+        // 另一个连接修改后，收到通知的缓存失效
+        let cmd = CommandRequest::new_hset("t1", "k1", "v2");
+        writer.execute_unary(&cmd).await.unwrap();
+        let mut value = None;
+        for _ in 0..100 {
+            value = client.get("t1", "k1").await.unwrap();
+            if value == Some("v2".into()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(value, Some("v2".into()));
+    }
+
+    #[tokio::test]
+    async fn own_write_should_invalidate_cache_immediately() {
+        let mut client = CachingClient::new(LocalConn(service()), 16);
+        assert_eq!(client.get("t1", "k1").await.unwrap(), None);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1");
+        client.execute_unary(&cmd).await.unwrap();
+        assert_eq!(client.get("t1", "k1").await.unwrap(), Some("v1".into()));
+    }
+
+    #[tokio::test]
+    async fn cache_should_evict_least_recently_used_and_expire_after_ttl() {
+        let mut client =
+            CachingClient::new(LocalConn(service()), 1).with_ttl(Duration::from_millis(20));
+        client.get("t1", "k1").await.unwrap();
+        client.get("t1", "k2").await.unwrap();
+        {
+            let state = client.state.lock().unwrap();
+            assert_eq!(state.entries.len(), 1);
+            let entry = state
+                .entries
+                .peek(&("t1".to_string(), "k2".to_string()))
+                .unwrap();
+            assert!(entry.is_fresh());
+        }
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let state = client.state.lock().unwrap();
+        let entry = state
+            .entries
+            .peek(&("t1".to_string(), "k2".to_string()))
+            .unwrap();
+        assert!(!entry.is_fresh());
+    }
+}
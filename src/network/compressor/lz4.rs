@@ -1,22 +1,31 @@
-use std::io::{Read, Write};
+use std::{io::Write, ops::RangeInclusive};
 
 use bytes::BufMut;
 use lz4::{Decoder, EncoderBuilder};
 
+use super::read_to_end_with_limit;
 use crate::{Compressor, KvError};
 
 pub struct Lz4;
 impl Compressor for Lz4 {
-    fn compress(src: &[u8], dst: &mut bytes::BytesMut) -> Result<(), KvError> {
-        let mut encoder = EncoderBuilder::new().build(dst.writer())?;
+    // 0 是快速模式，3 以上使用 LZ4 HC
+    const LEVELS: RangeInclusive<i32> = 0..=12;
+    const DEFAULT_LEVEL: i32 = 0;
+
+    fn compress_with_level(
+        src: &[u8],
+        dst: &mut bytes::BytesMut,
+        level: i32,
+    ) -> Result<(), KvError> {
+        let mut encoder = EncoderBuilder::new()
+            .level(level as u32)
+            .build(dst.writer())?;
         encoder.write_all(src)?;
         let _ = encoder.finish();
         Ok(())
     }
 
-    fn decompress(src: &[u8], dst: &mut Vec<u8>) -> Result<(), KvError> {
-        let mut decoder = Decoder::new(src)?;
-        decoder.read_to_end(dst)?;
-        Ok(())
+    fn decompress(src: &[u8], dst: &mut Vec<u8>, limit: usize) -> Result<(), KvError> {
+        read_to_end_with_limit(Decoder::new(src)?, dst, limit)
     }
 }
@@ -6,15 +6,28 @@ use crate::KvError;
 use bytes::BytesMut;
 use gzip::*;
 use lz4::*;
+use serde::{Deserialize, Serialize};
+use std::{io::Read, ops::RangeInclusive};
 use zstd::*;
 
 // 处理数据的压缩和解压
 pub trait Compressor {
-    fn compress(src: &[u8], dst: &mut BytesMut) -> Result<(), KvError>;
-    fn decompress(src: &[u8], dst: &mut Vec<u8>) -> Result<(), KvError>;
+    /// 支持的压缩级别，级别越高压缩率越高、速度越慢
+    const LEVELS: RangeInclusive<i32>;
+    /// 默认的压缩级别
+    const DEFAULT_LEVEL: i32;
+
+    fn compress(src: &[u8], dst: &mut BytesMut) -> Result<(), KvError> {
+        Self::compress_with_level(src, dst, Self::DEFAULT_LEVEL)
+    }
+    /// 使用指定的级别压缩，超出 LEVELS 的级别会被截断到范围内
+    fn compress_with_level(src: &[u8], dst: &mut BytesMut, level: i32) -> Result<(), KvError>;
+    /// 解压时最多写入 limit 字节，超出则返回 FrameError，防止解压炸弹
+    fn decompress(src: &[u8], dst: &mut Vec<u8>, limit: usize) -> Result<(), KvError>;
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum CompressorType {
     None = 0,
     GZIP,
@@ -22,28 +35,111 @@ pub enum CompressorType {
     ZSTD,
 }
 
-pub fn compress(compressor: CompressorType, src: &[u8], dst: &mut BytesMut) -> Result<(), KvError> {
+/// 压缩数据，level 为 None 时使用算法的默认级别
+pub fn compress(
+    compressor: CompressorType,
+    src: &[u8],
+    dst: &mut BytesMut,
+    level: Option<i32>,
+) -> Result<(), KvError> {
     match compressor {
-        CompressorType::GZIP => Gzip::compress(src, dst),
-        CompressorType::LZ4 => Lz4::compress(src, dst),
-        CompressorType::ZSTD => Zstd::compress(src, dst),
+        CompressorType::GZIP => compress_with::<Gzip>(src, dst, level),
+        CompressorType::LZ4 => compress_with::<Lz4>(src, dst, level),
+        CompressorType::ZSTD => compress_with::<Zstd>(src, dst, level),
         CompressorType::None => Ok(()),
     }
 }
 
+fn compress_with<C: Compressor>(
+    src: &[u8],
+    dst: &mut BytesMut,
+    level: Option<i32>,
+) -> Result<(), KvError> {
+    match level {
+        Some(level) => C::compress_with_level(src, dst, clamp_level::<C>(level)),
+        None => C::compress(src, dst),
+    }
+}
+
+// 把压缩级别截断到算法支持的范围内
+fn clamp_level<C: Compressor>(level: i32) -> i32 {
+    level.clamp(*C::LEVELS.start(), *C::LEVELS.end())
+}
+
 pub fn decompress(
     compressor: CompressorType,
     src: &[u8],
     dst: &mut Vec<u8>,
+    limit: usize,
 ) -> Result<(), KvError> {
     match compressor {
-        CompressorType::GZIP => Gzip::decompress(src, dst),
-        CompressorType::LZ4 => Lz4::decompress(src, dst),
-        CompressorType::ZSTD => Zstd::decompress(src, dst),
+        CompressorType::GZIP => Gzip::decompress(src, dst, limit),
+        CompressorType::LZ4 => Lz4::decompress(src, dst, limit),
+        CompressorType::ZSTD => Zstd::decompress(src, dst, limit),
         CompressorType::None => Ok(()),
     }
 }
 
+/// 估算熵时采样的块数和每块的大小，采样多块以覆盖 payload 中不同的 value
+const SAMPLE_BLOCKS: usize = 8;
+const SAMPLE_BLOCK_SIZE: usize = 256;
+/// 每字节的熵超过这个值（bit）时认为数据已经压缩过或者是随机数据，再压缩几乎没有收益
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.5;
+
+/// 根据采样的字节分布快速估算数据是否值得压缩，已经压缩过的数据（图片、gzip 等）和随机数据返回 true
+///
+/// 只统计单个字节的分布，看不到重复的长串，所以可能把可以压缩的数据误判为不可压缩，
+/// 但不会反过来，误判的代价只是少压缩了一些数据
+pub fn is_incompressible(data: &[u8]) -> bool {
+    let mut counts = [0usize; 256];
+    let mut total = 0;
+    let step = (data.len() / SAMPLE_BLOCKS).max(SAMPLE_BLOCK_SIZE);
+    for block in data.chunks(step).take(SAMPLE_BLOCKS) {
+        for &b in &block[..block.len().min(SAMPLE_BLOCK_SIZE)] {
+            counts[b as usize] += 1;
+            total += 1;
+        }
+    }
+    if total == 0 {
+        return false;
+    }
+
+    let entropy: f64 = counts
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| {
+            let p = n as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum();
+    entropy > INCOMPRESSIBLE_ENTROPY
+}
+
+// 从 reader 中读取解压后的数据，最多读取 limit 字节
+fn read_to_end_with_limit<R: Read>(
+    reader: R,
+    dst: &mut Vec<u8>,
+    limit: usize,
+) -> Result<(), KvError> {
+    // 多读一个字节，用来判断数据是否超过 limit
+    let n = reader.take(limit as u64 + 1).read_to_end(dst)?;
+    if n > limit {
+        return Err(KvError::FrameError);
+    }
+    Ok(())
+}
+
+impl From<usize> for CompressorType {
+    fn from(value: usize) -> Self {
+        match value {
+            1 => CompressorType::GZIP,
+            2 => CompressorType::LZ4,
+            3 => CompressorType::ZSTD,
+            _ => CompressorType::None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -69,21 +165,85 @@ mod tests {
         let mut compressed = BytesMut::new();
         let mut decompressed = Vec::new();
 
-        let res = compress(compressor_type, data, &mut compressed);
+        let res = compress(compressor_type, data, &mut compressed, None);
         assert!(res.is_ok());
 
-        let _ = decompress(compressor_type, &compressed, &mut decompressed);
+        let _ = decompress(compressor_type, &compressed, &mut decompressed, data.len());
         assert_eq!(decompressed, data);
+
+        // 超过 limit 的解压结果应该报错
+        let mut decompressed = Vec::new();
+        let res = decompress(
+            compressor_type,
+            &compressed,
+            &mut decompressed,
+            data.len() - 1,
+        );
+        assert!(matches!(res, Err(KvError::FrameError)));
     }
-}
 
-impl From<usize> for CompressorType {
-    fn from(value: usize) -> Self {
-        match value {
-            1 => CompressorType::GZIP,
-            2 => CompressorType::LZ4,
-            3 => CompressorType::ZSTD,
-            _ => CompressorType::None,
+    #[test]
+    fn higher_level_should_compress_better() {
+        // 重复但不完全相同的数据，级别越高越能找到更长的匹配
+        let data: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| format!("key{},value{};", i % 997, i % 113).into_bytes())
+            .collect();
+
+        for (compressor_type, low, high) in [
+            (CompressorType::GZIP, 1, 9),
+            (CompressorType::LZ4, 0, 12),
+            (CompressorType::ZSTD, 1, 19),
+        ] {
+            let mut fast = BytesMut::new();
+            compress(compressor_type, &data, &mut fast, Some(low)).unwrap();
+            let mut small = BytesMut::new();
+            compress(compressor_type, &data, &mut small, Some(high)).unwrap();
+            assert!(
+                small.len() < fast.len(),
+                "{compressor_type:?}: level {high} {} >= level {low} {}",
+                small.len(),
+                fast.len()
+            );
+
+            let mut decompressed = Vec::new();
+            decompress(compressor_type, &small, &mut decompressed, data.len()).unwrap();
+            assert_eq!(decompressed, data);
         }
     }
+
+    #[test]
+    fn random_data_should_be_incompressible() {
+        use rand::RngCore;
+
+        let mut data = vec![0u8; 64 * 1024];
+        rand::thread_rng().fill_bytes(&mut data);
+        assert!(is_incompressible(&data));
+
+        let text: Vec<u8> = (0..5000u32)
+            .flat_map(|i| format!("key{i},value{};", i % 113).into_bytes())
+            .collect();
+        assert!(!is_incompressible(&text));
+        assert!(!is_incompressible(&[1u8; 4096]));
+        assert!(!is_incompressible(&[]));
+    }
+
+    #[test]
+    fn out_of_range_level_should_be_clamped() {
+        assert_eq!(clamp_level::<Gzip>(100), 9);
+        assert_eq!(clamp_level::<Zstd>(-5), 1);
+        assert_eq!(clamp_level::<Lz4>(3), 3);
+
+        let data = b"data that will be compressed.";
+        let mut compressed = BytesMut::new();
+        compress(CompressorType::ZSTD, data, &mut compressed, Some(100)).unwrap();
+        let mut decompressed = Vec::new();
+        decompress(
+            CompressorType::ZSTD,
+            &compressed,
+            &mut decompressed,
+            data.len(),
+        )
+        .unwrap();
+        assert_eq!(decompressed, data);
+    }
 }
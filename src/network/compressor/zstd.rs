@@ -1,18 +1,26 @@
-use zstd::{decode_all, encode_all};
+use std::ops::RangeInclusive;
+use zstd::{encode_all, stream::read::Decoder};
 
+use super::read_to_end_with_limit;
 use crate::{Compressor, KvError};
 
 pub struct Zstd;
 impl Compressor for Zstd {
-    fn compress(src: &[u8], dst: &mut bytes::BytesMut) -> Result<(), KvError> {
-        let compressed = encode_all(src, 0)?;
+    // zstd 还支持负数的快速级别，这里不使用
+    const LEVELS: RangeInclusive<i32> = 1..=22;
+    const DEFAULT_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+    fn compress_with_level(
+        src: &[u8],
+        dst: &mut bytes::BytesMut,
+        level: i32,
+    ) -> Result<(), KvError> {
+        let compressed = encode_all(src, level)?;
         dst.extend_from_slice(&compressed);
         Ok(())
     }
 
-    fn decompress(src: &[u8], dst: &mut Vec<u8>) -> Result<(), KvError> {
-        let decompressed = decode_all(src)?;
-        dst.extend_from_slice(&decompressed);
-        Ok(())
+    fn decompress(src: &[u8], dst: &mut Vec<u8>, limit: usize) -> Result<(), KvError> {
+        read_to_end_with_limit(Decoder::new(src)?, dst, limit)
     }
 }
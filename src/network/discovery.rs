@@ -0,0 +1,277 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{info, warn};
+
+use crate::{KvError, ListenerConfig, NetworkType, ServerSecurityProtocol, ALPN_KV};
+
+/// 一个监听地址对外公布的信息，客户端用它来选择连接方式
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceEndpoint {
+    /// bind 之后的实际地址
+    pub addr: SocketAddr,
+    pub network: NetworkType,
+    /// "tls" 或 "noise"
+    pub security: &'static str,
+    /// TLS 握手时使用的 ALPN，QUIC 和 Noise 没有
+    pub alpn: Option<&'static str>,
+}
+
+impl ServiceEndpoint {
+    pub fn new(addr: SocketAddr, listener: &ListenerConfig) -> Self {
+        let (security, alpn) = match (&listener.security, &listener.network) {
+            (ServerSecurityProtocol::Tls(_), NetworkType::Quic) => ("tls", None),
+            (ServerSecurityProtocol::Tls(_), _) => ("tls", Some(ALPN_KV)),
+            (ServerSecurityProtocol::Noise(_), _) => ("noise", None),
+        };
+        Self {
+            addr,
+            network: listener.network.clone(),
+            security,
+            alpn,
+        }
+    }
+}
+
+/// 服务发现的注册接口：每个 listener bind 之后调用 register，服务器关闭时调用 deregister。
+///
+/// Unix socket 没有网络地址，不会注册
+pub trait ServiceRegistrar: Send + Sync + 'static {
+    fn register(&self, endpoint: &ServiceEndpoint) -> Result<(), KvError>;
+    fn deregister(&self, endpoint: &ServiceEndpoint) -> Result<(), KvError>;
+}
+
+/// 默认的 registrar，什么都不做
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopRegistrar;
+
+impl ServiceRegistrar for NoopRegistrar {
+    fn register(&self, _endpoint: &ServiceEndpoint) -> Result<(), KvError> {
+        Ok(())
+    }
+
+    fn deregister(&self, _endpoint: &ServiceEndpoint) -> Result<(), KvError> {
+        Ok(())
+    }
+}
+
+/// 记录已经注册的 endpoint，服务器关闭时统一注销。
+/// 注册失败不影响服务器运行，只记录日志
+pub(crate) struct Registry {
+    registrar: Arc<dyn ServiceRegistrar>,
+    registered: Mutex<Vec<ServiceEndpoint>>,
+}
+
+impl Registry {
+    pub(crate) fn new(registrar: Arc<dyn ServiceRegistrar>) -> Self {
+        Self {
+            registrar,
+            registered: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn register(&self, endpoint: ServiceEndpoint) {
+        match self.registrar.register(&endpoint) {
+            Ok(()) => {
+                info!("Registered {endpoint:?}");
+                self.registered.lock().unwrap().push(endpoint);
+            }
+            Err(e) => warn!("Failed to register {endpoint:?}: {e}"),
+        }
+    }
+
+    pub(crate) fn deregister_all(&self) {
+        for endpoint in self.registered.lock().unwrap().drain(..) {
+            if let Err(e) = self.registrar.deregister(&endpoint) {
+                warn!("Failed to deregister {endpoint:?}: {e}");
+            }
+        }
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new(Arc::new(NoopRegistrar))
+    }
+}
+
+#[cfg(feature = "mdns")]
+pub use mdns::MdnsRegistrar;
+
+#[cfg(feature = "mdns")]
+mod mdns {
+    use std::{collections::HashMap, net::SocketAddr, sync::Mutex};
+
+    use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+    use super::{ServiceEndpoint, ServiceRegistrar};
+    use crate::{KvError, NetworkType};
+
+    /// 通过 mDNS (DNS-SD) 公布服务器，TCP 上的服务类型是 _kv._tcp.local.，QUIC 是 _kv._udp.local.
+    ///
+    /// TXT 记录中带有 security 和 alpn，监听 0.0.0.0 时公布所有网卡的地址
+    pub struct MdnsRegistrar {
+        daemon: ServiceDaemon,
+        instance: String,
+        // endpoint 的地址 -> mDNS 中的完整服务名
+        fullnames: Mutex<HashMap<SocketAddr, String>>,
+    }
+
+    impl MdnsRegistrar {
+        /// instance 是实例名，同一个网络中的服务器应该使用不同的实例名
+        pub fn new(instance: impl Into<String>) -> Result<Self, KvError> {
+            Ok(Self {
+                daemon: ServiceDaemon::new().map_err(mdns_error)?,
+                instance: instance.into(),
+                fullnames: Mutex::new(HashMap::new()),
+            })
+        }
+    }
+
+    impl ServiceRegistrar for MdnsRegistrar {
+        fn register(&self, endpoint: &ServiceEndpoint) -> Result<(), KvError> {
+            let ty = match endpoint.network {
+                NetworkType::Quic => "_kv._udp.local.",
+                _ => "_kv._tcp.local.",
+            };
+            // 同一个实例可能有多个 listener，用端口区分
+            let name = format!("{}-{}", self.instance, endpoint.addr.port());
+            let host = format!("{}.local.", self.instance);
+            let ip = match endpoint.addr.ip().is_unspecified() {
+                true => String::new(),
+                false => endpoint.addr.ip().to_string(),
+            };
+            let properties = [
+                ("security", endpoint.security),
+                ("alpn", endpoint.alpn.unwrap_or_default()),
+            ];
+            let mut info =
+                ServiceInfo::new(ty, &name, &host, ip, endpoint.addr.port(), &properties[..])
+                    .map_err(mdns_error)?;
+            if endpoint.addr.ip().is_unspecified() {
+                info = info.enable_addr_auto();
+            }
+            let fullname = info.get_fullname().to_string();
+            self.daemon.register(info).map_err(mdns_error)?;
+            self.fullnames
+                .lock()
+                .unwrap()
+                .insert(endpoint.addr, fullname);
+            Ok(())
+        }
+
+        fn deregister(&self, endpoint: &ServiceEndpoint) -> Result<(), KvError> {
+            let fullname = self.fullnames.lock().unwrap().remove(&endpoint.addr);
+            if let Some(fullname) = fullname {
+                self.daemon.unregister(&fullname).map_err(mdns_error)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn mdns_error(e: mdns_sd::Error) -> KvError {
+        KvError::Internal(format!("mDNS error: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerTlsConfig;
+
+    #[derive(Default)]
+    struct MockRegistrar {
+        calls: Mutex<Vec<(&'static str, SocketAddr)>>,
+    }
+
+    impl ServiceRegistrar for MockRegistrar {
+        fn register(&self, endpoint: &ServiceEndpoint) -> Result<(), KvError> {
+            self.calls.lock().unwrap().push(("register", endpoint.addr));
+            Ok(())
+        }
+
+        fn deregister(&self, endpoint: &ServiceEndpoint) -> Result<(), KvError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(("deregister", endpoint.addr));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn endpoint_should_describe_listener() {
+        let addr: SocketAddr = "127.0.0.1:9527".parse().unwrap();
+        let tls = ServerTlsConfig {
+            cert: String::new(),
+            key: String::new(),
+            key_passphrase: None,
+            ca: None,
+            policy: Default::default(),
+        };
+        let listener = ListenerConfig {
+            addr: addr.to_string(),
+            network: NetworkType::Tcp,
+            security: ServerSecurityProtocol::Tls(tls),
+        };
+        let endpoint = ServiceEndpoint::new(addr, &listener);
+        assert_eq!((endpoint.security, endpoint.alpn), ("tls", Some(ALPN_KV)));
+
+        let listener = ListenerConfig {
+            network: NetworkType::Quic,
+            ..listener
+        };
+        let endpoint = ServiceEndpoint::new(addr, &listener);
+        assert_eq!((endpoint.security, endpoint.alpn), ("tls", None));
+
+        let listener = ListenerConfig {
+            security: ServerSecurityProtocol::Noise(Default::default()),
+            ..listener
+        };
+        let endpoint = ServiceEndpoint::new(addr, &listener);
+        assert_eq!((endpoint.security, endpoint.alpn), ("noise", None));
+    }
+
+    #[tokio::test]
+    async fn server_should_register_and_deregister_listeners() -> anyhow::Result<()> {
+        let mut config: crate::ServerConfig = toml::from_str(crate::TLS_SERVER_CONFIG)?;
+        config.general.addr = "127.0.0.1:0".into();
+        config.general.listeners = vec![];
+
+        let registrar = Arc::new(MockRegistrar::default());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn({
+            let registrar = registrar.clone();
+            async move {
+                let shutdown = async {
+                    rx.await.ok();
+                };
+                crate::start_server_with_registrar(&config, registrar, shutdown).await
+            }
+        });
+
+        // 等待 listener bind 之后注册
+        let mut registered = None;
+        for _ in 0..100 {
+            if let Some(&(_, addr)) = registrar.calls.lock().unwrap().first() {
+                registered = Some(addr);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let addr = registered.expect("listener should be registered");
+        // 注册的是 bind 之后的实际端口
+        assert_ne!(addr.port(), 0);
+
+        tx.send(()).unwrap();
+        server.await??;
+        assert_eq!(
+            *registrar.calls.lock().unwrap(),
+            vec![("register", addr), ("deregister", addr)]
+        );
+
+        Ok(())
+    }
+}
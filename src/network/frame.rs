@@ -1,22 +1,208 @@
+use std::borrow::Cow;
+
 use bytes::{Buf, BufMut, BytesMut};
 use prost::Message;
 use tokio::io::{AsyncRead, AsyncReadExt};
 use tracing::debug;
 
-use crate::{compress, decompress, CommandRequest, CommandResponse, CompressorType, KvError};
+use crate::{
+    compress, decompress, is_incompressible, BufferPool, CommandRequest, CommandResponse,
+    CompressorType, KeyBatch, KvError, Kvpair,
+};
 
-/// Frame头的长度占 4 个字节
+/// v1 的 Frame头的长度占 4 个字节
 const LEN_LEN: usize = 4;
-/// 长度占30 bit，所以最大的 Frame 是 1G
+/// v2 的 Frame头的长度占 8 个字节
+const LEN_LEN_V2: usize = 8;
+/// 所有协议版本中最长的 frame 头。frame 头增加字段（如校验和）时只需要修改 header_len 和这里，
+/// 读取 frame 头的 buffer 和 COMPRESSION_LIMIT 都由它决定
+pub const MAX_HEADER_LEN: usize = LEN_LEN_V2;
+/// v2 的 frame 头的第一个字节，v1 的 frame 头不会以它开头
+const V2_MARKER: u8 = 0xFF;
+/// payload（不包括 frame 头）的长度必须小于 MAX_FRAME。v1 中长度占30 bit，所以最大的 Frame 是 1G。
+/// v2 沿用这个限制，整个 frame 最长是 MAX_FRAME - 1 + MAX_HEADER_LEN
 const MAX_FRAME: usize = 1024 * 1024 * 1024;
-/// 如果 payload 长度超过 1436 字节，就做压缩。
-/// 以太网的 MTU 是 1500 字节，IP头、TCP头各占20字节，再除去IP头和TCP头可能包含的一些Option，我们预留 20 字节
-/// 还剩 1440 字节，再减去预留的 4 字节做帧长度。超过 1436 字节可能会导致分片，所以我们做压缩处理
-const COMPRESSION_LIMIT: usize = 1436;
+/// 以太网的 MTU 是 1500 字节，IP头、TCP头各占20字节，再除去IP头和TCP头可能包含的一些Option，我们预留 20 字节，
+/// 一个 TCP 包还剩 1440 字节
+const TCP_PAYLOAD: usize = 1440;
+/// 如果 payload 长度超过 TCP_PAYLOAD 减去最长的 frame 头，就做压缩（采样估算为不可压缩的 payload 除外）。
+/// 超过这个长度的 frame 可能会导致分片，所以我们做压缩处理
+const COMPRESSION_LIMIT: usize = TCP_PAYLOAD - MAX_HEADER_LEN;
 /// 代表压缩的 bit 的位置整个长度为4字节的最高位）
 const COMPRESSION_BIT: usize = 30;
 /// 用于消除最高2位的掩码
 const COMPRESSION_MASK: usize = 0x3FFFFFFF;
+/// 解压后的数据最大不能超过 MAX_FRAME，防止解压炸弹
+const MAX_DECOMPRESSED: usize = MAX_FRAME;
+
+// 每个版本的 frame 头都不能超过 MAX_HEADER_LEN，v1 的 30 bit 长度必须能表示所有合法的长度
+const _: () = assert!(LEN_LEN <= MAX_HEADER_LEN && LEN_LEN_V2 <= MAX_HEADER_LEN);
+const _: () = assert!(MAX_FRAME - 1 == COMPRESSION_MASK);
+
+/// 从一段 buffer 中解析出来的 frame
+#[derive(Debug, PartialEq)]
+pub struct DecodedFrame<'a> {
+    /// frame 使用的压缩算法
+    pub compressor: CompressorType,
+    /// 解压后的 protobuf payload，未压缩时直接借用输入
+    pub payload: Cow<'a, [u8]>,
+    /// 这个 frame 在输入中占用的字节数（包括 frame 头）
+    pub consumed: usize,
+}
+
+impl DecodedFrame<'_> {
+    /// 把 payload decode 成具体的 Message
+    pub fn message<T: Message + Default>(&self) -> Result<T, KvError> {
+        Ok(T::decode(&self.payload[..])?)
+    }
+}
+
+/// frame 头中的信息，不同协议版本的布局见 FrameHeader::encode。
+/// 构造和解析 frame 头都只通过 encode/decode，不要在其他地方直接读写 frame 头的字节
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameHeader {
+    /// frame 头之后的 payload 的长度（压缩后）
+    pub len: usize,
+    pub compressor: CompressorType,
+}
+
+/// 协议版本对应的 frame 头的长度，不超过 MAX_HEADER_LEN
+pub fn header_len(version: u8) -> Result<usize, KvError> {
+    match version {
+        1 => Ok(LEN_LEN),
+        2 => Ok(LEN_LEN_V2),
+        _ => Err(KvError::UnsupportedVersion(version)),
+    }
+}
+
+impl FrameHeader {
+    /// 按协议版本把 frame 头写入 dst，dst 的长度必须等于 header_len(version)
+    ///
+    /// v1 是 4 字节的 big endian u32，高 2 bit 是压缩算法，低 30 bit 是长度：
+    ///
+    /// ```text
+    ///  31 30 29                           0
+    /// +-----+------------------------------+
+    /// | 压缩 |         payload 长度         |
+    /// +-----+------------------------------+
+    /// ```
+    ///
+    /// 第一个字节为 0xFF 的头留给 v2 使用，所以 v1 不能表示 ZSTD 压缩且长度不小于 0x3F00_0000 的 frame。
+    ///
+    /// v2 是 8 字节，压缩算法占一个字节，flags 留给以后的扩展（如校验和），目前必须为 0：
+    ///
+    /// ```text
+    /// byte 0      1          2..4      4..8
+    /// +------+----------+---------+----------------------------+
+    /// | 0xFF | 压缩算法  |  flags  | payload 长度（big endian）  |
+    /// +------+----------+---------+----------------------------+
+    /// ```
+    pub fn encode(&self, version: u8, dst: &mut [u8]) -> Result<(), KvError> {
+        if dst.len() != header_len(version)? || self.len >= MAX_FRAME {
+            return Err(KvError::FrameError);
+        }
+        match version {
+            1 => {
+                let v = (self.len | ((self.compressor as usize) << COMPRESSION_BIT)) as u32;
+                let v = v.to_be_bytes();
+                if v[0] == V2_MARKER {
+                    return Err(KvError::FrameError);
+                }
+                dst.copy_from_slice(&v);
+            }
+            _ => {
+                dst[0] = V2_MARKER;
+                dst[1] = self.compressor as u8;
+                dst[2..4].copy_from_slice(&[0, 0]);
+                dst[4..].copy_from_slice(&(self.len as u32).to_be_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// 按协议版本从 buf 开头解析 frame 头，返回 frame 头和它占用的字节数
+    ///
+    /// 数据不足、头部不合法时返回 FrameError。用 v1 解析 v2 的头，或者用 v2 解析 v1 的头都会失败，
+    /// 不会按另一种布局错误地解析
+    pub fn decode(version: u8, buf: &[u8]) -> Result<(Self, usize), KvError> {
+        let header_len = header_len(version)?;
+        let Some(header) = buf.get(..header_len) else {
+            return Err(KvError::FrameError);
+        };
+        let header = match version {
+            1 => {
+                if header[0] == V2_MARKER {
+                    return Err(KvError::FrameError);
+                }
+                let v = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+                FrameHeader {
+                    len: v & COMPRESSION_MASK,
+                    compressor: ((v & !COMPRESSION_MASK) >> COMPRESSION_BIT).into(),
+                }
+            }
+            _ => {
+                if header[0] != V2_MARKER || header[1] > CompressorType::ZSTD as u8 {
+                    return Err(KvError::FrameError);
+                }
+                if header[2..4] != [0, 0] {
+                    return Err(KvError::FrameError);
+                }
+                FrameHeader {
+                    len: u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize,
+                    compressor: (header[1] as usize).into(),
+                }
+            }
+        };
+        if header.len >= MAX_FRAME {
+            return Err(KvError::FrameError);
+        }
+        Ok((header, header_len))
+    }
+}
+
+/// 从 buffer 开头解析一个 v1 的 frame，任何输入都不会 panic，只会返回 Ok 或 Err
+///
+/// 会检查 frame 头是否完整、长度是否超过 MAX_FRAME 或剩余数据，以及解压后的大小
+pub fn try_decode_frame(buf: &[u8]) -> Result<DecodedFrame<'_>, KvError> {
+    try_decode_frame_versioned(1, buf)
+}
+
+/// 和 try_decode_frame 一样，但按协议版本解析 frame 头
+pub fn try_decode_frame_versioned(version: u8, buf: &[u8]) -> Result<DecodedFrame<'_>, KvError> {
+    try_decode_frame_pooled(version, buf, &mut BufferPool::disabled())
+}
+
+// 解压时使用 pool 中的 buffer，调用者用完 payload 后应该把它还给 pool
+fn try_decode_frame_pooled<'a>(
+    version: u8,
+    buf: &'a [u8],
+    pool: &mut BufferPool,
+) -> Result<DecodedFrame<'a>, KvError> {
+    let (FrameHeader { len, compressor }, header_len) = FrameHeader::decode(version, buf)?;
+    debug!("Got a frame: msg len: {len}, compress_type: {compressor:?}");
+
+    let Some(data) = buf.get(header_len..header_len + len) else {
+        return Err(KvError::FrameError);
+    };
+
+    let payload = if compressor != CompressorType::None {
+        // 预分配的空间不超过 MAX_DECOMPRESSED，避免根据攻击者提供的长度分配过多内存
+        let mut buf_tmp = pool.take((len * 2).min(MAX_DECOMPRESSED));
+        if let Err(e) = decompress(compressor, data, &mut buf_tmp, MAX_DECOMPRESSED) {
+            pool.put(buf_tmp);
+            return Err(e);
+        }
+        Cow::Owned(buf_tmp)
+    } else {
+        Cow::Borrowed(data)
+    };
+
+    Ok(DecodedFrame {
+        compressor,
+        payload,
+        consumed: header_len + len,
+    })
+}
 
 // 处理 Frame 的 encode/decode
 pub trait FrameCoder
@@ -27,102 +213,183 @@ where
         self.encode_frame_with_compressor(buf, CompressorType::GZIP)
     }
 
-    // 把一个 Message encode 成一个 Frame
     fn encode_frame_with_compressor(
         &self,
         buf: &mut BytesMut,
         compressor_type: CompressorType,
     ) -> Result<(), KvError> {
-        let size = self.encoded_len();
-
-        if size >= MAX_FRAME {
-            return Err(KvError::FrameError);
-        }
-
-        // 先写入长度，如果需要压缩，再重写压缩后的长度
-        buf.put_u32(size as _);
-
-        if size > COMPRESSION_LIMIT {
-            let mut buf_tmp = Vec::with_capacity(size);
-            self.encode(&mut buf_tmp)?;
-
-            // 为了 Frame 头部拿走 4 个字节
-            let mut payload = buf.split_off(LEN_LEN);
-            buf.clear();
-
-            // 压缩
-            compress(compressor_type, &buf_tmp[..], &mut payload)?;
-            debug!("Encode a frame size: {size}({})", payload.len());
+        self.encode_frame_with_level(buf, compressor_type, None)
+    }
 
-            // 写入压缩后的长度，同时把最高位置 1 表示该组数据经过压缩
-            buf.put_u32((payload.len() | ((compressor_type as usize) << COMPRESSION_BIT)) as _);
+    // 把一个 Message encode 成一个 v1 的 Frame，level 为 None 时使用压缩算法的默认级别
+    fn encode_frame_with_level(
+        &self,
+        buf: &mut BytesMut,
+        compressor_type: CompressorType,
+        level: Option<i32>,
+    ) -> Result<(), KvError> {
+        self.encode_frame_versioned(1, buf, compressor_type, level)
+    }
 
-            // 合并 BytesMut
-            buf.unsplit(payload);
+    /// 按协议版本把一个 Message encode 成一个 Frame，追加到 buf 的末尾，出错时 buf 不变
+    fn encode_frame_versioned(
+        &self,
+        version: u8,
+        buf: &mut BytesMut,
+        compressor_type: CompressorType,
+        level: Option<i32>,
+    ) -> Result<(), KvError> {
+        self.encode_frame_pooled(
+            version,
+            buf,
+            compressor_type,
+            level,
+            &mut BufferPool::disabled(),
+        )
+    }
 
-            Ok(())
-        } else {
-            self.encode(buf)?;
-            Ok(())
+    /// 和 encode_frame_versioned 一样，但压缩前 encode payload 的临时 buffer 从 pool 中获取
+    fn encode_frame_pooled(
+        &self,
+        version: u8,
+        buf: &mut BytesMut,
+        compressor_type: CompressorType,
+        level: Option<i32>,
+        pool: &mut BufferPool,
+    ) -> Result<(), KvError> {
+        let start = buf.len();
+        let result = encode_frame_at(self, version, buf, compressor_type, level, pool);
+        if result.is_err() {
+            buf.truncate(start);
         }
+        result
     }
 
-    /// 把一个完整的 frame decode 成一个 Message
+    /// 把一个完整的 v1 frame decode 成一个 Message
     fn decode_frame(buf: &mut BytesMut) -> Result<Self, KvError> {
-        // 先取 4 字节，从中获得长度和 compression bit
-        let header = buf.get_u32() as usize;
-        let (len, compress_type) = decode_header(header);
-        debug!("Got a frame: msg len: {len}, compress_type: {compress_type:?}");
-
-        if compress_type != CompressorType::None {
-            // 解压缩
-            let mut buf_tmp = Vec::with_capacity(len * 2);
-            decompress(compress_type, &buf[..len], &mut buf_tmp)?;
-            buf.advance(len);
-
-            Ok(Self::decode(&buf_tmp[..buf_tmp.len()])?)
-        } else {
-            let msg = Self::decode(&buf[..len])?;
-            buf.advance(len);
-            Ok(msg)
+        Self::decode_frame_versioned(1, buf)
+    }
+
+    /// 按协议版本把一个完整的 frame decode 成一个 Message
+    fn decode_frame_versioned(version: u8, buf: &mut BytesMut) -> Result<Self, KvError> {
+        Self::decode_frame_pooled(version, buf, &mut BufferPool::disabled())
+    }
+
+    /// 和 decode_frame_versioned 一样，但解压用的临时 buffer 从 pool 中获取，用完后归还
+    fn decode_frame_pooled(
+        version: u8,
+        buf: &mut BytesMut,
+        pool: &mut BufferPool,
+    ) -> Result<Self, KvError> {
+        let frame = try_decode_frame_pooled(version, &buf[..], pool)?;
+        let msg = frame.message();
+        let consumed = frame.consumed;
+        if let Cow::Owned(payload) = frame.payload {
+            pool.put(payload);
         }
+        let msg = msg?;
+        buf.advance(consumed);
+        Ok(msg)
     }
 }
 
 impl FrameCoder for CommandRequest {}
 impl FrameCoder for CommandResponse {}
+impl FrameCoder for Kvpair {}
+impl FrameCoder for KeyBatch {}
+
+fn encode_frame_at<T: Message>(
+    msg: &T,
+    version: u8,
+    buf: &mut BytesMut,
+    compressor_type: CompressorType,
+    level: Option<i32>,
+    pool: &mut BufferPool,
+) -> Result<(), KvError> {
+    let size = msg.encoded_len();
+
+    if size >= MAX_FRAME {
+        return Err(KvError::FrameError);
+    }
 
-fn decode_header(header: usize) -> (usize, CompressorType) {
-    let len = header & COMPRESSION_MASK;
-    let compress_type: CompressorType = ((header & !COMPRESSION_MASK) >> COMPRESSION_BIT).into();
-    (len, compress_type)
+    // 先预留 frame 头的位置，写入 payload 之后才知道 frame 头的内容。
+    // 不压缩时 payload 的长度就是 size，一次预留好整个 frame 的空间，避免 encode 时再扩容
+    let start = buf.len();
+    let payload_start = start + header_len(version)?;
+    buf.reserve(payload_start - start + size);
+    buf.resize(payload_start, 0);
+
+    let compressor = if size > COMPRESSION_LIMIT && compressor_type != CompressorType::None {
+        let mut buf_tmp = pool.take(size);
+        msg.encode(&mut buf_tmp)?;
+
+        // 压缩后的数据写在 frame 头之后。已经压缩过的数据不再压缩；
+        // 压缩后没有变小时改为不压缩，frame 头中不设置压缩算法
+        let mut payload = buf.split_off(payload_start);
+        let result = if is_incompressible(&buf_tmp) {
+            Ok(CompressorType::None)
+        } else {
+            compress(compressor_type, &buf_tmp[..], &mut payload, level).map(|_| compressor_type)
+        };
+        let compressor = match result {
+            Ok(compressor) if compressor != CompressorType::None && payload.len() < size => {
+                compressor
+            }
+            Ok(_) => {
+                payload.clear();
+                payload.extend_from_slice(&buf_tmp);
+                CompressorType::None
+            }
+            Err(e) => {
+                pool.put(buf_tmp);
+                return Err(e);
+            }
+        };
+        pool.put(buf_tmp);
+        debug!("Encode a frame size: {size}({})", payload.len());
+        buf.unsplit(payload);
+        compressor
+    } else {
+        msg.encode(buf)?;
+        CompressorType::None
+    };
+
+    let header = FrameHeader {
+        len: buf.len() - payload_start,
+        compressor,
+    };
+    header.encode(version, &mut buf[start..payload_start])
 }
 
-/// 从 stream 中读取一个完整的 frame
-pub async fn read_frame<S>(stream: &mut S, buf: &mut BytesMut) -> Result<(), KvError>
+/// 按协议版本从 stream 中读取一个完整的 frame，frame 头不合法时返回 FrameError
+pub async fn read_frame<S>(stream: &mut S, version: u8, buf: &mut BytesMut) -> Result<(), KvError>
 where
     S: AsyncRead + Unpin + Send,
 {
-    let header = stream.read_u32().await? as usize;
-    let (len, _compressed) = decode_header(header);
+    let mut header = [0u8; MAX_HEADER_LEN];
+    let header = &mut header[..header_len(version)?];
+    stream.read_exact(header).await?;
+    let (FrameHeader { len, .. }, header_len) = FrameHeader::decode(version, header)?;
     // 确保内存至少可以放下一个 Frame。reserve()仅修改容量，即capacit()
-    buf.reserve(LEN_LEN + len);
-    buf.put_u32(header as _);
+    buf.reserve(header_len + len);
+    buf.put_slice(header);
     // advance_mut 是 unsafe 的原因是，从当前位置 pos 到 pos + len，
     // 这段内存目前没有初始化。我们就是为了 reserve 这段内存，然后从 stream
     // 里读取，读取完，它就是初始化的。所以，我们这么用是安全的
     // 通过advance_mut()将buf的长度增加，即len()。上面已经reserve()了，所以容量是够的
     unsafe { buf.advance_mut(len) };
-    stream.read_exact(&mut buf[LEN_LEN..]).await?;
+    stream.read_exact(&mut buf[header_len..]).await?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::*;
     use crate::utils::DummyStream;
     use crate::Value;
     use bytes::Bytes;
+    use proptest::prelude::*;
 
     #[tokio::test]
     async fn read_frame_should_work() {
@@ -132,7 +399,7 @@ mod tests {
         let mut stream = DummyStream { buf };
 
         let mut data = BytesMut::new();
-        read_frame(&mut stream, &mut data).await.unwrap();
+        read_frame(&mut stream, 1, &mut data).await.unwrap();
 
         let cmd_decoded = CommandRequest::decode_frame(&mut data).unwrap();
         assert_eq!(cmd, cmd_decoded);
@@ -147,7 +414,7 @@ mod tests {
             .unwrap();
 
         // 最高位未设置压缩标志
-        assert_eq!(is_compressed(&buf), false);
+        assert!(!is_compressed(&buf));
 
         let cmd_decoded = CommandRequest::decode_frame(&mut buf).unwrap();
         assert_eq!(cmd, cmd_decoded);
@@ -163,7 +430,7 @@ mod tests {
             .unwrap();
 
         // 最高位未设置压缩标志
-        assert_eq!(is_compressed(&buf), false);
+        assert!(!is_compressed(&buf));
 
         let res_decoded = CommandResponse::decode_frame(&mut buf).unwrap();
         assert_eq!(res, res_decoded);
@@ -178,12 +445,464 @@ mod tests {
         res.encode_frame_with_compressor(&mut buf, CompressorType::GZIP)
             .unwrap();
 
-        assert_eq!(is_compressed(&buf), true);
+        assert!(is_compressed(&buf));
 
         let res_decoded = CommandResponse::decode_frame(&mut buf).unwrap();
         assert_eq!(res, res_decoded);
     }
 
+    #[test]
+    fn incompressible_payload_should_not_be_compressed() {
+        use rand::RngCore;
+
+        let mut data = vec![0u8; 64 * 1024];
+        rand::thread_rng().fill_bytes(&mut data);
+        let res: CommandResponse = Value::from(Bytes::from(data)).into();
+
+        for compressor in COMPRESSORS {
+            for version in [1, 2] {
+                let mut buf = BytesMut::new();
+                res.encode_frame_versioned(version, &mut buf, compressor, None)
+                    .unwrap();
+                // 整个 payload 原样写入，frame 头中没有压缩算法
+                let frame = try_decode_frame_versioned(version, &buf).unwrap();
+                assert_eq!(frame.compressor, CompressorType::None);
+                assert_eq!(frame.payload.len(), res.encoded_len());
+                assert_eq!(
+                    CommandResponse::decode_frame_versioned(version, &mut buf).unwrap(),
+                    res
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn try_decode_frame_should_return_payload() {
+        let mut buf = BytesMut::new();
+        let cmd = CommandRequest::new_hdel("table", "key");
+        cmd.encode_frame(&mut buf).unwrap();
+
+        let frame = try_decode_frame(&buf).unwrap();
+        assert_eq!(frame.compressor, CompressorType::None);
+        assert_eq!(frame.consumed, buf.len());
+        assert_eq!(frame.message::<CommandRequest>().unwrap(), cmd);
+    }
+
+    #[test]
+    fn try_decode_frame_should_reject_malformed_input() {
+        let inputs: &[&[u8]] = &[
+            // 空输入和不完整的 frame 头
+            &[],
+            &[0x00, 0x00, 0x01],
+            // 头部声明的长度超过了剩余数据
+            &[0x00, 0x00, 0x00, 0x10, 0x01],
+            &[0x3f, 0xff, 0xff, 0xff],
+            // 压缩标志位设置了，但 payload 不是合法的压缩数据
+            &[0x40, 0x00, 0x00, 0x02, 0xde, 0xad],
+            &[0x80, 0x00, 0x00, 0x02, 0xde, 0xad],
+            &[0xc0, 0x00, 0x00, 0x02, 0xde, 0xad],
+            &[0xff, 0xff, 0xff, 0xff],
+        ];
+
+        for input in inputs {
+            assert!(try_decode_frame(input).is_err(), "input: {input:?}");
+        }
+
+        // payload 不是合法的 protobuf
+        let mut buf = BytesMut::from(&[0x00, 0x00, 0x00, 0x01, 0xff][..]);
+        assert!(CommandRequest::decode_frame(&mut buf).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn try_decode_frame_should_never_panic(
+            data in proptest::collection::vec(any::<u8>(), 0..4096),
+        ) {
+            if let Ok(frame) = try_decode_frame(&data) {
+                prop_assert!(frame.consumed <= data.len());
+                let _ = frame.message::<CommandRequest>();
+                let _ = frame.message::<CommandResponse>();
+            }
+        }
+
+        #[test]
+        fn try_decode_frame_should_never_panic_with_valid_header(
+            header in any::<u32>(),
+            body in proptest::collection::vec(any::<u8>(), 0..4096),
+        ) {
+            // 用合法长度的头部引导解析进入解压和 protobuf decode 的路径
+            let len = (body.len() as u32) | (header & !(COMPRESSION_MASK as u32));
+            let mut data = len.to_be_bytes().to_vec();
+            data.extend_from_slice(&body);
+            let _ = try_decode_frame(&data);
+        }
+
+        #[test]
+        fn no_header_should_decode_under_both_versions(
+            data in proptest::collection::vec(any::<u8>(), LEN_LEN_V2),
+        ) {
+            // v1 的头不以 0xFF 开头，v2 的头必须以 0xFF 开头
+            prop_assert!(FrameHeader::decode(1, &data).is_err() || FrameHeader::decode(2, &data).is_err());
+        }
+    }
+
+    const COMPRESSORS: [CompressorType; 4] = [
+        CompressorType::None,
+        CompressorType::GZIP,
+        CompressorType::LZ4,
+        CompressorType::ZSTD,
+    ];
+
+    fn assert_round_trip<T: FrameCoder + PartialEq + std::fmt::Debug>(msg: &T) {
+        for version in [1, 2] {
+            for compressor in COMPRESSORS {
+                let mut buf = BytesMut::new();
+                msg.encode_frame_versioned(version, &mut buf, compressor, None)
+                    .unwrap();
+                assert_eq!(&T::decode_frame_versioned(version, &mut buf).unwrap(), msg);
+                assert!(buf.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn header_should_round_trip_in_each_version() {
+        for version in [1, 2] {
+            let mut dst = vec![0; header_len(version).unwrap()];
+            assert!(dst.len() <= MAX_HEADER_LEN);
+            for compressor in COMPRESSORS {
+                for len in [0, 1, COMPRESSION_LIMIT, COMPRESSION_LIMIT + 1, 0x3EFF_FFFF] {
+                    let header = FrameHeader { len, compressor };
+                    header.encode(version, &mut dst).unwrap();
+                    assert_eq!(
+                        FrameHeader::decode(version, &dst).unwrap(),
+                        (header, dst.len())
+                    );
+                }
+            }
+        }
+        assert!(header_len(3).is_err());
+        assert!(FrameHeader::decode(3, &[0; MAX_HEADER_LEN]).is_err());
+    }
+
+    #[test]
+    fn v1_header_should_use_top_two_bits_for_compressor() {
+        // 压缩算法的每一种取值都只占用最高 2 bit，不会改变长度
+        for (bits, compressor) in [0b00u8, 0b01, 0b10, 0b11].into_iter().zip(COMPRESSORS) {
+            let header = FrameHeader {
+                len: 0x0012_3456,
+                compressor,
+            };
+            let mut dst = [0; LEN_LEN];
+            header.encode(1, &mut dst).unwrap();
+            assert_eq!(dst[0] >> 6, bits);
+            assert_eq!(dst, (((bits as u32) << 30) | 0x0012_3456).to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn header_should_accept_lengths_up_to_max_frame() {
+        for version in [1, 2] {
+            let mut dst = vec![0; header_len(version).unwrap()];
+            for compressor in COMPRESSORS {
+                let header = FrameHeader {
+                    len: MAX_FRAME - 1,
+                    compressor,
+                };
+                let result = header.encode(version, &mut dst);
+                // v1 中 ZSTD 压缩的最大长度会和 v2 的标记冲突
+                if version == 1 && compressor == CompressorType::ZSTD {
+                    assert!(result.is_err());
+                    continue;
+                }
+                result.unwrap();
+                assert_eq!(FrameHeader::decode(version, &dst).unwrap().0, header);
+
+                // 正好是 MAX_FRAME 时拒绝
+                let header = FrameHeader {
+                    len: MAX_FRAME,
+                    compressor,
+                };
+                assert!(header.encode(version, &mut dst).is_err());
+            }
+        }
+
+        // v1 的 30 bit 长度表示不了 MAX_FRAME，v2 的头中长度为 MAX_FRAME 时拒绝
+        let mut dst = [0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        dst[4..].copy_from_slice(&(MAX_FRAME as u32 - 1).to_be_bytes());
+        assert_eq!(FrameHeader::decode(2, &dst).unwrap().0.len, MAX_FRAME - 1);
+        dst[4..].copy_from_slice(&(MAX_FRAME as u32).to_be_bytes());
+        assert!(FrameHeader::decode(2, &dst).is_err());
+    }
+
+    #[test]
+    fn v2_header_should_reject_every_flag_bit() {
+        let header = FrameHeader {
+            len: 1,
+            compressor: CompressorType::None,
+        };
+        let mut dst = [0; LEN_LEN_V2];
+        header.encode(2, &mut dst).unwrap();
+        assert_eq!(&dst[2..4], &[0, 0]);
+        for bit in 0..16 {
+            let mut data = dst;
+            data[2..4].copy_from_slice(&(1u16 << bit).to_be_bytes());
+            assert!(FrameHeader::decode(2, &data).is_err(), "flag bit {bit}");
+        }
+    }
+
+    #[test]
+    fn header_should_encode_to_known_bytes() {
+        // 长度 300 = 0x012C，所有多字节的字段都是 big endian
+        let cases: [(CompressorType, [u8; LEN_LEN], [u8; LEN_LEN_V2]); 4] = [
+            (
+                CompressorType::None,
+                [0x00, 0x00, 0x01, 0x2C],
+                [0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x2C],
+            ),
+            (
+                CompressorType::GZIP,
+                [0x40, 0x00, 0x01, 0x2C],
+                [0xFF, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01, 0x2C],
+            ),
+            (
+                CompressorType::LZ4,
+                [0x80, 0x00, 0x01, 0x2C],
+                [0xFF, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x2C],
+            ),
+            (
+                CompressorType::ZSTD,
+                [0xC0, 0x00, 0x01, 0x2C],
+                [0xFF, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01, 0x2C],
+            ),
+        ];
+        for (compressor, v1, v2) in cases {
+            let header = FrameHeader {
+                len: 300,
+                compressor,
+            };
+            let mut dst = [0; LEN_LEN];
+            header.encode(1, &mut dst).unwrap();
+            assert_eq!(dst, v1, "{compressor:?}");
+            assert_eq!(FrameHeader::decode(1, &v1).unwrap(), (header, LEN_LEN));
+
+            let mut dst = [0; LEN_LEN_V2];
+            header.encode(2, &mut dst).unwrap();
+            assert_eq!(dst, v2, "{compressor:?}");
+            assert_eq!(FrameHeader::decode(2, &v2).unwrap(), (header, LEN_LEN_V2));
+        }
+    }
+
+    #[test]
+    fn uncompressed_frame_should_match_known_bytes() {
+        // CommandResponse { status: 200 } 的 protobuf 编码是 08 C8 01
+        let res = CommandResponse::ok();
+        let payload = [0x08, 0xC8, 0x01];
+
+        let mut buf = BytesMut::new();
+        res.encode_frame_versioned(1, &mut buf, CompressorType::None, None)
+            .unwrap();
+        assert_eq!(&buf[..], &[0x00, 0x00, 0x00, 0x03, 0x08, 0xC8, 0x01]);
+
+        let mut buf = BytesMut::new();
+        res.encode_frame_versioned(2, &mut buf, CompressorType::None, None)
+            .unwrap();
+        assert_eq!(
+            &buf[..],
+            &[0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x08, 0xC8, 0x01]
+        );
+
+        // 手工构造的 frame 可以解析，后面多余的数据不会被读取
+        let data = [0x00, 0x00, 0x00, 0x03, 0x08, 0xC8, 0x01, 0xAA];
+        let frame = try_decode_frame(&data).unwrap();
+        assert_eq!(frame.compressor, CompressorType::None);
+        assert_eq!(&frame.payload[..], &payload);
+        assert_eq!(frame.consumed, 7);
+        assert_eq!(frame.message::<CommandResponse>().unwrap(), res);
+    }
+
+    #[test]
+    fn compressed_frame_should_decode_from_hand_built_bytes() {
+        let res: CommandResponse = Value::from(Bytes::from(vec![0u8; 4096])).into();
+        let payload = res.encode_to_vec();
+        let mut compressed = BytesMut::new();
+        compress(CompressorType::GZIP, &payload, &mut compressed, None).unwrap();
+        let len = compressed.len() as u32;
+        assert!(len < 0x1_0000);
+
+        // v1: 最高 2 bit 为 01 表示 GZIP，剩下的 30 bit 是压缩后的长度
+        let mut data = vec![0x40, 0x00, (len >> 8) as u8, len as u8];
+        data.extend_from_slice(&compressed);
+        let frame = try_decode_frame(&data).unwrap();
+        assert_eq!(frame.compressor, CompressorType::GZIP);
+        assert_eq!(frame.payload, payload);
+        assert_eq!(frame.message::<CommandResponse>().unwrap(), res);
+
+        // v2: 第二个字节是压缩算法，flags 为 0
+        let mut data = vec![
+            0xFF,
+            0x01,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            (len >> 8) as u8,
+            len as u8,
+        ];
+        data.extend_from_slice(&compressed);
+        let frame = try_decode_frame_versioned(2, &data).unwrap();
+        assert_eq!(frame.compressor, CompressorType::GZIP);
+        assert_eq!(frame.message::<CommandResponse>().unwrap(), res);
+
+        // 编码出来的 frame 头和手工构造的一致
+        let mut buf = BytesMut::new();
+        res.encode_frame_versioned(1, &mut buf, CompressorType::GZIP, None)
+            .unwrap();
+        let len = (buf.len() - LEN_LEN) as u32;
+        assert_eq!(&buf[..LEN_LEN], &(0x4000_0000 | len).to_be_bytes());
+    }
+
+    #[test]
+    fn header_should_reject_invalid_fields() {
+        // 长度超过 MAX_FRAME
+        let header = FrameHeader {
+            len: MAX_FRAME,
+            compressor: CompressorType::None,
+        };
+        assert!(header.encode(2, &mut [0; LEN_LEN_V2]).is_err());
+        // dst 的长度和版本不匹配
+        let header = FrameHeader { len: 1, ..header };
+        assert!(header.encode(1, &mut [0; LEN_LEN_V2]).is_err());
+        assert!(header.encode(2, &mut [0; LEN_LEN]).is_err());
+
+        // v1 中 ZSTD 压缩的超大 frame 会和 v2 的标记冲突
+        let header = FrameHeader {
+            len: 0x3F00_0000,
+            compressor: CompressorType::ZSTD,
+        };
+        assert!(header.encode(1, &mut [0; LEN_LEN]).is_err());
+        assert!(header.encode(2, &mut [0; LEN_LEN_V2]).is_ok());
+
+        // v2 中未知的压缩算法、非 0 的 flags、不完整的头
+        let inputs: &[&[u8]] = &[
+            &[0xff, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            &[0xff, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00],
+            &[0xff, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00],
+            &[0xff, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00],
+            &[0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ];
+        for input in inputs {
+            assert!(FrameHeader::decode(2, input).is_err(), "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn frames_should_only_decode_under_their_own_version() {
+        let small = CommandRequest::new_hdel("table", "key");
+        let large: CommandResponse =
+            Value::from(Bytes::from(vec![7u8; COMPRESSION_LIMIT * 2])).into();
+        for compressor in COMPRESSORS {
+            for (version, other) in [(1, 2), (2, 1)] {
+                let mut buf = BytesMut::new();
+                small
+                    .encode_frame_versioned(version, &mut buf, compressor, None)
+                    .unwrap();
+                assert!(try_decode_frame_versioned(other, &buf).is_err());
+                assert_eq!(
+                    CommandRequest::decode_frame_versioned(version, &mut buf).unwrap(),
+                    small
+                );
+
+                let mut buf = BytesMut::new();
+                large
+                    .encode_frame_versioned(version, &mut buf, compressor, None)
+                    .unwrap();
+                let frame = try_decode_frame_versioned(version, &buf).unwrap();
+                assert_eq!(frame.compressor, compressor);
+                // 用另一个版本解析时返回 FrameError，而不是按另一种布局得到错误的长度或压缩算法
+                assert!(matches!(
+                    try_decode_frame_versioned(other, &buf),
+                    Err(KvError::FrameError)
+                ));
+                assert!(CommandResponse::decode_frame_versioned(other, &mut buf.clone()).is_err());
+                assert_eq!(
+                    CommandResponse::decode_frame_versioned(version, &mut buf).unwrap(),
+                    large
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_reject_other_version() {
+        let cmd = CommandRequest::new_hdel("table", "key");
+        for (version, other) in [(1, 2), (2, 1)] {
+            let mut buf = BytesMut::new();
+            cmd.encode_frame_versioned(version, &mut buf, CompressorType::None, None)
+                .unwrap();
+            let mut stream = DummyStream { buf: buf.clone() };
+            let mut data = BytesMut::new();
+            read_frame(&mut stream, version, &mut data).await.unwrap();
+            assert_eq!(
+                CommandRequest::decode_frame_versioned(version, &mut data).unwrap(),
+                cmd
+            );
+
+            let mut stream = DummyStream { buf };
+            let mut data = BytesMut::new();
+            let res = read_frame(&mut stream, other, &mut data).await;
+            assert!(matches!(res, Err(KvError::FrameError)));
+        }
+    }
+
+    #[test]
+    fn pooled_frames_should_reuse_buffers_without_leaking_data() {
+        let mut pool = BufferPool::new(Default::default());
+        let first = CommandRequest::new_hset("table", "key", Bytes::from(vec![1u8; 64 * 1024]));
+        // 第二个 frame 更短，如果 buffer 没有清空，会读到第一个 frame 残留的数据
+        let second = CommandRequest::new_hset("t", "k", Bytes::from(vec![2u8; 4 * 1024]));
+
+        for _ in 0..10 {
+            for cmd in [&first, &second] {
+                let mut buf = BytesMut::new();
+                cmd.encode_frame_pooled(2, &mut buf, CompressorType::LZ4, None, &mut pool)
+                    .unwrap();
+                assert_eq!(
+                    &CommandRequest::decode_frame_pooled(2, &mut buf, &mut pool).unwrap(),
+                    cmd
+                );
+                assert!(buf.is_empty());
+            }
+        }
+        // 只有第一次 encode 和 decode 需要分配
+        assert_eq!(pool.allocations(), 2);
+    }
+
+    proptest! {
+        #[test]
+        fn command_request_should_round_trip(cmd in arb_command_request()) {
+            assert_round_trip(&cmd);
+        }
+
+        #[test]
+        fn command_response_should_round_trip(res in arb_command_response()) {
+            assert_round_trip(&res);
+        }
+
+        #[test]
+        fn value_should_round_trip(pair in arb_kvpair()) {
+            assert_round_trip(&pair);
+        }
+
+        #[test]
+        fn large_value_should_round_trip(
+            data in proptest::collection::vec(any::<u8>(), COMPRESSION_LIMIT..COMPRESSION_LIMIT * 4),
+        ) {
+            let res: CommandResponse = Value::from(Bytes::from(data)).into();
+            assert_round_trip(&res);
+        }
+    }
+
     fn is_compressed(data: &[u8]) -> bool {
         if let &[v] = &data[..1] {
             v >> 6 != 0b00
@@ -1,27 +1,160 @@
+mod cache;
 mod compressor;
+mod discovery;
 mod frame;
 mod multiplex;
+mod pool;
+mod priority;
+mod retry;
 mod security;
 mod stream;
 mod stream_result;
+mod transport;
 
+pub use cache::*;
 pub use compressor::*;
-pub use frame::FrameCoder;
+pub use discovery::*;
+pub use frame::{
+    header_len, try_decode_frame, try_decode_frame_versioned, DecodedFrame, FrameCoder,
+    FrameHeader, MAX_HEADER_LEN,
+};
 pub use multiplex::*;
+pub use pool::*;
+pub use retry::*;
 pub use security::*;
+pub use stream::PROTOCOL_VERSIONS;
 use stream::*;
+pub use transport::*;
 
-use futures::{SinkExt, StreamExt};
+use bytes::{Bytes, BytesMut};
+use futures::{
+    future::{self, BoxFuture, FutureExt},
+    stream::{self, FuturesOrdered},
+    SinkExt, StreamExt,
+};
+use priority::PriorityQueue;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 use stream_result::StreamResult;
-use tokio::io::{AsyncRead, AsyncWrite};
-use tracing::info;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::Notify,
+    task, time,
+};
+use tracing::{info, warn};
 
-use crate::{CommandRequest, CommandResponse, KvError, Service, Storage};
+use crate::{
+    command_request::RequestData, keyspace_topic, log_topic, BufferPoolConfig, CommandRequest,
+    CommandResponse, CommandTimeout, CompressionConfig, KeyBatch, KvError, Kvpair, LifecycleEvent,
+    Service, Storage, StreamingResponse, Value,
+};
+
+/// BulkLoad 时每攒够这么多 Kvpair 就写入一次存储
+const BULK_LOAD_BATCH_SIZE: usize = 1024;
+
+// 正在执行的命令，按开始执行的顺序返回响应
+type InFlight = FuturesOrdered<BoxFuture<'static, StreamingResponse>>;
 
 // 处理服务端某个 accept 下来的 socket 的读写
 pub struct ProstServerStream<S, Store> {
     inner: ProstStream<S, CommandRequest, CommandResponse>,
     service: Service<Store>,
+    // 每收到一个命令就通知一次，用于检测连接是否空闲
+    activity: Option<Arc<Notify>>,
+    // 执行命令的超时
+    timeout: CommandTimeout,
+    // 对端地址
+    peer_addr: Option<SocketAddr>,
+    // 同时执行的命令数的上限
+    max_in_flight: usize,
+    // 已经读取、等待执行的命令数的上限
+    queue_depth: usize,
+    // 所在连接的订阅
+    subscriptions: Arc<SubscriptionQuota>,
+}
+
+/// 一个连接上正在进行的订阅，连接上的所有 stream 共享
+#[derive(Debug, Default)]
+pub struct SubscriptionQuota {
+    limit: Option<usize>,
+    active: AtomicUsize,
+    // subscription id -> 主题，收到 subscription id 之后才加入
+    owned: Mutex<BTreeMap<u32, String>>,
+}
+
+impl SubscriptionQuota {
+    /// limit 为 None 时不限制，只计数
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            active: AtomicUsize::new(0),
+            owned: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// 正在进行的订阅数
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// 正在进行的订阅的主题和 subscription id，按 id 排序
+    pub fn subscriptions(&self) -> Vec<(String, u32)> {
+        let owned = self.owned.lock().unwrap();
+        owned
+            .iter()
+            .map(|(id, topic)| (topic.clone(), *id))
+            .collect()
+    }
+
+    // 除了 id 之外正在进行的订阅数，id 对应的订阅可能还没有收到结束标记
+    fn remaining(&self, id: u32) -> usize {
+        let owned = self.owned.lock().unwrap();
+        owned.keys().filter(|&&k| k != id).count()
+    }
+
+    // 占用一个订阅，达到上限时返回 None。返回的 guard 被 drop 时释放
+    fn acquire(self: &Arc<Self>) -> Option<SubscriptionGuard> {
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| match self.limit {
+                Some(limit) if n >= limit => None,
+                _ => Some(n + 1),
+            })
+            .ok()?;
+        Some(SubscriptionGuard {
+            quota: self.clone(),
+            id: None,
+        })
+    }
+}
+
+// 订阅结束（取消订阅、服务器关闭）或者 stream 被 drop（连接断开）时释放占用的订阅
+struct SubscriptionGuard {
+    quota: Arc<SubscriptionQuota>,
+    id: Option<u32>,
+}
+
+impl SubscriptionGuard {
+    // 收到 subscription id 之后记录订阅的主题
+    fn register(&mut self, topic: String, id: u32) {
+        self.quota.owned.lock().unwrap().insert(id, topic);
+        self.id = Some(id);
+    }
+}
+
+impl Drop for SubscriptionGuard {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.quota.owned.lock().unwrap().remove(&id);
+        }
+        self.quota.active.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 // 处理客户端 socket 的读写
@@ -36,22 +169,310 @@ where
 {
     pub fn new(stream: S, service: Service<Store>) -> Self {
         Self {
-            inner: ProstStream::new(stream),
+            inner: ProstStream::new(stream).with_compression(service.compression()),
             service,
+            activity: None,
+            timeout: CommandTimeout::default(),
+            peer_addr: None,
+            max_in_flight: 1,
+            queue_depth: 0,
+            subscriptions: Arc::new(SubscriptionQuota::new(None)),
         }
     }
 
+    /// 设置活跃通知，每收到一个命令都会通知一次
+    pub fn with_activity(mut self, activity: Arc<Notify>) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+
+    /// 设置执行命令的超时，超时后返回 504，stream 可以继续处理后续的命令
+    pub fn with_timeout(mut self, timeout: CommandTimeout) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 设置同时执行的命令数的上限，默认为 1，即逐个执行。
+    /// 达到上限（以及优先级队列已满）后不再读取新的命令，直到最早的命令的响应发送出去，对端的写入会因此阻塞
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// 设置优先级队列的长度，默认为 0，即按读取的顺序执行。
+    /// 执行的命令达到 max_in_flight 后继续读取最多 depth 个命令，按优先级从高到低执行
+    pub fn with_priority_queue(mut self, depth: usize) -> Self {
+        self.queue_depth = depth;
+        self
+    }
+
+    /// 设置发送响应时的压缩算法和级别，默认使用 service 的配置
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.inner = self.inner.with_compression(compression);
+        self
+    }
+
+    /// 设置 frame 编解码时复用的临时 buffer
+    pub fn with_buffer_pool(mut self, config: BufferPoolConfig) -> Self {
+        self.inner = self.inner.with_buffer_pool(config);
+        self
+    }
+
+    /// 设置所在连接的订阅，同一个连接上的 stream 共享订阅数的上限，MYSUBSCRIPTIONS 也会列出
+    /// 其他 stream 上的订阅，UNSUBSCRIBE 返回的剩余订阅数也包括其他 stream 上的订阅。
+    /// 超过上限的 SUBSCRIBE 返回 429。默认每个 stream 单独计算，不限制
+    pub fn with_subscription_quota(mut self, quota: Arc<SubscriptionQuota>) -> Self {
+        self.subscriptions = quota;
+        self
+    }
+
+    /// 设置对端地址
+    pub fn with_peer_addr(mut self, addr: Option<SocketAddr>) -> Self {
+        self.peer_addr = addr;
+        self
+    }
+
+    /// 对端地址，Unix socket 的对端通常没有地址
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
     pub async fn process(mut self) -> Result<(), KvError> {
-        let stream = &mut self.inner;
-        while let Some(Ok(cmd)) = stream.next().await {
-            info!("Got a new command: {cmd:?}");
-            let mut res = self.service.execute(cmd);
-            while let Some(data) = res.next().await {
-                stream.send(&data).await?;
+        // 在读取任何命令之前协商协议版本，协商失败时关闭 stream
+        if let Err(e) = self.inner.negotiate_server(PROTOCOL_VERSIONS).await {
+            warn!("Failed to negotiate protocol version: {e}");
+            return Ok(());
+        }
+        self.service
+            .broadcaster()
+            .emit(LifecycleEvent::StreamOpened(self.peer_addr));
+        let mut in_flight = InFlight::new();
+        // 已经读取、等待执行的命令
+        let mut queue = PriorityQueue::default();
+        let capacity = self.max_in_flight + self.queue_depth;
+        let mut closed = false;
+        loop {
+            // 先读取所有已经到达的命令，pipeline 中的命令才能按优先级执行
+            while !closed && in_flight.len() + queue.len() < capacity {
+                match self.inner.next().now_or_never() {
+                    Some(Some(Ok(cmd))) => self.accept(cmd, &mut queue, &mut in_flight).await?,
+                    Some(_) => closed = true,
+                    None => break,
+                }
+            }
+            self.dispatch(&mut queue, &mut in_flight);
+            // 对端关闭写方向后，仍然发送已经读取的命令的响应
+            if closed && in_flight.is_empty() {
+                break;
+            }
+
+            tokio::select! {
+                cmd = self.inner.next(), if !closed && in_flight.len() + queue.len() < capacity => {
+                    match cmd {
+                        Some(Ok(cmd)) => self.accept(cmd, &mut queue, &mut in_flight).await?,
+                        _ => closed = true,
+                    }
+                }
+                Some(res) = in_flight.next() => {
+                    send_responses(&mut self.inner, res).await?;
+                }
             }
         }
         Ok(())
     }
+
+    /// 不执行命令，对 stream 上的第一个命令返回 res 后关闭 stream，
+    /// 用于服务器无法处理新连接时告诉客户端原因，避免客户端一直等待
+    pub async fn reject(mut self, mut res: CommandResponse) -> Result<(), KvError> {
+        if let Err(e) = self.inner.negotiate_server(PROTOCOL_VERSIONS).await {
+            warn!("Failed to negotiate protocol version: {e}");
+            return Ok(());
+        }
+        if let Some(Ok(cmd)) = self.inner.next().await {
+            res.request_id = cmd.request_id;
+            self.inner.send(&res).await?;
+        }
+        Ok(())
+    }
+
+    // 把读取到的命令放入队列，BulkLoad / BulkDel 会继续读取 stream，所以立即执行
+    async fn accept(
+        &mut self,
+        cmd: CommandRequest,
+        queue: &mut PriorityQueue,
+        in_flight: &mut InFlight,
+    ) -> Result<(), KvError> {
+        info!("Got a new command: {cmd:?}");
+        if let Some(activity) = &self.activity {
+            activity.notify_one();
+        }
+        let is_bulk = matches!(
+            cmd.request_data,
+            Some(RequestData::BulkLoad(_) | RequestData::BulkDel(_))
+        );
+        if !is_bulk {
+            queue.push(cmd);
+            return Ok(());
+        }
+        // 先按优先级执行完排队的命令，发送它们的响应
+        loop {
+            self.dispatch(queue, in_flight);
+            let Some(res) = in_flight.next().await else {
+                break;
+            };
+            send_responses(&mut self.inner, res).await?;
+        }
+        let (stream, service) = (&mut self.inner, &self.service);
+        let mut res = match &cmd.request_data {
+            Some(RequestData::BulkDel(param)) => {
+                bulk_del(stream, service, &param.table, cmd.request_id).await
+            }
+            Some(RequestData::BulkLoad(param)) => bulk_load(stream, service, &param.table).await,
+            _ => unreachable!(),
+        };
+        res.request_id = cmd.request_id;
+        self.inner.send(&res).await
+    }
+
+    // 从队列中取出优先级最高的命令开始执行，直到达到 max_in_flight
+    fn dispatch(&self, queue: &mut PriorityQueue, in_flight: &mut InFlight) {
+        while in_flight.len() < self.max_in_flight {
+            let Some(cmd) = queue.pop() else {
+                break;
+            };
+            in_flight.push_back(self.execute(cmd));
+        }
+    }
+
+    // 执行一个命令，返回的 future 完成时得到响应
+    fn execute(&self, cmd: CommandRequest) -> BoxFuture<'static, StreamingResponse> {
+        let request_id = cmd.request_id;
+        match &cmd.request_data {
+            Some(
+                RequestData::Subscribe(_)
+                | RequestData::SubscribeLogs(_)
+                | RequestData::WatchKey(_),
+            ) => {
+                return Box::pin(future::ready(self.subscribe(cmd)));
+            }
+            Some(RequestData::MySubscriptions(_)) => {
+                return Box::pin(future::ready(self.my_subscriptions(request_id)));
+            }
+            Some(RequestData::Unsubscribe(_)) => {
+                return Box::pin(future::ready(self.unsubscribe(cmd)));
+            }
+            _ => {}
+        }
+
+        let timeout = self.timeout.get(&cmd);
+        let offload = self.service.is_blocking() || timeout.is_some();
+        if !offload || !is_storage_command(&cmd) {
+            return Box::pin(future::ready(self.service.execute(cmd)));
+        }
+
+        // storage 的接口是同步的，可能阻塞的调用放到 blocking 线程里执行，避免阻塞 worker 线程上的其他连接，
+        // 这样也能被超时打断。超时后命令仍会在后台执行完，只是不再等待它的结果
+        let svc = self.service.clone();
+        let fut = task::spawn_blocking(move || svc.execute(cmd));
+        Box::pin(async move {
+            let res = match timeout {
+                Some(timeout) => match time::timeout(timeout, fut).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        warn!("Command timed out after {timeout:?}");
+                        return error_response(KvError::Timeout(timeout), request_id);
+                    }
+                },
+                None => fut.await,
+            };
+            res.unwrap_or_else(|e| error_response(KvError::Internal(e.to_string()), request_id))
+        })
+    }
+
+    // 订阅数达到上限时返回 429，否则订阅结束前一直占用一个订阅
+    fn subscribe(&self, cmd: CommandRequest) -> StreamingResponse {
+        let quota = &self.subscriptions;
+        let Some(guard) = quota.acquire() else {
+            let limit = quota.limit.unwrap_or_default();
+            let e = format!("at most {limit} subscriptions per connection");
+            return error_response(KvError::TooManyRequests(e), cmd.request_id);
+        };
+        let topic = match &cmd.request_data {
+            Some(RequestData::Subscribe(param)) => param.topic.clone(),
+            Some(RequestData::SubscribeLogs(param)) => log_topic(&param.level).unwrap_or_default(),
+            Some(RequestData::WatchKey(param)) => keyspace_topic(&param.table),
+            _ => String::new(),
+        };
+        // 在发送结束标记之前释放，客户端收到结束标记后可以立即再次订阅
+        let mut guard = Some(guard);
+        let mut first = true;
+        Box::pin(self.service.execute(cmd).map(move |data| {
+            if data.is_stream_end() {
+                guard.take();
+            } else if std::mem::take(&mut first) {
+                // 第一个响应是 subscription id
+                if let (Some(guard), Ok(id)) = (&mut guard, i64::try_from(data.as_ref())) {
+                    guard.register(topic.clone(), id as u32);
+                }
+            }
+            data
+        }))
+    }
+
+    // 取消订阅成功时，在被删除的 subscription id 之后加上所在连接剩余的订阅数
+    fn unsubscribe(&self, cmd: CommandRequest) -> StreamingResponse {
+        let quota = self.subscriptions.clone();
+        Box::pin(self.service.execute(cmd).map(move |data| {
+            let Ok(id) = i64::try_from(data.as_ref()) else {
+                return data;
+            };
+            let remaining = quota.remaining(id as u32) as i64;
+            let mut res = data.as_ref().clone();
+            res.values.push(remaining.into());
+            Arc::new(res)
+        }))
+    }
+
+    // 列出所在连接上的订阅：主题 => subscription id
+    fn my_subscriptions(&self, request_id: u64) -> StreamingResponse {
+        let pairs: Vec<_> = self
+            .subscriptions
+            .subscriptions()
+            .into_iter()
+            .map(|(topic, id)| Kvpair::new(topic, id as i64))
+            .collect();
+        let res = CommandResponse {
+            request_id,
+            ..pairs.into()
+        };
+        Box::pin(stream::once(future::ready(Arc::new(res))))
+    }
+}
+
+// 发送一个命令的所有响应
+async fn send_responses<S>(
+    stream: &mut ProstStream<S, CommandRequest, CommandResponse>,
+    mut res: StreamingResponse,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    while let Some(data) = res.next().await {
+        stream.send(&data).await?;
+        // 流式命令中途出错时，发送错误后结束 stream
+        if data.status >= 400 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn error_response(e: KvError, request_id: u64) -> StreamingResponse {
+    let res = CommandResponse {
+        request_id,
+        ..e.into()
+    };
+    Box::pin(stream::once(future::ready(Arc::new(res))))
 }
 
 impl<S> ProstClientStream<S>
@@ -64,12 +485,33 @@ where
         }
     }
 
+    /// 设置发送命令时的压缩算法和级别
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.inner = self.inner.with_compression(compression);
+        self
+    }
+
+    /// 设置 frame 编解码时复用的临时 buffer
+    pub fn with_buffer_pool(mut self, config: BufferPoolConfig) -> Self {
+        self.inner = self.inner.with_buffer_pool(config);
+        self
+    }
+
+    // 第一次发送命令之前协商协议版本
+    async fn negotiate(&mut self) -> Result<(), KvError> {
+        if self.inner.version().is_none() {
+            self.inner.negotiate_client(PROTOCOL_VERSIONS).await?;
+        }
+        Ok(())
+    }
+
     pub async fn execute_unary(
         &mut self,
         cmd: &CommandRequest,
     ) -> Result<CommandResponse, KvError> {
+        self.negotiate().await?;
         let stream = &mut self.inner;
-        stream.send(&cmd).await?;
+        stream.send(cmd).await?;
 
         match stream.next().await {
             Some(v) => v,
@@ -77,13 +519,260 @@ where
         }
     }
 
-    pub async fn execute_streaming(self, cmd: &CommandRequest) -> Result<StreamResult, KvError> {
-        let mut stream = self.inner;
+    /// 和 execute_unary 一样，但这个命令使用指定的压缩算法发送，不改变连接默认的压缩配置。
+    /// frame 头中带有压缩算法，服务器不需要知道客户端的选择
+    pub async fn execute_unary_with_compressor(
+        &mut self,
+        cmd: &CommandRequest,
+        compressor: CompressorType,
+    ) -> Result<CommandResponse, KvError> {
+        self.negotiate().await?;
+        let stream = &mut self.inner;
+        stream.send_with_compressor(cmd, compressor).await?;
 
-        stream.send(cmd).await?;
-        stream.close().await?;
+        match stream.next().await {
+            Some(v) => v,
+            None => Err(KvError::Internal("Didn't get any response".into())),
+        }
+    }
+
+    /// 批量写入一组 kv pair，省去了逐个 HSET 时每个命令的开销，返回写入的数量
+    pub async fn bulk_load(
+        &mut self,
+        table: impl Into<String>,
+        pairs: impl IntoIterator<Item = Kvpair>,
+    ) -> Result<CommandResponse, KvError> {
+        self.negotiate().await?;
+        let stream = &mut self.inner;
+        stream
+            .feed_message(&CommandRequest::new_bulk_load(table))
+            .await?;
+        for pair in pairs {
+            stream.feed_message(&pair).await?;
+        }
+        // 以一个空的 Kvpair 结束
+        stream.feed_message(&Kvpair::default()).await?;
+        stream.flush_messages().await?;
+
+        match stream.next().await {
+            Some(v) => v,
+            None => Err(KvError::Internal("Didn't get any response".into())),
+        }
+    }
+
+    /// 批量删除 key，每一批 key 用一个 frame 发送，key 的总数不受 frame 大小的限制。
+    /// 返回每一批的响应（删除的数量或者错误），最后一个响应是删除的总数
+    pub async fn bulk_del(
+        &mut self,
+        table: impl Into<String>,
+        batches: impl IntoIterator<Item = Vec<String>>,
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        self.negotiate().await?;
+        let stream = &mut self.inner;
+        stream
+            .feed_message(&CommandRequest::new_bulk_del(table))
+            .await?;
+        let mut count = 0;
+        // 空的 KeyBatch 表示结束，所以跳过空的批次
+        for keys in batches.into_iter().filter(|keys| !keys.is_empty()) {
+            stream.feed_message(&KeyBatch { keys }).await?;
+            count += 1;
+        }
+        stream.feed_message(&KeyBatch::default()).await?;
+        stream.flush_messages().await?;
+
+        let mut responses = Vec::with_capacity(count + 1);
+        for _ in 0..=count {
+            responses.push(self.next_response().await?);
+        }
+        Ok(responses)
+    }
 
-        StreamResult::new(stream).await
+    /// 只发送命令，不等待响应，之后用 next_response 按顺序读取响应
+    pub async fn send(&mut self, cmd: &CommandRequest) -> Result<(), KvError> {
+        self.negotiate().await?;
+        self.inner.send(cmd).await
+    }
+
+    /// 读取下一个响应，对端关闭 stream 后返回错误
+    pub async fn next_response(&mut self) -> Result<CommandResponse, KvError> {
+        match self.inner.next().await {
+            Some(v) => v,
+            None => Err(KvError::Internal("Didn't get any response".into())),
+        }
+    }
+
+    /// 发送写缓存中的数据并关闭写方向，读方向保持打开，可以继续读取响应。
+    /// 之后不能再发送命令，服务器处理完已收到的命令后会关闭 stream
+    ///
+    /// 半关闭由底层 stream 的 poll_shutdown 实现：yamux 的 substream 和 QUIC 的双向 stream 都支持，
+    /// 只关闭发送方向。直接在 TLS / Noise 连接上使用时，TLS 会先发送 close_notify 再关闭 TCP 的写方向，
+    /// Noise 直接关闭 TCP 的写方向，对端能否继续发送取决于对端的实现
+    pub async fn finish_sending(&mut self) -> Result<(), KvError> {
+        self.inner.close().await
+    }
+
+    /// 执行流式命令。SUBSCRIBE 等待服务器返回 subscription id，ScanStream 和 HgetChunked 没有 id，
+    /// 返回的 StreamResult 中是一个个 kv pair 的 chunk 或者 value 的一段
+    pub async fn execute_streaming(
+        mut self,
+        cmd: &CommandRequest,
+    ) -> Result<StreamResult, KvError> {
+        self.send(cmd).await?;
+        self.finish_sending().await?;
+
+        match cmd.request_data {
+            Some(RequestData::ScanStream(_) | RequestData::HgetChunked(_)) => {
+                Ok(StreamResult::without_id(self.inner))
+            }
+            _ => StreamResult::new(self.inner).await,
+        }
+    }
+
+    /// 用 HgetChunked 分段读取一个很大的 value，把所有的段按顺序拼接起来
+    pub async fn hget_chunked(
+        self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        chunk_size: u32,
+    ) -> Result<Bytes, KvError> {
+        let cmd = CommandRequest::new_hget_chunked(table, key, chunk_size);
+        let mut stream = self.execute_streaming(&cmd).await?;
+        let mut data = BytesMut::new();
+        while let Some(res) = stream.next().await {
+            let res = res?;
+            for value in res.values {
+                let chunk: Bytes = value.try_into()?;
+                data.extend_from_slice(&chunk);
+            }
+        }
+        Ok(data.freeze())
+    }
+}
+
+// 处理 BulkLoad：持续读取 Kvpair 直到收到一个空的 Kvpair，按批写入存储
+async fn bulk_load<S, Store>(
+    stream: &mut ProstStream<S, CommandRequest, CommandResponse>,
+    service: &Service<Store>,
+    table: &str,
+) -> CommandResponse
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage,
+{
+    let mut total = 0;
+    let mut batch = Vec::with_capacity(BULK_LOAD_BATCH_SIZE);
+    let mut error = None;
+    loop {
+        let pair: Kvpair = match stream.read_message().await {
+            Ok(pair) => pair,
+            Err(e) => return e.into(),
+        };
+        let end = pair.key.is_empty();
+        // 出错后继续读完剩下的 Kvpair，以免它们被当作命令处理
+        if !end && error.is_none() {
+            batch.push(pair);
+        }
+
+        if !batch.is_empty() && (end || batch.len() >= BULK_LOAD_BATCH_SIZE) {
+            let batch = std::mem::take(&mut batch);
+            let table = table.to_string();
+            match run_batch(service, move |svc| svc.bulk_load(&table, batch)).await {
+                Ok(n) => total += n,
+                Err(e) => error = Some(e),
+            }
+        }
+
+        if end {
+            break;
+        }
+    }
+
+    match error {
+        Some(e) => e.into(),
+        None => Value::from(total as i64).into(),
+    }
+}
+
+// 处理 BulkDel：持续读取 KeyBatch 直到收到一个空的 KeyBatch，每一批删除后立即返回删除的数量，
+// 出错的批次返回错误，不影响之后的批次。返回删除的总数
+async fn bulk_del<S, Store>(
+    stream: &mut ProstStream<S, CommandRequest, CommandResponse>,
+    service: &Service<Store>,
+    table: &str,
+    request_id: u64,
+) -> CommandResponse
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage,
+{
+    let mut total = 0;
+    loop {
+        let batch: KeyBatch = match stream.read_message().await {
+            Ok(batch) => batch,
+            Err(e) => return e.into(),
+        };
+        if batch.keys.is_empty() {
+            break;
+        }
+
+        let table = table.to_string();
+        let res = run_batch(service, move |svc| svc.bulk_del(&table, batch.keys)).await;
+        let mut res: CommandResponse = match res {
+            Ok(n) => {
+                total += n;
+                Value::from(n as i64).into()
+            }
+            Err(e) => e.into(),
+        };
+        res.request_id = request_id;
+        if let Err(e) = stream.send(&res).await {
+            return e.into();
+        }
+    }
+    Value::from(total as i64).into()
+}
+
+// 和 execute 中的普通命令一样，storage 可能阻塞时把一批写入放到 blocking 线程里执行，
+// 不阻塞 worker 线程上的其他连接
+async fn run_batch<Store, T>(
+    service: &Service<Store>,
+    f: impl FnOnce(&Service<Store>) -> Result<T, KvError> + Send + 'static,
+) -> Result<T, KvError>
+where
+    Store: Storage,
+    T: Send + 'static,
+{
+    if !service.is_blocking() {
+        return f(service);
+    }
+    let svc = service.clone();
+    task::spawn_blocking(move || f(&svc))
+        .await
+        .unwrap_or_else(|e| Err(KvError::Internal(e.to_string())))
+}
+
+// pub/sub 命令不访问 storage，其余命令都会调用同步的 storage 接口
+fn is_storage_command(cmd: &CommandRequest) -> bool {
+    !matches!(
+        cmd.request_data,
+        Some(
+            RequestData::Subscribe(_)
+                | RequestData::SubscribeLogs(_)
+                | RequestData::Unsubscribe(_)
+                | RequestData::Publish(_)
+                | RequestData::Mpublish(_)
+        )
+    )
+}
+
+/// 等待连接空闲：在 timeout 内一直没有收到活跃通知时返回
+pub async fn wait_idle(activity: &Notify, timeout: Duration) {
+    loop {
+        tokio::select! {
+            _ = activity.notified() => {}
+            _ = time::sleep(timeout) => return,
+        }
     }
 }
 
@@ -95,7 +784,7 @@ mod tests {
 
     use tokio::net::{TcpListener, TcpStream};
 
-    use crate::{assert_res_ok, MemTable, ServiceInner, Value};
+    use crate::{assert_res_error, assert_res_ok, LimitsConfig, MemTable, ServiceInner, Value};
 
     use super::*;
 
@@ -145,11 +834,615 @@ mod tests {
         let cmd = CommandRequest::new_hget("table", "key");
         let res = client.execute_unary(&cmd).await.unwrap();
 
-        assert_res_ok(&res, &[value.into()], &[]);
+        assert_res_ok(&res, &[value], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_should_return_remaining_connection_subscriptions() -> Result<()> {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let quota = Arc::new(SubscriptionQuota::new(None));
+        // 同一个连接上的每个 stream 共享 quota
+        let open_stream = |quota: Arc<SubscriptionQuota>| {
+            let (client, server) = tokio::io::duplex(4096);
+            let server =
+                ProstServerStream::new(server, service.clone()).with_subscription_quota(quota);
+            tokio::spawn(server.process());
+            ProstClientStream::new(client)
+        };
+
+        let mut streams = vec![];
+        for topic in ["lobby", "news"] {
+            let client = open_stream(quota.clone());
+            let cmd = CommandRequest::new_subscribe(topic);
+            streams.push((client.execute_streaming(&cmd).await?, topic));
+        }
+        // 其他连接上的订阅不计算在内
+        let other = open_stream(Arc::new(SubscriptionQuota::new(None)));
+        let _other = other
+            .execute_streaming(&CommandRequest::new_subscribe("lobby"))
+            .await?;
+
+        // 返回被删除的 id 以及所在连接剩余的订阅数
+        let mut client = open_stream(quota.clone());
+        let (mut stream, topic) = streams.remove(0);
+        let cmd = CommandRequest::new_unsubscribe(topic, stream.id);
+        let res = client.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &[(stream.id as i64).into(), 1.into()], &[]);
+        assert!(stream.next().await.is_none());
+
+        let (stream, topic) = &streams[0];
+        let cmd = CommandRequest::new_unsubscribe(*topic, stream.id);
+        let res = client.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &[(stream.id as i64).into(), 0.into()], &[]);
+
+        // 已经取消的订阅返回 404
+        let res = client.execute_unary(&cmd).await?;
+        let msg = format!("Not found subscription: {}", stream.id);
+        assert_res_error(&res, 404, &msg);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_per_request_compression_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        // 同一个 stream 上的命令可以使用不同的压缩算法
+        let value: Value = Bytes::from(vec![1u8; 16384]).into();
+        let cmd = CommandRequest::new_hset("table", "zstd", value.clone());
+        let res = client
+            .execute_unary_with_compressor(&cmd, CompressorType::ZSTD)
+            .await?;
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hset("table", "none", value.clone());
+        let res = client
+            .execute_unary_with_compressor(&cmd, CompressorType::None)
+            .await?;
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hmget("table", vec!["zstd".into(), "none".into()]);
+        let res = client.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &[value.clone(), value], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_bulk_load_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let n = BULK_LOAD_BATCH_SIZE * 2 + 10;
+        let pairs = (0..n).map(|i| Kvpair::new(format!("key{i}"), i as i64));
+        let res = client.bulk_load("table", pairs).await?;
+        assert_res_ok(&res, &[(n as i64).into()], &[]);
+
+        // bulk load 之后 stream 可以继续处理普通命令
+        let cmd = CommandRequest::new_hget("table", format!("key{}", n - 1));
+        let res = client.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &[(n as i64 - 1).into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_bulk_del_should_work() -> Result<()> {
+        let limits = LimitsConfig {
+            max_keys_per_command: Some(500),
+            ..Default::default()
+        };
+        let service: Service = ServiceInner::new(MemTable::new()).limits(limits).into();
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(ProstServerStream::new(server, service).process());
+        let mut client = ProstClientStream::new(client);
+
+        let pairs = (0..2000).map(|i| Kvpair::new(format!("key{i}"), i as i64));
+        client.bulk_load("table", pairs).await?;
+
+        // 每批 400 个 key，最后一批中一半的 key 不存在
+        let key = |i: usize| format!("key{i}");
+        let mut batches: Vec<Vec<_>> = (0..4)
+            .map(|b| (b * 400..(b + 1) * 400).map(key).collect())
+            .collect();
+        batches.push((1800..2200).map(key).collect());
+        // 超过 max_keys_per_command 的一批返回错误，不影响其他批次
+        batches.insert(1, (1900..2501).map(key).collect());
+        let res = client.bulk_del("table", batches).await?;
+        assert_eq!(res.len(), 7);
+        for (i, n) in [400, 0, 400, 400, 400, 200].into_iter().enumerate() {
+            if i == 1 {
+                assert_res_error(&res[i], 400, "too many keys");
+            } else {
+                assert_res_ok(&res[i], &[(n as i64).into()], &[]);
+            }
+        }
+        assert_res_ok(&res[6], &[1800_i64.into()], &[]);
+
+        // 剩下 key1600..key1800
+        let res = client
+            .execute_unary(&CommandRequest::new_hgetall("table"))
+            .await?;
+        assert_eq!(res.pairs.len(), 200);
+        let cmd = CommandRequest::new_hexist("table", "key1900");
+        let res = client.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &[false.into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_id_should_round_trip() -> Result<()> {
+        let service = Service::new(MemTable::new());
+        let connect = || {
+            let (client, server) = tokio::io::duplex(4096);
+            tokio::spawn(ProstServerStream::new(server, service.clone()).process());
+            ProstClientStream::new(client)
+        };
+        let mut client = connect();
+
+        let cmd = CommandRequest::new_hset("table", "key", "value").with_request_id(42);
+        let res = client.execute_unary(&cmd).await?;
+        assert_eq!(res.request_id, 42);
+
+        // 出错的响应也带有 request_id
+        let cmd = CommandRequest::new_hget("table", "missing").with_request_id(43);
+        let res = client.execute_unary(&cmd).await?;
+        assert_eq!((res.status, res.request_id), (404, 43));
+
+        // 没有设置 id 时响应中也没有
+        let cmd = CommandRequest::new_hget("table", "key");
+        let res = client.execute_unary(&cmd).await?;
+        assert_eq!(res.request_id, 0);
+
+        // 流式命令的每个响应都带有 request_id
+        let cmd = CommandRequest::new_subscribe("chat").with_request_id(44);
+        let mut stream = client.execute_streaming(&cmd).await?;
+        let cmd = CommandRequest::new_publish("chat", vec!["hello".into()]);
+        connect().execute_unary(&cmd).await?;
+        let res = stream.next().await.unwrap()?;
+        assert_res_ok(&res, &["hello".into()], &[]);
+        assert_eq!(res.request_id, 44);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scan_stream_should_push_all_pairs_in_chunks() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = ProstClientStream::new(TcpStream::connect(addr).await?);
+        let n = 10_000;
+        let pairs = (0..n).map(|i| Kvpair::new(format!("key{i}"), i as i64));
+        client.bulk_load("table", pairs).await?;
+
+        let cmd = CommandRequest::new_scan_stream("table", 128).with_request_id(7);
+        let mut stream = client.execute_streaming(&cmd).await?;
+        let (mut count, mut chunks) = (0, 0);
+        while let Some(res) = stream.next().await {
+            let res = res?;
+            assert_eq!(res.request_id, 7);
+            assert!(!res.pairs.is_empty() && res.pairs.len() <= 128);
+            count += res.pairs.len();
+            chunks += 1;
+        }
+        assert_eq!(count, n);
+        assert_eq!(chunks, n.div_ceil(128));
+
+        // 不存在的 table 没有数据，直接结束
+        let client = ProstClientStream::new(TcpStream::connect(addr).await?);
+        let cmd = CommandRequest::new_scan_stream("empty", 0);
+        let mut stream = client.execute_streaming(&cmd).await?;
+        assert!(stream.next().await.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hget_chunked_should_reassemble_large_value() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = ProstClientStream::new(TcpStream::connect(addr).await?);
+        let data: Bytes = (0..5 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let cmd = CommandRequest::new_hset("table", "blob", data.clone());
+        client.execute_unary(&cmd).await?;
+
+        // 每一段都不超过 chunk_size，按顺序返回
+        let chunk_size = 256 * 1024;
+        let client = ProstClientStream::new(TcpStream::connect(addr).await?);
+        let cmd = CommandRequest::new_hget_chunked("table", "blob", chunk_size);
+        let mut stream = client.execute_streaming(&cmd).await?;
+        let mut offset = 0;
+        while let Some(res) = stream.next().await {
+            let chunk: Bytes = res?.values[0].clone().try_into()?;
+            assert!(chunk.len() <= chunk_size as usize);
+            assert_eq!(chunk, data.slice(offset..offset + chunk.len()));
+            offset += chunk.len();
+        }
+        assert_eq!(offset, data.len());
+
+        let client = ProstClientStream::new(TcpStream::connect(addr).await?);
+        let value = client.hget_chunked("table", "blob", 0).await?;
+        assert_eq!(value, data);
+
+        // 不存在的 key 返回错误
+        let client = ProstClientStream::new(TcpStream::connect(addr).await?);
+        assert!(client.hget_chunked("table", "missing", 0).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slow_command_should_time_out() -> Result<()> {
+        let (client, server) = tokio::io::duplex(4096);
+        let service = Service::new(SlowStore(MemTable::new()));
+        let timeout = CommandTimeout {
+            read: Some(50),
+            ..Default::default()
+        };
+        let server = ProstServerStream::new(server, service).with_timeout(timeout);
+        tokio::spawn(server.process());
+        let mut client = ProstClientStream::new(client);
+
+        let cmd = CommandRequest::new_hset("table", "key", "value");
+        let res = client.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        // 读命令超时，返回 504
+        let cmd = CommandRequest::new_hget("table", "key");
+        let res = client.execute_unary(&cmd).await?;
+        assert_res_error(&res, 504, "timed out");
+
+        // 超时后连接仍然可用
+        let cmd = CommandRequest::new_hexist("table", "key");
+        let res = client.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &[true.into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn slow_storage_should_not_block_runtime() -> Result<()> {
+        let (client, server) = tokio::io::duplex(4096);
+        let service = Service::new(SlowStore(MemTable::new()));
+        tokio::spawn(ProstServerStream::new(server, service).process());
+        let mut client = ProstClientStream::new(client);
+
+        let cmd = CommandRequest::new_hset("table", "key", "value");
+        client.execute_unary(&cmd).await?;
+
+        let slow = tokio::spawn(async move {
+            let cmd = CommandRequest::new_hget("table", "key");
+            client.execute_unary(&cmd).await
+        });
+
+        // 慢查询在 blocking 线程里执行，唯一的 worker 线程仍然可以调度其他任务
+        let start = time::Instant::now();
+        time::sleep(Duration::from_millis(20)).await;
+        assert!(start.elapsed() < Duration::from_millis(150));
+
+        let res = slow.await??;
+        assert_res_ok(&res, &["value".into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn slow_storage_should_not_block_memtable() -> Result<()> {
+        let (slow_client, slow_server) = tokio::io::duplex(4096);
+        let service = Service::new(SlowStore(MemTable::new()));
+        tokio::spawn(ProstServerStream::new(slow_server, service).process());
+        let mut slow_client = ProstClientStream::new(slow_client);
+
+        let (fast_client, fast_server) = tokio::io::duplex(4096);
+        let service = Service::new(MemTable::new());
+        assert!(!service.is_blocking());
+        tokio::spawn(ProstServerStream::new(fast_server, service).process());
+        let mut fast_client = ProstClientStream::new(fast_client);
+
+        let slow = tokio::spawn(async move {
+            let cmd = CommandRequest::new_hget("table", "key");
+            slow_client.execute_unary(&cmd).await
+        });
+
+        // BulkLoad 的每一批也在 blocking 线程里写入
+        let (bulk_client, bulk_server) = tokio::io::duplex(4096);
+        let service = Service::new(SlowStore(MemTable::new()));
+        tokio::spawn(ProstServerStream::new(bulk_server, service).process());
+        let mut bulk_client = ProstClientStream::new(bulk_client);
+        let bulk = tokio::spawn(async move {
+            let pairs = (0..10i64).map(|i| Kvpair::new(format!("key{i}"), i));
+            bulk_client.bulk_load("table", pairs).await
+        });
+        tokio::task::yield_now().await;
+
+        // 慢查询和 BulkLoad 执行期间，MemTable 的读写在 worker 线程里直接执行，不会被阻塞
+        let start = time::Instant::now();
+        for i in 0..10i64 {
+            let cmd = CommandRequest::new_hset("table", format!("key{i}"), i);
+            fast_client.execute_unary(&cmd).await?;
+            let cmd = CommandRequest::new_hget("table", format!("key{i}"));
+            let res = fast_client.execute_unary(&cmd).await?;
+            assert_res_ok(&res, &[i.into()], &[]);
+        }
+        assert!(start.elapsed() < Duration::from_millis(150));
+        assert!(!slow.is_finished());
+        assert!(!bulk.is_finished());
+
+        let res = slow.await??;
+        assert_res_error(&res, 404, "Not found");
+        let res = bulk.await??;
+        assert_res_ok(&res, &[10.into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipelined_commands_should_be_limited_by_max_in_flight() -> Result<()> {
+        static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+        let (client, server) = tokio::io::duplex(4096);
+        let service: Service<SlowStore> = ServiceInner::new(SlowStore(MemTable::new()))
+            .fn_received(|_| {
+                RECEIVED.fetch_add(1, Ordering::SeqCst);
+            })
+            .into();
+        let server = ProstServerStream::new(server, service).with_max_in_flight(2);
+        tokio::spawn(server.process());
+        let mut client = ProstClientStream::new(client);
+
+        for i in 0..4 {
+            let cmd = CommandRequest::new_hset("table", format!("key{i}"), i);
+            client.execute_unary(&cmd).await?;
+        }
+        let received = RECEIVED.load(Ordering::SeqCst);
+
+        // 一次发送 4 个慢命令，只有前 2 个开始执行，之后的命令等响应发送后才读取
+        for i in 0..4 {
+            let cmd = CommandRequest::new_hget("table", format!("key{i}"));
+            client.send(&cmd).await?;
+        }
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(RECEIVED.load(Ordering::SeqCst) - received, 2);
+
+        // 响应按发送的顺序返回
+        for i in 0..4 {
+            let res = client.next_response().await?;
+            assert_res_ok(&res, &[i.into()], &[]);
+        }
+        assert_eq!(RECEIVED.load(Ordering::SeqCst) - received, 4);
 
         Ok(())
     }
 
+    #[tokio::test]
+    async fn queued_commands_should_be_executed_by_priority() -> Result<()> {
+        let (client, server) = tokio::io::duplex(4096);
+        let service: Service<SlowStore> = ServiceInner::new(SlowStore(MemTable::new())).into();
+        let server = ProstServerStream::new(server, service).with_priority_queue(4);
+        tokio::spawn(server.process());
+        let mut client = ProstClientStream::new(client);
+
+        for i in 0..4 {
+            let cmd = CommandRequest::new_hset("table", format!("key{i}"), i);
+            client.execute_unary(&cmd).await?;
+        }
+
+        // 第一个慢命令执行期间，之后的命令在队列中等待
+        let cmd = CommandRequest::new_hget("table", "key0").with_request_id(1);
+        client.send(&cmd).await?;
+        time::sleep(Duration::from_millis(50)).await;
+        for i in 1..4 {
+            let priority = if i == 3 { 5 } else { 0 };
+            let cmd = CommandRequest::new_hget("table", format!("key{i}"))
+                .with_request_id(i + 1)
+                .with_priority(priority);
+            client.send(&cmd).await?;
+        }
+
+        // 高优先级的命令排在之前到达的低优先级命令前面执行
+        for id in [1, 4, 2, 3] {
+            let res = client.next_response().await?;
+            assert_eq!(res.request_id, id);
+            assert_res_ok(&res, &[(id as i64 - 1).into()], &[]);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscriptions_should_be_limited_per_connection() -> Result<()> {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let quota = Arc::new(SubscriptionQuota::new(Some(2)));
+        // 同一个连接上的每个 stream 共享 quota
+        let open_stream = || {
+            let (client, server) = tokio::io::duplex(4096);
+            let server = ProstServerStream::new(server, service.clone())
+                .with_subscription_quota(quota.clone());
+            tokio::spawn(server.process());
+            ProstClientStream::new(client)
+        };
+
+        let mut subscriptions = vec![];
+        for _ in 0..2 {
+            let mut client = open_stream();
+            client.send(&CommandRequest::new_subscribe("lobby")).await?;
+            let res = client.next_response().await?;
+            let id: i64 = (&res).try_into()?;
+            subscriptions.push((client, id as u32));
+        }
+        assert_eq!(quota.active(), 2);
+
+        // 超过上限的订阅返回 429
+        let mut client = open_stream();
+        client.send(&CommandRequest::new_subscribe("lobby")).await?;
+        let res = client.next_response().await?;
+        assert_res_error(&res, 429, "at most 2 subscriptions");
+
+        // 取消一个订阅后可以再次订阅
+        let (mut subscription, id) = subscriptions.pop().unwrap();
+        let cmd = CommandRequest::new_unsubscribe("lobby", id);
+        client.execute_unary(&cmd).await?;
+        assert!(subscription.next_response().await?.is_stream_end());
+        assert_eq!(quota.active(), 1);
+
+        client.send(&CommandRequest::new_subscribe("lobby")).await?;
+        let res = client.next_response().await?;
+        assert_eq!(res.status, 200);
+        assert_eq!(quota.active(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn my_subscriptions_should_list_connection_subscriptions() -> Result<()> {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let quota = Arc::new(SubscriptionQuota::new(None));
+        let open_stream = |quota: Arc<SubscriptionQuota>| {
+            let (client, server) = tokio::io::duplex(4096);
+            let server =
+                ProstServerStream::new(server, service.clone()).with_subscription_quota(quota);
+            tokio::spawn(server.process());
+            ProstClientStream::new(client)
+        };
+
+        let mut subscriptions = vec![];
+        for topic in ["lobby", "news", "lobby"] {
+            let mut client = open_stream(quota.clone());
+            client.send(&CommandRequest::new_subscribe(topic)).await?;
+            let id: i64 = (&client.next_response().await?).try_into()?;
+            subscriptions.push((client, topic, id));
+        }
+        // 其他连接上的订阅不会被列出
+        let mut other = open_stream(Arc::new(SubscriptionQuota::new(None)));
+        other.send(&CommandRequest::new_subscribe("lobby")).await?;
+        other.next_response().await?;
+
+        let mut client = open_stream(quota.clone());
+        let cmd = CommandRequest::new_my_subscriptions();
+        let res = client.execute_unary(&cmd).await?;
+        let mut pairs: Vec<_> = subscriptions
+            .iter()
+            .map(|(_, topic, id)| Kvpair::new(*topic, *id))
+            .collect();
+        pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_res_ok(&res, &[], &pairs);
+
+        // 取消的订阅不再列出
+        let (mut subscription, topic, id) = subscriptions.remove(0);
+        let cmd = CommandRequest::new_unsubscribe(topic, id as u32);
+        client.execute_unary(&cmd).await?;
+        assert!(subscription.next_response().await?.is_stream_end());
+        let res = client
+            .execute_unary(&CommandRequest::new_my_subscriptions())
+            .await?;
+        assert_res_ok(&res, &[], &pairs[1..]);
+
+        // stream 断开后订阅也被释放
+        drop(subscriptions);
+        let mut res = CommandResponse::default();
+        for _ in 0..100 {
+            res = client
+                .execute_unary(&CommandRequest::new_my_subscriptions())
+                .await?;
+            if res.pairs.is_empty() {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_res_ok(&res, &[], &[]);
+
+        Ok(())
+    }
+
+    // get 和 set_batch 很慢的存储
+    struct SlowStore(MemTable);
+
+    impl Storage for SlowStore {
+        fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            std::thread::sleep(Duration::from_millis(200));
+            self.0.get(table, key)
+        }
+
+        fn set(
+            &self,
+            table: &str,
+            key: impl Into<String>,
+            value: impl Into<Value>,
+        ) -> Result<Option<Value>, KvError> {
+            self.0.set(table, key, value)
+        }
+
+        fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+            std::thread::sleep(Duration::from_millis(200));
+            self.0.set_batch(table, pairs)
+        }
+
+        fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+            self.0.set_batch_if_absent(table, pairs)
+        }
+
+        fn move_nx(
+            &self,
+            src_table: &str,
+            src_key: &str,
+            dst_table: &str,
+            dst_key: &str,
+        ) -> Result<bool, KvError> {
+            self.0.move_nx(src_table, src_key, dst_table, dst_key)
+        }
+
+        fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+            self.0.contains(table, key)
+        }
+
+        fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            self.0.del(table, key)
+        }
+
+        fn update(
+            &self,
+            table: &str,
+            key: &str,
+            f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+        ) -> Result<Value, KvError> {
+            self.0.update(table, key, f)
+        }
+
+        fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+            self.0.get_all(table)
+        }
+
+        fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+            self.0.get_iter(table)
+        }
+
+        fn len(&self, table: &str) -> Result<usize, KvError> {
+            self.0.len(table)
+        }
+
+        fn tables(&self) -> Result<Vec<String>, KvError> {
+            self.0.tables()
+        }
+
+        fn has_table(&self, table: &str) -> Result<bool, KvError> {
+            self.0.has_table(table)
+        }
+
+        fn create_table(&self, table: &str) -> Result<bool, KvError> {
+            self.0.create_table(table)
+        }
+
+        fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+            self.0.drop_table(table)
+        }
+    }
+
     async fn start_server() -> Result<SocketAddr> {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
@@ -29,8 +29,9 @@ mod tests {
     use anyhow::Result;
 
     use crate::{
-        start_quic_client_with_config, start_quic_server, ClientConfig, MemTable, ServerConfig,
-        ServerSecurityProtocol, QUIC_CLIENT_CONFIG, QUIC_SERVER_CONFIG,
+        start_quic_client_with_config, start_quic_server, ClientConfig, ClientSecurityProtocol,
+        CommandRequest, MemTable, ServerConfig, ServerSecurityProtocol, Service,
+        QUIC_CLIENT_CONFIG, QUIC_SERVER_CONFIG,
     };
 
     use super::*;
@@ -40,9 +41,14 @@ mod tests {
         let server_config: ServerConfig = toml::from_str(QUIC_SERVER_CONFIG).unwrap();
         tokio::spawn(async move {
             if let ServerSecurityProtocol::Tls(tls) = &server_config.security {
-                start_quic_server(&server_config.general.addr, MemTable::new(), tls)
-                    .await
-                    .unwrap()
+                start_quic_server(
+                    &server_config.general.addr,
+                    Service::new(MemTable::new()),
+                    tls,
+                    server_config.general.command_timeout,
+                )
+                .await
+                .unwrap()
             }
         });
 
@@ -53,4 +59,67 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn quic_with_client_cert_should_work() -> Result<()> {
+        let addr = "127.0.0.1:1975";
+        start_server(addr);
+
+        let mut client = start_client(addr, true).await?;
+        let mut stream = client.open_stream().await?;
+        let res = stream
+            .execute_unary(&CommandRequest::new_hset("t1", "k1", "v1"))
+            .await?;
+        assert_eq!(res.status, 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quic_with_client_has_no_cert_should_not_work() -> Result<()> {
+        let addr = "127.0.0.1:1976";
+        start_server(addr);
+
+        // 客户端可能在服务器验证证书之前完成握手，此时后续的请求会失败
+        let result = async {
+            let mut client = start_client(addr, false).await?;
+            let mut stream = client.open_stream().await?;
+            stream
+                .execute_unary(&CommandRequest::new_hget("t1", "k1"))
+                .await?;
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    // 启动要求客户端证书的 QUIC server
+    fn start_server(addr: &'static str) {
+        let server_config: ServerConfig = toml::from_str(QUIC_SERVER_CONFIG).unwrap();
+        tokio::spawn(async move {
+            if let ServerSecurityProtocol::Tls(tls) = &server_config.security {
+                start_quic_server(
+                    addr,
+                    Service::new(MemTable::new()),
+                    tls,
+                    server_config.general.command_timeout,
+                )
+                .await
+                .unwrap()
+            }
+        });
+    }
+
+    async fn start_client(addr: &str, client_cert: bool) -> Result<QuicConn> {
+        let mut client_config: ClientConfig = toml::from_str(QUIC_CLIENT_CONFIG).unwrap();
+        client_config.general.addr = addr.into();
+        if let ClientSecurityProtocol::Tls(tls) = &mut client_config.security {
+            if !client_cert {
+                tls.identity = None;
+            }
+        }
+        start_quic_client_with_config(&client_config).await
+    }
 }
@@ -1,20 +1,25 @@
-use std::{future, marker::PhantomData, sync::Arc};
+use std::{future, io, marker::PhantomData, sync::Arc};
 
 use futures::{stream, Future, TryStreamExt};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     sync::{mpsc, oneshot, Mutex},
+    task::JoinHandle,
 };
 use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
-use tracing::instrument;
+use tracing::{instrument, warn};
 use yamux::{Config, Connection, ConnectionError, Mode};
 
-use crate::{AppStream, KvError, ProstClientStream};
+use crate::{spawn_named, AppStream, KvError, ProstClientStream};
 
 // Yamux 控制结构
 pub struct YamuxConn<S> {
     // sender 目前仅用于发送创建新的子流
     sender: mpsc::Sender<oneshot::Sender<Compat<yamux::Stream>>>,
+    // 驱动 yamux connection 的任务
+    driver: JoinHandle<()>,
+    // 驱动任务退出的原因
+    closed: ClosedReason,
     _s: PhantomData<S>,
 }
 
@@ -60,7 +65,9 @@ where
 
         let (tx, mut rx) = mpsc::channel::<oneshot::Sender<Compat<yamux::Stream>>>(32);
         let conn_cloned = conn.clone();
-        tokio::spawn(async move {
+        let closed: ClosedReason = Default::default();
+        let closed_cloned = closed.clone();
+        let driver = spawn_named("yamux driver", async move {
             loop {
                 // 在 tokio::select! 中，每个分支的 Future 都会被逐一 poll，因此即使 poll_next_inbound 分支正在运行，只要 rx.recv() 分支准备好，
                 // 它就会被选中执行，获取锁并创建新子流。 因为 tokio::select! 会取消未选中的分支的 Future，并在下一次轮询中重新 poll 它们，
@@ -68,31 +75,50 @@ where
                 tokio::select! {
                     Some(sender) = rx.recv() => {
                         let mut conn = conn_cloned.lock().await;
-                        // TODO(Wiccy): if ask for creating new substream before connection is fully initialzied，panic
-                        // time::sleep(Duration::from_millis(100)).await;
-                        let stream = future::poll_fn(|cx| conn.poll_new_outbound(cx)).await.expect("connection is probably not initialized yet");
-                        let _ = sender.send(stream.compat());
+                        match future::poll_fn(|cx| conn.poll_new_outbound(cx)).await {
+                            Ok(stream) => {
+                                let _ = sender.send(stream.compat());
+                            }
+                            // 先记录原因再 drop sender，open_stream 才能读到
+                            Err(e) => {
+                                closed_cloned.set(e);
+                                break;
+                            }
+                        }
                     }
-                    _ = async { // 一直执行，要么在处理子流数据，要么在等待子流数据到来，除非 poll_next_inbound() 返回 None
+                    res = async { // 一直执行，要么在处理子流数据，要么在等待子流数据到来，除非 poll_next_inbound() 返回 None
                         let mut conn = conn_cloned.lock().await;
                         // 每个 Future 执行完都会释放锁， 所以在任意小 Future 挂起时或大 Future 取消时释放锁
                         // 在单调度器中，因为不会被 rx.recv() 分支抢占，所以永远不会挂起或取消
                         stream::poll_fn(|cx| conn.poll_next_inbound(cx))
-                            .try_for_each_concurrent(None, |stream| {
-                                let f = f(stream);
-                                f
-                            })
+                            .try_for_each_concurrent(None, |stream| f(stream))
                             .await
-                    } => {}
+                    } => {
+                        // 连接已经关闭，对端正常关闭时没有错误
+                        closed_cloned.set(res.err().unwrap_or(ConnectionError::Closed));
+                        break;
+                    }
                 }
             }
         });
 
         Self {
             sender: tx,
+            driver,
+            closed,
             _s: Default::default(),
         }
     }
+
+    /// 关闭 yamux 连接，所有子流都会随之关闭
+    pub fn close(&self) {
+        self.driver.abort();
+    }
+
+    /// 等待 yamux 连接关闭
+    pub async fn closed(&mut self) {
+        let _ = (&mut self.driver).await;
+    }
 }
 
 impl<S> AppStream for YamuxConn<S> {
@@ -103,9 +129,38 @@ impl<S> AppStream for YamuxConn<S> {
     async fn open_stream(&mut self) -> Result<ProstClientStream<Self::InnerStream>, KvError> {
         let (tx, rx) = oneshot::channel();
         let _ = self.sender.send(tx).await;
-        Ok(ProstClientStream::new(
-            rx.await.map_err(|_| ConnectionError::Closed)?,
-        ))
+        match rx.await {
+            Ok(stream) => Ok(ProstClientStream::new(stream)),
+            // 驱动任务已经退出，返回连接关闭的原因
+            Err(_) => Err(self.closed.get().into()),
+        }
+    }
+}
+
+// 记录 yamux 连接关闭的原因，连接断开后每次 open_stream 都返回这个错误
+#[derive(Clone, Default)]
+struct ClosedReason(Arc<std::sync::Mutex<Option<ConnectionError>>>);
+
+impl ClosedReason {
+    fn set(&self, e: ConnectionError) {
+        warn!("Yamux connection closed: {e}");
+        *self.0.lock().unwrap() = Some(e);
+    }
+
+    // ConnectionError 没有实现 Clone，按照同样的类型和描述复制一份。
+    // 没有记录原因时（如调用了 close）返回 Closed
+    fn get(&self) -> ConnectionError {
+        match self.0.lock().unwrap().as_ref() {
+            Some(ConnectionError::Io(e)) => {
+                ConnectionError::Io(io::Error::new(e.kind(), e.to_string()))
+            }
+            Some(ConnectionError::NoMoreStreamIds) => ConnectionError::NoMoreStreamIds,
+            Some(ConnectionError::TooManyStreams) => ConnectionError::TooManyStreams,
+            Some(ConnectionError::Closed) | None => ConnectionError::Closed,
+            Some(e) => {
+                ConnectionError::Io(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+            }
+        }
     }
 }
 #[cfg(test)]
@@ -113,12 +168,18 @@ mod tests {
     use crate::{
         assert_res_ok,
         tls_utils::{tls_acceptor, tls_connector},
-        CommandRequest, KvError, MemTable, ProstServerStream, SecureStreamAccept,
-        SecureStreamConnect, Service, ServiceInner, Storage, TlsServerAcceptor,
+        wait_idle, CommandRequest, ConnSettings, ConnectionInfo, KvError, MemTable,
+        ProstServerStream, SecureStreamAccept, SecureStreamConnect, Service, ServiceInner, Storage,
+        TlsServerAcceptor, TLS_CLIENT_CERT,
     };
     use anyhow::Result;
-    use std::net::SocketAddr;
-    use tokio::net::{TcpListener, TcpStream};
+    use std::{net::SocketAddr, time::Duration};
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+        sync::Notify,
+        time,
+    };
     use tokio_rustls::server;
     use tracing::warn;
 
@@ -149,6 +210,166 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn yamux_stream_should_read_responses_after_finish_sending() -> Result<()> {
+        let acceptor = tls_acceptor(false)?;
+        let addr = start_yamux_server("127.0.0.1:0", acceptor, MemTable::new()).await?;
+
+        let connector = tls_connector(false)?;
+        let stream = TcpStream::connect(addr).await?;
+        let stream = connector.connect(stream).await?;
+        let mut client = YamuxConn::new_client(stream, None);
+
+        // 发送 SUBSCRIBE 后关闭写方向，仍然可以收到推送的数据
+        let mut sub = client.open_stream().await?;
+        sub.send(&CommandRequest::new_subscribe("lobby")).await?;
+        sub.finish_sending().await?;
+        let res = sub.next_response().await?;
+        assert_eq!(res.status, 200);
+
+        let mut publisher = client.open_stream().await?;
+        let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
+        publisher.execute_unary(&cmd).await?;
+
+        let res = sub.next_response().await?;
+        assert_res_ok(&res, &["hello".into()], &[]);
+
+        // 写方向已经关闭，不能再发送命令
+        let cmd = CommandRequest::new_hget("table", "key");
+        assert!(sub.send(&cmd).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn yamux_server_should_close_idle_connection() -> Result<()> {
+        let acceptor = tls_acceptor(false)?;
+        let f = |stream, service: Service| {
+            let activity = Arc::new(Notify::new());
+            let activity_cloned = activity.clone();
+            let conn = YamuxConn::new_server(stream, None, move |s| {
+                let svc = service.clone();
+                let activity = activity_cloned.clone();
+                async move {
+                    let stream = ProstServerStream::new(s.compat(), svc).with_activity(activity);
+                    stream.process().await.unwrap();
+                    Ok(())
+                }
+            });
+            tokio::spawn(async move {
+                wait_idle(&activity, Duration::from_millis(500)).await;
+                conn.close();
+            });
+        };
+        let addr = start_server_with("127.0.0.1:0", acceptor, MemTable::new(), f).await?;
+
+        let connector = tls_connector(false)?;
+        let stream = TcpStream::connect(addr).await?;
+        let stream = connector.connect(stream).await?;
+        let mut client = YamuxConn::new_client(stream, None);
+        let mut stream = client.open_stream().await?;
+
+        let cmd = CommandRequest::new_hset("table", "key", "value");
+        stream.execute_unary(&cmd).await?;
+
+        // 持续有命令的连接不会被关闭
+        let cmd = CommandRequest::new_hget("table", "key");
+        for _ in 0..3 {
+            time::sleep(Duration::from_millis(200)).await;
+            let res = stream.execute_unary(&cmd).await?;
+            assert_res_ok(&res, &["value".into()], &[]);
+        }
+
+        // 空闲超时后连接被关闭
+        time::sleep(Duration::from_millis(1000)).await;
+        assert!(stream.execute_unary(&cmd).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn yamux_server_should_notify_connect_and_disconnect() -> Result<()> {
+        static CONNECTED: std::sync::Mutex<Vec<ConnectionInfo>> = std::sync::Mutex::new(Vec::new());
+        static DISCONNECTED: std::sync::Mutex<Vec<ConnectionInfo>> =
+            std::sync::Mutex::new(Vec::new());
+
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_connect(|info| CONNECTED.lock().unwrap().push(info.clone()))
+            .fn_disconnect(|info| DISCONNECTED.lock().unwrap().push(info.clone()))
+            .into();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (stream, remote_addr) = listener.accept().await.unwrap();
+            let acceptor = tls_acceptor(true).unwrap();
+            crate::serve_yamux_conn(
+                stream,
+                remote_addr,
+                Some(remote_addr),
+                acceptor,
+                service,
+                ConnSettings::default(),
+            )
+            .await;
+        });
+
+        let connector = tls_connector(true)?;
+        let stream = TcpStream::connect(addr).await?;
+        let local_addr = stream.local_addr()?;
+        let mut client = YamuxConn::new_client(connector.connect(stream).await?, None);
+        let mut stream = client.open_stream().await?;
+        let cmd = CommandRequest::new_hset("table", "key", "value");
+        stream.execute_unary(&cmd).await?;
+
+        // 连接建立后触发 on_connect，带上客户端地址和客户端证书
+        let cert = rustls_pemfile::certs(&mut TLS_CLIENT_CERT.as_bytes())
+            .next()
+            .unwrap()?;
+        let expected = ConnectionInfo {
+            remote_addr: Some(local_addr),
+            peer_identity: Some(cert.to_vec()),
+        };
+        assert_eq!(*CONNECTED.lock().unwrap(), [expected.clone()]);
+        assert!(DISCONNECTED.lock().unwrap().is_empty());
+
+        // 客户端关闭连接后触发 on_disconnect
+        client.close();
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*DISCONNECTED.lock().unwrap(), [expected]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn open_stream_should_return_why_connection_died() -> Result<()> {
+        let (stream, mut peer) = tokio::io::duplex(4096);
+        let mut client = YamuxConn::new_client(stream, None);
+
+        // 对端发送的不是 yamux frame，连接因为解码失败而断开
+        peer.write_all(&[0xff; 12]).await?;
+        client.closed().await;
+
+        for _ in 0..2 {
+            let Err(e) = client.open_stream().await else {
+                panic!("open_stream should fail after the connection died");
+            };
+            assert!(matches!(e, KvError::YamuxConnectionError(_)));
+            assert!(e.to_string().contains("decode error"), "{e}");
+        }
+
+        // 对端正常关闭时返回 Closed
+        let (stream, peer) = tokio::io::duplex(4096);
+        let mut client = YamuxConn::new_client(stream, None);
+        drop(peer);
+        client.closed().await;
+        let Err(e) = client.open_stream().await else {
+            panic!("open_stream should fail after the connection closed");
+        };
+        assert!(e.to_string().contains("connection is closed"), "{e}");
+
+        Ok(())
+    }
+
     pub async fn start_server_with<Store>(
         addr: &str,
         tls: TlsServerAcceptor,
@@ -0,0 +1,104 @@
+use crate::BufferPoolConfig;
+
+/// 复用 frame 编解码时的临时 buffer（压缩前 encode 的 payload、解压后的 payload），
+/// 减少每个 frame 的内存分配。每个 stream 有自己的 pool，不需要加锁
+///
+/// 取出的 buffer 总是空的，归还时会清空，所以之前 frame 的数据不会出现在之后的 frame 中
+#[derive(Debug, Default)]
+pub struct BufferPool {
+    buffers: Vec<Vec<u8>>,
+    config: BufferPoolConfig,
+    // 因为池中没有可用的 buffer 而新分配的次数
+    allocations: usize,
+}
+
+impl BufferPool {
+    pub fn new(config: BufferPoolConfig) -> Self {
+        Self {
+            buffers: Vec::with_capacity(config.max_buffers),
+            config,
+            allocations: 0,
+        }
+    }
+
+    /// 不缓存任何 buffer 的 pool，每次都重新分配
+    pub fn disabled() -> Self {
+        Self::new(BufferPoolConfig {
+            max_buffers: 0,
+            max_buffer_size: 0,
+        })
+    }
+
+    /// 取出一个空的 buffer，容量至少是 capacity
+    pub fn take(&mut self, capacity: usize) -> Vec<u8> {
+        match self.buffers.pop() {
+            Some(mut buf) => {
+                buf.reserve(capacity);
+                buf
+            }
+            None => {
+                self.allocations += 1;
+                Vec::with_capacity(capacity)
+            }
+        }
+    }
+
+    /// 归还 buffer。池已满，或者 buffer 的容量超过 max_buffer_size 时直接释放，
+    /// 避免偶尔的一个大 frame 之后一直占用内存
+    pub fn put(&mut self, mut buf: Vec<u8>) {
+        if self.buffers.len() < self.config.max_buffers
+            && buf.capacity() <= self.config.max_buffer_size
+        {
+            buf.clear();
+            self.buffers.push(buf);
+        }
+    }
+
+    /// 新分配 buffer 的次数，复用的 buffer 不计入
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_pool_should_reuse_cleared_buffers() {
+        let mut pool = BufferPool::new(BufferPoolConfig {
+            max_buffers: 1,
+            max_buffer_size: 1024,
+        });
+        let mut buf = pool.take(16);
+        buf.extend_from_slice(b"secret");
+        let ptr = buf.as_ptr();
+        pool.put(buf);
+
+        // 复用同一块内存，但里面的数据已经被清空
+        let buf = pool.take(16);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert!(buf.is_empty());
+        assert_eq!(pool.allocations(), 1);
+
+        // 池满了之后归还的 buffer 被释放
+        let other = pool.take(16);
+        pool.put(buf);
+        pool.put(other);
+        assert_eq!(pool.buffers.len(), 1);
+
+        // 太大的 buffer 不放回池中
+        let mut pool = BufferPool::new(BufferPoolConfig {
+            max_buffers: 1,
+            max_buffer_size: 1024,
+        });
+        pool.put(Vec::with_capacity(4096));
+        assert!(pool.buffers.is_empty());
+
+        let mut pool = BufferPool::disabled();
+        let buf = pool.take(16);
+        pool.put(buf);
+        pool.take(16);
+        assert_eq!(pool.allocations(), 2);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use crate::CommandRequest;
+
+/// 每有这么多个后到的命令排在前面，等待中的命令就提升一级优先级，低优先级的命令不会一直得不到执行
+const PRIORITY_AGING: u64 = 8;
+
+/// 等待执行的命令，优先级高的先出队，优先级相同时按到达的顺序出队。
+///
+/// 第 seq 个到达、优先级为 p 的命令的有效优先级是 p * PRIORITY_AGING - seq：所有命令以同样的速度
+/// "变老"，所以只需要在入队时计算一次。优先级为 p 的命令最多被 (p' - p) * PRIORITY_AGING 个
+/// 后到的、优先级为 p' 的命令超过
+#[derive(Default)]
+pub(crate) struct PriorityQueue {
+    heap: BinaryHeap<Entry>,
+    next_seq: u64,
+}
+
+struct Entry {
+    key: i128,
+    seq: u64,
+    cmd: CommandRequest,
+}
+
+impl PriorityQueue {
+    pub fn push(&mut self, cmd: CommandRequest) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let key = cmd.priority as i128 * PRIORITY_AGING as i128 - seq as i128;
+        self.heap.push(Entry { key, seq, cmd });
+    }
+
+    pub fn pop(&mut self) -> Option<CommandRequest> {
+        self.heap.pop().map(|entry| entry.cmd)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // key 相同时先到的命令优先
+        self.key
+            .cmp(&other.key)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.seq == other.seq
+    }
+}
+
+impl Eq for Entry {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(id: u64, priority: u32) -> CommandRequest {
+        CommandRequest::new_hget("t1", "k1")
+            .with_request_id(id)
+            .with_priority(priority)
+    }
+
+    fn drain(queue: &mut PriorityQueue) -> Vec<u64> {
+        std::iter::from_fn(|| queue.pop().map(|c| c.request_id)).collect()
+    }
+
+    #[test]
+    fn priority_queue_should_pop_higher_priority_first() {
+        let mut queue = PriorityQueue::default();
+        queue.push(cmd(1, 0));
+        queue.push(cmd(2, 0));
+        queue.push(cmd(3, 2));
+        queue.push(cmd(4, 1));
+        queue.push(cmd(5, 0));
+        assert_eq!(queue.len(), 5);
+        // 优先级相同的命令保持到达的顺序
+        assert_eq!(drain(&mut queue), vec![3, 4, 1, 2, 5]);
+    }
+
+    #[test]
+    fn low_priority_command_should_not_starve() {
+        let mut queue = PriorityQueue::default();
+        queue.push(cmd(0, 0));
+        for id in 1..=20 {
+            queue.push(cmd(id, 1));
+        }
+        // 低优先级的命令最多被 PRIORITY_AGING 个后到的高优先级命令超过
+        let order = drain(&mut queue);
+        let pos = order.iter().position(|id| *id == 0).unwrap();
+        assert_eq!(pos, PRIORITY_AGING as usize - 1);
+        assert!(order[..pos].iter().all(|id| *id < PRIORITY_AGING));
+    }
+}
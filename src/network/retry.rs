@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time,
+};
+use tracing::warn;
+
+use crate::{AppStream, CommandRequest, CommandResponse, KvError, ProstClientStream};
+
+/// 客户端重试策略
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// 最多重试的次数
+    pub max_retries: usize,
+    /// 第一次重试前等待的时间，之后每次翻倍
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize, backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            backoff,
+        }
+    }
+
+    // 第 attempt 次重试前需要等待的时间
+    fn delay(&self, attempt: usize) -> Duration {
+        self.backoff * 2u32.saturating_pow(attempt as u32)
+    }
+}
+
+/// 带自动重试的客户端，出现连接错误时重新打开 stream 并重试幂等的命令；
+/// 服务器返回可以重试的错误（见 CommandResponse::is_retryable）时在同一个 stream 上重试幂等的命令
+pub struct RetryClient<C: AppStream> {
+    conn: C,
+    stream: Option<ProstClientStream<C::InnerStream>>,
+    policy: RetryPolicy,
+}
+
+impl<C> RetryClient<C>
+where
+    C: AppStream,
+    C::InnerStream: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    pub fn new(conn: C, policy: RetryPolicy) -> Self {
+        Self {
+            conn,
+            stream: None,
+            policy,
+        }
+    }
+
+    /// 执行命令，只有幂等的命令在遇到连接错误时才会重试，避免写操作被重复执行
+    pub async fn execute_unary(
+        &mut self,
+        cmd: &CommandRequest,
+    ) -> Result<CommandResponse, KvError> {
+        let mut attempt = 0;
+        loop {
+            let res = match self.stream.as_mut() {
+                Some(stream) => stream.execute_unary(cmd).await,
+                None => match self.conn.open_stream().await {
+                    Ok(stream) => self.stream.insert(stream).execute_unary(cmd).await,
+                    Err(e) => Err(e),
+                },
+            };
+
+            match res {
+                Err(e) if e.is_transient() => {
+                    // 出错的 stream 不再可用，下次重新打开
+                    self.stream = None;
+                    if !cmd.is_idempotent() || attempt >= self.policy.max_retries {
+                        return Err(e);
+                    }
+                    warn!("Failed to execute {cmd:?}: {e:?}, retrying");
+                    time::sleep(self.policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                Ok(res)
+                    if res.is_retryable()
+                        && cmd.is_idempotent()
+                        && attempt < self.policy.max_retries =>
+                {
+                    warn!("Failed to execute {cmd:?}: {}, retrying", res.message);
+                    time::sleep(self.policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::*;
+    use crate::{
+        assert_res_error, assert_res_ok, MemTable, ProstServerStream, Service, ServiceInner,
+    };
+
+    // 前 failures 次打开的 stream 都是断开的，之后的 stream 连接到真正的 service
+    struct FlakyConn {
+        service: Service,
+        failures: usize,
+        opened: Arc<AtomicUsize>,
+    }
+
+    impl AppStream for FlakyConn {
+        type InnerStream = DuplexStream;
+
+        async fn open_stream(&mut self) -> Result<ProstClientStream<DuplexStream>, KvError> {
+            let opened = self.opened.fetch_add(1, Ordering::SeqCst);
+            let (client, server) = duplex(4096);
+            if opened < self.failures {
+                drop(server);
+            } else {
+                let stream = ProstServerStream::new(server, self.service.clone());
+                tokio::spawn(stream.process());
+            }
+            Ok(ProstClientStream::new(client))
+        }
+    }
+
+    fn flaky_client(failures: usize) -> (RetryClient<FlakyConn>, Arc<AtomicUsize>) {
+        flaky_client_with_service(failures, Service::new(MemTable::new()))
+    }
+
+    fn flaky_client_with_service(
+        failures: usize,
+        service: Service,
+    ) -> (RetryClient<FlakyConn>, Arc<AtomicUsize>) {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let conn = FlakyConn {
+            service,
+            failures,
+            opened: opened.clone(),
+        };
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+        (RetryClient::new(conn, policy), opened)
+    }
+
+    #[tokio::test]
+    async fn idempotent_command_should_be_retried() {
+        let (mut client, opened) = flaky_client(2);
+
+        let cmd = CommandRequest::new_hexist("table", "key");
+        let res = client.execute_unary(&cmd).await.unwrap();
+        assert_res_ok(&res, &[false.into()], &[]);
+        assert_eq!(opened.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn non_idempotent_command_should_not_be_retried() {
+        let (mut client, opened) = flaky_client(1);
+
+        let cmd = CommandRequest::new_hset("table", "key", "value");
+        assert!(client.execute_unary(&cmd).await.is_err());
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+
+        // 出错后会重新打开 stream，之后的命令可以正常执行
+        let res = client.execute_unary(&cmd).await.unwrap();
+        assert_res_ok(&res, &[Default::default()], &[]);
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retry_should_stop_after_max_retries() {
+        let (mut client, opened) = flaky_client(10);
+
+        let cmd = CommandRequest::new_hget("table", "key");
+        assert!(client.execute_unary(&cmd).await.is_err());
+        assert_eq!(opened.load(Ordering::SeqCst), 4);
+    }
+
+    // 前两个响应变成 503
+    static UNAVAILABLE: AtomicUsize = AtomicUsize::new(0);
+
+    fn unavailable_twice(res: &mut CommandResponse) {
+        if UNAVAILABLE.fetch_add(1, Ordering::SeqCst) < 2 {
+            *res = KvError::ServiceUnavailable("sledb".into()).into();
+        }
+    }
+
+    #[tokio::test]
+    async fn retryable_response_should_be_retried_on_same_stream() {
+        let service = ServiceInner::new(MemTable::new())
+            .fn_before_send(unavailable_twice)
+            .into();
+        let (mut client, opened) = flaky_client_with_service(0, service);
+
+        // 503 重试两次后得到 404，404 不可重试，直接返回
+        let cmd = CommandRequest::new_hget("table", "key");
+        let res = client.execute_unary(&cmd).await.unwrap();
+        assert_res_error(&res, 404, "Not found");
+        assert_eq!(UNAVAILABLE.load(Ordering::SeqCst), 3);
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,6 +1,8 @@
 mod noise;
+mod quic;
 mod tls;
 pub use noise::*;
+pub use quic::*;
 pub use tls::*;
 
 use std::future::Future;
@@ -17,3 +19,8 @@ pub trait SecureStreamAccept<S: AsyncRead + AsyncWrite + Send + Unpin> {
     type InnerStream: AsyncRead + AsyncWrite + Send + Unpin;
     fn accept(&self, stream: S) -> impl Future<Output = Result<Self::InnerStream, KvError>> + Send;
 }
+
+/// 握手后对端的身份，没有验证对端身份时返回 None
+pub trait PeerIdentity {
+    fn peer_identity(&self) -> Option<Vec<u8>>;
+}
@@ -1,37 +1,230 @@
 use futures::ready;
-use snow::{Builder, TransportState};
+use snow::{params::NoiseParams, Builder, HandshakeState, TransportState};
 use std::{io::ErrorKind, pin::Pin, task::Poll};
 use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 
-use crate::{KvError, SecureStreamAccept, SecureStreamConnect};
+use crate::{KvError, NoiseConfig, PeerIdentity, SecureStreamAccept, SecureStreamConnect};
 
-// TODO(Wiccy): Support multi pattern
-static PATTERN: &'static str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+/// 默认的 pattern，双方都没有静态密钥
+pub const DEFAULT_NOISE_PATTERN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+// noise 协议规定的单个消息的最大长度
+const MAX_MESSAGE_LEN: usize = 65535;
+// 握手前发送的 pattern 名字的最大长度
+const MAX_PATTERN_LEN: usize = 255;
 
+/// noise 握手的配置，connect 和 accept 两端的 pattern 必须完全一致
+///
+/// 需要静态密钥的 pattern（比如 XX）要用 with_static_key 设置本地的私钥，
+/// 设置了 with_trusted_keys 时，对端的静态公钥不在其中则握手失败。
+/// IK 等发起方预先知道响应方公钥的 pattern，客户端要用 with_remote_key 设置服务端的公钥
 #[derive(Clone)]
-pub struct NoiseBuilder;
+pub struct NoiseBuilder {
+    pattern: String,
+    static_key: Option<Vec<u8>>,
+    trusted_keys: Option<Vec<Vec<u8>>>,
+    remote_key: Option<Vec<u8>>,
+}
 
-// 提供 connect 方法将底层协议转换成 noise，目前仅支持 Noise 下的 NN 方式
+// 提供 connect 方法将底层协议转换成 noise
 pub struct NoiseInitiator<S> {
     stream: S,
     initiator: TransportState,
     read_buf: Vec<u8>,
     write_buf: Vec<u8>,
 }
-// 提供 accept 方法将底层协议转换成 noise，目前仅支持 Noise 下的 NN 方式
+// 提供 accept 方法将底层协议转换成 noise
 pub struct NoiseResponder<S> {
     stream: S,
     responder: TransportState,
     read_buf: Vec<u8>,
+    // 加密后还没有写完的数据，written 是已经写入 stream 的字节数
     write_buf: Vec<u8>,
+    written: usize,
+}
+
+impl Default for NoiseBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NoiseBuilder {
     pub fn new() -> Self {
-        Self
+        Self {
+            pattern: DEFAULT_NOISE_PATTERN.into(),
+            static_key: None,
+            trusted_keys: None,
+            remote_key: None,
+        }
+    }
+
+    /// 根据配置创建，配置中的密钥是 hex 编码的
+    pub fn from_config(config: &NoiseConfig) -> Result<Self, KvError> {
+        let mut builder = Self::new();
+        if let Some(pattern) = &config.pattern {
+            builder = builder.with_pattern(pattern)?;
+        }
+        if let Some(key) = &config.static_key {
+            builder = builder.with_static_key(decode_key(key)?);
+        }
+        if !config.trusted_keys.is_empty() {
+            let keys: Result<Vec<_>, _> =
+                config.trusted_keys.iter().map(|k| decode_key(k)).collect();
+            builder = builder.with_trusted_keys(keys?);
+        }
+        if let Some(key) = &config.remote_key {
+            builder = builder.with_remote_key(decode_key(key)?);
+        }
+        Ok(builder)
+    }
+
+    /// 使用其他的 pattern，比如 Noise_XX_25519_ChaChaPoly_BLAKE2s
+    pub fn with_pattern(mut self, pattern: &str) -> Result<Self, KvError> {
+        let _: NoiseParams = pattern.parse()?;
+        self.pattern = pattern.into();
+        Ok(self)
+    }
+
+    /// 设置本地的静态私钥
+    pub fn with_static_key(mut self, private_key: impl Into<Vec<u8>>) -> Self {
+        self.static_key = Some(private_key.into());
+        self
+    }
+
+    /// 只信任这些对端的静态公钥，对端没有静态密钥时同样握手失败
+    pub fn with_trusted_keys(mut self, keys: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        self.trusted_keys = Some(keys.into_iter().collect());
+        self
+    }
+
+    /// 设置对端（服务端）的静态公钥，IK 等 pattern 在握手前就需要。
+    /// 握手完成时对端的静态公钥必须与之一致
+    pub fn with_remote_key(mut self, public_key: impl Into<Vec<u8>>) -> Self {
+        self.remote_key = Some(public_key.into());
+        self
+    }
+
+    /// 为当前的 pattern 生成一对静态密钥，返回 (私钥, 公钥)
+    pub fn generate_keypair(&self) -> Result<(Vec<u8>, Vec<u8>), KvError> {
+        let keypair = Builder::new(self.pattern.parse()?).generate_keypair()?;
+        Ok((keypair.private, keypair.public))
+    }
+
+    // pattern 同时作为 prologue，中间人篡改了握手前发送的 pattern 时握手无法完成
+    fn build(&self, initiator: bool) -> Result<HandshakeState, KvError> {
+        let mut builder = Builder::new(self.pattern.parse()?).prologue(self.pattern.as_bytes());
+        if let Some(key) = &self.static_key {
+            builder = builder.local_private_key(key);
+        }
+        if initiator && knows_responder_key(&self.pattern) {
+            let key = self.remote_key.as_ref().ok_or_else(|| {
+                KvError::NoiseError(format!(
+                    "pattern {} requires the remote static key",
+                    self.pattern
+                ))
+            })?;
+            builder = builder.remote_public_key(key);
+        }
+        let state = if initiator {
+            builder.build_initiator()?
+        } else {
+            builder.build_responder()?
+        };
+        Ok(state)
+    }
+
+    // 交替读写握手消息直到握手完成，一旦得到对端的静态公钥就进行校验，
+    // 不信任时立即中止，不再发送后续的消息
+    async fn handshake<S>(
+        &self,
+        mut state: HandshakeState,
+        stream: &mut S,
+    ) -> Result<TransportState, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut msg = vec![0u8; MAX_MESSAGE_LEN];
+        let mut payload = vec![0u8; MAX_MESSAGE_LEN];
+        while !state.is_handshake_finished() {
+            if state.is_my_turn() {
+                let len = state.write_message(&[], &mut msg)?;
+                write_message(stream, &msg[..len]).await?;
+            } else {
+                let len = read_message(stream, &mut msg).await?;
+                state.read_message(&msg[..len], &mut payload)?;
+                if let Some(key) = state.get_remote_static() {
+                    self.verify_remote_key(Some(key))?;
+                }
+            }
+        }
+        self.verify_remote_key(state.get_remote_static())?;
+        Ok(state.into_transport_mode()?)
+    }
+
+    fn verify_remote_key(&self, key: Option<&[u8]>) -> Result<(), KvError> {
+        if let Some(expected) = &self.remote_key {
+            if key != Some(expected.as_slice()) {
+                return Err(KvError::NoiseError(
+                    "remote static key does not match the configured key".into(),
+                ));
+            }
+        }
+        let Some(trusted) = &self.trusted_keys else {
+            return Ok(());
+        };
+        match key {
+            Some(key) if trusted.iter().any(|k| k == key) => Ok(()),
+            Some(_) => Err(KvError::NoiseError(
+                "remote static key is not trusted".into(),
+            )),
+            None => Err(KvError::NoiseError(format!(
+                "pattern {} does not authenticate the remote peer",
+                self.pattern
+            ))),
+        }
     }
 }
 
+// pattern 名字的第二个字母是 K 时（如 IK、NK），发起方在握手前就知道响应方的静态公钥
+fn knows_responder_key(pattern: &str) -> bool {
+    pattern.split('_').nth(1).and_then(|p| p.chars().nth(1)) == Some('K')
+}
+
+// 配置中的密钥是 hex 编码的，出错时不输出密钥的内容
+fn decode_key(hex: &str) -> Result<Vec<u8>, KvError> {
+    let invalid = || KvError::NoiseError("invalid hex key".into());
+    if hex.len() % 2 != 0 {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+// 握手消息前加上 2 字节的长度，避免一次 read 读到多个消息或者半个消息，
+// 读取时超过 buf 大小的消息直接返回错误
+async fn write_message<S: AsyncWrite + Unpin>(stream: &mut S, msg: &[u8]) -> io::Result<()> {
+    stream.write_u16(msg.len() as u16).await?;
+    stream.write_all(msg).await
+}
+
+async fn read_message<S: AsyncRead + Unpin>(stream: &mut S, buf: &mut [u8]) -> io::Result<usize> {
+    let len = stream.read_u16().await? as usize;
+    if len > buf.len() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("handshake message of {len} bytes is too large"),
+        ));
+    }
+    stream.read_exact(&mut buf[..len]).await?;
+    Ok(len)
+}
+
 impl<S> SecureStreamConnect<S> for NoiseBuilder
 where
     S: AsyncRead + AsyncWrite + Send + Unpin,
@@ -39,19 +232,15 @@ where
     type InnerStream = NoiseInitiator<S>;
 
     async fn connect(&self, mut stream: S) -> Result<Self::InnerStream, KvError> {
-        let mut initiator = Builder::new(PATTERN.parse()?).build_initiator()?;
+        let initiator = self.build(true)?;
 
-        // Noise handshake
-        let mut first_msg = [0u8; 65535];
-        let len = initiator.write_message(&[], &mut first_msg)?;
-        stream.write_all(&first_msg[..len]).await?;
-        let len = stream.read(&mut first_msg).await?;
-        let mut read_buf = [0u8; 65535];
-        initiator.read_message(&first_msg[..len], &mut read_buf)?;
+        // 先告诉对端使用的 pattern，不一致时对端直接拒绝，避免被降级到更弱的 pattern
+        write_message(&mut stream, self.pattern.as_bytes()).await?;
+        let initiator = self.handshake(initiator, &mut stream).await?;
 
         Ok(NoiseInitiator {
             stream,
-            initiator: initiator.into_transport_mode()?,
+            initiator,
             read_buf: Vec::new(),
             write_buf: Vec::new(),
         })
@@ -65,25 +254,36 @@ where
     type InnerStream = NoiseResponder<S>;
 
     async fn accept(&self, mut stream: S) -> Result<Self::InnerStream, KvError> {
-        let mut responder = Builder::new(PATTERN.parse()?).build_responder()?;
-
-        // Noise handshake
-        let mut first_msg = [0u8; 65535];
-        let len = stream.read(&mut first_msg).await?;
-        let mut read_buf = [0u8; 65535];
-        responder.read_message(&first_msg[..len], &mut read_buf)?;
-        let len = responder.write_message(&[], &mut first_msg)?;
-        stream.write_all(&first_msg[..len]).await?;
+        let responder = self.build(false)?;
+
+        let mut pattern = [0u8; MAX_PATTERN_LEN];
+        let len = read_message(&mut stream, &mut pattern).await?;
+        if pattern[..len] != *self.pattern.as_bytes() {
+            return Err(KvError::NoiseError(format!(
+                "pattern mismatch, expect {}, got {}",
+                self.pattern,
+                String::from_utf8_lossy(&pattern[..len])
+            )));
+        }
+        let responder = self.handshake(responder, &mut stream).await?;
 
         Ok(NoiseResponder {
             stream,
-            responder: responder.into_transport_mode()?,
+            responder,
             read_buf: Vec::new(),
             write_buf: Vec::new(),
+            written: 0,
         })
     }
 }
 
+impl<S> PeerIdentity for NoiseResponder<S> {
+    // 对端的静态公钥，NN 等 pattern 下对端没有静态密钥，无法得知对端的身份
+    fn peer_identity(&self) -> Option<Vec<u8>> {
+        self.responder.get_remote_static().map(|k| k.to_vec())
+    }
+}
+
 impl<S: Unpin + AsyncRead> AsyncRead for NoiseInitiator<S> {
     fn poll_read(
         mut self: std::pin::Pin<&mut Self>,
@@ -101,7 +301,7 @@ impl<S: Unpin + AsyncRead> AsyncRead for NoiseInitiator<S> {
             let len = self
                 .initiator
                 .read_message(&temp_buf[..n], &mut decrypted_buf)
-                .map_err(|_| io::Error::new(ErrorKind::Other, "Decryption error"))?;
+                .map_err(|_| io::Error::other("Decryption error"))?;
 
             self.read_buf.extend_from_slice(&decrypted_buf[..len]);
         }
@@ -126,7 +326,7 @@ impl<S: Unpin + AsyncWrite> AsyncWrite for NoiseInitiator<S> {
             let len = this
                 .initiator
                 .write_message(buf, &mut this.write_buf)
-                .map_err(|_| io::Error::new(ErrorKind::Other, "Encryption error"))?;
+                .map_err(|_| io::Error::other("Encryption error"))?;
             this.write_buf.truncate(len);
         }
 
@@ -189,7 +389,7 @@ impl<S: Unpin + AsyncRead> AsyncRead for NoiseResponder<S> {
             let len = self
                 .responder
                 .read_message(&temp_buf[..n], &mut decrypted_buf)
-                .map_err(|_| io::Error::new(ErrorKind::Other, "Decryption error"))?;
+                .map_err(|_| io::Error::other("Decryption error"))?;
 
             self.read_buf.extend_from_slice(&decrypted_buf[..len]);
         }
@@ -211,27 +411,32 @@ impl<S: Unpin + AsyncWrite> AsyncWrite for NoiseResponder<S> {
     ) -> Poll<Result<usize, std::io::Error>> {
         let this = self.get_mut();
 
-        // 加密数据并填充到内部写入缓冲区。
-        this.write_buf.clear();
-        this.write_buf.resize(buf.len() + 16, 0); // 确保有足够的空间存放加密数据。
-        let len = this
-            .responder
-            .write_message(buf, &mut this.write_buf)
-            .map_err(|_| io::Error::new(ErrorKind::Other, "Encryption error"))?;
-
-        // 从内部写入缓冲区写入数据到stream。
-        let mut written = 0;
-        while written < len {
-            let n =
-                ready!(Pin::new(&mut this.stream).poll_write(cx, &this.write_buf[written..len]))?;
+        // 上次返回 Pending 时 write_buf 中还有没写完的密文，调用方会用同样的 buf 再次调用，
+        // 这时继续写 write_buf，不能重新加密，否则 nonce 会前进，对端无法解密
+        if this.write_buf.is_empty() {
+            this.write_buf.resize(buf.len() + 16, 0); // 确保有足够的空间存放加密数据。
+            let len = this
+                .responder
+                .write_message(buf, &mut this.write_buf)
+                .map_err(|_| io::Error::other("Encryption error"))?;
+            this.write_buf.truncate(len);
+            this.written = 0;
+        }
+
+        // 从内部写入缓冲区写入数据到stream，Pending 时保留写入的进度
+        while this.written < this.write_buf.len() {
+            let pending = &this.write_buf[this.written..];
+            let n = ready!(Pin::new(&mut this.stream).poll_write(cx, pending))?;
             if n == 0 {
                 return Poll::Ready(Err(io::Error::new(
                     ErrorKind::WriteZero,
                     "write zero bytes",
                 )));
             }
-            written += n;
+            this.written += n;
         }
+        this.write_buf.clear();
+        this.written = 0;
 
         Poll::Ready(Ok(buf.len()))
     }
@@ -256,7 +461,10 @@ mod tests {
     use std::net::SocketAddr;
 
     use anyhow::Result;
-    use tokio::net::{TcpListener, TcpStream};
+    use tokio::{
+        net::{TcpListener, TcpStream},
+        task::JoinHandle,
+    };
 
     use super::*;
 
@@ -274,19 +482,269 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn noise_xx_should_authenticate_trusted_keys() -> Result<()> {
+        let server = NoiseBuilder::new().with_pattern(XX)?;
+        let (server_private, server_public) = server.generate_keypair()?;
+        let (client_private, client_public) = server.generate_keypair()?;
+        let server = server
+            .with_static_key(server_private)
+            .with_trusted_keys([client_public.clone()]);
+        let (addr, accepted) = start_server_with(server).await?;
+
+        let client = NoiseBuilder::new()
+            .with_pattern(XX)?
+            .with_static_key(client_private)
+            .with_trusted_keys([server_public]);
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = client.connect(stream).await?;
+        stream.write_all(b"hello world!").await?;
+        let mut buf = [0; 12];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello world!");
+
+        // 服务端可以得到客户端的静态公钥
+        assert_eq!(accepted.await?.unwrap(), Some(client_public));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn noise_pattern_mismatch_should_fail() -> Result<()> {
+        let (addr, accepted) = start_server_with(NoiseBuilder::new()).await?;
+
+        let client = NoiseBuilder::new().with_pattern(XX)?;
+        let (private, _) = client.generate_keypair()?;
+        let client = client.with_static_key(private);
+        let stream = TcpStream::connect(addr).await?;
+        assert!(client.connect(stream).await.is_err());
+
+        let err = accepted.await?.unwrap_err();
+        assert!(matches!(err, KvError::NoiseError(msg) if msg.contains("mismatch")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn noise_untrusted_remote_key_should_fail() -> Result<()> {
+        let server = NoiseBuilder::new().with_pattern(XX)?;
+        let (server_private, _) = server.generate_keypair()?;
+        let (client_private, _) = server.generate_keypair()?;
+        let (_, other_public) = server.generate_keypair()?;
+
+        // 客户端不信任服务端的公钥，在收到服务端的静态公钥后中止握手
+        let server = server.with_static_key(server_private);
+        let (addr, accepted) = start_server_with(server.clone()).await?;
+        let client = NoiseBuilder::new()
+            .with_pattern(XX)?
+            .with_static_key(client_private.clone())
+            .with_trusted_keys([other_public.clone()]);
+        let stream = TcpStream::connect(addr).await?;
+        let err = client.connect(stream).await.err().unwrap();
+        assert!(matches!(err, KvError::NoiseError(msg) if msg.contains("not trusted")));
+        assert!(accepted.await?.is_err());
+
+        // 服务端不信任客户端的公钥
+        let server = server.with_trusted_keys([other_public]);
+        let (addr, accepted) = start_server_with(server).await?;
+        let client = NoiseBuilder::new()
+            .with_pattern(XX)?
+            .with_static_key(client_private);
+        let stream = TcpStream::connect(addr).await?;
+        let _ = client.connect(stream).await;
+        let err = accepted.await?.unwrap_err();
+        assert!(matches!(err, KvError::NoiseError(msg) if msg.contains("not trusted")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn noise_ik_should_authenticate_known_server() -> Result<()> {
+        let server = NoiseBuilder::new().with_pattern(IK)?;
+        let (server_private, server_public) = server.generate_keypair()?;
+        let (client_private, client_public) = server.generate_keypair()?;
+        let (addr, accepted) = start_server_with(server.with_static_key(server_private)).await?;
+
+        let client = NoiseBuilder::new()
+            .with_pattern(IK)?
+            .with_static_key(client_private)
+            .with_remote_key(server_public);
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = client.connect(stream).await?;
+        stream.write_all(b"hello world!").await?;
+        let mut buf = [0; 12];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello world!");
+
+        // IK 中客户端同样提供了静态公钥
+        assert_eq!(accepted.await?.unwrap(), Some(client_public));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn noise_wrong_server_key_should_fail() -> Result<()> {
+        let server = NoiseBuilder::new().with_pattern(IK)?;
+        let (server_private, _) = server.generate_keypair()?;
+        let (client_private, _) = server.generate_keypair()?;
+        let (_, other_public) = server.generate_keypair()?;
+        let server = server.with_static_key(server_private.clone());
+
+        // IK 的第一个消息用配置的公钥加密，服务端无法解密
+        let (addr, accepted) = start_server_with(server).await?;
+        let client = NoiseBuilder::new()
+            .with_pattern(IK)?
+            .with_static_key(client_private.clone())
+            .with_remote_key(other_public.clone());
+        let stream = TcpStream::connect(addr).await?;
+        assert!(client.connect(stream).await.is_err());
+        assert!(accepted.await?.is_err());
+
+        // XX 中客户端在握手过程中得到服务端的公钥，与配置的不一致时中止
+        let server = NoiseBuilder::new()
+            .with_pattern(XX)?
+            .with_static_key(server_private);
+        let (addr, _) = start_server_with(server).await?;
+        let client = NoiseBuilder::new()
+            .with_pattern(XX)?
+            .with_static_key(client_private)
+            .with_remote_key(other_public);
+        let stream = TcpStream::connect(addr).await?;
+        let err = client.connect(stream).await.err().unwrap();
+        assert!(matches!(err, KvError::NoiseError(msg) if msg.contains("does not match")));
+
+        // 没有配置服务端的公钥时无法使用 IK
+        let client = NoiseBuilder::new().with_pattern(IK)?;
+        assert!(client.build(true).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn noise_builder_should_load_hex_keys_from_config() {
+        let config = NoiseConfig {
+            pattern: Some(IK.into()),
+            static_key: Some("00ff".repeat(16)),
+            remote_key: Some("0A1b".repeat(16)),
+            ..Default::default()
+        };
+        let builder = NoiseBuilder::from_config(&config).unwrap();
+        assert_eq!(builder.static_key, Some([0x00, 0xff].repeat(16)));
+        assert_eq!(builder.remote_key, Some([0x0a, 0x1b].repeat(16)));
+
+        for key in ["abc", "zz", "é1"] {
+            let config = NoiseConfig {
+                static_key: Some(key.into()),
+                ..Default::default()
+            };
+            assert!(NoiseBuilder::from_config(&config).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn noise_responder_should_resume_partial_writes() -> Result<()> {
+        let (mut initiator, responder) = transport_pair()?;
+        let mut stream = NoiseResponder {
+            stream: TrickleWriter::default(),
+            responder,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            written: 0,
+        };
+
+        let payload: Vec<u8> = (0..100).collect();
+        stream.write_all(&payload).await?;
+
+        // 每次只写一个字节，对端仍然可以解密出完整的一条消息
+        let sent = &stream.stream.buf;
+        assert_eq!(sent.len(), payload.len() + 16);
+        let mut decrypted = vec![0u8; sent.len()];
+        let len = initiator.read_message(sent, &mut decrypted)?;
+        assert_eq!(&decrypted[..len], &payload[..]);
+
+        Ok(())
+    }
+
+    // 每次 poll_write 只接受一个字节，并且每隔一次返回 Pending
+    #[derive(Default)]
+    struct TrickleWriter {
+        buf: Vec<u8>,
+        ready: bool,
+    }
+
+    impl AsyncWrite for TrickleWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            self.buf.push(buf[0]);
+            Poll::Ready(Ok(1))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    // 在内存中完成 NN 握手，返回 (initiator, responder)
+    fn transport_pair() -> Result<(TransportState, TransportState)> {
+        let builder = NoiseBuilder::new();
+        let mut initiator = builder.build(true)?;
+        let mut responder = builder.build(false)?;
+        let mut msg = vec![0u8; MAX_MESSAGE_LEN];
+        let mut payload = vec![0u8; MAX_MESSAGE_LEN];
+        let len = initiator.write_message(&[], &mut msg)?;
+        responder.read_message(&msg[..len], &mut payload)?;
+        let len = responder.write_message(&[], &mut msg)?;
+        initiator.read_message(&msg[..len], &mut payload)?;
+        Ok((
+            initiator.into_transport_mode()?,
+            responder.into_transport_mode()?,
+        ))
+    }
+
+    const XX: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+    const IK: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+
     async fn start_server() -> Result<SocketAddr> {
+        let (addr, _) = start_server_with(NoiseBuilder::new()).await?;
+        Ok(addr)
+    }
+
+    // 用 builder 启动一个 echo 服务器，返回的 handle 得到握手的结果和对端的身份
+    async fn start_server_with(
+        builder: NoiseBuilder,
+    ) -> Result<(SocketAddr, JoinHandle<Result<Option<Vec<u8>>, KvError>>)> {
         let echo = TcpListener::bind("127.0.0.1:0").await?;
         let addr = echo.local_addr().unwrap();
 
-        tokio::spawn(async move {
+        let handle = tokio::spawn(async move {
             let (stream, _) = echo.accept().await.unwrap();
-            if let Ok(mut stream) = NoiseBuilder::new().accept(stream).await {
-                let mut buf = [0; 12];
-                stream.read_exact(&mut buf).await.unwrap();
-                stream.write_all(&buf).await.unwrap();
+            let mut stream = builder.accept(stream).await?;
+            let identity = stream.peer_identity();
+            let mut buf = [0; 12];
+            if stream.read_exact(&mut buf).await.is_ok() {
+                stream.write_all(&buf).await?;
             }
+            Ok(identity)
         });
 
-        Ok(addr)
+        Ok((addr, handle))
     }
 }
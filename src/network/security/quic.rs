@@ -0,0 +1,61 @@
+use s2n_quic::provider::tls::s2n_tls::{
+    callbacks::VerifyHostNameCallback, Client as QuicTlsClient, Server as QuicTlsServer,
+};
+use tracing::instrument;
+
+use crate::KvError;
+
+/// 生成 QUIC server 的 TLS 配置
+/// client_ca 不为空时要求客户端提供证书，并用 client_ca 验证
+#[instrument(name = "quic_server_tls", skip_all)]
+pub fn quic_server_tls(
+    cert: &str,
+    key: &str,
+    client_ca: Option<&str>,
+) -> Result<QuicTlsServer, KvError> {
+    let err = |_| KvError::CertifcateParseError("server", "cert");
+    let builder = QuicTlsServer::builder()
+        .with_certificate(cert, key)
+        .map_err(err)?;
+
+    let builder = match client_ca {
+        None => builder,
+        Some(ca) => builder
+            .with_trusted_certificate(ca)
+            .and_then(|b| b.with_client_authentication())
+            // 和 TlsServerAcceptor 一样，只验证证书链，不验证客户端证书中的主机名
+            .and_then(|b| b.with_verify_host_name_callback(AnyClientName))
+            .map_err(err)?,
+    };
+
+    builder.build().map_err(err)
+}
+
+/// 生成 QUIC client 的 TLS 配置
+/// identity 不为空时向服务器提供客户端证书
+#[instrument(name = "quic_client_tls", skip_all)]
+pub fn quic_client_tls(
+    identity: Option<(&str, &str)>,
+    server_ca: Option<&str>,
+) -> Result<QuicTlsClient, KvError> {
+    let err = |_| KvError::CertifcateParseError("client", "cert");
+    let mut builder = QuicTlsClient::builder();
+
+    // 没有 CA 证书时使用本地信任的根证书链
+    if let Some(ca) = server_ca {
+        builder = builder.with_certificate(ca).map_err(err)?;
+    }
+    if let Some((cert, key)) = identity {
+        builder = builder.with_client_identity(cert, key).map_err(err)?;
+    }
+
+    builder.build().map_err(err)
+}
+
+struct AnyClientName;
+
+impl VerifyHostNameCallback for AnyClientName {
+    fn verify_host_name(&self, _host_name: &str) -> bool {
+        true
+    }
+}
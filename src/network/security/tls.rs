@@ -1,16 +1,21 @@
-use crate::{KvError, SecureStreamAccept, SecureStreamConnect};
+use crate::{KvError, PeerIdentity, SecureStreamAccept, SecureStreamConnect, TlsPolicyConfig};
 use std::io::Cursor;
 use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::crypto::{aws_lc_rs, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName,
+};
 use tokio_rustls::rustls::server::WebPkiClientVerifier;
-use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::rustls::{
+    self, ClientConfig, RootCertStore, ServerConfig, SupportedProtocolVersion,
+};
 use tokio_rustls::{client::TlsStream as ClientTlsStream, server::TlsStream as ServerTlsStream};
 use tokio_rustls::{TlsAcceptor, TlsConnector};
 use tracing::instrument;
 
 /// KV Server 自己的 ALPN (Application-Layer Protocol Negotiation)
-const ALPN_KV: &str = "kv";
+pub const ALPN_KV: &str = "kv";
 
 /// 存放 TLS ServerConfig 并提供方法 accept 把底层的协议转换成 TLS
 #[derive(Clone)]
@@ -28,14 +33,36 @@ pub struct TlsClientConnector {
 impl TlsClientConnector {
     /// 加载 client cert / CA cert，生成 ClientConfig
     /// server_ca 选项应传递根证书
-    #[instrument(name = "tls_connector_new", skip_all)]
     pub fn new(
         domain: impl Into<String>,
         identity: Option<(&str, &str)>,
+        server_ca: Option<&str>,
+    ) -> Result<Self, KvError> {
+        Self::new_with_passphrase(domain, identity, None, server_ca)
+    }
+
+    /// 同 new，identity 中的 key 是加密的私钥时用 key_passphrase 解密
+    pub fn new_with_passphrase(
+        domain: impl Into<String>,
+        identity: Option<(&str, &str)>,
+        key_passphrase: Option<&str>,
+        server_ca: Option<&str>,
+    ) -> Result<Self, KvError> {
+        let policy = TlsPolicyConfig::default();
+        Self::new_with_policy(domain, identity, key_passphrase, server_ca, &policy)
+    }
+
+    /// 同 new_with_passphrase，只使用 policy 中允许的协议版本和加密套件
+    #[instrument(name = "tls_connector_new", skip_all)]
+    pub fn new_with_policy(
+        domain: impl Into<String>,
+        identity: Option<(&str, &str)>,
+        key_passphrase: Option<&str>,
         // 在 TLS（传输层安全性）协议中，server_ca 选项是用于指定服务器证书的信任链根证书（CA 证书），而不是服务器证书本身。
         // 这是因为客户端需要验证服务器提供的证书是否可信，而这种验证通常是通过一个或多个根证书（CA 证书）来完成的。
         // 传递根证书而不是服务器证书，目的是让客户端能够信任由该 CA 颁发的所有证书。
         server_ca: Option<&str>,
+        policy: &TlsPolicyConfig,
     ) -> Result<Self, KvError> {
         let mut root_cert_store = RootCertStore::empty();
 
@@ -52,20 +79,20 @@ impl TlsClientConnector {
             }
         }
 
+        let (provider, versions) = crypto_provider(policy)?;
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&versions)?
+            .with_root_certificates(root_cert_store);
         let config = match identity {
             Some((cert, key)) => {
                 let certs = load_certs(cert)?;
-                let key = load_key(key)?;
-                ClientConfig::builder()
-                    .with_root_certificates(root_cert_store)
-                    .with_client_auth_cert(
-                        certs.into_iter().map(|cert| cert.into_owned()).collect(),
-                        key.clone_key(),
-                    )?
+                let key = load_key(key, key_passphrase)?;
+                builder.with_client_auth_cert(
+                    certs.into_iter().map(|cert| cert.into_owned()).collect(),
+                    key.clone_key(),
+                )?
             }
-            None => ClientConfig::builder()
-                .with_root_certificates(root_cert_store)
-                .with_no_client_auth(),
+            None => builder.with_no_client_auth(),
         };
 
         Ok(Self {
@@ -97,16 +124,41 @@ where
 impl TlsServerAcceptor {
     /// 加载 server cert / CA cert，生成 ServerConfig
     /// client_ca 不为空时将验证客户端证书
-    #[instrument(name = "tls_acceptor_new", skip_all)]
     pub fn new(cert: &str, key: &str, client_ca: Option<&str>) -> Result<Self, KvError> {
+        Self::new_with_passphrase(cert, key, None, client_ca)
+    }
+
+    /// 同 new，key 是加密的私钥时用 key_passphrase 解密
+    pub fn new_with_passphrase(
+        cert: &str,
+        key: &str,
+        key_passphrase: Option<&str>,
+        client_ca: Option<&str>,
+    ) -> Result<Self, KvError> {
+        let policy = TlsPolicyConfig::default();
+        Self::new_with_policy(cert, key, key_passphrase, client_ca, &policy)
+    }
+
+    /// 同 new_with_passphrase，只使用 policy 中允许的协议版本和加密套件
+    #[instrument(name = "tls_acceptor_new", skip_all)]
+    pub fn new_with_policy(
+        cert: &str,
+        key: &str,
+        key_passphrase: Option<&str>,
+        client_ca: Option<&str>,
+        policy: &TlsPolicyConfig,
+    ) -> Result<Self, KvError> {
         let certs = load_certs(cert)?
             .into_iter()
             .map(|cert| cert.into_owned())
             .collect();
-        let key = load_key(key)?.clone_key();
+        let key = load_key(key, key_passphrase)?.clone_key();
 
+        let (provider, versions) = crypto_provider(policy)?;
+        let builder =
+            ServerConfig::builder_with_provider(provider).with_protocol_versions(&versions)?;
         let config = match client_ca {
-            None => ServerConfig::builder().with_no_client_auth(),
+            None => builder.with_no_client_auth(),
             Some(cert) => {
                 // 如果客户端证书是某个 CA 证书签发的，则把这个 CA 证书加载到信任链中
                 let mut client_root_cert_store = RootCertStore::empty();
@@ -116,7 +168,7 @@ impl TlsServerAcceptor {
                     // .allow_unauthenticated()
                     .build()
                     .map_err(|_| KvError::CertifcateParseError("server", "cert verifier"))?;
-                ServerConfig::builder().with_client_cert_verifier(client_auth)
+                builder.with_client_cert_verifier(client_auth)
             }
         };
 
@@ -144,20 +196,87 @@ where
     }
 }
 
-fn load_certs(cert: &str) -> Result<Vec<CertificateDer>, KvError> {
+impl<S> PeerIdentity for ServerTlsStream<S> {
+    /// 客户端证书（DER 编码），只有 client_ca 不为空时客户端才会提供证书
+    fn peer_identity(&self) -> Option<Vec<u8>> {
+        let (_, conn) = self.get_ref();
+        let cert = conn.peer_certificates()?.first()?;
+        Some(cert.to_vec())
+    }
+}
+
+// 按 policy 过滤 rustls 默认的协议版本和加密套件，名字不认识时返回错误。
+// 加密套件和协议版本没有交集时 with_protocol_versions 会返回错误
+fn crypto_provider(
+    policy: &TlsPolicyConfig,
+) -> Result<(Arc<CryptoProvider>, Vec<&'static SupportedProtocolVersion>), KvError> {
+    let mut provider = aws_lc_rs::default_provider();
+    if !policy.cipher_suites.is_empty() {
+        let suites = policy
+            .cipher_suites
+            .iter()
+            .map(|name| {
+                provider
+                    .cipher_suites
+                    .iter()
+                    .find(|s| format!("{:?}", s.suite()) == *name)
+                    .copied()
+                    .ok_or_else(|| unsupported("cipher suite", name))
+            })
+            .collect::<Result<_, _>>()?;
+        provider.cipher_suites = suites;
+    }
+
+    let versions = match policy.versions.is_empty() {
+        true => rustls::DEFAULT_VERSIONS.to_vec(),
+        false => policy
+            .versions
+            .iter()
+            .map(|v| match v.as_str() {
+                "1.2" => Ok(&rustls::version::TLS12),
+                "1.3" => Ok(&rustls::version::TLS13),
+                _ => Err(unsupported("protocol version", v)),
+            })
+            .collect::<Result<_, _>>()?,
+    };
+    Ok((Arc::new(provider), versions))
+}
+
+fn unsupported(kind: &str, name: &str) -> KvError {
+    KvError::TlsError(rustls::Error::General(format!(
+        "unsupported {kind}: {name}"
+    )))
+}
+
+fn load_certs(cert: &str) -> Result<Vec<CertificateDer<'_>>, KvError> {
     let mut cert = Cursor::new(cert);
     rustls_pemfile::certs(&mut cert)
         .map(|cert| cert.map_err(|e| e.into()))
         .collect()
 }
 
-fn load_key(key: &str) -> Result<PrivateKeyDer, KvError> {
+fn load_key<'a>(key: &'a str, passphrase: Option<&str>) -> Result<PrivateKeyDer<'a>, KvError> {
+    // 加密的私钥（ENCRYPTED PRIVATE KEY）需要先用口令解密成 PKCS#8
+    if let Ok((label, doc)) = pkcs8::der::Document::from_pem(key.trim()) {
+        if label == "ENCRYPTED PRIVATE KEY" {
+            let passphrase = passphrase.ok_or(KvError::CertifcateParseError(
+                "private",
+                "encrypted key (no passphrase)",
+            ))?;
+            let info = pkcs8::EncryptedPrivateKeyInfo::try_from(doc.as_bytes())
+                .map_err(|_| KvError::CertifcateParseError("private", "encrypted key"))?;
+            let key = info.decrypt(passphrase).map_err(|_| {
+                KvError::CertifcateParseError("private", "encrypted key (wrong passphrase)")
+            })?;
+            return Ok(PrivatePkcs8KeyDer::from(key.as_bytes().to_vec()).into());
+        }
+    }
+
     let mut cursor = Cursor::new(key);
 
     // PKCS#8 是一种标准的私钥信息语法，支持多种加密算法。它可以包含 RSA、DSA、ECDSA 等各种类型的私钥。
     // 使用 PKCS#8 格式加载私钥可以处理不同类型的私钥，因此优先尝试这种格式。
     if let Some(key) = rustls_pemfile::pkcs8_private_keys(&mut cursor)
-        .into_iter()
         // Result::ok 是方法指针，此处直接传递给filter_map，由filter_map来调用
         .filter_map(Result::ok)
         .next()
@@ -166,10 +285,18 @@ fn load_key(key: &str) -> Result<PrivateKeyDer, KvError> {
         return Ok(key.into());
     }
 
-    // 再尝试加载 RSA key
+    // 再尝试加载 RSA key（PKCS#1）
     cursor.set_position(0);
     if let Some(key) = rustls_pemfile::rsa_private_keys(&mut cursor)
-        .into_iter()
+        .filter_map(Result::ok)
+        .next()
+    {
+        return Ok(key.into());
+    }
+
+    // 最后尝试加载 EC key（SEC1）
+    cursor.set_position(0);
+    if let Some(key) = rustls_pemfile::ec_private_keys(&mut cursor)
         .filter_map(Result::ok)
         .next()
     {
@@ -177,7 +304,10 @@ fn load_key(key: &str) -> Result<PrivateKeyDer, KvError> {
     }
 
     // 不支持的私钥类型
-    Err(KvError::CertifcateParseError("private", "key"))
+    Err(KvError::CertifcateParseError(
+        "private",
+        "key (tried PKCS#8, PKCS#1, SEC1)",
+    ))
 }
 
 #[cfg(test)]
@@ -185,6 +315,7 @@ mod tests {
     use std::net::SocketAddr;
 
     use super::*;
+    use crate::{QUIC_SERVER_CERT, QUIC_SERVER_KEY, TLS_CA_CERT, TLS_SERVER_CERT, TLS_SERVER_KEY};
     use anyhow::Result;
     use tls_utils::{tls_acceptor, tls_connector};
     use tokio::{
@@ -192,6 +323,10 @@ mod tests {
         net::{TcpListener, TcpStream},
     };
 
+    // 与 QUIC_SERVER_KEY 相同的私钥，分别以 SEC1 格式和用 kvserver 加密的 PKCS#8 格式保存
+    const SEC1_SERVER_KEY: &str = include_str!("../../../fixtures/quic/server.sec1.key");
+    const ENCRYPTED_SERVER_KEY: &str = include_str!("../../../fixtures/quic/server.encrypted.key");
+
     #[tokio::test]
     async fn tls_should_work() -> Result<()> {
         let addr = start_server(false).await?;
@@ -251,9 +386,116 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn load_key_should_support_pkcs8_and_sec1() {
+        let key = load_key(TLS_SERVER_KEY, None).unwrap();
+        assert!(matches!(key, PrivateKeyDer::Pkcs8(_)));
+        let key = load_key(QUIC_SERVER_KEY, None).unwrap();
+        assert!(matches!(key, PrivateKeyDer::Pkcs8(_)));
+
+        let key = load_key(SEC1_SERVER_KEY, None).unwrap();
+        assert!(matches!(key, PrivateKeyDer::Sec1(_)));
+        assert!(TlsServerAcceptor::new(QUIC_SERVER_CERT, SEC1_SERVER_KEY, None).is_ok());
+    }
+
+    #[test]
+    fn load_key_should_decrypt_encrypted_key() {
+        let key = load_key(ENCRYPTED_SERVER_KEY, Some("kvserver")).unwrap();
+        assert!(matches!(key, PrivateKeyDer::Pkcs8(_)));
+        assert!(TlsServerAcceptor::new_with_passphrase(
+            QUIC_SERVER_CERT,
+            ENCRYPTED_SERVER_KEY,
+            Some("kvserver"),
+            None
+        )
+        .is_ok());
+
+        assert!(load_key(ENCRYPTED_SERVER_KEY, Some("wrong")).is_err());
+        assert!(load_key(ENCRYPTED_SERVER_KEY, None).is_err());
+        assert!(load_key("not a key", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn tls12_client_should_not_connect_to_tls13_server() -> Result<()> {
+        let policy = TlsPolicyConfig {
+            versions: vec!["1.3".into()],
+            cipher_suites: vec!["TLS13_AES_256_GCM_SHA384".into()],
+        };
+        let acceptor = TlsServerAcceptor::new_with_policy(
+            TLS_SERVER_CERT,
+            TLS_SERVER_KEY,
+            None,
+            None,
+            &policy,
+        )?;
+        let addr = start_server_with(acceptor).await?;
+
+        let policy = TlsPolicyConfig {
+            versions: vec!["1.2".into()],
+            ..Default::default()
+        };
+        let connector = TlsClientConnector::new_with_policy(
+            "kvserver.acme.inc",
+            None,
+            None,
+            Some(TLS_CA_CERT),
+            &policy,
+        )?;
+        let stream = TcpStream::connect(addr).await?;
+        assert!(connector.connect(stream).await.is_err());
+
+        // 同样只允许 TLS 1.3 的客户端可以连接
+        let addr = start_server_with(tls_acceptor(false)?).await?;
+        let policy = TlsPolicyConfig {
+            versions: vec!["1.3".into()],
+            ..Default::default()
+        };
+        let connector = TlsClientConnector::new_with_policy(
+            "kvserver.acme.inc",
+            None,
+            None,
+            Some(TLS_CA_CERT),
+            &policy,
+        )?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = connector.connect(stream).await?;
+        stream.write_all(b"hello world!").await?;
+        let mut buf = [0; 12];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello world!");
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_tls_policy_should_fail() {
+        let unsupported = [
+            (vec!["1.1"], vec![]),
+            (vec![], vec!["TLS_RSA_WITH_RC4_128_MD5"]),
+            // TLS 1.3 的加密套件不能用于 TLS 1.2
+            (vec!["1.2"], vec!["TLS13_AES_128_GCM_SHA256"]),
+        ];
+        for (versions, suites) in unsupported {
+            let policy = TlsPolicyConfig {
+                versions: versions.into_iter().map(Into::into).collect(),
+                cipher_suites: suites.into_iter().map(Into::into).collect(),
+            };
+            let result = TlsServerAcceptor::new_with_policy(
+                TLS_SERVER_CERT,
+                TLS_SERVER_KEY,
+                None,
+                None,
+                &policy,
+            );
+            assert!(matches!(result, Err(KvError::TlsError(_))));
+        }
+    }
+
     async fn start_server(client_cert: bool) -> Result<SocketAddr> {
-        let acceptor = tls_acceptor(client_cert)?;
+        start_server_with(tls_acceptor(client_cert)?).await
+    }
 
+    async fn start_server_with(acceptor: TlsServerAcceptor) -> Result<SocketAddr> {
         let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = echo.local_addr().unwrap();
 
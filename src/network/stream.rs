@@ -1,14 +1,24 @@
 use std::{
     marker::PhantomData,
+    ops::RangeInclusive,
     pin::Pin,
     task::{Context, Poll},
 };
 
 use bytes::BytesMut;
 use futures::{ready, FutureExt, Sink, Stream};
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-use crate::{network::frame::read_frame, FrameCoder, KvError};
+use crate::{
+    network::frame::read_frame, BufferPool, BufferPoolConfig, CompressionConfig, CompressorType,
+    FrameCoder, KvError,
+};
+
+/// 批量写入时，写缓存超过这个大小就写入 stream
+const WRITE_BUFFER_LIMIT: usize = 64 * 1024;
+
+/// 支持的协议版本，frame 格式变化时增加版本号。v1 和 v2 的 frame 头见 FrameHeader::encode
+pub const PROTOCOL_VERSIONS: RangeInclusive<u8> = 1..=2;
 
 // 处理 KV server prost frame 的 stream
 pub struct ProstStream<S, In, Out> {
@@ -20,6 +30,12 @@ pub struct ProstStream<S, In, Out> {
     written: usize,
     // 读缓存
     rbuf: BytesMut,
+    // 协商出的协议版本，协商之前为 None
+    version: Option<u8>,
+    // 发送 frame 时的压缩算法和级别
+    compression: CompressionConfig,
+    // 压缩和解压 frame 时复用的临时 buffer
+    pool: BufferPool,
 
     _in: PhantomData<In>,
     _out: PhantomData<Out>,
@@ -35,9 +51,119 @@ where
             written: 0,
             wbuf: BytesMut::new(),
             rbuf: BytesMut::new(),
-            _in: PhantomData::default(),
-            _out: PhantomData::default(),
+            version: None,
+            compression: CompressionConfig::default(),
+            pool: BufferPool::new(BufferPoolConfig::default()),
+            _in: PhantomData,
+            _out: PhantomData,
+        }
+    }
+
+    /// 设置发送 frame 时的压缩算法和级别
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// 设置 frame 编解码时复用的临时 buffer
+    pub fn with_buffer_pool(mut self, config: BufferPoolConfig) -> Self {
+        self.pool = BufferPool::new(config);
+        self
+    }
+
+    /// 协商出的协议版本，encode/decode 可以根据它处理不同版本的 frame 格式
+    pub fn version(&self) -> Option<u8> {
+        self.version
+    }
+
+    /// 客户端在发送任何命令之前协商协议版本：发送自己支持的最高版本，
+    /// 服务器返回双方都支持的最高版本，返回 0 表示服务器拒绝
+    pub async fn negotiate_client(&mut self, versions: RangeInclusive<u8>) -> Result<u8, KvError> {
+        self.stream.write_all(&[*versions.end()]).await?;
+        self.stream.flush().await?;
+        let version = self.stream.read_u8().await?;
+        if !versions.contains(&version) {
+            return Err(KvError::UnsupportedVersion(version));
+        }
+        self.version = Some(version);
+        Ok(version)
+    }
+
+    /// 服务器在读取任何命令之前协商协议版本：选择双方都支持的最高版本，
+    /// 没有共同支持的版本时返回 0，然后拒绝连接
+    pub async fn negotiate_server(&mut self, versions: RangeInclusive<u8>) -> Result<u8, KvError> {
+        let client_version = self.stream.read_u8().await?;
+        let version = client_version.min(*versions.end());
+        if !versions.contains(&version) {
+            self.stream.write_all(&[0]).await?;
+            self.stream.flush().await?;
+            return Err(KvError::UnsupportedVersion(client_version));
         }
+        self.stream.write_all(&[version]).await?;
+        self.stream.flush().await?;
+        self.version = Some(version);
+        Ok(version)
+    }
+
+    /// 从 stream 中读取一个任意类型的 frame
+    pub async fn read_message<T: FrameCoder>(&mut self) -> Result<T, KvError> {
+        let version = self.frame_version();
+        read_frame(&mut self.stream, version, &mut self.rbuf).await?;
+        T::decode_frame_pooled(version, &mut self.rbuf, &mut self.pool)
+    }
+
+    /// 把一个任意类型的 frame 放入写缓存，写缓存超过 WRITE_BUFFER_LIMIT 时写入 stream
+    pub async fn feed_message<T: FrameCoder>(&mut self, msg: &T) -> Result<(), KvError> {
+        self.encode_message(msg)?;
+        if self.wbuf.len() >= WRITE_BUFFER_LIMIT {
+            self.write_buffer().await?;
+        }
+        Ok(())
+    }
+
+    /// 用指定的压缩算法发送一个 frame，不影响之后的 frame。压缩级别仍然使用配置中该算法的级别
+    pub async fn send_with_compressor<T: FrameCoder>(
+        &mut self,
+        msg: &T,
+        compressor: CompressorType,
+    ) -> Result<(), KvError> {
+        let compression = CompressionConfig {
+            algorithm: compressor,
+            ..self.compression
+        };
+        let version = self.frame_version();
+        let level = compression.level();
+        msg.encode_frame_pooled(version, &mut self.wbuf, compressor, level, &mut self.pool)?;
+        self.flush_messages().await
+    }
+
+    /// 把写缓存中的数据全部写入 stream
+    pub async fn flush_messages(&mut self) -> Result<(), KvError> {
+        self.write_buffer().await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn write_buffer(&mut self) -> Result<(), KvError> {
+        self.stream.write_all(&self.wbuf[self.written..]).await?;
+        self.wbuf.clear();
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl<S, In, Out> ProstStream<S, In, Out> {
+    // 协商之前使用 v1 的 frame 格式
+    fn frame_version(&self) -> u8 {
+        self.version.unwrap_or(1)
+    }
+
+    // 按配置的压缩算法和级别把 msg encode 到写缓存
+    fn encode_message<T: FrameCoder>(&mut self, msg: &T) -> Result<(), KvError> {
+        let compression = self.compression;
+        let (algorithm, level) = (compression.algorithm, compression.level());
+        let version = self.frame_version();
+        msg.encode_frame_pooled(version, &mut self.wbuf, algorithm, level, &mut self.pool)
     }
 }
 
@@ -60,14 +186,17 @@ where
         let mut rest = self.rbuf.split_off(0);
 
         // 使用 read_frame 来获取数据
-        let fut = read_frame(&mut self.stream, &mut rest);
+        let version = self.frame_version();
+        let fut = read_frame(&mut self.stream, version, &mut rest);
         ready!(Box::pin(fut).poll_unpin(cx))?;
 
         // 拿到一个 frame 的数据，把 buffer 合并回去
         self.rbuf.unsplit(rest);
 
         // 调用 decode_frame 获取解包后的数据
-        Poll::Ready(Some(In::decode_frame(&mut self.rbuf)))
+        let this = &mut *self;
+        let msg = In::decode_frame_pooled(version, &mut this.rbuf, &mut this.pool);
+        Poll::Ready(Some(msg))
     }
 }
 
@@ -87,10 +216,7 @@ where
     }
 
     fn start_send(self: Pin<&mut Self>, item: &Out) -> Result<(), Self::Error> {
-        let this = self.get_mut();
-        item.encode_frame(&mut this.wbuf)?;
-
-        Ok(())
+        self.get_mut().encode_message(item)
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -124,8 +250,9 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{utils::DummyStream, CommandRequest};
+    use crate::{utils::DummyStream, CommandRequest, CommandResponse, Value};
     use anyhow::Result;
+    use bytes::Bytes;
     use futures::prelude::*;
 
     #[tokio::test]
@@ -134,11 +261,91 @@ mod tests {
         let mut stream = ProstStream::<_, CommandRequest, CommandRequest>::new(stream);
         let cmd = CommandRequest::new_hdel("table", "key");
         stream.send(&cmd).await?;
-        if let Some(Ok(s)) = stream.next().await {
-            assert_eq!(s, cmd)
-        } else {
-            assert!(false)
-        }
+        let s = stream.next().await.unwrap()?;
+        assert_eq!(s, cmd);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn newer_client_should_negotiate_down_to_server_version() -> Result<()> {
+        let (client, server) = tokio::io::duplex(64);
+        let mut client = ProstStream::<_, CommandResponse, CommandRequest>::new(client);
+        let mut server = ProstStream::<_, CommandRequest, CommandResponse>::new(server);
+
+        // v2 的客户端兼容 v1，和 v1 的服务器使用 v1
+        let (client_version, server_version) = tokio::join!(
+            client.negotiate_client(1..=2),
+            server.negotiate_server(1..=1)
+        );
+        assert_eq!(client_version?, 1);
+        assert_eq!(server_version?, 1);
+        assert_eq!(client.version(), Some(1));
+        assert_eq!(server.version(), Some(1));
+
+        // 协商后可以正常收发 frame
+        let cmd = CommandRequest::new_hget("table", "key");
+        client.send(&cmd).await?;
+        assert_eq!(server.next().await.unwrap()?, cmd);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn v2_streams_should_use_extended_header() -> Result<()> {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut client = ProstStream::<_, CommandResponse, CommandRequest>::new(client);
+        let mut server = ProstStream::<_, CommandRequest, CommandResponse>::new(server);
+        let (client_version, server_version) = tokio::join!(
+            client.negotiate_client(PROTOCOL_VERSIONS),
+            server.negotiate_server(PROTOCOL_VERSIONS)
+        );
+        assert_eq!(client_version?, 2);
+        assert_eq!(server_version?, 2);
+
+        let cmd = CommandRequest::new_hget("table", "key");
+        client.send(&cmd).await?;
+        assert_eq!(server.next().await.unwrap()?, cmd);
+
+        // 需要压缩的 frame 也使用 v2 的 frame 头
+        let res: CommandResponse = Value::from(Bytes::from(vec![1u8; 16384])).into();
+        server.send(&res).await?;
+        assert_eq!(client.next().await.unwrap()?, res);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incompatible_versions_should_fail_cleanly() -> Result<()> {
+        // 只支持 v2 的客户端不能和 v1 的服务器通信
+        let (client, server) = tokio::io::duplex(64);
+        let mut client = ProstStream::<_, CommandResponse, CommandRequest>::new(client);
+        let mut server = ProstStream::<_, CommandRequest, CommandResponse>::new(server);
+        let (client_version, server_version) = tokio::join!(
+            client.negotiate_client(2..=2),
+            server.negotiate_server(1..=1)
+        );
+        assert!(matches!(
+            client_version,
+            Err(KvError::UnsupportedVersion(1))
+        ));
+        assert_eq!(server_version?, 1);
+
+        // 只支持 v2 的服务器拒绝 v1 的客户端
+        let (client, server) = tokio::io::duplex(64);
+        let mut client = ProstStream::<_, CommandResponse, CommandRequest>::new(client);
+        let mut server = ProstStream::<_, CommandRequest, CommandResponse>::new(server);
+        let (client_version, server_version) = tokio::join!(
+            client.negotiate_client(1..=1),
+            server.negotiate_server(2..=2)
+        );
+        assert!(matches!(
+            client_version,
+            Err(KvError::UnsupportedVersion(0))
+        ));
+        assert!(matches!(
+            server_version,
+            Err(KvError::UnsupportedVersion(1))
+        ));
+        assert_eq!(client.version(), None);
+        assert_eq!(server.version(), None);
         Ok(())
     }
 }
@@ -1,40 +1,105 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
 
-use futures::{stream::BoxStream, Stream, StreamExt};
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+use tokio::time;
 
 use crate::{CommandResponse, KvError};
 
+/// 等待 subscription id 的默认超时
+pub const SUBSCRIBE_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// 创建时之间取得 subscription id，并使用 Deref/DerefMut 使其用起来和 Stream 一致
+///
+/// 服务器正常结束 stream 时返回 None；服务器返回错误，或者连接在正常结束前断开时，
+/// 返回 Some(Err(...))，之后 stream 结束
 pub struct StreamResult {
     pub id: u32,
     inner: BoxStream<'static, Result<CommandResponse, KvError>>,
 }
 
 impl StreamResult {
-    pub async fn new<T>(mut stream: T) -> Result<Self, KvError>
+    pub async fn new<T>(stream: T) -> Result<Self, KvError>
     where
         T: Stream<Item = Result<CommandResponse, KvError>> + Send + Unpin + 'static,
     {
-        let id = match stream.next().await {
+        Self::with_timeout(stream, SUBSCRIBE_ACK_TIMEOUT).await
+    }
+
+    /// 在 timeout 内没有收到 subscription id 时返回 KvError::Timeout
+    pub async fn with_timeout<T>(mut stream: T, timeout: Duration) -> Result<Self, KvError>
+    where
+        T: Stream<Item = Result<CommandResponse, KvError>> + Send + Unpin + 'static,
+    {
+        let res = time::timeout(timeout, stream.next())
+            .await
+            .map_err(|_| KvError::Timeout(timeout))?;
+        let id = match res {
             Some(Ok(CommandResponse {
                 status: 200,
                 values: v,
                 ..
-            })) => {
-                if v.is_empty() {
-                    return Err(KvError::Internal("Invalid stream".into()));
-                }
-                let id: i64 = (&v[0]).try_into().unwrap();
-                Ok(id as u32)
-            }
-            _ => Err(KvError::Internal("Invalid stream".into())),
+            })) => parse_id(&v)?,
+            Some(Err(e)) => return Err(e),
+            _ => return Err(KvError::Internal("Invalid stream".into())),
         };
 
         Ok(Self {
-            id: id?,
-            inner: Box::pin(stream),
+            id,
+            inner: Box::pin(terminated(stream)),
         })
     }
+
+    /// 第一个响应不是 subscription id 的 stream（如 ScanStream），id 为 0
+    pub fn without_id<T>(stream: T) -> Self
+    where
+        T: Stream<Item = Result<CommandResponse, KvError>> + Send + Unpin + 'static,
+    {
+        Self {
+            id: 0,
+            inner: Box::pin(terminated(stream)),
+        }
+    }
+}
+
+// 收到 204 时正常结束 stream，收到错误的 Response 时返回错误并结束 stream
+fn terminated<T>(stream: T) -> impl Stream<Item = Result<CommandResponse, KvError>>
+where
+    T: Stream<Item = Result<CommandResponse, KvError>> + Send + Unpin + 'static,
+{
+    stream::unfold(Some(stream), |stream| async move {
+        let mut stream = stream?;
+        let item = match stream.next().await {
+            Some(Ok(res)) if res.is_stream_end() => return None,
+            Some(Ok(res)) if res.status >= 400 => Err(KvError::Internal(format!(
+                "Stream failed with status {}: {}",
+                res.status, res.message
+            ))),
+            Some(Ok(res)) => return Some((Ok(res), Some(stream))),
+            Some(Err(e)) => Err(e),
+            None => Err(KvError::Internal("Stream closed before it ended".into())),
+        };
+        Some((item, None))
+    })
+}
+
+// 服务器分配的 subscription id 从 1 开始
+fn parse_id(values: &[crate::Value]) -> Result<u32, KvError> {
+    let Some(v) = values.first() else {
+        return Err(KvError::Internal("Missing subscription id".into()));
+    };
+    let id: i64 = v
+        .try_into()
+        .map_err(|_| KvError::Internal(format!("Invalid subscription id: {v:?}")))?;
+    match u32::try_from(id) {
+        Ok(id) if id > 0 => Ok(id),
+        _ => Err(KvError::Internal(format!("Invalid subscription id: {id}"))),
+    }
 }
 
 impl Deref for StreamResult {
@@ -50,3 +115,83 @@ impl DerefMut for StreamResult {
         &mut self.inner
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+    use crate::Value;
+
+    fn mock(values: Vec<Value>) -> impl Stream<Item = Result<CommandResponse, KvError>> + Unpin {
+        mock_with(values, vec![Ok(Value::from("hello").into())])
+    }
+
+    // 第一个 Response 是 subscription id，之后是 rest
+    fn mock_with(
+        values: Vec<Value>,
+        rest: Vec<Result<CommandResponse, KvError>>,
+    ) -> impl Stream<Item = Result<CommandResponse, KvError>> + Unpin {
+        stream::iter(std::iter::once(Ok(values.into())).chain(rest))
+    }
+
+    #[tokio::test]
+    async fn stream_result_should_get_subscription_id() {
+        let mut res = StreamResult::new(mock(vec![1.into()])).await.unwrap();
+        assert_eq!(res.id, 1);
+        // 之后的数据仍可以从 stream 中读到
+        let data = res.next().await.unwrap().unwrap();
+        assert_eq!(data.values, vec![Value::from("hello")]);
+    }
+
+    #[tokio::test]
+    async fn stream_result_should_timeout_without_subscription_id() {
+        let timeout = Duration::from_millis(10);
+        let res = StreamResult::with_timeout(stream::pending(), timeout).await;
+        assert!(matches!(res, Err(KvError::Timeout(t)) if t == timeout));
+    }
+
+    #[tokio::test]
+    async fn stream_result_should_reject_bad_subscription_id() {
+        for values in [vec![], vec![0.into()], vec![(-1).into()], vec!["1".into()]] {
+            let res = StreamResult::new(mock(values)).await;
+            assert!(matches!(res, Err(KvError::Internal(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_result_should_end_on_stream_end() {
+        let rest = vec![
+            Ok(Value::from("hello").into()),
+            Ok(CommandResponse::stream_end()),
+            Ok(Value::from("world").into()),
+        ];
+        let mut res = StreamResult::new(mock_with(vec![1.into()], rest))
+            .await
+            .unwrap();
+        let data = res.next().await.unwrap().unwrap();
+        assert_eq!(data.values, vec![Value::from("hello")]);
+        assert!(res.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_result_should_return_error_mid_stream() {
+        let rest = vec![
+            Ok(Value::from("hello").into()),
+            Ok(KvError::NotFound("topic".into()).into()),
+            Ok(Value::from("world").into()),
+        ];
+        let mut res = StreamResult::new(mock_with(vec![1.into()], rest))
+            .await
+            .unwrap();
+        assert!(res.next().await.unwrap().is_ok());
+        assert!(matches!(res.next().await, Some(Err(KvError::Internal(_)))));
+        assert!(res.next().await.is_none());
+
+        // 连接在收到 204 前断开也是错误
+        let mut res = StreamResult::new(mock(vec![1.into()])).await.unwrap();
+        assert!(res.next().await.unwrap().is_ok());
+        assert!(matches!(res.next().await, Some(Err(KvError::Internal(_)))));
+        assert!(res.next().await.is_none());
+    }
+}
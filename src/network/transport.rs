@@ -0,0 +1,755 @@
+use std::{
+    fmt::Display,
+    fs,
+    future::Future,
+    io,
+    marker::PhantomData,
+    net::{self, SocketAddr},
+    os::{fd::OwnedFd, unix::fs::FileTypeExt},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+use s2n_quic::{client::Connect, Client, Server};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{lookup_host, TcpListener, TcpSocket, TcpStream, UnixListener, UnixStream},
+};
+
+use crate::{
+    quic_client_tls, quic_server_tls, AcceptRateConfig, AppStream, BufferPoolConfig,
+    ClientTlsConfig, CommandTimeout, KvError, NoiseBuilder, PeerIdentity, QuicConn,
+    SecureStreamAccept, SecureStreamConnect, ServerTlsConfig, Service, Storage, TlsClientConnector,
+    TlsServerAcceptor, YamuxConn,
+};
+
+/// TCP listener 默认的 listen backlog，和 TcpListener::bind 一样
+pub const DEFAULT_LISTEN_BACKLOG: u32 = 1024;
+/// accept 遇到暂时性的错误后，第一次重试之前等待的时间，之后每次加倍
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+/// accept 重试的最长等待时间
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+/// 文件描述符或内存暂时耗尽时 accept 返回的 errno（Linux）：ENFILE、EMFILE、ENOBUFS、ENOMEM
+const TRANSIENT_ACCEPT_ERRNOS: [i32; 4] = [23, 24, 105, 12];
+
+/// 服务端处理连接的设置
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnSettings {
+    /// 连接空闲超过这个时间后关闭，目前只对 yamux 连接生效
+    pub idle_timeout: Option<Duration>,
+    pub timeout: CommandTimeout,
+    /// 每个 stream 同时执行的命令数，0 和 1 一样表示逐个执行
+    pub max_in_flight: usize,
+    /// 每个 stream 的优先级队列的长度，0 表示按读取的顺序执行
+    pub priority_queue: usize,
+    /// 每个连接同时进行的订阅数的上限
+    pub max_subscriptions: Option<usize>,
+    /// 每个 stream 上复用的 frame 临时 buffer
+    pub buffer_pool: BufferPoolConfig,
+    /// yamux 连接完成 TLS/Noise 握手的超时，None 表示不超时
+    pub handshake_timeout: Option<Duration>,
+    /// 接受新连接的速率限制，None 表示不限制
+    pub accept_rate: Option<AcceptRateConfig>,
+}
+
+/// 传输层，把底层的连接（TCP、Unix socket、QUIC）和安全层（TLS、Noise）组合在一起：
+/// 服务端 bind 之后不断 accept 连接，客户端 connect 之后在连接上打开 stream
+pub trait Transport: Send + Sync + 'static {
+    type Listener: TransportListener;
+    type Conn: AppStream;
+
+    fn bind(&self, addr: &str) -> impl Future<Output = Result<Self::Listener, KvError>> + Send;
+    fn connect(&self, addr: &str) -> impl Future<Output = Result<Self::Conn, KvError>>;
+    /// 使用已经 bind 好的 socket（比如 systemd socket activation 传入的 fd）代替 bind
+    fn adopt(&self, fd: OwnedFd) -> Result<Self::Listener, KvError>;
+}
+
+/// 服务端的 listener
+pub trait TransportListener: Send + 'static {
+    /// accept 一个连接，返回对端的地址，和在连接上用 service 处理所有 stream 的 future，
+    /// future 在连接关闭后结束，调用者应该在单独的 task 中运行它，不阻塞后续的 accept
+    fn accept<Store: Storage>(
+        &mut self,
+        service: Service<Store>,
+        settings: ConnSettings,
+    ) -> impl Future<Output = Result<(String, BoxFuture<'static, ()>), KvError>> + Send;
+
+    /// bind 之后的实际地址，Unix socket 没有网络地址
+    fn local_addr(&self) -> Option<SocketAddr>;
+}
+
+/// 可以运行 yamux 的 socket，目前有 TCP 和 Unix socket
+pub trait Socket: Sized + Send + Sync + 'static {
+    type Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
+
+    /// backlog 是等待 accept 的连接队列的长度，不支持设置的 socket 忽略它
+    fn bind(addr: &str, backlog: u32) -> impl Future<Output = io::Result<Self>> + Send;
+    /// 从已经处于 listen 状态的 fd 创建，必须在 tokio runtime 中调用
+    fn from_fd(fd: OwnedFd) -> io::Result<Self>;
+    /// accept 一个连接，返回连接、用于日志的对端地址和对端的 IP 地址
+    fn accept(
+        &self,
+    ) -> impl Future<Output = io::Result<(Self::Stream, String, Option<SocketAddr>)>> + Send;
+    fn connect(addr: &str) -> impl Future<Output = io::Result<Self::Stream>> + Send;
+    fn local_addr(&self) -> Option<SocketAddr>;
+}
+
+impl Socket for TcpListener {
+    type Stream = TcpStream;
+
+    async fn bind(addr: &str, backlog: u32) -> io::Result<Self> {
+        // 和 TcpListener::bind 一样依次尝试解析出的每个地址，返回最后一个错误
+        let mut last_err = None;
+        for addr in lookup_host(addr).await? {
+            let socket = match addr {
+                SocketAddr::V4(_) => TcpSocket::new_v4()?,
+                SocketAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+            socket.set_reuseaddr(true)?;
+            match socket.bind(addr).and_then(|_| socket.listen(backlog)) {
+                Ok(listener) => return Ok(listener),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        let listener = net::TcpListener::from(fd);
+        listener.set_nonblocking(true)?;
+        TcpListener::from_std(listener)
+    }
+
+    async fn accept(&self) -> io::Result<(Self::Stream, String, Option<SocketAddr>)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+        Ok((stream, addr.to_string(), Some(addr)))
+    }
+
+    async fn connect(addr: &str) -> io::Result<Self::Stream> {
+        TcpStream::connect(addr).await
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        TcpListener::local_addr(self).ok()
+    }
+}
+
+impl Socket for UnixListener {
+    type Stream = UnixStream;
+
+    // UnixListener 不支持设置 backlog，使用系统的默认值
+    async fn bind(addr: &str, _backlog: u32) -> io::Result<Self> {
+        // 删除上次运行遗留的 socket 文件，否则 bind 会失败
+        if fs::metadata(addr).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(addr)?;
+        }
+        UnixListener::bind(addr)
+    }
+
+    fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        let listener = std::os::unix::net::UnixListener::from(fd);
+        listener.set_nonblocking(true)?;
+        UnixListener::from_std(listener)
+    }
+
+    async fn accept(&self) -> io::Result<(Self::Stream, String, Option<SocketAddr>)> {
+        let (stream, addr) = UnixListener::accept(self).await?;
+        Ok((stream, format!("{addr:?}"), None))
+    }
+
+    async fn connect(addr: &str) -> io::Result<Self::Stream> {
+        UnixStream::connect(addr).await
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+/// 在 TCP 或 Unix socket 上，先用 acceptor/connector 建立安全连接，再运行 yamux
+///
+/// 服务端只需要 acceptor，客户端只需要 connector，缺少的一方在 bind/connect 时返回错误
+pub struct YamuxTransport<L, A, C> {
+    acceptor: Option<A>,
+    connector: Option<C>,
+    backlog: u32,
+    _socket: PhantomData<fn() -> L>,
+}
+
+/// TLS 上的 yamux
+pub type TlsTransport<L> = YamuxTransport<L, TlsServerAcceptor, TlsClientConnector>;
+/// Noise 上的 yamux
+pub type NoiseTransport<L> = YamuxTransport<L, NoiseBuilder, NoiseBuilder>;
+
+impl<L, A, C> YamuxTransport<L, A, C> {
+    pub fn new(acceptor: Option<A>, connector: Option<C>) -> Self {
+        Self {
+            acceptor,
+            connector,
+            backlog: DEFAULT_LISTEN_BACKLOG,
+            _socket: PhantomData,
+        }
+    }
+
+    /// 设置 bind 时的 listen backlog，默认为 DEFAULT_LISTEN_BACKLOG
+    pub fn with_backlog(mut self, backlog: u32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// 只用于服务端的 transport
+    pub fn server(acceptor: A) -> Self {
+        Self::new(Some(acceptor), None)
+    }
+
+    /// 只用于客户端的 transport
+    pub fn client(connector: C) -> Self {
+        Self::new(None, Some(connector))
+    }
+}
+
+impl<L, A: Clone, C> YamuxTransport<L, A, C> {
+    /// 在已经 bind 好的 socket 上 accept，不再自己 bind
+    pub fn listen_on(&self, listener: L) -> Result<YamuxListener<L, A>, KvError> {
+        let acceptor = self
+            .acceptor
+            .clone()
+            .ok_or_else(|| KvError::Internal("transport can not accept".into()))?;
+        Ok(YamuxListener { listener, acceptor })
+    }
+}
+
+impl<L> NoiseTransport<L> {
+    /// Noise 的两端使用同样的配置
+    pub fn noise() -> Self {
+        Self::new(Some(NoiseBuilder::new()), Some(NoiseBuilder::new()))
+    }
+}
+
+impl<L, A, C> Transport for YamuxTransport<L, A, C>
+where
+    L: Socket,
+    A: SecureStreamAccept<L::Stream> + Clone + Send + Sync + 'static,
+    A::InnerStream: PeerIdentity + 'static,
+    C: SecureStreamConnect<L::Stream> + Send + Sync + 'static,
+    C::InnerStream: 'static,
+{
+    type Listener = YamuxListener<L, A>;
+    type Conn = YamuxConn<C::InnerStream>;
+
+    async fn bind(&self, addr: &str) -> Result<Self::Listener, KvError> {
+        if self.acceptor.is_none() {
+            return Err(KvError::Internal(format!(
+                "transport on {addr} can not accept"
+            )));
+        }
+        self.listen_on(L::bind(addr, self.backlog).await?)
+    }
+
+    async fn connect(&self, addr: &str) -> Result<Self::Conn, KvError> {
+        let connector = self
+            .connector
+            .as_ref()
+            .ok_or_else(|| KvError::Internal(format!("transport to {addr} can not connect")))?;
+        let stream = L::connect(addr).await?;
+        let stream = connector.connect(stream).await?;
+        Ok(YamuxConn::new_client(stream, None))
+    }
+
+    fn adopt(&self, fd: OwnedFd) -> Result<Self::Listener, KvError> {
+        self.listen_on(L::from_fd(fd)?)
+    }
+}
+
+pub struct YamuxListener<L, A> {
+    listener: L,
+    acceptor: A,
+}
+
+impl<L, A> TransportListener for YamuxListener<L, A>
+where
+    L: Socket,
+    A: SecureStreamAccept<L::Stream> + Clone + Send + Sync + 'static,
+    A::InnerStream: PeerIdentity + 'static,
+{
+    async fn accept<Store: Storage>(
+        &mut self,
+        service: Service<Store>,
+        settings: ConnSettings,
+    ) -> Result<(String, BoxFuture<'static, ()>), KvError> {
+        let (stream, peer, remote_addr) = self.listener.accept().await?;
+        // 握手在返回的 future 中进行，不阻塞后续的 accept
+        let conn = crate::serve_yamux_conn(
+            stream,
+            peer.clone(),
+            remote_addr,
+            self.acceptor.clone(),
+            service,
+            settings,
+        );
+        Ok((peer, Box::pin(conn)))
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+/// QUIC 自带 TLS 和多路复用
+///
+/// 和 YamuxTransport 一样，服务端只需要 server 的 TLS 配置，客户端只需要 client 的 TLS 配置
+#[derive(Clone, Debug, Default)]
+pub struct QuicTransport {
+    server: Option<ServerTlsConfig>,
+    client: Option<ClientTlsConfig>,
+}
+
+impl QuicTransport {
+    /// 只用于服务端的 transport
+    pub fn server(tls: ServerTlsConfig) -> Self {
+        Self {
+            server: Some(tls),
+            client: None,
+        }
+    }
+
+    /// 只用于客户端的 transport
+    pub fn client(tls: ClientTlsConfig) -> Self {
+        Self {
+            server: None,
+            client: Some(tls),
+        }
+    }
+
+    /// 在已经 bind 好的 UDP socket 上接受 QUIC 连接，不再自己 bind
+    pub fn listen_on(&self, socket: net::UdpSocket) -> Result<QuicListener, KvError> {
+        let tls = self
+            .server
+            .as_ref()
+            .ok_or_else(|| KvError::Internal("transport can not accept".into()))?;
+        let tls = quic_server_tls(&tls.cert, &tls.key, tls.ca.as_deref())?;
+        socket.set_nonblocking(true)?;
+        let io = s2n_quic::provider::io::tokio::Builder::default()
+            .with_rx_socket(socket)?
+            .build()?;
+        let server = Server::builder()
+            .with_tls(tls)
+            .map_err(quic_error)?
+            .with_io(io)
+            .map_err(quic_error)?
+            .start()
+            .map_err(quic_error)?;
+        Ok(QuicListener(server))
+    }
+}
+
+impl Transport for QuicTransport {
+    type Listener = QuicListener;
+    type Conn = QuicConn;
+
+    async fn bind(&self, addr: &str) -> Result<Self::Listener, KvError> {
+        let tls = self
+            .server
+            .as_ref()
+            .ok_or_else(|| KvError::Internal(format!("transport on {addr} can not accept")))?;
+        let tls = quic_server_tls(&tls.cert, &tls.key, tls.ca.as_deref())?;
+        let server = Server::builder()
+            .with_tls(tls)
+            .map_err(quic_error)?
+            .with_io(addr)
+            .map_err(quic_error)?
+            .start()
+            .map_err(quic_error)?;
+        Ok(QuicListener(server))
+    }
+
+    async fn connect(&self, addr: &str) -> Result<Self::Conn, KvError> {
+        let tls = self
+            .client
+            .as_ref()
+            .ok_or_else(|| KvError::Internal(format!("transport to {addr} can not connect")))?;
+        let addr = SocketAddr::from_str(addr)
+            .map_err(|e| KvError::Internal(format!("Invalid address {addr}. Error: {e}")))?;
+        let identity = tls.identity.as_ref().map(|(c, k)| (c.as_str(), k.as_str()));
+        let client = Client::builder()
+            .with_tls(quic_client_tls(identity, tls.ca.as_deref())?)
+            .map_err(quic_error)?
+            .with_io("0.0.0.0:0")
+            .map_err(quic_error)?
+            .start()
+            .map_err(quic_error)?;
+
+        // "Server Name Indication" (SNI) 在生成时证书设置，以绑定证书与特定主机，用于防止中间人攻击
+        let connect = Connect::new(addr).with_server_name("kvserver.acme.inc");
+        let mut conn = client.connect(connect).await?;
+
+        conn.keep_alive(true)?;
+
+        Ok(QuicConn::new(conn))
+    }
+
+    fn adopt(&self, fd: OwnedFd) -> Result<Self::Listener, KvError> {
+        self.listen_on(net::UdpSocket::from(fd))
+    }
+}
+
+// s2n-quic 创建 client/server 时的各种错误
+fn quic_error(e: impl Display) -> KvError {
+    KvError::Internal(format!("Failed to start QUIC endpoint. Error: {e}"))
+}
+
+pub struct QuicListener(Server);
+
+impl TransportListener for QuicListener {
+    async fn accept<Store: Storage>(
+        &mut self,
+        service: Service<Store>,
+        settings: ConnSettings,
+    ) -> Result<(String, BoxFuture<'static, ()>), KvError> {
+        let conn = self
+            .0
+            .accept()
+            .await
+            .ok_or_else(|| KvError::Internal("QUIC server is closed".into()))?;
+        let peer = conn
+            .remote_addr()
+            .map_or_else(|e| format!("unknown ({e})"), |addr| addr.to_string());
+        Ok((peer, crate::serve_quic_conn(conn, service, settings)))
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.0.local_addr().ok()
+    }
+}
+
+/// 在 listener 上不断 accept 连接，每个连接在单独的 task 中处理。
+/// accept 遇到暂时性的错误（如文件描述符耗尽）时等待一段时间后继续，遇到其他错误时返回
+pub async fn serve<L, Store>(
+    mut listener: L,
+    service: Service<Store>,
+    settings: ConnSettings,
+) -> Result<(), KvError>
+where
+    L: TransportListener,
+    Store: Storage,
+{
+    let mut backoff = ACCEPT_BACKOFF;
+    let mut limiter = settings.accept_rate.map(AcceptLimiter::new);
+    loop {
+        // 超过速率时先不 accept，连接留在 backlog 中，不会开始握手
+        if let Some(limiter) = &mut limiter {
+            limiter.acquire().await;
+        }
+        match listener.accept(service.clone(), settings).await {
+            Ok((peer, conn)) => {
+                tracing::info!("Client {peer} connected");
+                crate::spawn_named(&format!("conn {peer}"), conn);
+                backoff = ACCEPT_BACKOFF;
+            }
+            Err(e) if is_transient_accept_error(&e) => {
+                tracing::warn!("Failed to accept connection, retry after {backoff:?}: {e}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// accept 的令牌桶，每个 listener 单独计算。桶里最多有 burst 个令牌，每秒补充 per_second 个
+struct AcceptLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl AcceptLimiter {
+    fn new(config: AcceptRateConfig) -> Self {
+        let rate = config.per_second.max(1) as f64;
+        let burst = config.burst.unwrap_or(config.per_second).max(1) as f64;
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    // 取走一个令牌，没有令牌时等到补充出一个
+    async fn acquire(&mut self) {
+        self.refill();
+        if self.tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - self.tokens) / self.rate);
+            tokio::time::sleep(wait).await;
+            self.refill();
+        }
+        self.tokens -= 1.0;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+}
+
+// 对端在 accept 之前断开，或者资源暂时耗尽，之后的 accept 还可能成功
+fn is_transient_accept_error(e: &KvError) -> bool {
+    let KvError::IoError(e) = e else {
+        return false;
+    };
+    match e.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut => true,
+        _ => e
+            .raw_os_error()
+            .is_some_and(|errno| TRANSIENT_ACCEPT_ERRNOS.contains(&errno)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tokio::time;
+
+    use super::*;
+    use crate::{
+        assert_res_ok,
+        tls_utils::{tls_acceptor, tls_connector},
+        ClientConfig, ClientSecurityProtocol, CommandRequest, MemTable, ServerConfig,
+        ServerSecurityProtocol, ServiceInner, Value, QUIC_CLIENT_CONFIG, QUIC_SERVER_CONFIG,
+    };
+
+    #[tokio::test]
+    async fn tls_over_tcp_transport_should_round_trip() -> Result<()> {
+        let server = TlsTransport::<TcpListener>::server(tls_acceptor(false)?);
+        let client = TlsTransport::<TcpListener>::client(tls_connector(false)?);
+        round_trip(server, client, "127.0.0.1:1980").await
+    }
+
+    #[tokio::test]
+    async fn tls_over_unix_transport_should_round_trip() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("kv.sock");
+        let server = TlsTransport::<UnixListener>::server(tls_acceptor(false)?);
+        let client = TlsTransport::<UnixListener>::client(tls_connector(false)?);
+        round_trip(server, client, path.to_str().unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn quic_transport_should_round_trip() -> Result<()> {
+        let server_config: ServerConfig = toml::from_str(QUIC_SERVER_CONFIG)?;
+        let client_config: ClientConfig = toml::from_str(QUIC_CLIENT_CONFIG)?;
+        let (ServerSecurityProtocol::Tls(server), ClientSecurityProtocol::Tls(client)) =
+            (server_config.security, client_config.security)
+        else {
+            panic!("QUIC config should use TLS");
+        };
+        let server = QuicTransport::server(server);
+        let client = QuicTransport::client(client);
+        round_trip(server, client, "127.0.0.1:1981").await
+    }
+
+    // TODO(Wiccy): Currently noise can not work with yamux, so skip this
+    // #[tokio::test]
+    #[allow(dead_code)]
+    async fn noise_transport_should_round_trip() -> Result<()> {
+        let transport = NoiseTransport::<TcpListener>::noise();
+        let client = NoiseTransport::<TcpListener>::noise();
+        round_trip(transport, client, "127.0.0.1:1982").await
+    }
+
+    #[tokio::test]
+    async fn transport_without_role_should_fail() {
+        let transport = TlsTransport::<TcpListener>::client(tls_connector(false).unwrap());
+        assert!(transport.bind("127.0.0.1:0").await.is_err());
+        let transport = QuicTransport::default();
+        assert!(transport.bind("127.0.0.1:0").await.is_err());
+        assert!(transport.connect("127.0.0.1:1983").await.is_err());
+    }
+
+    // 前几次 accept 返回指定的错误，之后正常 accept
+    struct FlakyListener<L> {
+        inner: L,
+        errors: Vec<io::Error>,
+    }
+
+    impl<L: TransportListener> TransportListener for FlakyListener<L> {
+        async fn accept<Store: Storage>(
+            &mut self,
+            service: Service<Store>,
+            settings: ConnSettings,
+        ) -> Result<(String, BoxFuture<'static, ()>), KvError> {
+            match self.errors.pop() {
+                Some(e) => Err(e.into()),
+                None => self.inner.accept(service, settings).await,
+            }
+        }
+
+        fn local_addr(&self) -> Option<SocketAddr> {
+            self.inner.local_addr()
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_accept_error_should_not_stop_server() -> Result<()> {
+        let server = TlsTransport::<TcpListener>::server(tls_acceptor(false)?).with_backlog(16);
+        let listener = server.bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr().unwrap().to_string();
+        // EMFILE：进程打开的文件太多
+        let errors = vec![
+            io::Error::from(io::ErrorKind::ConnectionAborted),
+            io::Error::from_raw_os_error(24),
+        ];
+        let listener = FlakyListener {
+            inner: listener,
+            errors,
+        };
+        let service = Service::new(MemTable::new());
+        let server = tokio::spawn(serve(listener, service, ConnSettings::default()));
+
+        let client = TlsTransport::<TcpListener>::client(tls_connector(false)?);
+        let mut stream = client.connect(&addr).await?.open_stream().await?;
+        let cmd = CommandRequest::new_hset("table", "key", "value");
+        let res = stream.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &[Value::default()], &[]);
+        assert!(!server.is_finished());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fatal_accept_error_should_stop_server() -> Result<()> {
+        let server = TlsTransport::<TcpListener>::server(tls_acceptor(false)?);
+        let listener = FlakyListener {
+            inner: server.bind("127.0.0.1:0").await?,
+            errors: vec![io::Error::from(io::ErrorKind::InvalidInput)],
+        };
+        let service = Service::new(MemTable::new());
+        let res = serve(listener, service, ConnSettings::default()).await;
+        assert!(matches!(res, Err(KvError::IoError(_))));
+        Ok(())
+    }
+
+    // 记录每次 accept 成功的时间
+    struct TimedListener<L> {
+        inner: L,
+        accepted: Arc<Mutex<Vec<Instant>>>,
+    }
+
+    impl<L: TransportListener> TransportListener for TimedListener<L> {
+        async fn accept<Store: Storage>(
+            &mut self,
+            service: Service<Store>,
+            settings: ConnSettings,
+        ) -> Result<(String, BoxFuture<'static, ()>), KvError> {
+            let res = self.inner.accept(service, settings).await;
+            self.accepted.lock().unwrap().push(Instant::now());
+            res
+        }
+
+        fn local_addr(&self) -> Option<SocketAddr> {
+            self.inner.local_addr()
+        }
+    }
+
+    #[tokio::test]
+    async fn accept_rate_should_be_limited() -> Result<()> {
+        let server = TlsTransport::<TcpListener>::server(tls_acceptor(false)?);
+        let accepted = Arc::new(Mutex::new(vec![]));
+        let listener = TimedListener {
+            inner: server.bind("127.0.0.1:0").await?,
+            accepted: accepted.clone(),
+        };
+        let addr = listener.local_addr().unwrap().to_string();
+        let settings = ConnSettings {
+            accept_rate: Some(AcceptRateConfig {
+                per_second: 20,
+                burst: Some(2),
+            }),
+            ..Default::default()
+        };
+        tokio::spawn(serve(listener, Service::new(MemTable::new()), settings));
+
+        // 同时发起 6 个连接，前 2 个立即被 accept，之后每 50ms accept 一个
+        let client = TlsTransport::<TcpListener>::client(tls_connector(false)?);
+        let conns = (0..6).map(|_| client.connect(&addr));
+        for conn in futures::future::join_all(conns).await {
+            conn?;
+        }
+
+        let accepted = accepted.lock().unwrap();
+        assert_eq!(accepted.len(), 6);
+        assert!(accepted[1] - accepted[0] < Duration::from_millis(40));
+        assert!(accepted[5] - accepted[1] >= Duration::from_millis(180));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_should_be_rejected_when_server_is_full() -> Result<()> {
+        let server = TlsTransport::<TcpListener>::server(tls_acceptor(false)?);
+        let listener = server.bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr().unwrap().to_string();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .max_connections(Some(1))
+            .into();
+        tokio::spawn(serve(listener, service.clone(), ConnSettings::default()));
+
+        let client = TlsTransport::<TcpListener>::client(tls_connector(false)?);
+        let mut first = client.connect(&addr).await?;
+        let cmd = CommandRequest::new_hset("table", "key", "value");
+        first.open_stream().await?.execute_unary(&cmd).await?;
+        assert_eq!(service.connections(), (1, Some(1)));
+
+        // 第二个客户端收到 503 而不是一直等待
+        let mut second = client.connect(&addr).await?;
+        let mut stream = second.open_stream().await?;
+        let res = time::timeout(Duration::from_secs(1), stream.execute_unary(&cmd)).await??;
+        assert_eq!(res.status, 503);
+        assert_eq!(res.error_code, KvError::ServerFull(1).code());
+        assert_eq!(service.connections(), (1, Some(1)));
+
+        // 第一个连接关闭后可以接受新的连接
+        first.close();
+        while service.connections().0 > 0 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut third = client.connect(&addr).await?;
+        let res = third.open_stream().await?.execute_unary(&cmd).await?;
+        assert_eq!(res.status, 200);
+        Ok(())
+    }
+
+    async fn round_trip(server: impl Transport, client: impl Transport, addr: &str) -> Result<()> {
+        // bind 之后再 connect，避免客户端连接时服务器还没有开始监听
+        let listener = server.bind(addr).await?;
+        let service = Service::new(MemTable::new());
+        tokio::spawn(serve(listener, service, ConnSettings::default()));
+
+        let mut conn = client.connect(addr).await?;
+        let mut stream = conn.open_stream().await?;
+        let cmd = CommandRequest::new_hset("table", "key", "value");
+        let res = stream.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hget("table", "key");
+        let res = stream.execute_unary(&cmd).await?;
+        assert_res_ok(&res, &["value".into()], &[]);
+
+        Ok(())
+    }
+}
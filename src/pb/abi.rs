@@ -6,9 +6,17 @@
 pub struct CommandRequest {
     #[prost(
         oneof = "command_request::RequestData",
-        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12"
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41"
     )]
     pub request_data: ::core::option::Option<command_request::RequestData>,
+    /// 客户端分配的请求 id，服务器在这个命令的所有响应中原样返回，用于关联请求和响应；
+    /// 0 表示没有 id。使用较大的 tag，给 request_data 中新增的命令留出空间
+    #[prost(uint64, tag = "100")]
+    pub request_id: u64,
+    /// 优先级，越大越优先。同一个 stream 上排队等待执行的命令中，优先级高的先执行；
+    /// 等待时间越长优先级越高，低优先级的命令不会一直得不到执行
+    #[prost(uint32, tag = "101")]
+    pub priority: u32,
 }
 /// Nested message and enum types in `CommandRequest`.
 pub mod command_request {
@@ -40,6 +48,64 @@ pub mod command_request {
         Unsubscribe(super::Unsubscribe),
         #[prost(message, tag = "12")]
         Publish(super::Publish),
+        #[prost(message, tag = "13")]
+        Mpublish(super::Mpublish),
+        #[prost(message, tag = "14")]
+        BulkLoad(super::BulkLoad),
+        #[prost(message, tag = "15")]
+        Hgetdel(super::Hgetdel),
+        #[prost(message, tag = "16")]
+        Hgetset(super::Hgetset),
+        #[prost(message, tag = "17")]
+        CreateTable(super::CreateTable),
+        #[prost(message, tag = "18")]
+        DropTable(super::DropTable),
+        #[prost(message, tag = "19")]
+        Hexpire(super::Hexpire),
+        #[prost(message, tag = "20")]
+        Hpersist(super::Hpersist),
+        #[prost(message, tag = "21")]
+        Httl(super::Httl),
+        #[prost(message, tag = "22")]
+        Hincrbyfloat(super::Hincrbyfloat),
+        #[prost(message, tag = "23")]
+        Sizeof(super::Sizeof),
+        #[prost(message, tag = "24")]
+        Hmsetnx(super::Hmsetnx),
+        #[prost(message, tag = "25")]
+        Compact(super::Compact),
+        #[prost(message, tag = "26")]
+        Stats(super::Stats),
+        #[prost(message, tag = "27")]
+        Diagnostics(super::Diagnostics),
+        #[prost(message, tag = "28")]
+        Hgetfield(super::Hgetfield),
+        #[prost(message, tag = "29")]
+        ScanStream(super::ScanStream),
+        #[prost(message, tag = "30")]
+        MySubscriptions(super::MySubscriptions),
+        #[prost(message, tag = "31")]
+        HgetChunked(super::HgetChunked),
+        #[prost(message, tag = "32")]
+        HgetIfNewer(super::HgetIfNewer),
+        #[prost(message, tag = "33")]
+        HincrEx(super::HincrEx),
+        #[prost(message, tag = "34")]
+        RenameTable(super::RenameTable),
+        #[prost(message, tag = "35")]
+        MultiTableGet(super::MultiTableGet),
+        #[prost(message, tag = "36")]
+        SubscribeLogs(super::SubscribeLogs),
+        #[prost(message, tag = "37")]
+        HincrInit(super::HincrInit),
+        #[prost(message, tag = "38")]
+        WatchKey(super::WatchKey),
+        #[prost(message, tag = "39")]
+        BulkDel(super::BulkDel),
+        #[prost(message, tag = "40")]
+        MoveNx(super::MoveNx),
+        #[prost(message, tag = "41")]
+        HsetCas(super::HsetCas),
     }
 }
 /// 服务器的响应
@@ -59,6 +125,15 @@ pub struct CommandResponse {
     /// 成功返回的 kv pairs
     #[prost(message, repeated, tag = "4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// 错误码，由 KvError 的类型决定，成功时为 0
+    #[prost(uint32, tag = "5")]
+    pub error_code: u32,
+    /// 发布到主题的数据在该主题内的序号，从 1 开始递增；其他响应为 0
+    #[prost(uint64, tag = "6")]
+    pub seq: u64,
+    /// 对应的 CommandRequest 的 request_id
+    #[prost(uint64, tag = "7")]
+    pub request_id: u64,
 }
 /// 从 table 中获取一个 key，返回 value
 #[derive(PartialOrd)]
@@ -70,6 +145,33 @@ pub struct Hget {
     #[prost(string, tag = "2")]
     pub key: ::prost::alloc::string::String,
 }
+/// 从 table 中获取一个 key 的值中的一个字段，key 的值必须是 Map，只返回这个字段的 value
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hgetfield {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub field: ::prost::alloc::string::String,
+}
+/// 从 table 中分块获取一个 key 的值，用于很大的 binary 或 string value。
+/// 服务器每个响应返回 value 的一段（binary），按顺序发送，最后发送 status 为 204 的结束标记；
+/// 和 ScanStream 一样，第一个响应不是 subscription id
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HgetChunked {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    /// 每一段的字节数，0 表示使用默认值 1MB，超过 64MB 时按 64MB 处理
+    #[prost(uint32, tag = "3")]
+    pub chunk_size: u32,
+}
 /// 从 table 中获取所有的 Kvpair
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -79,6 +181,7 @@ pub struct Hgetall {
     pub table: ::prost::alloc::string::String,
 }
 /// 从 table 中获取一组 key，返回它们的 value
+/// 每个 key 按位置返回一个 value，重复的 key 也各返回一个，不存在的 key 返回空的 value
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -88,12 +191,31 @@ pub struct Hmget {
     #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// table 中的一个 key
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TableKey {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 从多个 table 中获取一组 key，相当于跨 table 的 Hmget
+/// 每个 key 按位置返回一个 value，不存在的 key 返回空的 value
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MultiTableGet {
+    #[prost(message, repeated, tag = "1")]
+    pub requests: ::prost::alloc::vec::Vec<TableKey>,
+}
 /// 返回的值
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5")]
+    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5, 6, 7, 8")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
@@ -112,8 +234,25 @@ pub mod value {
         Float(f64),
         #[prost(bool, tag = "5")]
         Bool(bool),
+        /// Unix 时间戳，单位毫秒
+        #[prost(int64, tag = "6")]
+        Timestamp(i64),
+        /// 时间长度，单位毫秒，不能为负数
+        #[prost(int64, tag = "7")]
+        Duration(i64),
+        /// 嵌套的对象，从 HashMap 转换时按 key 排序
+        #[prost(message, tag = "8")]
+        Map(super::ValueMap),
     }
 }
+/// Value 中嵌套的对象
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueMap {
+    #[prost(message, repeated, tag = "1")]
+    pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+}
 /// 返回的 kvpair
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -134,9 +273,25 @@ pub struct Hset {
     pub table: ::prost::alloc::string::String,
     #[prost(message, optional, tag = "2")]
     pub pair: ::core::option::Option<Kvpair>,
+    /// 是否返回之前的值，默认返回。为 false 时返回空的 value，存储可以省去读取旧值的开销
+    #[prost(bool, optional, tag = "3")]
+    pub get_old: ::core::option::Option<bool>,
+}
+/// 往 table 里原子地存一个 kvpair，返回之前的值，
+/// 如果 table 不存在就创建这个 table
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hgetset {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
 }
 /// 往 table 中存一组 kvpair，
 /// 如果 table 不存在就创建这个 table
+/// 按顺序逐个写入，每个位置返回写入前的值。重复的 key 以最后一个为准，
+/// 之后位置返回的旧值是前一个位置写入的值
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -146,6 +301,17 @@ pub struct Hmset {
     #[prost(message, repeated, tag = "2")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
 }
+/// 所有的 key 都不存在时，原子地往 table 中存一组 kvpair，返回是否写入；
+/// 只要有一个 key 已经存在，就什么都不写入
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hmsetnx {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+}
 /// 从 table 中删除一个 key，返回它之前的值
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -155,6 +321,19 @@ pub struct Hdel {
     pub table: ::prost::alloc::string::String,
     #[prost(string, tag = "2")]
     pub key: ::prost::alloc::string::String,
+    /// 是否返回之前的值，默认返回。为 false 时返回空的 value，存储可以省去读取旧值的开销
+    #[prost(bool, optional, tag = "3")]
+    pub get_old: ::core::option::Option<bool>,
+}
+/// 从 table 中原子地获取并删除一个 key，返回它之前的值，key 不存在时返回 404
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hgetdel {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
 }
 /// 从 table 中删除一组 key，返回它们之前的值
 #[derive(PartialOrd)]
@@ -186,14 +365,279 @@ pub struct Hmexist {
     #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// 创建 table，table 已存在时什么也不做
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreateTable {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 删除 table 及其中所有的数据，返回 table 之前是否存在
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DropTable {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 把 table from 改名为 to，返回 to 之前是否存在。to 存在时 overwrite 为 true 才会用 from 的数据
+/// 替换 to，否则返回错误；from 不存在时返回 404。磁盘存储原子地移动数据，并发的读者不会看到写了一半的 table
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RenameTable {
+    #[prost(string, tag = "1")]
+    pub from: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub to: ::prost::alloc::string::String,
+    #[prost(bool, tag = "3")]
+    pub overwrite: bool,
+}
+/// dst_table 中的 dst_key 不存在时，原子地把 src_table 中的 src_key 移动过去，返回是否移动了。
+/// dst_key 已经存在或者 src_key 不存在时两个 key 都保持不变。key 的过期时间跟着移动
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MoveNx {
+    #[prost(string, tag = "1")]
+    pub src_table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub src_key: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub dst_table: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub dst_key: ::prost::alloc::string::String,
+}
+/// 设置 key 的过期时间（秒），不改变 key 的值，返回 key 是否存在
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hexpire {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub ttl: u64,
+}
+/// 删除 key 的过期时间，让 key 永久保存，返回 key 之前是否有过期时间
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hpersist {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 获取 key 剩余的过期时间（秒），key 没有过期时间返回 -1，key 不存在返回 -2
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Httl {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 原子地给 key 的值加上 delta，返回新的值；key 不存在时从 0.0 开始，
+/// Integer 的值会转换成 Float，其他类型的值返回错误。delta 和结果都必须是有限的值
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrbyfloat {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag = "3")]
+    pub delta: f64,
+}
+/// 原子地给 key 的整数值加上 delta，返回新的值；key 不存在时从 0 开始，并在创建 key 的同时
+/// 设置 ttl 秒后过期，用于限流的计数器。refresh_ttl 为 true 时每次都重新设置过期时间，
+/// ttl 为 0 时不设置。值不是 Integer 或者溢出时返回错误
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HincrEx {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub delta: i64,
+    #[prost(uint64, tag = "4")]
+    pub ttl: u64,
+    #[prost(bool, tag = "5")]
+    pub refresh_ttl: bool,
+}
+/// 原子地初始化或者增加 key 的整数值：key 不存在时设置为 initial，存在时加上 delta，返回新的值。
+/// 并发执行时只有一个请求会初始化。值不是 Integer 或者溢出时返回错误
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HincrInit {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub initial: i64,
+    #[prost(int64, tag = "4")]
+    pub delta: i64,
+}
+/// 获取 key 的 value 编码后占用的字节数，不指定 key 时获取整个 table 占用的字节数。
+/// 返回两个值：字节数和是否是估算值；key 不存在时返回 404
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sizeof {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, optional, tag = "2")]
+    pub key: ::core::option::Option<::prost::alloc::string::String>,
+}
+/// 压缩磁盘存储，回收删除和覆盖的数据占用的空间，不指定 table 时压缩所有 table。
+/// 这是管理命令，服务器开启 admin_commands 后才能使用。
+/// 压缩完成后返回两个值：压缩前和压缩后估算的字节数
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Compact {
+    #[prost(string, optional, tag = "1")]
+    pub table: ::core::option::Option<::prost::alloc::string::String>,
+}
+/// 流式遍历 table，服务器每读到 chunk_size 个 kv pair 就发送一个响应，遍历结束后发送 status 为 204
+/// 的结束标记。和 Subscribe 不同，第一个响应不是 subscription id。
+/// 客户端读取得慢时服务器暂停遍历，不会在内存中缓存整个 table
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ScanStream {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    /// 每个响应中 kv pair 的数量，0 表示使用默认值 100，超过 1000 时按 1000 处理
+    #[prost(uint32, tag = "2")]
+    pub chunk_size: u32,
+}
+/// 获取服务器的运行统计，以 Kvpair 返回：uptime、处理的命令数、连接数、订阅数、
+/// 每个存储后端的 table 数（tables.<backend>）和 key 的总数
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Stats {}
+/// 获取用于排查问题的诊断信息，以 Kvpair 返回：版本、运行统计、去掉敏感信息的配置、
+/// 每个主题的订阅数、每个存储后端的 table 数和各个状态码的错误数。只读取内存中的状态，
+/// 不遍历数据，需要服务器开启 admin_commands
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Diagnostics {}
+/// 列出当前连接上正在进行的订阅，以 Kvpair 返回：主题 => subscription id，按 id 排序。
+/// 连接上的所有 stream 共享订阅状态，所以可以在订阅之外的 stream 上查询
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MySubscriptions {}
+/// 批量写入数据到 table，如果 table 不存在就创建这个 table
+/// 发送这个命令后，客户端紧接着发送一组 Kvpair frame，以一个空的 Kvpair 结束
+/// 服务器返回写入的数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BulkLoad {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 批量删除 table 中的 key，key 的数量不受单个 frame 大小的限制
+/// 发送这个命令后，客户端紧接着发送一组 KeyBatch frame，以一个空的 KeyBatch 结束
+/// 服务器对每个 KeyBatch 返回删除的数量（或者错误），最后返回删除的总数
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BulkDel {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// BulkDel 之后发送的一批 key
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyBatch {
+    #[prost(string, repeated, tag = "1")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
 /// subscribe 到某一个主题，任何发布到这个主题的数据都会被收到
 /// 成功后，第一个返回的 CommandResponse ，返回一个唯一的 subscription id
+/// 订阅被取消后，最后返回一个 status 为 204 的 CommandResponse 表示 stream 正常结束；
+/// stream 中途出错时返回一个错误的 CommandResponse，之后 stream 结束
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Subscribe {
     #[prost(string, tag = "1")]
     pub topic: ::prost::alloc::string::String,
+    /// 从该序号开始重放缓存的数据，0 表示不重放
+    #[prost(uint64, tag = "2")]
+    pub from_seq: u64,
+    /// 两次推送之间的最小间隔（毫秒），间隔内只推送最新的一条数据，0 表示不限制
+    #[prost(uint64, tag = "3")]
+    pub throttle_ms: u64,
+    /// 每 N 条数据只推送一条，0 和 1 表示全部推送
+    #[prost(uint32, tag = "4")]
+    pub sample_every: u32,
+}
+/// 订阅服务器的日志（管理命令），level 是最低的日志级别（error/warn/info/debug/trace），为空时是 info。
+/// 和 Subscribe 一样，第一个响应是 subscription id，之后每条日志是一个响应，values 依次是级别、target 和内容
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeLogs {
+    #[prost(string, tag = "1")]
+    pub level: ::prost::alloc::string::String,
+}
+/// key 的版本号比 since_version 新时才返回它的值，需要服务器开启 key_versioning。
+/// 返回的 values 依次是 value 和当前的版本号；版本号没有变化时返回 status 为 304、不带 value 的响应，
+/// key 不存在时返回 404。since_version 为 0 时总是返回当前的值
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HgetIfNewer {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub since_version: u64,
+}
+/// key 当前的版本号等于 expected_version 时写入 value，需要服务器开启 key_versioning。
+/// expected_version 为 0 表示 key 必须不存在。成功时 values 是新的版本号；版本号不一致时返回 status 为 409
+/// 的响应，values 是 key 当前的版本号（不存在时为 0）。不改变 key 的过期时间
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HsetCas {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+    #[prost(uint64, tag = "4")]
+    pub expected_version: u64,
+}
+/// 订阅 key 的修改，需要服务器开启 keyspace_notifications。第一个响应是 subscription id，
+/// 第二个是 key 当前的值（不存在时 values 为空），之后是 table 中涉及这个 key 的 keyspace 通知。
+/// 读取当前值和开始订阅是原子的：每个写入要么体现在当前值中，要么出现在之后的通知中
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WatchKey {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
 }
 /// 取消对某个主题的订阅
 #[derive(PartialOrd)]
@@ -215,3 +659,13 @@ pub struct Publish {
     #[prost(message, repeated, tag = "2")]
     pub data: ::prost::alloc::vec::Vec<Value>,
 }
+/// 发布数据到一组主题，返回收到数据的订阅数
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Mpublish {
+    #[prost(string, repeated, tag = "1")]
+    pub topics: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag = "2")]
+    pub data: ::prost::alloc::vec::Vec<Value>,
+}
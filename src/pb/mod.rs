@@ -4,11 +4,27 @@ use abi::{command_request::RequestData, *};
 use bytes::Bytes;
 use http::StatusCode;
 use prost::Message;
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::KvError;
+use crate::{is_retryable_status, KvError};
 
 impl CommandRequest {
+    /// 设置请求 id，服务器会在响应中原样返回
+    pub fn with_request_id(mut self, request_id: u64) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    /// 设置优先级，服务器开启优先级队列时，排队的命令中优先级高的先执行
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
     /// 创建 HGET 命令
     pub fn new_hget(table: impl Into<String>, key: impl Into<String>) -> Self {
         Self {
@@ -16,6 +32,55 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HGETCHUNKED 命令，chunk_size 为 0 时使用服务器的默认值
+    pub fn new_hget_chunked(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        chunk_size: u32,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::HgetChunked(HgetChunked {
+                table: table.into(),
+                key: key.into(),
+                chunk_size,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HgetIfNewer 命令，key 的版本号比 since_version 新时才返回它的值
+    pub fn new_hget_if_newer(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        since_version: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::HgetIfNewer(HgetIfNewer {
+                table: table.into(),
+                key: key.into(),
+                since_version,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HGETFIELD 命令
+    pub fn new_hgetfield(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        field: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetfield(Hgetfield {
+                table: table.into(),
+                key: key.into(),
+                field: field.into(),
+            })),
+            ..Default::default()
         }
     }
 
@@ -25,7 +90,18 @@ impl CommandRequest {
             request_data: Some(RequestData::Hgetall(Hgetall {
                 table: table.into(),
             })),
+            ..Default::default()
+        }
+    }
+
+    /// HSET/HDEL 不返回之前的值，对其他命令没有影响
+    pub fn without_old_value(mut self) -> Self {
+        match &mut self.request_data {
+            Some(RequestData::Hset(v)) => v.get_old = Some(false),
+            Some(RequestData::Hdel(v)) => v.get_old = Some(false),
+            _ => {}
         }
+        self
     }
 
     /// 创建 HSET 命令
@@ -38,7 +114,42 @@ impl CommandRequest {
             request_data: Some(RequestData::Hset(Hset {
                 table: table.into(),
                 pair: Some(Kvpair::new(key, value)),
+                get_old: None,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HsetCas 命令，key 当前的版本号等于 expected_version 时才写入，0 表示 key 必须不存在
+    pub fn new_hset_cas(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected_version: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::HsetCas(HsetCas {
+                table: table.into(),
+                key: key.into(),
+                value: Some(value.into()),
+                expected_version,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HGETSET 命令
+    pub fn new_hgetset(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetset(Hgetset {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
             })),
+            ..Default::default()
         }
     }
 
@@ -48,7 +159,20 @@ impl CommandRequest {
             request_data: Some(RequestData::Hdel(Hdel {
                 table: table.into(),
                 key: key.into(),
+                get_old: None,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HGETDEL 命令
+    pub fn new_hgetdel(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetdel(Hgetdel {
+                table: table.into(),
+                key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -59,6 +183,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -69,6 +194,22 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys.into_iter().map(|key| key.into()).collect(),
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 MultiTableGet 命令，requests 中是 (table, key)
+    pub fn new_multi_table_get(requests: Vec<(impl Into<String>, impl Into<String>)>) -> Self {
+        let requests = requests
+            .into_iter()
+            .map(|(table, key)| TableKey {
+                table: table.into(),
+                key: key.into(),
+            })
+            .collect();
+        Self {
+            request_data: Some(RequestData::MultiTableGet(MultiTableGet { requests })),
+            ..Default::default()
         }
     }
 
@@ -79,8 +220,21 @@ impl CommandRequest {
                 table: table.into(),
                 pairs: pairs.into_iter().map(|pair| pair.into()).collect(),
             })),
+            ..Default::default()
         }
     }
+
+    /// 创建 HMSETNX 命令
+    pub fn new_hmsetnx(table: impl Into<String>, pairs: Vec<impl Into<Kvpair>>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmsetnx(Hmsetnx {
+                table: table.into(),
+                pairs: pairs.into_iter().map(|pair| pair.into()).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
     /// 创建 HMDEL 命令
     pub fn new_hmdel(table: impl Into<String>, keys: Vec<impl Into<String>>) -> Self {
         Self {
@@ -88,6 +242,7 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys.into_iter().map(|key| key.into()).collect(),
             })),
+            ..Default::default()
         }
     }
 
@@ -98,13 +253,82 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys.into_iter().map(|key| key.into()).collect(),
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 BULKLOAD 命令
+    pub fn new_bulk_load(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::BulkLoad(BulkLoad {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 BULKDEL 命令
+    pub fn new_bulk_del(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::BulkDel(BulkDel {
+                table: table.into(),
+            })),
+            ..Default::default()
         }
     }
 
     /// 创建 SUBSCRIBE 命令
     pub fn new_subscribe(name: impl Into<String>) -> Self {
+        Self::new_subscribe_from(name, 0)
+    }
+
+    /// 创建 SUBSCRIBE 命令，并重放序号不小于 from_seq 的缓存数据
+    pub fn new_subscribe_from(name: impl Into<String>, from_seq: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: name.into(),
+                from_seq,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 SUBSCRIBE 命令，每 throttle_ms 毫秒最多推送一条数据，并且每 sample_every 条数据只推送一条
+    pub fn new_subscribe_throttled(
+        name: impl Into<String>,
+        throttle_ms: u64,
+        sample_every: u32,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: name.into(),
+                throttle_ms,
+                sample_every,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 SubscribeLogs 命令，接收不低于 level 的服务器日志
+    pub fn new_subscribe_logs(level: impl Into<String>) -> Self {
         Self {
-            request_data: Some(RequestData::Subscribe(Subscribe { topic: name.into() })),
+            request_data: Some(RequestData::SubscribeLogs(SubscribeLogs {
+                level: level.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 WatchKey 命令，先收到 key 当前的值，之后收到它的修改通知
+    pub fn new_watch_key(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::WatchKey(WatchKey {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
         }
     }
 
@@ -115,6 +339,7 @@ impl CommandRequest {
                 topic: name.into(),
                 id,
             })),
+            ..Default::default()
         }
     }
 
@@ -125,9 +350,251 @@ impl CommandRequest {
                 topic: name.into(),
                 data,
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 MPUBLISH 命令
+    pub fn new_mpublish(names: Vec<impl Into<String>>, data: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Mpublish(Mpublish {
+                topics: names.into_iter().map(|name| name.into()).collect(),
+                data,
+            })),
+            ..Default::default()
         }
     }
 
+    /// 创建 CreateTable 命令
+    pub fn new_create_table(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::CreateTable(CreateTable {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 DropTable 命令
+    pub fn new_drop_table(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::DropTable(DropTable {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 RenameTable 命令，overwrite 为 true 时替换已经存在的 to
+    pub fn new_rename_table(
+        from: impl Into<String>,
+        to: impl Into<String>,
+        overwrite: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::RenameTable(RenameTable {
+                from: from.into(),
+                to: to.into(),
+                overwrite,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 MoveNx 命令，dst_key 不存在时把 src_key 移动过去
+    pub fn new_move_nx(
+        src_table: impl Into<String>,
+        src_key: impl Into<String>,
+        dst_table: impl Into<String>,
+        dst_key: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::MoveNx(MoveNx {
+                src_table: src_table.into(),
+                src_key: src_key.into(),
+                dst_table: dst_table.into(),
+                dst_key: dst_key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HEXPIRE 命令，ttl 的单位是秒
+    pub fn new_hexpire(table: impl Into<String>, key: impl Into<String>, ttl: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexpire(Hexpire {
+                table: table.into(),
+                key: key.into(),
+                ttl,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HPERSIST 命令
+    pub fn new_hpersist(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hpersist(Hpersist {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HTTL 命令
+    pub fn new_httl(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Httl(Httl {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HINCRBYFLOAT 命令
+    pub fn new_hincrbyfloat(table: impl Into<String>, key: impl Into<String>, delta: f64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincrbyfloat(Hincrbyfloat {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HINCREX 命令，ttl 的单位是秒，refresh_ttl 为 true 时每次都重新设置过期时间
+    pub fn new_hincr_ex(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        delta: i64,
+        ttl: u64,
+        refresh_ttl: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::HincrEx(HincrEx {
+                table: table.into(),
+                key: key.into(),
+                delta,
+                ttl,
+                refresh_ttl,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HINCRINIT 命令，key 不存在时设置为 initial，存在时加上 delta
+    pub fn new_hincr_init(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        initial: i64,
+        delta: i64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::HincrInit(HincrInit {
+                table: table.into(),
+                key: key.into(),
+                initial,
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 SIZEOF 命令，key 为 None 时获取整个 table 的大小
+    pub fn new_sizeof(table: impl Into<String>, key: Option<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Sizeof(Sizeof {
+                table: table.into(),
+                key,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 COMPACT 命令，table 为 None 时压缩所有 table
+    pub fn new_compact(table: Option<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Compact(Compact { table })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 SCANSTREAM 命令，chunk_size 为 0 时使用服务器的默认值
+    pub fn new_scan_stream(table: impl Into<String>, chunk_size: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::ScanStream(ScanStream {
+                table: table.into(),
+                chunk_size,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 STATS 命令
+    pub fn new_stats() -> Self {
+        Self {
+            request_data: Some(RequestData::Stats(Stats {})),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 DIAGNOSTICS 命令
+    pub fn new_diagnostics() -> Self {
+        Self {
+            request_data: Some(RequestData::Diagnostics(Diagnostics {})),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 MYSUBSCRIPTIONS 命令
+    pub fn new_my_subscriptions() -> Self {
+        Self {
+            request_data: Some(RequestData::MySubscriptions(MySubscriptions {})),
+            ..Default::default()
+        }
+    }
+
+    /// 是否是管理命令，服务器开启 admin_commands 后才能执行
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self.request_data,
+            Some(
+                RequestData::Compact(_)
+                    | RequestData::Diagnostics(_)
+                    | RequestData::SubscribeLogs(_)
+            )
+        )
+    }
+
+    /// 是否是幂等的命令，幂等的命令可以安全地重试
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self.request_data,
+            Some(
+                RequestData::Hget(_)
+                    | RequestData::Hgetall(_)
+                    | RequestData::ScanStream(_)
+                    | RequestData::Hmget(_)
+                    | RequestData::MultiTableGet(_)
+                    | RequestData::Hgetfield(_)
+                    | RequestData::HgetChunked(_)
+                    | RequestData::HgetIfNewer(_)
+                    | RequestData::Hexist(_)
+                    | RequestData::Hmexist(_)
+                    | RequestData::Httl(_)
+                    | RequestData::Sizeof(_)
+                    | RequestData::Stats(_)
+                    | RequestData::CreateTable(_)
+                    | RequestData::Compact(_)
+                    | RequestData::Diagnostics(_)
+                    | RequestData::MySubscriptions(_)
+            )
+        )
+    }
+
     /// 转换成 string 做错误处理
     pub fn format(&self) -> String {
         format!("{:?}", self)
@@ -142,6 +609,32 @@ impl CommandResponse {
         }
     }
 
+    /// 流式命令（如 Subscribe）正常结束时，服务器最后发送的 Response
+    pub fn stream_end() -> Self {
+        CommandResponse {
+            status: StatusCode::NO_CONTENT.as_u16() as _,
+            ..Default::default()
+        }
+    }
+
+    /// HgetIfNewer 读取的 key 没有更新的版本时返回的 Response
+    pub fn not_modified() -> Self {
+        CommandResponse {
+            status: StatusCode::NOT_MODIFIED.as_u16() as _,
+            ..Default::default()
+        }
+    }
+
+    /// 是否是流式命令的结束标记
+    pub fn is_stream_end(&self) -> bool {
+        self.status == StatusCode::NO_CONTENT.as_u16() as u32
+    }
+
+    /// 稍后重试同样的命令是否可能成功，见 is_retryable_status
+    pub fn is_retryable(&self) -> bool {
+        is_retryable_status(self.status)
+    }
+
     pub fn internal_error(msg: String) -> Self {
         CommandResponse {
             status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
@@ -216,6 +709,76 @@ impl From<i64> for Value {
     }
 }
 
+/// 从f64转成Value，NaN 和 Infinity 没有确定的顺序和相等性，所以不允许存入
+impl TryFrom<f64> for Value {
+    type Error = KvError;
+
+    fn try_from(f: f64) -> Result<Self, Self::Error> {
+        if !f.is_finite() {
+            return Err(KvError::ConvertError(f.to_string(), "finite Float"));
+        }
+        Ok(Self {
+            value: Some(value::Value::Float(f)),
+        })
+    }
+}
+
+/// 从SystemTime转成Value，精度为毫秒，超出 i64 范围时取最大/最小值
+impl From<SystemTime> for Value {
+    fn from(t: SystemTime) -> Self {
+        let millis = match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => i64::try_from(d.as_millis()).unwrap_or(i64::MAX),
+            Err(e) => i64::try_from(e.duration().as_millis()).map_or(i64::MIN, |v| -v),
+        };
+        Self {
+            value: Some(value::Value::Timestamp(millis)),
+        }
+    }
+}
+
+/// 从Duration转成Value，精度为毫秒，超出 i64 范围时取最大值
+impl From<Duration> for Value {
+    fn from(d: Duration) -> Self {
+        let millis = i64::try_from(d.as_millis()).unwrap_or(i64::MAX);
+        Self {
+            value: Some(value::Value::Duration(millis)),
+        }
+    }
+}
+
+impl Value {
+    /// 检查 Value 是否可以存入 storage，Float 必须是有限的值（不能是 NaN 或 Infinity），
+    /// 这样存储的数据之间总能用 partial_cmp 比较；Duration 不能是负数
+    pub fn validate(&self) -> Result<(), KvError> {
+        match self.value {
+            Some(value::Value::Float(f)) if !f.is_finite() => Err(KvError::InvalidCommand(
+                format!("float value must be finite, got {f}"),
+            )),
+            Some(value::Value::Duration(d)) if d < 0 => Err(KvError::InvalidCommand(format!(
+                "duration value must not be negative, got {d}"
+            ))),
+            Some(value::Value::Map(ref map)) => map
+                .pairs
+                .iter()
+                .filter_map(|p| p.value.as_ref())
+                .try_for_each(|v| v.validate()),
+            _ => Ok(()),
+        }
+    }
+
+    /// 获取 Map 中的一个字段，不是 Map 时返回 None
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        match &self.value {
+            Some(value::Value::Map(map)) => map
+                .pairs
+                .iter()
+                .find(|p| p.key == name)
+                .and_then(|p| p.value.as_ref()),
+            _ => None,
+        }
+    }
+}
+
 /// 从Value转换成CommandResponse
 impl From<Value> for CommandResponse {
     fn from(v: Value) -> Self {
@@ -241,20 +804,14 @@ impl From<Vec<Kvpair>> for CommandResponse {
 /// 从KvError 转换成 CommandResponse
 impl From<KvError> for CommandResponse {
     fn from(e: KvError) -> Self {
-        let mut result = Self {
-            status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
+        Self {
+            status: e.status().as_u16() as _,
             message: e.to_string(),
             values: vec![],
             pairs: vec![],
-        };
-
-        match e {
-            KvError::NotFound(_) => result.status = StatusCode::NOT_FOUND.as_u16() as _,
-            KvError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
-            _ => {}
-        };
-
-        result
+            error_code: e.code(),
+            seq: 0,
+        }
     }
 }
 
@@ -291,6 +848,17 @@ impl TryFrom<&Value> for i64 {
     }
 }
 
+impl TryFrom<Value> for String {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::String(s)) => Ok(s),
+            _ => Err(KvError::ConvertError(v.format(), "String")),
+        }
+    }
+}
+
 impl TryFrom<Value> for f64 {
     type Error = KvError;
 
@@ -324,6 +892,35 @@ impl TryFrom<Value> for bool {
     }
 }
 
+impl TryFrom<Value> for SystemTime {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Timestamp(t)) => {
+                let offset = Duration::from_millis(t.unsigned_abs());
+                let time = match t >= 0 {
+                    true => UNIX_EPOCH.checked_add(offset),
+                    false => UNIX_EPOCH.checked_sub(offset),
+                };
+                time.ok_or_else(|| KvError::ConvertError(format!("{t}"), "SystemTime"))
+            }
+            _ => Err(KvError::ConvertError(v.format(), "Timestamp")),
+        }
+    }
+}
+
+impl TryFrom<Value> for Duration {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Duration(d)) if d >= 0 => Ok(Duration::from_millis(d as u64)),
+            _ => Err(KvError::ConvertError(v.format(), "Duration")),
+        }
+    }
+}
+
 impl TryFrom<Value> for Vec<u8> {
     type Error = KvError;
 
@@ -352,6 +949,32 @@ impl From<(Box<[u8]>, Box<[u8]>)> for Kvpair {
     }
 }
 
+/// 从HashMap转成Value，按 key 排序，这样同样内容的 Map 总是相等的
+impl From<HashMap<String, Value>> for Value {
+    fn from(map: HashMap<String, Value>) -> Self {
+        let mut pairs: Vec<Kvpair> = map.into_iter().map(Into::into).collect();
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        Self {
+            value: Some(value::Value::Map(ValueMap { pairs })),
+        }
+    }
+}
+
+impl TryFrom<Value> for HashMap<String, Value> {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Map(map)) => Ok(map
+                .pairs
+                .into_iter()
+                .map(|p| (p.key, p.value.unwrap_or_default()))
+                .collect()),
+            _ => Err(KvError::ConvertError(v.format(), "HashMap")),
+        }
+    }
+}
+
 impl<const N: usize> From<&[u8; N]> for Value {
     fn from(buf: &[u8; N]) -> Self {
         Bytes::copy_from_slice(&buf[..]).into()
@@ -366,33 +989,81 @@ impl From<Bytes> for Value {
     }
 }
 
+/// 用于 CLI 和日志的可读格式：字符串原样输出，整数、浮点数和 bool 输出字面值，
+/// 二进制输出为 0x 开头的小写 hex，空的 Value 输出 (nil)
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.value {
-            Some(value) => write!(f, "{:?}", value),
-            None => Ok({}),
+            Some(value::Value::String(s)) => write!(f, "{s}"),
+            Some(value::Value::Binary(buf)) => {
+                write!(f, "0x")?;
+                buf.iter().try_for_each(|b| write!(f, "{b:02x}"))
+            }
+            Some(value::Value::Integer(i)) => write!(f, "{i}"),
+            // 使用 Debug 格式，整数值的浮点数输出为 1.0，和整数区分开
+            Some(value::Value::Float(n)) => write!(f, "{n:?}"),
+            Some(value::Value::Bool(b)) => write!(f, "{b}"),
+            Some(value::Value::Timestamp(t)) => write!(f, "Timestamp({})", format_timestamp(*t)),
+            Some(value::Value::Duration(d)) => write!(f, "Duration({}.{:03}s)", d / 1000, d % 1000),
+            Some(value::Value::Map(map)) => {
+                write!(f, "Map({{")?;
+                for (i, pair) in map.pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match &pair.value {
+                        Some(value) => write!(f, "{}: {}", pair.key, value)?,
+                        None => write!(f, "{}: (nil)", pair.key)?,
+                    }
+                }
+                write!(f, "}})")
+            }
+            None => write!(f, "(nil)"),
         }
     }
 }
 
+// 把 Unix 毫秒时间戳格式化成 RFC 3339 格式的 UTC 时间，如 2024-07-11T04:10:32.000Z
+fn format_timestamp(millis: i64) -> String {
+    let secs = millis.div_euclid(1000);
+    let (days, secs_of_day) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // 从 1970-01-01 开始的天数转换成公历日期，算法来自 http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        millis.rem_euclid(1000)
+    )
+}
+
 impl Display for Kvpair {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.value.is_some() {
-            return write!(
-                f,
-                "key: {}, value: {}",
-                self.key,
-                self.value.as_ref().unwrap(),
-            );
-        } else {
-            return write!(f, "key: {}, value: None", self.key,);
+        match &self.value {
+            Some(value) => write!(f, "{} => {}", self.key, value),
+            None => write!(f, "{} => (nil)", self.key),
         }
     }
 }
 
 impl Display for CommandResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Status: {}\n", self.status)?;
+        writeln!(f, "Status: {}", self.status)?;
+
+        if self.error_code != 0 {
+            writeln!(f, "Error code: {}", self.error_code)?;
+        }
 
         if !self.message.is_empty() {
             writeln!(f, "Message: {}", self.message)?;
@@ -421,12 +1092,147 @@ impl TryFrom<&CommandResponse> for i64 {
 
     fn try_from(value: &CommandResponse) -> Result<Self, Self::Error> {
         if value.status != StatusCode::OK.as_u16() as u32 {
-            return Err(KvError::ConvertError(value.format(), "CommandResponse"));
+            Err(KvError::ConvertError(value.format(), "CommandResponse"))
         } else {
-            match value.values.get(0) {
+            match value.values.first() {
                 Some(v) => v.try_into(),
                 None => Err(KvError::ConvertError(value.format(), "CommandResponse")),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_should_convert_from_and_to_system_time() {
+        let t = UNIX_EPOCH + Duration::from_millis(1_720_671_032_123);
+        let v: Value = t.into();
+        assert_eq!(v.value, Some(value::Value::Timestamp(1_720_671_032_123)));
+        assert_eq!(SystemTime::try_from(v.clone()).unwrap(), t);
+        assert_eq!(v.to_string(), "Timestamp(2024-07-11T04:10:32.123Z)");
+
+        // 1970 年之前的时间是负数
+        let t = UNIX_EPOCH - Duration::from_millis(1500);
+        let v: Value = t.into();
+        assert_eq!(v.value, Some(value::Value::Timestamp(-1500)));
+        assert_eq!(SystemTime::try_from(v.clone()).unwrap(), t);
+        assert_eq!(v.to_string(), "Timestamp(1969-12-31T23:59:58.500Z)");
+
+        assert!(SystemTime::try_from(Value::from(10)).is_err());
+    }
+
+    #[test]
+    fn duration_should_convert_from_and_to_std_duration() {
+        let v: Value = Duration::from_millis(1500).into();
+        assert_eq!(v.value, Some(value::Value::Duration(1500)));
+        assert_eq!(
+            Duration::try_from(v.clone()).unwrap(),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(v.to_string(), "Duration(1.500s)");
+
+        // 负数的 Duration 不能转换，也不能存入 storage
+        let v = Value {
+            value: Some(value::Value::Duration(-1)),
+        };
+        assert!(Duration::try_from(v.clone()).is_err());
+        assert!(v.validate().is_err());
+    }
+
+    #[test]
+    fn timestamps_and_durations_should_be_ordered() {
+        let earlier: Value = (UNIX_EPOCH + Duration::from_secs(1)).into();
+        let later: Value = (UNIX_EPOCH + Duration::from_secs(2)).into();
+        assert!(earlier < later);
+
+        let short: Value = Duration::from_secs(1).into();
+        let long: Value = Duration::from_secs(2).into();
+        assert!(short < long);
+
+        // 不同类型之间按 oneof 中定义的顺序比较，和值的大小无关
+        assert!(Value::from(i64::MAX) < later);
+        assert!(later < short);
+    }
+
+    #[test]
+    fn value_and_kvpair_should_display() {
+        let cases = [
+            (Value::from("hello"), "hello"),
+            (Value::from(""), ""),
+            (Value::from(b"\x00\xffab"), "0x00ff6162"),
+            (Value::from(Bytes::new()), "0x"),
+            (Value::from(-42), "-42"),
+            (Value::try_from(1.5).unwrap(), "1.5"),
+            (Value::try_from(2.0).unwrap(), "2.0"),
+            (Value::from(true), "true"),
+            (Value::from(false), "false"),
+            (Value::from(Duration::from_millis(1500)), "Duration(1.500s)"),
+            (Value::default(), "(nil)"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(value.to_string(), expected);
+        }
+
+        assert_eq!(Kvpair::new("k", "v").to_string(), "k => v");
+        assert_eq!(Kvpair::new("k", 1).to_string(), "k => 1");
+        let pair = Kvpair {
+            key: "k".into(),
+            value: None,
+        };
+        assert_eq!(pair.to_string(), "k => (nil)");
+    }
+
+    fn user(name: &str, age: i64) -> Value {
+        HashMap::from([
+            ("name".to_string(), Value::from(name)),
+            ("age".to_string(), Value::from(age)),
+        ])
+        .into()
+    }
+
+    #[test]
+    fn map_should_convert_display_and_order() {
+        let v = user("alice", 30);
+        assert_eq!(v.to_string(), "Map({age: 30, name: alice})");
+        assert_eq!(v.field("name"), Some(&"alice".into()));
+        assert_eq!(v.field("email"), None);
+
+        let map = HashMap::<String, Value>::try_from(v.clone()).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(Value::from(map), v);
+        assert!(HashMap::<String, Value>::try_from(Value::from(1)).is_err());
+
+        // Map 按排序后的字段逐个比较，排在其他类型之后
+        assert!(user("alice", 30) < user("alice", 31));
+        assert!(user("bob", 30) < user("alice", 31));
+        assert!(user("alice", 30) < user("bob", 30));
+        assert!(Value::from(Duration::from_secs(1)) < v);
+
+        // 嵌套的 value 也需要是合法的
+        let invalid = Value {
+            value: Some(value::Value::Map(ValueMap {
+                pairs: vec![Kvpair::new(
+                    "score",
+                    Value {
+                        value: Some(value::Value::Float(f64::NAN)),
+                    },
+                )],
+            })),
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn map_should_round_trip_through_frame() {
+        use crate::FrameCoder;
+        use bytes::BytesMut;
+
+        let res = CommandResponse::from(vec![Kvpair::new("u1", user("alice", 30))]);
+        let mut buf = BytesMut::new();
+        res.encode_frame(&mut buf).unwrap();
+        assert_eq!(CommandResponse::decode_frame(&mut buf).unwrap(), res);
+    }
+}
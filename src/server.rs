@@ -1,10 +1,9 @@
-use std::{env, str::FromStr};
+use std::{env, fs, str::FromStr};
 
 use anyhow::Result;
-use kv::{start_server_with_config, RotationConfig, ServerConfig, QUIC_SERVER_CONFIG};
+use kv::{start_server_with_config, LogBridge, RotationConfig, ServerConfig, QUIC_SERVER_CONFIG};
 use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::{runtime, trace, Resource};
-use tokio::fs;
 use tracing::span;
 use tracing_subscriber::{
     filter,
@@ -14,15 +13,19 @@ use tracing_subscriber::{
     EnvFilter, Layer,
 };
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     // 如果有环境变量，使用环境变量中的 config
     let config = match env::var("KV_SERVER_CONFIG") {
-        Ok(path) => fs::read_to_string(&path).await?,
+        Ok(path) => fs::read_to_string(&path)?,
         Err(_) => QUIC_SERVER_CONFIG.to_string(),
     };
     let config: ServerConfig = toml::from_str(&config)?;
 
+    // 根据配置创建 runtime，而不是使用 #[tokio::main] 的默认配置
+    config.runtime.build()?.block_on(run(config))
+}
+
+async fn run(config: ServerConfig) -> Result<()> {
     let tracer = opentelemetry_otlp::new_pipeline()
         .tracing()
         .with_exporter(
@@ -61,11 +64,18 @@ async fn main() -> Result<()> {
         .event_format(format().compact())
         .with_writer(non_blocking);
 
+    // console layer 需要 tokio 的 trace 级别的事件，所以 EnvFilter 只作用于日志相关的 layer
+    let log_layers = stdout_log
+        .and_then(fmt_layer.with_filter(log_file_level))
+        .and_then(opentelemetry.with_filter(jaeger_level))
+        .with_filter(EnvFilter::from_default_env());
+    // 管理员可以用 SubscribeLogs 订阅的日志
+    let log_bridge = LogBridge::global().clone().with_filter(level);
+
     tracing_subscriber::registry()
-        .with(EnvFilter::from_default_env())
-        .with(stdout_log)
-        .with(fmt_layer.with_filter(log_file_level))
-        .with(opentelemetry.with_filter(jaeger_level))
+        .with(console_layer(log.tokio_console))
+        .with(log_layers)
+        .with(log_bridge)
         .init();
 
     let root = span!(tracing::Level::INFO, "app_start", work_units = 2);
@@ -75,3 +85,24 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+// 默认监听 127.0.0.1:6669，可以用 TOKIO_CONSOLE_BIND 等环境变量修改
+#[cfg(feature = "tokio-console")]
+fn console_layer<S>(enable: bool) -> Option<impl Layer<S>>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    enable.then(|| {
+        console_subscriber::ConsoleLayer::builder()
+            .with_default_env()
+            .spawn()
+    })
+}
+
+#[cfg(not(feature = "tokio-console"))]
+fn console_layer<S: tracing::Subscriber>(enable: bool) -> Option<impl Layer<S>> {
+    if enable {
+        eprintln!("tokio_console is ignored: kvs is built without the tokio-console feature");
+    }
+    None::<tracing_subscriber::layer::Identity>
+}
@@ -10,6 +10,31 @@ impl CommandService for Hget {
     }
 }
 
+impl CommandService for Hgetfield {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let value = match store.get(&self.table, &self.key) {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                return KvError::NotFound(format!("table {}, key {}", self.table, self.key)).into()
+            }
+            Err(e) => return e.into(),
+        };
+        if !matches!(value.value, Some(value::Value::Map(_))) {
+            return KvError::InvalidCommand(format!("value of key {} is not a map", self.key))
+                .into();
+        }
+        match value.field(&self.field) {
+            Some(v) => v.clone().into(),
+            None => KvError::NotFound(format!(
+                "table {}, key {}, field {}",
+                self.table, self.key, self.field
+            ))
+            .into(),
+        }
+    }
+}
+
+// 按位置返回，重复的 key 也各返回一个
 impl CommandService for Hmget {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         self.keys
@@ -23,6 +48,20 @@ impl CommandService for Hmget {
     }
 }
 
+// 和 Hmget 一样按位置返回，每个 key 可以在不同的 table 中
+impl CommandService for MultiTableGet {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        self.requests
+            .iter()
+            .map(|r| match store.get(&r.table, &r.key) {
+                Ok(Some(v)) => v,
+                _ => Value::default(),
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
 impl CommandService for Hgetall {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         match store.get_all(&self.table) {
@@ -34,17 +73,39 @@ impl CommandService for Hgetall {
 
 impl CommandService for Hset {
     fn execute(self, store: &impl Storage) -> CommandResponse {
+        let Some(v) = self.pair else {
+            return Value::default().into();
+        };
+        let value = v.value.unwrap_or_default();
+        if !self.get_old.unwrap_or(true) {
+            return match store.put(&self.table, v.key, value) {
+                Ok(()) => Value::default().into(),
+                Err(e) => e.into(),
+            };
+        }
+        match store.set(&self.table, v.key, value) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hgetset {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // set 返回旧的值，各个 storage 保证读旧值和写入是原子的
         match self.pair {
             Some(v) => match store.set(&self.table, v.key, v.value.unwrap_or_default()) {
                 Ok(Some(v)) => v.into(),
                 Ok(None) => Value::default().into(),
                 Err(e) => e.into(),
             },
-            None => Value::default().into(),
+            None => KvError::InvalidCommand("Hgetset has no pair".into()).into(),
         }
     }
 }
 
+// 按顺序写入，重复的 key 最后一个生效，每个位置返回它写入前的值
 impl CommandService for Hmset {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         let pairs = self.pairs;
@@ -62,8 +123,24 @@ impl CommandService for Hmset {
     }
 }
 
+impl CommandService for Hmsetnx {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // 各个 storage 保证检查和写入是原子的，要么全部写入，要么都不写入
+        match store.set_batch_if_absent(&self.table, self.pairs) {
+            Ok(applied) => Value::from(applied).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
 impl CommandService for Hdel {
     fn execute(self, store: &impl Storage) -> CommandResponse {
+        if !self.get_old.unwrap_or(true) {
+            return match store.remove(&self.table, &self.key) {
+                Ok(()) => Value::default().into(),
+                Err(e) => e.into(),
+            };
+        }
         match store.del(&self.table, &self.key) {
             Ok(Some(v)) => v.into(),
             Ok(None) => Value::default().into(),
@@ -72,6 +149,17 @@ impl CommandService for Hdel {
     }
 }
 
+impl CommandService for Hgetdel {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // del 返回被删除的值，各个 storage 保证它是原子的
+        match store.del(&self.table, &self.key) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => KvError::NotFound(format!("table {}, key {}", self.table, self.key)).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
 impl CommandService for Hmdel {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         self.keys
@@ -85,6 +173,47 @@ impl CommandService for Hmdel {
     }
 }
 
+impl CommandService for CreateTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.create_table(&self.table) {
+            Ok(_) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for DropTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.drop_table(&self.table) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for MoveNx {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.move_nx(
+            &self.src_table,
+            &self.src_key,
+            &self.dst_table,
+            &self.dst_key,
+        ) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for RenameTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.rename_table(&self.from, &self.to, self.overwrite) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
 impl CommandService for Hexist {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         match store.contains(&self.table, &self.key) {
@@ -107,8 +236,111 @@ impl CommandService for Hmexist {
     }
 }
 
+impl CommandService for Hincrbyfloat {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // 和存入的 Float 一样，NaN 和 Infinity 不允许参与计算
+        if !self.delta.is_finite() {
+            return KvError::InvalidCommand(format!(
+                "float delta must be finite, got {}",
+                self.delta
+            ))
+            .into();
+        }
+        // update 保证读取旧值和写入新值是原子的
+        let result = store.update(&self.table, &self.key, |old| {
+            let old = match old {
+                None => 0.0,
+                Some(v) => match v.value {
+                    None => 0.0,
+                    Some(value::Value::Float(f)) => f,
+                    Some(value::Value::Integer(i)) => i as f64,
+                    _ => return Err(KvError::ConvertError(v.format(), "Float")),
+                },
+            };
+            Value::try_from(old + self.delta)
+        });
+        match result {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for HincrInit {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // 和 HINCREX 一样在 update 中读取旧值并写入新值，并发时不会重复初始化
+        let result = store.update(&self.table, &self.key, |old| {
+            let Some(v) = old else {
+                return Ok(self.initial.into());
+            };
+            let old = match v.value {
+                Some(value::Value::Integer(i)) => i,
+                _ => return Err(KvError::ConvertError(v.format(), "Integer")),
+            };
+            let new = old.checked_add(self.delta).ok_or_else(|| {
+                KvError::InvalidCommand(format!("increment {old} by {} overflows", self.delta))
+            })?;
+            Ok(new.into())
+        });
+        match result {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sizeof {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.size_of(&self.table, self.key.as_deref()) {
+            Ok(Some(size)) => vec![
+                Value::from(size.bytes as i64),
+                Value::from(size.approximate),
+            ]
+            .into(),
+            Ok(None) => KvError::NotFound(format!(
+                "table {}, key {}",
+                self.table,
+                self.key.unwrap_or_default()
+            ))
+            .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Compact {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let table = self.table.as_deref();
+        let compact = || -> Result<Vec<Value>, KvError> {
+            let before = estimate_size(store, table)?;
+            store.compact(table)?;
+            let after = estimate_size(store, table)?;
+            Ok(vec![(before as i64).into(), (after as i64).into()])
+        };
+        match compact() {
+            Ok(values) => values.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// table 为 None 时累加所有 table 的大小
+fn estimate_size(store: &impl Storage, table: Option<&str>) -> Result<u64, KvError> {
+    let tables = match table {
+        Some(table) => vec![table.to_string()],
+        None => store.tables()?,
+    };
+    let mut bytes = 0;
+    for table in tables {
+        bytes += store.size_of(&table, None)?.map_or(0, |f| f.bytes);
+    }
+    Ok(bytes)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use super::*;
     use crate::{assert_res_error, assert_res_ok};
 
@@ -125,6 +357,194 @@ mod tests {
         assert_res_ok(&res, &["world".into()], &[]);
     }
 
+    #[test]
+    fn hgetset_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hgetset("table", "key", 1);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hgetset("table", "key", 2);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[1.into()], &[]);
+    }
+
+    #[test]
+    fn concurrent_hgetset_should_not_lose_updates() {
+        test_concurrent_hgetset(MemTable::new());
+
+        let dir = tempfile::tempdir().unwrap();
+        test_concurrent_hgetset(SledDb::new(dir.path()));
+
+        let dir = tempfile::tempdir().unwrap();
+        test_concurrent_hgetset(RocksDB::new(dir.path()));
+    }
+
+    fn test_concurrent_hgetset(store: impl Storage) {
+        let cmd = CommandRequest::new_hset("table", "key", 0);
+        dispatch(cmd, &store);
+
+        // 每个线程写入不同的值，所有返回的旧值加上最终的值，应该恰好是写入过的所有值
+        let mut values: Vec<i64> = std::thread::scope(|s| {
+            let handles: Vec<_> = (1..=8)
+                .map(|i| {
+                    let store = &store;
+                    s.spawn(move || {
+                        (0..50)
+                            .map(|j| {
+                                let cmd = CommandRequest::new_hgetset("table", "key", i * 100 + j);
+                                let res = dispatch(cmd, store);
+                                res.values[0].clone().try_into().unwrap()
+                            })
+                            .collect::<Vec<i64>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        let last = store.get("table", "key").unwrap().unwrap();
+        values.push(last.try_into().unwrap());
+        values.sort();
+
+        let mut expected: Vec<i64> = (1..=8)
+            .flat_map(|i| (0..50).map(move |j| i * 100 + j))
+            .collect();
+        expected.push(0);
+        expected.sort();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn hincrbyfloat_should_work() {
+        let store = MemTable::new();
+        // key 不存在时从 0.0 开始
+        let cmd = CommandRequest::new_hincrbyfloat("table", "gauge", 1.5);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[Value::try_from(1.5).unwrap()], &[]);
+
+        let cmd = CommandRequest::new_hincrbyfloat("table", "gauge", -0.25);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[Value::try_from(1.25).unwrap()], &[]);
+        assert_eq!(
+            store.get("table", "gauge").unwrap(),
+            Some(Value::try_from(1.25).unwrap())
+        );
+
+        // Integer 转换成 Float
+        dispatch(CommandRequest::new_hset("table", "count", 10), &store);
+        let cmd = CommandRequest::new_hincrbyfloat("table", "count", 0.5);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[Value::try_from(10.5).unwrap()], &[]);
+    }
+
+    #[test]
+    fn hincrbyfloat_with_non_numeric_value_should_fail() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("table", "key", "hello"), &store);
+        let cmd = CommandRequest::new_hincrbyfloat("table", "key", 1.0);
+        let res = dispatch(cmd, &store);
+        assert_res_error(&res, 400, "Cannot convert value");
+        assert_eq!(store.get("table", "key").unwrap(), Some("hello".into()));
+    }
+
+    #[test]
+    fn hincrbyfloat_should_reject_non_finite() {
+        let store = MemTable::new();
+        for delta in [f64::NAN, f64::INFINITY] {
+            let cmd = CommandRequest::new_hincrbyfloat("table", "key", delta);
+            let res = dispatch(cmd, &store);
+            assert_res_error(&res, 400, "must be finite");
+        }
+        assert_eq!(store.get("table", "key").unwrap(), None);
+
+        // 结果溢出成 Infinity 时不写入
+        dispatch(
+            CommandRequest::new_hincrbyfloat("table", "key", f64::MAX),
+            &store,
+        );
+        let cmd = CommandRequest::new_hincrbyfloat("table", "key", f64::MAX);
+        let res = dispatch(cmd, &store);
+        assert_res_error(&res, 400, "finite Float");
+        assert_eq!(
+            store.get("table", "key").unwrap(),
+            Some(Value::try_from(f64::MAX).unwrap())
+        );
+    }
+
+    #[test]
+    fn concurrent_hincrbyfloat_should_not_lose_updates() {
+        test_concurrent_hincrbyfloat(MemTable::new());
+
+        let dir = tempfile::tempdir().unwrap();
+        test_concurrent_hincrbyfloat(SledDb::new(dir.path()));
+
+        let dir = tempfile::tempdir().unwrap();
+        test_concurrent_hincrbyfloat(RocksDB::new(dir.path()));
+    }
+
+    fn test_concurrent_hincrbyfloat(store: impl Storage) {
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        let cmd = CommandRequest::new_hincrbyfloat("table", "key", 0.5);
+                        dispatch(cmd, &store);
+                    }
+                });
+            }
+        });
+        let value = store.get("table", "key").unwrap().unwrap();
+        assert_eq!(value, Value::try_from(200.0).unwrap());
+    }
+
+    #[test]
+    fn hincr_init_should_initialize_then_increment() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hincr_init("table", "count", 10, 2);
+        let res = dispatch(cmd.clone(), &store);
+        assert_res_ok(&res, &[10.into()], &[]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[12.into()], &[]);
+
+        // 不是整数的值和溢出都返回错误，不修改值
+        dispatch(CommandRequest::new_hset("table", "name", "alice"), &store);
+        let res = dispatch(
+            CommandRequest::new_hincr_init("table", "name", 0, 1),
+            &store,
+        );
+        assert_res_error(&res, 400, "Cannot convert value");
+        let cmd = CommandRequest::new_hincr_init("table", "count", 0, i64::MAX);
+        assert_res_error(&dispatch(cmd, &store), 400, "overflows");
+        assert_eq!(store.get("table", "count").unwrap(), Some(12.into()));
+    }
+
+    #[test]
+    fn concurrent_hincr_init_should_initialize_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        let initialized = std::sync::atomic::AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        let cmd = CommandRequest::new_hincr_init("table", "key", 1000, 1);
+                        let res = dispatch(cmd, &store);
+                        if res.values[0] == 1000.into() {
+                            initialized.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        }
+                    }
+                });
+            }
+        });
+        // 一次初始化，其余 399 次各加 1
+        assert_eq!(initialized.into_inner(), 1);
+        let value = store.get("table", "key").unwrap().unwrap();
+        assert_eq!(value, 1399.into());
+    }
+
     #[test]
     fn hget_should_work() {
         let store = MemTable::new();
@@ -135,6 +555,38 @@ mod tests {
         assert_res_ok(&res, &[10.into()], &[]);
     }
 
+    #[test]
+    fn hgetfield_should_work() {
+        let store = MemTable::new();
+        let user: Value = HashMap::from([
+            ("name".to_string(), Value::from("alice")),
+            ("age".to_string(), Value::from(30)),
+        ])
+        .into();
+        dispatch(
+            CommandRequest::new_hset("users", "u1", user.clone()),
+            &store,
+        );
+        dispatch(CommandRequest::new_hset("users", "u2", "bob"), &store);
+
+        // HGET 返回完整的 Map
+        let res = dispatch(CommandRequest::new_hget("users", "u1"), &store);
+        assert_res_ok(&res, &[user], &[]);
+
+        let res = dispatch(CommandRequest::new_hgetfield("users", "u1", "age"), &store);
+        assert_res_ok(&res, &[30.into()], &[]);
+
+        let res = dispatch(
+            CommandRequest::new_hgetfield("users", "u1", "email"),
+            &store,
+        );
+        assert_res_error(&res, 404, "field email");
+        let res = dispatch(CommandRequest::new_hgetfield("users", "u3", "age"), &store);
+        assert_res_error(&res, 404, "key u3");
+        let res = dispatch(CommandRequest::new_hgetfield("users", "u2", "age"), &store);
+        assert_res_error(&res, 400, "not a map");
+    }
+
     #[test]
     fn hdel_should_work() {
         let store = MemTable::new();
@@ -150,6 +602,96 @@ mod tests {
         assert_res_ok(&res, &[false.into()], &[]);
     }
 
+    #[test]
+    fn hset_and_hdel_without_old_value_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hset("table", "key", 10);
+        dispatch(cmd, &store);
+
+        // 不返回旧值，但仍然写入
+        let cmd = CommandRequest::new_hset("table", "key", 20).without_old_value();
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[Value::default()], &[]);
+        let res = dispatch(CommandRequest::new_hget("table", "key"), &store);
+        assert_res_ok(&res, &[20.into()], &[]);
+
+        let cmd = CommandRequest::new_hdel("table", "key").without_old_value();
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[Value::default()], &[]);
+        let res = dispatch(CommandRequest::new_hexist("table", "key"), &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+    }
+
+    #[test]
+    fn hgetdel_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hset("table", "key", 10);
+        dispatch(cmd, &store);
+
+        let cmd = CommandRequest::new_hgetdel("table", "key");
+        let res = dispatch(cmd.clone(), &store);
+        assert_res_ok(&res, &[10.into()], &[]);
+
+        // key 已经被删除
+        let res = dispatch(cmd, &store);
+        assert_res_error(&res, 404, "Not found");
+    }
+
+    #[test]
+    fn concurrent_hgetdel_should_return_value_once() {
+        test_concurrent_hgetdel(MemTable::new());
+
+        let dir = tempfile::tempdir().unwrap();
+        test_concurrent_hgetdel(SledDb::new(dir.path()));
+
+        let dir = tempfile::tempdir().unwrap();
+        test_concurrent_hgetdel(RocksDB::new(dir.path()));
+    }
+
+    fn test_concurrent_hgetdel(store: impl Storage) {
+        let cmd = CommandRequest::new_hset("table", "token", "secret");
+        dispatch(cmd, &store);
+
+        let cmd = CommandRequest::new_hgetdel("table", "token");
+        let succeeded: usize = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| s.spawn(|| dispatch(cmd.clone(), &store)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|res| res.status == 200)
+                .inspect(|res| assert_res_ok(res, &["secret".into()], &[]))
+                .count()
+        });
+        assert_eq!(succeeded, 1);
+    }
+
+    #[test]
+    fn create_drop_table_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_create_table("table");
+        let res = dispatch(cmd.clone(), &store);
+        assert_res_ok(&res, &[], &[]);
+        // 再次创建什么也不做
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[], &[]);
+
+        let cmd = CommandRequest::new_hset("table", "key", 10);
+        dispatch(cmd, &store);
+
+        let cmd = CommandRequest::new_drop_table("table");
+        let res = dispatch(cmd.clone(), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+
+        // 删除 table 之后读不到之前的数据
+        let cmd = CommandRequest::new_hget("table", "key");
+        let res = dispatch(cmd, &store);
+        assert_res_error(&res, 404, "Not found");
+    }
+
     #[test]
     fn hexist_should_work() {
         let store = MemTable::new();
@@ -228,6 +770,96 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sizeof_should_grow_after_large_insert() {
+        test_sizeof(MemTable::new(), false);
+
+        let dir = tempfile::tempdir().unwrap();
+        test_sizeof(SledDb::new(dir.path()), true);
+
+        let dir = tempfile::tempdir().unwrap();
+        test_sizeof(RocksDB::new(dir.path()), true);
+    }
+
+    fn test_sizeof(store: impl Storage, approximate: bool) {
+        let size = |key: Option<&str>| {
+            let cmd = CommandRequest::new_sizeof("table", key.map(|k| k.to_string()));
+            let res = dispatch(cmd, &store);
+            assert_eq!(res.status, 200);
+            assert_eq!(res.values[1], approximate.into());
+            i64::try_from(res.values[0].clone()).unwrap()
+        };
+
+        dispatch(CommandRequest::new_hset("table", "small", "v"), &store);
+        let before = size(None);
+
+        let large = "x".repeat(64 * 1024);
+        dispatch(CommandRequest::new_hset("table", "large", large), &store);
+        assert!(size(None) > before);
+
+        // 单个 key 的大小是精确的
+        let cmd = CommandRequest::new_sizeof("table", Some("large".into()));
+        let res = dispatch(cmd, &store);
+        let bytes = i64::try_from(res.values[0].clone()).unwrap();
+        assert!(bytes > 64 * 1024);
+        assert_eq!(res.values[1], false.into());
+    }
+
+    #[test]
+    fn compact_should_work_after_many_deletes() {
+        test_compact(MemTable::new());
+
+        let dir = tempfile::tempdir().unwrap();
+        test_compact(SledDb::new(dir.path()));
+
+        let dir = tempfile::tempdir().unwrap();
+        test_compact(RocksDB::new(dir.path()));
+    }
+
+    fn test_compact(store: impl Storage) {
+        let value = "x".repeat(1024);
+        for i in 0..1000 {
+            dispatch(
+                CommandRequest::new_hset("t1", i.to_string(), value.as_str()),
+                &store,
+            );
+            dispatch(
+                CommandRequest::new_hset("t2", i.to_string(), value.as_str()),
+                &store,
+            );
+        }
+        for i in 10..1000 {
+            dispatch(CommandRequest::new_hdel("t1", i.to_string()), &store);
+        }
+
+        for cmd in [
+            CommandRequest::new_compact(Some("t1".into())),
+            CommandRequest::new_compact(None),
+        ] {
+            let res = dispatch(cmd, &store);
+            assert_eq!(res.status, 200);
+            assert_eq!(res.values.len(), 2);
+        }
+
+        // 压缩不影响数据
+        assert_eq!(store.len("t1").unwrap(), 10);
+        assert_eq!(store.len("t2").unwrap(), 1000);
+        let res = dispatch(CommandRequest::new_hget("t1", "9"), &store);
+        assert_res_ok(&res, &[value.into()], &[]);
+    }
+
+    #[test]
+    fn sizeof_with_non_exist_key_should_return_404() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_sizeof("table", Some("key".into()));
+        let res = dispatch(cmd, &store);
+        assert_res_error(&res, 404, "table table, key key");
+
+        // 不存在的 table 大小为 0
+        let res = dispatch(CommandRequest::new_sizeof("table", None), &store);
+        assert_res_ok(&res, &[0.into(), false.into()], &[]);
+    }
+
     #[test]
     fn hmset_should_work() {
         let store = MemTable::new();
@@ -246,6 +878,123 @@ mod tests {
         assert_res_ok(&res, &[], pairs);
     }
 
+    #[test]
+    fn hmget_with_duplicate_keys_should_return_each_position() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("table", "key1", 1), &store);
+        dispatch(CommandRequest::new_hset("table", "key2", 2), &store);
+
+        let keys = vec!["key2", "key1", "not exist key", "key2", "key1"];
+        let res = dispatch(CommandRequest::new_hmget("table", keys), &store);
+        let values = [2.into(), 1.into(), Value::default(), 2.into(), 1.into()];
+        assert_res_ok(&res, &values, &[]);
+    }
+
+    #[test]
+    fn multi_table_get_should_return_values_by_position() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", 1), &store);
+        dispatch(CommandRequest::new_hset("t2", "k1", "v2"), &store);
+        dispatch(CommandRequest::new_hset("t3", "k3", true), &store);
+
+        let requests = vec![
+            ("t3", "k3"),
+            ("t1", "k1"),
+            ("t2", "not exist key"),
+            ("t2", "k1"),
+            ("not exist table", "k1"),
+        ];
+        let res = dispatch(CommandRequest::new_multi_table_get(requests), &store);
+        let values = [
+            true.into(),
+            1.into(),
+            Value::default(),
+            "v2".into(),
+            Value::default(),
+        ];
+        assert_res_ok(&res, &values, &[]);
+    }
+
+    #[test]
+    fn hmset_with_duplicate_keys_should_apply_in_order() {
+        test_hmset_duplicate_keys(MemTable::new());
+
+        let dir = tempfile::tempdir().unwrap();
+        test_hmset_duplicate_keys(SledDb::new(dir.path()));
+
+        let dir = tempfile::tempdir().unwrap();
+        test_hmset_duplicate_keys(RocksDB::new(dir.path()));
+    }
+
+    fn test_hmset_duplicate_keys(store: impl Storage) {
+        dispatch(CommandRequest::new_hset("table", "key1", "old"), &store);
+
+        let pairs = vec![
+            Kvpair::new("key1", 1),
+            Kvpair::new("key2", 2),
+            Kvpair::new("key1", 3),
+            Kvpair::new("key1", 4),
+        ];
+        let res = dispatch(CommandRequest::new_hmset("table", pairs), &store);
+        // 每个位置返回前一次写入的值
+        let values = ["old".into(), Value::default(), 1.into(), 3.into()];
+        assert_res_ok(&res, &values, &[]);
+
+        // 最后一个写入生效
+        let res = dispatch(CommandRequest::new_hgetall("table"), &store);
+        let pairs = [Kvpair::new("key1", 4), Kvpair::new("key2", 2)];
+        assert_res_ok(&res, &[], &pairs);
+    }
+
+    #[test]
+    fn hmsetnx_should_write_all_fresh_keys() {
+        test_hmsetnx_fresh_keys(MemTable::new());
+
+        let dir = tempfile::tempdir().unwrap();
+        test_hmsetnx_fresh_keys(SledDb::new(dir.path()));
+
+        let dir = tempfile::tempdir().unwrap();
+        test_hmsetnx_fresh_keys(RocksDB::new(dir.path()));
+    }
+
+    fn test_hmsetnx_fresh_keys(store: impl Storage) {
+        let pairs = vec![Kvpair::new("key1", 1), Kvpair::new("key2", 2)];
+        let cmd = CommandRequest::new_hmsetnx("table", pairs.clone());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hgetall("table"), &store);
+        assert_res_ok(&res, &[], &pairs);
+    }
+
+    #[test]
+    fn hmsetnx_with_existing_key_should_write_nothing() {
+        test_hmsetnx_existing_key(MemTable::new());
+
+        let dir = tempfile::tempdir().unwrap();
+        test_hmsetnx_existing_key(SledDb::new(dir.path()));
+
+        let dir = tempfile::tempdir().unwrap();
+        test_hmsetnx_existing_key(RocksDB::new(dir.path()));
+    }
+
+    fn test_hmsetnx_existing_key(store: impl Storage) {
+        dispatch(CommandRequest::new_hset("table", "key2", "old"), &store);
+
+        let pairs = vec![
+            Kvpair::new("key1", 1),
+            Kvpair::new("key2", 2),
+            Kvpair::new("key3", 3),
+        ];
+        let cmd = CommandRequest::new_hmsetnx("table", pairs);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+
+        // 已经存在的 key 阻止了整个写入
+        let res = dispatch(CommandRequest::new_hgetall("table"), &store);
+        assert_res_ok(&res, &[], &[Kvpair::new("key2", "old")]);
+    }
+
     #[test]
     fn hmdel_should_work() {
         let store = MemTable::new();
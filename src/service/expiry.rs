@@ -0,0 +1,441 @@
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    command_request::RequestData, value, CommandRequest, CommandResponse, KvError, Kvpair, Storage,
+    Value,
+};
+
+/// key 的过期时间，保存在内存中，只对当前进程有效。
+///
+/// key 过期后不会立即删除，而是在下次有命令访问它时从 storage 中删除
+#[derive(Debug, Default)]
+pub struct Expiry {
+    // table -> key -> 过期的时间点
+    deadlines: DashMap<String, DashMap<String, Instant>>,
+}
+
+impl Expiry {
+    /// 设置 key 的过期时间，key 不存在时返回 false
+    pub fn expire(
+        &self,
+        store: &impl Storage,
+        table: &str,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<bool, KvError> {
+        let deadline = deadline_after(ttl)?;
+        self.purge(store, table, [key])?;
+        if !store.contains(table, key)? {
+            return Ok(false);
+        }
+        self.deadlines
+            .entry(table.to_string())
+            .or_default()
+            .insert(key.to_string(), deadline);
+        Ok(true)
+    }
+
+    /// 原子地给 key 的整数值加上 delta，返回新的值。key 是这次新建的，或者 refresh 为 true 时，
+    /// 同时设置过期时间；ttl 为 0 时不设置。
+    ///
+    /// 写入期间一直持有 key 的过期时间的锁，其他命令 purge 这个 key 时会等待，
+    /// 不会看到还没有过期时间的新 key
+    pub fn incr_ex(
+        &self,
+        store: &impl Storage,
+        table: &str,
+        key: &str,
+        delta: i64,
+        ttl: Duration,
+        refresh: bool,
+    ) -> Result<i64, KvError> {
+        // 在写入之前算好过期时间，ttl 太大时不修改值
+        let deadline = if ttl.is_zero() {
+            None
+        } else {
+            Some(deadline_after(ttl)?)
+        };
+        self.purge(store, table, [key])?;
+        let deadlines = self
+            .deadlines
+            .entry(table.to_string())
+            .or_default()
+            .downgrade();
+        let entry = deadlines.entry(key.to_string());
+
+        // 有并发写入时 f 可能被调用多次，以最后一次为准
+        let (mut created, mut count) = (false, 0);
+        store.update(table, key, |old| {
+            created = old.is_none();
+            let old = match old {
+                None => 0,
+                Some(v) => match v.value {
+                    None => 0,
+                    Some(value::Value::Integer(i)) => i,
+                    _ => return Err(KvError::ConvertError(v.format(), "Integer")),
+                },
+            };
+            count = old.checked_add(delta).ok_or_else(|| {
+                KvError::InvalidCommand(format!("increment {old} by {delta} overflows"))
+            })?;
+            Ok(count.into())
+        })?;
+
+        if let Some(deadline) = deadline.filter(|_| created || refresh) {
+            entry.insert(deadline);
+        }
+        Ok(count)
+    }
+
+    /// 删除 key 的过期时间，返回 key 之前是否有过期时间
+    pub fn persist(&self, table: &str, key: &str) -> bool {
+        self.deadlines
+            .get(table)
+            .is_some_and(|t| t.remove(key).is_some())
+    }
+
+    /// key 剩余的过期时间（秒），没有过期时间返回 -1，key 不存在返回 -2
+    pub fn ttl(&self, store: &impl Storage, table: &str, key: &str) -> Result<i64, KvError> {
+        self.purge(store, table, [key])?;
+        if !store.contains(table, key)? {
+            return Ok(-2);
+        }
+        let deadline = self
+            .deadlines
+            .get(table)
+            .and_then(|t| t.get(key).map(|d| *d));
+        Ok(match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()).as_secs() as i64,
+            None => -1,
+        })
+    }
+
+    /// 从 storage 中删除这些 key 中已经过期的 key
+    pub fn purge<'a>(
+        &self,
+        store: &impl Storage,
+        table: &str,
+        keys: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), KvError> {
+        let Some(deadlines) = self.deadlines.get(table) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        for key in keys {
+            if deadlines.remove_if(key, |_, d| *d <= now).is_some() {
+                store.del(table, key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 从 storage 中删除 table 中所有已经过期的 key
+    pub fn purge_table(&self, store: &impl Storage, table: &str) -> Result<(), KvError> {
+        let keys: Vec<String> = match self.deadlines.get(table) {
+            Some(deadlines) => deadlines.iter().map(|d| d.key().clone()).collect(),
+            None => return Ok(()),
+        };
+        self.purge(store, table, keys.iter().map(|k| k.as_str()))
+    }
+
+    /// 删除 table 中所有 key 的过期时间
+    pub fn clear_table(&self, table: &str) {
+        self.deadlines.remove(table);
+    }
+
+    /// 给 table 改名，from 中 key 的过期时间跟着移到 to，to 中原有的过期时间被清除
+    pub fn rename_table(
+        &self,
+        store: &impl Storage,
+        from: &str,
+        to: &str,
+        overwrite: bool,
+    ) -> Result<bool, KvError> {
+        self.purge_table(store, from)?;
+        let existed = store.rename_table(from, to, overwrite)?;
+        self.deadlines.remove(to);
+        if let Some((_, deadlines)) = self.deadlines.remove(from) {
+            self.deadlines.insert(to.to_string(), deadlines);
+        }
+        Ok(existed)
+    }
+
+    /// dst_key 不存在时把 src_key 移动过去，src_key 的过期时间跟着移到 dst_key
+    pub fn move_nx(
+        &self,
+        store: &impl Storage,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError> {
+        self.purge(store, src_table, [src_key])?;
+        self.purge(store, dst_table, [dst_key])?;
+        if !store.move_nx(src_table, src_key, dst_table, dst_key)? {
+            return Ok(false);
+        }
+        let deadline = self
+            .deadlines
+            .get(src_table)
+            .and_then(|t| t.remove(src_key))
+            .map(|(_, d)| d);
+        match deadline {
+            Some(deadline) => {
+                self.deadlines
+                    .entry(dst_table.to_string())
+                    .or_default()
+                    .insert(dst_key.to_string(), deadline);
+            }
+            None => {
+                self.persist(dst_table, dst_key);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 执行命令前处理过期：删除命令访问的已过期的 key；
+    /// 写入和删除 key 的命令会清除这些 key 的过期时间
+    pub fn before_execute(
+        &self,
+        store: &impl Storage,
+        cmd: &CommandRequest,
+    ) -> Result<(), KvError> {
+        let (table, keys, overwrite) = match &cmd.request_data {
+            Some(RequestData::Hget(v)) => (&v.table, vec![v.key.as_str()], false),
+            Some(RequestData::Hgetfield(v)) => (&v.table, vec![v.key.as_str()], false),
+            Some(RequestData::HgetChunked(v)) => (&v.table, vec![v.key.as_str()], false),
+            Some(RequestData::HgetIfNewer(v)) => (&v.table, vec![v.key.as_str()], false),
+            Some(RequestData::Hmget(v)) => (&v.table, str_keys(&v.keys), false),
+            Some(RequestData::Hexist(v)) => (&v.table, vec![v.key.as_str()], false),
+            Some(RequestData::Hmexist(v)) => (&v.table, str_keys(&v.keys), false),
+            Some(RequestData::MultiTableGet(v)) => {
+                for r in &v.requests {
+                    self.purge(store, &r.table, [r.key.as_str()])?;
+                }
+                return Ok(());
+            }
+            Some(RequestData::Hgetall(v)) => return self.purge_table(store, &v.table),
+            Some(RequestData::ScanStream(v)) => return self.purge_table(store, &v.table),
+            Some(RequestData::Sizeof(v)) => match &v.key {
+                Some(key) => (&v.table, vec![key.as_str()], false),
+                None => return self.purge_table(store, &v.table),
+            },
+            Some(RequestData::Hset(v)) => (&v.table, pair_keys(&v.pair), true),
+            Some(RequestData::Hgetset(v)) => (&v.table, pair_keys(&v.pair), true),
+            Some(RequestData::Hmset(v)) => (&v.table, pair_keys(&v.pairs), true),
+            // 有 key 存在时什么都不写入，不能清除它们的过期时间；写入时这些 key 本来就不存在
+            Some(RequestData::Hmsetnx(v)) => (&v.table, pair_keys(&v.pairs), false),
+            Some(RequestData::Hdel(v)) => (&v.table, vec![v.key.as_str()], true),
+            Some(RequestData::Hgetdel(v)) => (&v.table, vec![v.key.as_str()], true),
+            Some(RequestData::Hmdel(v)) => (&v.table, str_keys(&v.keys), true),
+            // 修改值但保留过期时间
+            Some(RequestData::Hincrbyfloat(v)) => (&v.table, vec![v.key.as_str()], false),
+            Some(RequestData::HincrInit(v)) => (&v.table, vec![v.key.as_str()], false),
+            Some(RequestData::HsetCas(v)) => (&v.table, vec![v.key.as_str()], false),
+            // 过期时间由 incr_ex 处理
+            Some(RequestData::HincrEx(v)) => (&v.table, vec![v.key.as_str()], false),
+            Some(RequestData::WatchKey(v)) => (&v.table, vec![v.key.as_str()], false),
+            Some(RequestData::DropTable(v)) => {
+                self.clear_table(&v.table);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+
+        // 返回旧值的写命令不应该返回已经过期的值，所以写之前也要先删除过期的 key
+        self.purge(store, table, keys.iter().copied())?;
+        if overwrite {
+            for key in keys {
+                self.persist(table, key);
+            }
+        }
+        Ok(())
+    }
+
+    /// 执行 HEXPIRE/HPERSIST/HTTL/HINCREX/RenameTable/MoveNx 命令，其他命令返回 None
+    pub fn execute(&self, store: &impl Storage, cmd: &CommandRequest) -> Option<CommandResponse> {
+        let res = match &cmd.request_data {
+            Some(RequestData::Hexpire(v)) => self
+                .expire(store, &v.table, &v.key, Duration::from_secs(v.ttl))
+                .map(Value::from),
+            Some(RequestData::Hpersist(v)) => Ok(self.persist(&v.table, &v.key).into()),
+            Some(RequestData::Httl(v)) => self.ttl(store, &v.table, &v.key).map(Value::from),
+            Some(RequestData::HincrEx(v)) => self
+                .incr_ex(
+                    store,
+                    &v.table,
+                    &v.key,
+                    v.delta,
+                    Duration::from_secs(v.ttl),
+                    v.refresh_ttl,
+                )
+                .map(Value::from),
+            Some(RequestData::RenameTable(v)) => self
+                .rename_table(store, &v.from, &v.to, v.overwrite)
+                .map(Value::from),
+            Some(RequestData::MoveNx(v)) => self
+                .move_nx(store, &v.src_table, &v.src_key, &v.dst_table, &v.dst_key)
+                .map(Value::from),
+            _ => return None,
+        };
+        Some(match res {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        })
+    }
+}
+
+// ttl 太大时 Instant 会溢出，返回错误而不是 panic
+fn deadline_after(ttl: Duration) -> Result<Instant, KvError> {
+    Instant::now()
+        .checked_add(ttl)
+        .ok_or_else(|| KvError::InvalidCommand(format!("ttl {}s is too large", ttl.as_secs())))
+}
+
+fn str_keys(keys: &[String]) -> Vec<&str> {
+    keys.iter().map(|k| k.as_str()).collect()
+}
+
+fn pair_keys<'a>(pairs: impl IntoIterator<Item = &'a Kvpair>) -> Vec<&'a str> {
+    pairs.into_iter().map(|p| p.key.as_str()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::MemTable;
+
+    #[test]
+    fn expire_should_remove_key_after_ttl() {
+        let store = MemTable::new();
+        let expiry = Expiry::default();
+        store.set("t1", "k1", "v1").unwrap();
+
+        // 不存在的 key 不能设置过期时间
+        assert!(!expiry
+            .expire(&store, "t1", "k2", Duration::from_secs(1))
+            .unwrap());
+        assert!(expiry
+            .expire(&store, "t1", "k1", Duration::from_millis(10))
+            .unwrap());
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+
+        thread::sleep(Duration::from_millis(20));
+        expiry.purge(&store, "t1", ["k1"]).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
+        assert_eq!(expiry.ttl(&store, "t1", "k1").unwrap(), -2);
+    }
+
+    #[test]
+    fn ttl_and_persist_should_work() {
+        let store = MemTable::new();
+        let expiry = Expiry::default();
+        store.set("t1", "k1", "v1").unwrap();
+
+        assert_eq!(expiry.ttl(&store, "t1", "k1").unwrap(), -1);
+        assert_eq!(expiry.ttl(&store, "t1", "k2").unwrap(), -2);
+
+        expiry
+            .expire(&store, "t1", "k1", Duration::from_secs(100))
+            .unwrap();
+        let ttl = expiry.ttl(&store, "t1", "k1").unwrap();
+        assert!((99..=100).contains(&ttl));
+
+        assert!(expiry.persist("t1", "k1"));
+        assert!(!expiry.persist("t1", "k1"));
+        assert_eq!(expiry.ttl(&store, "t1", "k1").unwrap(), -1);
+    }
+
+    #[test]
+    fn expire_with_huge_ttl_should_return_error() {
+        let store = MemTable::new();
+        let expiry = Expiry::default();
+        store.set("t1", "k1", "v1").unwrap();
+
+        let res = expiry.expire(&store, "t1", "k1", Duration::from_secs(u64::MAX));
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+        assert_eq!(expiry.ttl(&store, "t1", "k1").unwrap(), -1);
+
+        // 通过命令执行时返回错误的响应
+        let cmd = CommandRequest::new_hexpire("t1", "k1", u64::MAX);
+        let res = expiry.execute(&store, &cmd).unwrap();
+        assert_eq!(res.status, 400);
+    }
+
+    #[test]
+    fn incr_ex_should_set_ttl_only_on_creation() {
+        let store = MemTable::new();
+        let expiry = Expiry::default();
+        let ttl = Duration::from_millis(50);
+
+        assert_eq!(
+            expiry.incr_ex(&store, "t1", "k1", 1, ttl, false).unwrap(),
+            1
+        );
+        assert_eq!(expiry.ttl(&store, "t1", "k1").unwrap(), 0);
+
+        // 不是新建的 key，不刷新过期时间
+        expiry.persist("t1", "k1");
+        assert_eq!(
+            expiry.incr_ex(&store, "t1", "k1", 2, ttl, false).unwrap(),
+            3
+        );
+        assert_eq!(expiry.ttl(&store, "t1", "k1").unwrap(), -1);
+
+        // refresh 时每次都设置过期时间
+        assert_eq!(
+            expiry.incr_ex(&store, "t1", "k1", -1, ttl, true).unwrap(),
+            2
+        );
+        assert_eq!(expiry.ttl(&store, "t1", "k1").unwrap(), 0);
+
+        // 过期后计数器从 0 重新开始
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(expiry.ttl(&store, "t1", "k1").unwrap(), -2);
+        assert_eq!(
+            expiry.incr_ex(&store, "t1", "k1", 5, ttl, false).unwrap(),
+            5
+        );
+
+        // 不是整数的值和溢出都返回错误，不修改值
+        store.set("t1", "k2", "v2").unwrap();
+        assert!(expiry.incr_ex(&store, "t1", "k2", 1, ttl, false).is_err());
+        store.set("t1", "k3", i64::MAX).unwrap();
+        assert!(expiry.incr_ex(&store, "t1", "k3", 1, ttl, false).is_err());
+        assert_eq!(store.get("t1", "k3").unwrap(), Some(i64::MAX.into()));
+
+        // ttl 太大时返回错误，也不创建 key
+        let huge = Duration::from_secs(u64::MAX);
+        let res = expiry.incr_ex(&store, "t1", "k4", 1, huge, false);
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+        assert_eq!(store.get("t1", "k4").unwrap(), None);
+    }
+
+    #[test]
+    fn move_nx_should_move_ttl_with_key() {
+        let store = MemTable::new();
+        let expiry = Expiry::default();
+        store.set("t1", "k1", "v1").unwrap();
+        store.set("t2", "k2", "old").unwrap();
+        expiry
+            .expire(&store, "t1", "k1", Duration::from_secs(100))
+            .unwrap();
+        expiry
+            .expire(&store, "t2", "k2", Duration::from_millis(10))
+            .unwrap();
+
+        // 已经过期的目标 key 不会阻止移动
+        thread::sleep(Duration::from_millis(20));
+        assert!(expiry.move_nx(&store, "t1", "k1", "t2", "k2").unwrap());
+        assert_eq!(store.get("t2", "k2").unwrap(), Some("v1".into()));
+        assert_eq!(expiry.ttl(&store, "t1", "k1").unwrap(), -2);
+        let ttl = expiry.ttl(&store, "t2", "k2").unwrap();
+        assert!((99..=100).contains(&ttl));
+
+        // 源 key 不存在时不移动
+        assert!(!expiry.move_nx(&store, "t1", "k1", "t2", "k3").unwrap());
+    }
+}
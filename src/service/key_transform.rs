@@ -0,0 +1,124 @@
+use sha2::{Digest, Sha256};
+
+use crate::{command_request::RequestData, CommandRequest, Kvpair};
+
+/// 访问存储之前对 key 做的转换，如大小写归一化、把过长的 key 哈希成固定长度
+///
+/// 转换必须是确定的：同一个 table 和 key 总是得到同样的结果，否则写入的 key 之后读不到。
+/// 转换后的 key 会出现在 HGETALL、ScanStream 的结果和 keyspace 通知中
+pub trait KeyTransform: Send + Sync + 'static {
+    fn transform(&self, table: &str, key: &str) -> String;
+}
+
+/// 把 key 转换成小写，这样 key 不区分大小写
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowercaseKeys;
+
+impl KeyTransform for LowercaseKeys {
+    fn transform(&self, _table: &str, key: &str) -> String {
+        key.to_lowercase()
+    }
+}
+
+/// 超过 max_len 字节的 key 换成 "sha256:" 加上 key 的 SHA-256 的十六进制，其他 key 不变
+#[derive(Debug, Clone, Copy)]
+pub struct HashLongKeys {
+    pub max_len: usize,
+}
+
+impl KeyTransform for HashLongKeys {
+    fn transform(&self, _table: &str, key: &str) -> String {
+        if key.len() <= self.max_len {
+            return key.to_string();
+        }
+        let digest = Sha256::digest(key.as_bytes());
+        let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+        format!("sha256:{hex}")
+    }
+}
+
+// 转换命令中所有的 key，读写命令都经过这里，保证同一个 key 读写时得到同样的结果
+pub(crate) fn transform_command(cmd: &mut CommandRequest, t: &dyn KeyTransform) {
+    let Some(data) = cmd.request_data.as_mut() else {
+        return;
+    };
+    let key = |table: &str, key: &mut String| *key = t.transform(table, key);
+    match data {
+        RequestData::Hget(v) => key(&v.table, &mut v.key),
+        RequestData::Hgetfield(v) => key(&v.table, &mut v.key),
+        RequestData::HgetChunked(v) => key(&v.table, &mut v.key),
+        RequestData::HgetIfNewer(v) => key(&v.table, &mut v.key),
+        RequestData::HsetCas(v) => key(&v.table, &mut v.key),
+        RequestData::Hmget(v) => v.keys.iter_mut().for_each(|k| key(&v.table, k)),
+        RequestData::MultiTableGet(v) => v
+            .requests
+            .iter_mut()
+            .for_each(|r| key(&r.table, &mut r.key)),
+        RequestData::Hset(v) => transform_pairs(&v.table, v.pair.iter_mut(), t),
+        RequestData::Hgetset(v) => transform_pairs(&v.table, v.pair.iter_mut(), t),
+        RequestData::Hmset(v) => transform_pairs(&v.table, v.pairs.iter_mut(), t),
+        RequestData::Hmsetnx(v) => transform_pairs(&v.table, v.pairs.iter_mut(), t),
+        RequestData::Hdel(v) => key(&v.table, &mut v.key),
+        RequestData::Hgetdel(v) => key(&v.table, &mut v.key),
+        RequestData::Hmdel(v) => v.keys.iter_mut().for_each(|k| key(&v.table, k)),
+        RequestData::Hexist(v) => key(&v.table, &mut v.key),
+        RequestData::Hmexist(v) => v.keys.iter_mut().for_each(|k| key(&v.table, k)),
+        RequestData::Hexpire(v) => key(&v.table, &mut v.key),
+        RequestData::Hpersist(v) => key(&v.table, &mut v.key),
+        RequestData::Httl(v) => key(&v.table, &mut v.key),
+        RequestData::Hincrbyfloat(v) => key(&v.table, &mut v.key),
+        RequestData::HincrInit(v) => key(&v.table, &mut v.key),
+        RequestData::HincrEx(v) => key(&v.table, &mut v.key),
+        RequestData::WatchKey(v) => key(&v.table, &mut v.key),
+        RequestData::MoveNx(v) => {
+            key(&v.src_table, &mut v.src_key);
+            key(&v.dst_table, &mut v.dst_key);
+        }
+        RequestData::Sizeof(v) => {
+            if let Some(k) = v.key.as_mut() {
+                key(&v.table, k);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn transform_pairs<'a>(
+    table: &str,
+    pairs: impl Iterator<Item = &'a mut Kvpair>,
+    t: &dyn KeyTransform,
+) {
+    for pair in pairs {
+        pair.key = t.transform(table, &pair.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_long_keys_should_only_hash_long_keys() {
+        let t = HashLongKeys { max_len: 8 };
+        assert_eq!(t.transform("t", "short"), "short");
+
+        let long = "a".repeat(100);
+        let hashed = t.transform("t", &long);
+        assert_eq!(hashed.len(), "sha256:".len() + 64);
+        assert_eq!(hashed, t.transform("t", &long));
+        assert_ne!(hashed, t.transform("t", &"a".repeat(101)));
+    }
+
+    #[test]
+    fn transform_command_should_cover_keys() {
+        let mut cmd =
+            CommandRequest::new_hmset("t", vec![Kvpair::new("Key1", 1), Kvpair::new("KEY2", 2)]);
+        transform_command(&mut cmd, &LowercaseKeys);
+        let pairs = vec![Kvpair::new("key1", 1), Kvpair::new("key2", 2)];
+        assert_eq!(cmd, CommandRequest::new_hmset("t", pairs));
+
+        let mut cmd = CommandRequest::new_sizeof("t", Some("Key".into()));
+        transform_command(&mut cmd, &LowercaseKeys);
+        assert_eq!(cmd, CommandRequest::new_sizeof("t", Some("key".into())));
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    cell::Cell,
+    fmt::{self, Write},
+    sync::{Arc, OnceLock, RwLock, Weak},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+use crate::{Broadcaster, CommandResponse, KvError, Topic, Value};
+
+/// 日志 topic 的前缀，后面是最低的日志级别，如 "__logs@info"。只有管理员可以订阅和发布
+pub const LOG_TOPIC_PREFIX: &str = "__logs@";
+
+/// 只转发这个 crate 的日志
+const CRATE_TARGET: &str = "kv";
+
+// 投递日志的路径上产生的日志，转发它们会形成循环
+const IGNORED_TARGETS: [&str; 3] = [
+    "kv::service::topic",
+    "kv::service::log_bridge",
+    "kv::network::frame",
+];
+
+const LEVELS: [Level; 5] = [
+    Level::ERROR,
+    Level::WARN,
+    Level::INFO,
+    Level::DEBUG,
+    Level::TRACE,
+];
+
+thread_local! {
+    // 正在转发日志，这期间产生的日志不再转发
+    static FORWARDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// 把 tracing 的事件转发到 Broadcaster 的日志 topic 的 Layer，管理员可以用 SubscribeLogs
+/// 实时查看服务器的日志。每条日志是一个 Response，values 依次是级别、target 和格式化后的内容。
+///
+/// 只转发这个 crate 的事件，投递日志的过程中产生的事件会被忽略，避免形成循环
+#[derive(Clone, Default)]
+pub struct LogBridge {
+    broadcasters: Arc<RwLock<Vec<Weak<Broadcaster>>>>,
+}
+
+impl LogBridge {
+    /// 进程中默认的 LogBridge，kvs 把它注册为 tracing 的 Layer，开启了管理命令的服务器会连接到它
+    pub fn global() -> &'static LogBridge {
+        static BRIDGE: OnceLock<LogBridge> = OnceLock::new();
+        BRIDGE.get_or_init(LogBridge::default)
+    }
+
+    /// 把日志转发到 broadcaster，broadcaster 被释放后自动断开
+    pub fn attach(&self, broadcaster: &Arc<Broadcaster>) {
+        let mut broadcasters = self.broadcasters.write().unwrap();
+        broadcasters.retain(|b| b.strong_count() > 0);
+        broadcasters.push(Arc::downgrade(broadcaster));
+    }
+
+    fn forward(&self, event: &Event<'_>) {
+        let meta = event.metadata();
+        // 日志的级别不低于 topic 的级别时才发布到这个 topic
+        let topics: Vec<_> = LEVELS
+            .iter()
+            .filter(|level| meta.level() <= *level)
+            .map(level_topic)
+            .collect();
+        let broadcasters: Vec<_> = {
+            let broadcasters = self.broadcasters.read().unwrap();
+            broadcasters
+                .iter()
+                .filter_map(|b| b.upgrade())
+                .filter(|b| topics.iter().any(|t| b.has_subscribers(t)))
+                .collect()
+        };
+        if broadcasters.is_empty() {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let values = vec![
+            Value::from(meta.level().as_str()),
+            Value::from(meta.target()),
+            Value::from(visitor.0.trim_start()),
+        ];
+        let res = Arc::new(CommandResponse::from(values));
+        for broadcaster in broadcasters {
+            for topic in topics.iter().filter(|t| broadcaster.has_subscribers(t)) {
+                broadcaster.clone().publish(topic.clone(), res.clone());
+            }
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBridge {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let target = event.metadata().target();
+        let own = target == CRATE_TARGET || target.starts_with("kv::");
+        if !own || IGNORED_TARGETS.iter().any(|t| target.starts_with(t)) {
+            return;
+        }
+        // publish 需要在 tokio runtime 中执行
+        if FORWARDING.get() || tokio::runtime::Handle::try_current().is_err() {
+            return;
+        }
+        FORWARDING.set(true);
+        self.forward(event);
+        FORWARDING.set(false);
+    }
+}
+
+/// 订阅不低于 level 的日志的 topic，level 为空时使用 info
+pub fn log_topic(level: &str) -> Result<String, KvError> {
+    if level.is_empty() {
+        return Ok(level_topic(&Level::INFO));
+    }
+    let level: Level = level
+        .parse()
+        .map_err(|_| KvError::InvalidCommand(format!("invalid log level: {level}")))?;
+    Ok(level_topic(&level))
+}
+
+fn level_topic(level: &Level) -> String {
+    format!("{LOG_TOPIC_PREFIX}{}", level.as_str().to_lowercase())
+}
+
+// 把 message 和其他字段格式化成一行
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = match field.name() {
+            "message" => write!(self.0, " {value:?}"),
+            name => write!(self.0, " {name}={value:?}"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tokio::time;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{CommandRequest, MemTable, Service, ServiceInner};
+
+    #[tokio::test]
+    async fn admin_should_receive_logs_of_other_commands() {
+        let bridge = LogBridge::default();
+        let subscriber = tracing_subscriber::registry().with(bridge.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let service: Service = ServiceInner::new(MemTable::new())
+            .admin_commands(true)
+            .log_bridge(&bridge)
+            .into();
+
+        let mut logs = service.execute(CommandRequest::new_subscribe_logs("debug"));
+        // 第一个响应是 subscription id
+        logs.next().await.unwrap();
+
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1"));
+        let found = time::timeout(Duration::from_secs(1), async {
+            while let Some(res) = logs.next().await {
+                let message = String::try_from(res.values[2].clone()).unwrap();
+                if message.contains("Got request") && message.contains("k1") {
+                    assert_eq!(res.values[0], "DEBUG".into());
+                    assert_eq!(res.values[1], "kv::service".into());
+                    return true;
+                }
+            }
+            false
+        })
+        .await;
+        assert_eq!(found, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn logs_should_be_filtered_by_level() {
+        let bridge = LogBridge::default();
+        let subscriber = tracing_subscriber::registry().with(bridge.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let service: Service = ServiceInner::new(MemTable::new())
+            .admin_commands(true)
+            .log_bridge(&bridge)
+            .into();
+
+        let mut logs = service.execute(CommandRequest::new_subscribe_logs("warn"));
+        logs.next().await.unwrap();
+
+        // debug 级别的日志不会发送给只订阅 warn 的管理员
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1"));
+        tracing::warn!(target: "kv::test", "disk is almost full");
+        let res = time::timeout(Duration::from_secs(1), logs.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.values[0], "WARN".into());
+        assert_eq!(res.values[2], "disk is almost full".into());
+
+        assert!(log_topic("verbose").is_err());
+        assert_eq!(log_topic("").unwrap(), "__logs@info");
+    }
+
+    #[tokio::test]
+    async fn log_topics_should_require_admin() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let cmds = [
+            CommandRequest::new_subscribe_logs("info"),
+            CommandRequest::new_subscribe("__logs@info"),
+            CommandRequest::new_publish("__logs@info", vec!["fake".into()]),
+        ];
+        for cmd in cmds {
+            let res = service.execute(cmd).next().await.unwrap();
+            assert_eq!(res.status, 403);
+        }
+    }
+}
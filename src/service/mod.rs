@@ -1,18 +1,57 @@
 mod command_service;
+mod expiry;
+mod key_transform;
+mod log_bridge;
 mod topic;
 mod topic_service;
+mod versions;
 
-pub use topic::{Broadcaster, Topic};
-use topic_service::{StreamingResponse, TopicService};
+pub use expiry::Expiry;
+pub use key_transform::{HashLongKeys, KeyTransform, LowercaseKeys};
+pub use log_bridge::{log_topic, LogBridge, LOG_TOPIC_PREFIX};
+pub use topic::{
+    Broadcaster, DeliveryFailure, DeliveryOptions, LifecycleEvent, SubscriptionStats, Topic,
+    MAX_HISTORY_SIZE,
+};
+pub use topic_service::StreamingResponse;
+use topic_service::TopicService;
+pub use versions::Versions;
 
-use futures::stream;
-use std::sync::Arc;
+use bytes::Bytes;
+use dashmap::DashMap;
+use futures::{stream, StreamExt};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock, RwLockReadGuard,
+    },
+    time::Instant,
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task,
+};
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::{debug, instrument};
 
 use crate::{
-    command_request::RequestData, CommandRequest, CommandResponse, KvError, MemTable, Storage,
+    command_request::RequestData, value, CommandRequest, CommandResponse, CompressionConfig,
+    KvError, Kvpair, LimitsConfig, MemTable, ServerConfig, Storage, Value,
 };
 
+/// ScanStream 不指定 chunk_size 时每个响应中 kv pair 的数量
+pub const SCAN_CHUNK_SIZE: usize = 100;
+/// ScanStream 每个响应中 kv pair 数量的上限
+pub const MAX_SCAN_CHUNK_SIZE: usize = 1000;
+/// HgetChunked 不指定 chunk_size 时每一段的字节数
+pub const VALUE_CHUNK_SIZE: usize = 1024 * 1024;
+/// HgetChunked 每一段字节数的上限
+pub const MAX_VALUE_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+// ScanStream 最多缓存的响应数，缓存满了之后暂停遍历，直到客户端读走数据
+const SCAN_BUFFER: usize = 4;
+
 /// 对command的处理的抽象
 pub trait CommandService {
     // 处理 Command，返回 Response
@@ -47,32 +86,231 @@ impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
     }
 }
 
+/// 连接的信息，连接建立和断开时传给 on_connect / on_disconnect 回调
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// 对端地址，Unix socket 的对端通常没有地址
+    pub remote_addr: Option<SocketAddr>,
+    /// 对端的身份，目前是 TLS 客户端证书（DER 编码）
+    pub peer_identity: Option<Vec<u8>>,
+}
+
 /// Service 数据结构
 pub struct Service<Store = MemTable> {
     // TODO(Wiccy): 通过对key做哈希映射将操作分散到多个线程各自持有的HashMap中，避免加锁
     inner: Arc<ServiceInner<Store>>,
-    broadcaster: Arc<Broadcaster>,
 }
 
 impl<Store> Clone for Service<Store> {
     fn clone(&self) -> Self {
         Self {
             inner: Arc::clone(&self.inner),
-            broadcaster: Arc::clone(&self.broadcaster),
         }
     }
 }
 
 impl<Store: Storage> Service<Store> {
+    pub fn new(store: Store) -> Self {
+        ServiceInner::new(store).into()
+    }
+
+    /// 批量写入一组 kv pair，返回写入的数量
+    pub fn bulk_load(&self, table: &str, mut pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+        if let Some(t) = &self.inner.key_transform {
+            key_transform::transform_pairs(table, pairs.iter_mut(), t.as_ref());
+        }
+        check_values(&pairs)?;
+        self.check_table(table)?;
+        self.check_limits(table, pairs.iter().map(|p| p.key.as_str()))?;
+        let _guard = self.write_guard(true);
+        // 从清除 TTL 到更新完版本号都持有 Versions 的读锁，和其他写命令一样
+        let _versions = self.inner.versions.as_ref().map(|v| v.write_guard());
+        for pair in &pairs {
+            self.inner.expiry.persist(table, &pair.key);
+        }
+        let keys: Vec<String> = match &self.inner.versions {
+            Some(_) => pairs.iter().map(|p| p.key.clone()).collect(),
+            None => vec![],
+        };
+        let count = self.inner.store.set_batch(table, pairs)?;
+        if let Some(versions) = &self.inner.versions {
+            versions.bump(table, keys.iter().map(|k| k.as_str()));
+        }
+        // 批量写入的 key 可能很多，通知中不带 key，表示整个 table 都被修改了
+        self.notify_keyspace(table, "bulk_load", vec![]);
+        Ok(count)
+    }
+
+    /// 批量删除一组 key，返回存在并被删除的数量，key 的数量受 max_keys_per_command 限制
+    pub fn bulk_del(&self, table: &str, mut keys: Vec<String>) -> Result<usize, KvError> {
+        if let Some(t) = &self.inner.key_transform {
+            keys.iter_mut().for_each(|k| *k = t.transform(table, k));
+        }
+        self.check_batch_size(keys.len())?;
+        self.check_table(table)?;
+        let _guard = self.write_guard(true);
+        let _versions = self.inner.versions.as_ref().map(|v| v.write_guard());
+        // 已经过期的 key 不计入删除的数量
+        let store = &self.inner.store;
+        let expiry = &self.inner.expiry;
+        expiry.purge(store, table, keys.iter().map(|k| k.as_str()))?;
+        for key in &keys {
+            expiry.persist(table, key);
+        }
+        let count = store.del_batch(table, &keys)?;
+        if let Some(versions) = &self.inner.versions {
+            versions.remove(table, keys.iter().map(|k| k.as_str()));
+        }
+        self.notify_keyspace(table, "bulk_del", keys.iter().map(|k| k.as_str()).collect());
+        Ok(count)
+    }
+
+    /// 底层存储的调用是否可能阻塞线程
+    pub fn is_blocking(&self) -> bool {
+        self.inner.store.is_blocking()
+    }
+
+    /// 连接建立后调用，通知 on_connect 回调。连接数达到 max_connections 时返回 ServerFull，
+    /// 这个连接不计入连接数，也不需要再调用 disconnected
+    pub fn connected(&self, info: &ConnectionInfo) -> Result<(), KvError> {
+        let max = self.inner.max_connections;
+        self.inner
+            .stats
+            .connections
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| match max {
+                Some(max) if n >= max => None,
+                _ => Some(n + 1),
+            })
+            .map_err(|_| KvError::ServerFull(max.unwrap_or_default()))?;
+        self.inner.on_connect.notify(info);
+        self.inner
+            .broadcaster
+            .emit(LifecycleEvent::ConnectionAccepted(info.remote_addr));
+        Ok(())
+    }
+
+    /// 当前的连接数和连接数的上限
+    pub fn connections(&self) -> (usize, Option<usize>) {
+        let current = self.inner.stats.connections.load(Ordering::Relaxed);
+        (current, self.inner.max_connections)
+    }
+
+    /// 连接断开后调用，通知 on_disconnect 回调
+    pub fn disconnected(&self, info: &ConnectionInfo) {
+        self.inner.stats.connections.fetch_sub(1, Ordering::Relaxed);
+        self.inner.on_disconnect.notify(info);
+    }
+
+    /// 发送响应时的压缩算法和级别
+    pub fn compression(&self) -> CompressionConfig {
+        self.inner.compression
+    }
+
+    /// 订阅连接、stream 和订阅的生命周期事件
+    pub fn events(&self) -> broadcast::Receiver<LifecycleEvent> {
+        self.inner.broadcaster.events()
+    }
+
+    /// 获取 service 使用的 Broadcaster，所有 clone 出来的 service 共享同一个
+    pub fn broadcaster(&self) -> Arc<Broadcaster> {
+        Arc::clone(&self.inner.broadcaster)
+    }
+
     #[instrument(name = "service_execute", skip_all)]
-    pub fn execute(&self, cmd: CommandRequest) -> StreamingResponse {
+    pub fn execute(&self, mut cmd: CommandRequest) -> StreamingResponse {
         debug!("Got request: {:?}", cmd);
+        let request_id = cmd.request_id;
+        self.inner.stats.commands.fetch_add(1, Ordering::Relaxed);
         self.inner.on_received.notify(&cmd);
-        let mut res = dispatch(cmd.clone(), &self.inner.store);
+        if let Some(t) = &self.inner.key_transform {
+            key_transform::transform_command(&mut cmd, t.as_ref());
+        }
+        let checked = match &cmd.request_data {
+            Some(RequestData::Hset(v)) => check_values(&v.pair),
+            Some(RequestData::Hgetset(v)) => check_values(&v.pair),
+            Some(RequestData::Hmset(v)) => check_values(&v.pairs),
+            Some(RequestData::Hmsetnx(v)) => check_values(&v.pairs),
+            Some(RequestData::HsetCas(v)) => v.value.iter().try_for_each(|v| v.validate()),
+            _ => Ok(()),
+        };
+        let checked = checked.and_then(|_| self.check_admin(&cmd));
+        let checked = checked.and_then(|_| self.check_keys_count(&cmd));
+        let checked = checked.and_then(|_| {
+            data_tables(&cmd)
+                .into_iter()
+                .try_for_each(|table| self.check_table(table))
+        });
+        let checked = checked.and_then(|_| match &cmd.request_data {
+            Some(RequestData::Hset(param)) => {
+                let keys = param.pair.iter().map(|p| p.key.as_str());
+                self.check_limits(&param.table, keys)
+            }
+            Some(RequestData::Hgetset(param)) => {
+                let keys = param.pair.iter().map(|p| p.key.as_str());
+                self.check_limits(&param.table, keys)
+            }
+            Some(RequestData::Hmset(param)) => {
+                let keys = param.pairs.iter().map(|p| p.key.as_str());
+                self.check_limits(&param.table, keys)
+            }
+            Some(RequestData::Hmsetnx(param)) => {
+                let keys = param.pairs.iter().map(|p| p.key.as_str());
+                self.check_limits(&param.table, keys)
+            }
+            Some(RequestData::HsetCas(param)) => {
+                self.check_limits(&param.table, [param.key.as_str()].into_iter())
+            }
+            Some(RequestData::Hincrbyfloat(param)) => {
+                self.check_limits(&param.table, [param.key.as_str()].into_iter())
+            }
+            Some(RequestData::HincrInit(param)) => {
+                self.check_limits(&param.table, [param.key.as_str()].into_iter())
+            }
+            Some(RequestData::HincrEx(param)) => {
+                self.check_limits(&param.table, [param.key.as_str()].into_iter())
+            }
+            Some(RequestData::MoveNx(param)) => {
+                self.check_limits(&param.dst_table, [param.dst_key.as_str()].into_iter())
+            }
+            _ => Ok(()),
+        });
+        let is_scan = matches!(cmd.request_data, Some(RequestData::ScanStream(_)));
+        if is_scan && checked.is_ok() {
+            return with_request_id(self.scan_stream(cmd), request_id);
+        }
+        let is_chunked = matches!(cmd.request_data, Some(RequestData::HgetChunked(_)));
+        if is_chunked && checked.is_ok() {
+            return with_request_id(self.hget_chunked(&cmd), request_id);
+        }
+        let is_watch = matches!(cmd.request_data, Some(RequestData::WatchKey(_)));
+        if is_watch && checked.is_ok() {
+            return with_request_id(self.watch_key(&cmd), request_id);
+        }
+        let is_write = !keyspace_event(&cmd).is_empty();
+        let guard = self.write_guard(is_write);
+        let version_guard = self.versions_guard(&cmd);
+        let mut res = match checked {
+            Ok(()) => self.dispatch_with_expiry(cmd.clone()),
+            Err(e) => e.into(),
+        };
+        if res.status >= 400 {
+            *self.inner.stats.errors.entry(res.status).or_default() += 1;
+        }
+        if res.status == 200 {
+            if let Some(versions) = &self.inner.versions {
+                versions.after_execute(&cmd);
+            }
+            for (table, event, keys) in keyspace_event(&cmd) {
+                self.notify_keyspace(table, event, keys);
+            }
+        }
+        drop(version_guard);
+        drop(guard);
 
         if res == CommandResponse::default() {
-            dispatch_stream(cmd, Arc::clone(&self.broadcaster))
+            with_request_id(dispatch_stream(cmd, self.broadcaster()), request_id)
         } else {
+            res.request_id = request_id;
             debug!("Executed response: {:?}", res);
             self.inner.on_executed.notify(&res);
             self.inner.on_before_send.notify(&mut res);
@@ -84,26 +322,519 @@ impl<Store: Storage> Service<Store> {
     }
 }
 
+impl<Store: Storage> Service<Store> {
+    // 先处理命令涉及的 key 的过期，再执行命令
+    fn dispatch_with_expiry(&self, cmd: CommandRequest) -> CommandResponse {
+        // STATS 需要 service 的状态，不经过 dispatch
+        let pairs = match cmd.request_data {
+            Some(RequestData::Stats(_)) => Some(self.stats()),
+            Some(RequestData::Diagnostics(_)) => Some(self.diagnostics()),
+            _ => None,
+        };
+        if let Some(pairs) = pairs {
+            return match pairs {
+                Ok(pairs) => pairs.into(),
+                Err(e) => e.into(),
+            };
+        }
+        let store = &self.inner.store;
+        let expiry = &self.inner.expiry;
+        if let Err(e) = expiry.before_execute(store, &cmd) {
+            return e.into();
+        }
+        if let Some(res) = self.execute_versioned(&cmd) {
+            return res;
+        }
+        match expiry.execute(store, &cmd) {
+            Some(res) => res,
+            None => dispatch(cmd, store),
+        }
+    }
+
+    // 在 blocking 线程里遍历 table，每读满一个 chunk 就放入 channel，channel 满了时阻塞，
+    // 这样客户端读取得慢时服务器最多缓存 SCAN_BUFFER 个 chunk，而不是整个 table
+    fn scan_stream(&self, cmd: CommandRequest) -> StreamingResponse {
+        let (tx, rx) = mpsc::channel(SCAN_BUFFER);
+        let svc = self.clone();
+        task::spawn_blocking(move || {
+            if let Err(e) = svc.scan(&cmd, &tx) {
+                let _ = tx.blocking_send(Arc::new(e.into()));
+            }
+        });
+        Box::pin(ReceiverStream::new(rx))
+    }
+
+    fn scan(
+        &self,
+        cmd: &CommandRequest,
+        tx: &mpsc::Sender<Arc<CommandResponse>>,
+    ) -> Result<(), KvError> {
+        let Some(RequestData::ScanStream(param)) = &cmd.request_data else {
+            return Err(KvError::InvalidCommand("expect ScanStream".into()));
+        };
+        let chunk_size = match param.chunk_size as usize {
+            0 => SCAN_CHUNK_SIZE,
+            n => n.min(MAX_SCAN_CHUNK_SIZE),
+        };
+        let store = &self.inner.store;
+        self.inner.expiry.before_execute(store, cmd)?;
+
+        let mut chunk = Vec::with_capacity(chunk_size);
+        for pair in store.get_iter(&param.table)? {
+            chunk.push(pair);
+            if chunk.len() < chunk_size {
+                continue;
+            }
+            let pairs = std::mem::replace(&mut chunk, Vec::with_capacity(chunk_size));
+            // 客户端已经断开，不再继续遍历
+            if tx.blocking_send(Arc::new(pairs.into())).is_err() {
+                return Ok(());
+            }
+        }
+        if !chunk.is_empty() && tx.blocking_send(Arc::new(chunk.into())).is_err() {
+            return Ok(());
+        }
+        let _ = tx.blocking_send(Arc::new(CommandResponse::stream_end()));
+        Ok(())
+    }
+
+    // 分段发送 value，每一段都是 value 的一个 slice，不复制数据
+    fn hget_chunked(&self, cmd: &CommandRequest) -> StreamingResponse {
+        let (data, chunk_size) = match self.chunked_value(cmd) {
+            Ok(v) => v,
+            Err(e) => return Box::pin(stream::once(async { Arc::new(e.into()) })),
+        };
+        let chunks = (0..data.len()).step_by(chunk_size).map(move |start| {
+            let end = (start + chunk_size).min(data.len());
+            Arc::new(Value::from(data.slice(start..end)).into())
+        });
+        let end = stream::once(async { Arc::new(CommandResponse::stream_end()) });
+        Box::pin(stream::iter(chunks).chain(end))
+    }
+
+    fn chunked_value(&self, cmd: &CommandRequest) -> Result<(Bytes, usize), KvError> {
+        let Some(RequestData::HgetChunked(param)) = &cmd.request_data else {
+            return Err(KvError::InvalidCommand("expect HgetChunked".into()));
+        };
+        let chunk_size = match param.chunk_size as usize {
+            0 => VALUE_CHUNK_SIZE,
+            n => n.min(MAX_VALUE_CHUNK_SIZE),
+        };
+        let store = &self.inner.store;
+        self.inner.expiry.before_execute(store, cmd)?;
+
+        let (table, key) = (&param.table, &param.key);
+        let Some(value) = store.get(table, key)? else {
+            return Err(KvError::NotFound(format!("table {table}, key {key}")));
+        };
+        let data = match value.value {
+            Some(value::Value::Binary(data)) => data,
+            Some(value::Value::String(s)) => Bytes::from(s),
+            _ => {
+                let e = format!("value of key {key} is not binary or string");
+                return Err(KvError::InvalidCommand(e));
+            }
+        };
+        Ok((data, chunk_size))
+    }
+
+    // 订阅 keyspace topic 并读取 key 当前的值，之后只转发涉及这个 key 的通知
+    fn watch_key(&self, cmd: &CommandRequest) -> StreamingResponse {
+        let (rx, current) = match self.watch(cmd) {
+            Ok(v) => v,
+            Err(e) => return Box::pin(stream::once(async { Arc::new(e.into()) })),
+        };
+        let Some(RequestData::WatchKey(param)) = &cmd.request_data else {
+            unreachable!();
+        };
+        let key = Value::from(param.key.as_str());
+        let mut current = Some(Arc::new(current));
+        let events = ReceiverStream::new(rx).flat_map(move |data| {
+            let data = match current.take() {
+                // 第一个响应是 subscription id，紧接着发送当前的值
+                Some(current) => vec![data, current],
+                // 没有 key 的通知表示整个 table 都被修改了
+                None if data.values.len() == 1 || data.values[1..].contains(&key) => vec![data],
+                None => vec![],
+            };
+            stream::iter(data)
+        });
+        let end = stream::once(async { Arc::new(CommandResponse::stream_end()) });
+        Box::pin(events.chain(end))
+    }
+
+    // 持有 watch_lock 的写锁订阅并读取当前值，这期间没有写命令在执行或者发布通知，
+    // 所以每个写入要么体现在读到的值中，要么在订阅之后才发布通知，不会遗漏也不会重复
+    fn watch(
+        &self,
+        cmd: &CommandRequest,
+    ) -> Result<(mpsc::Receiver<Arc<CommandResponse>>, CommandResponse), KvError> {
+        let Some(RequestData::WatchKey(param)) = &cmd.request_data else {
+            return Err(KvError::InvalidCommand("expect WatchKey".into()));
+        };
+        if !self.inner.keyspace_notifications {
+            let e = "WatchKey requires keyspace notifications".into();
+            return Err(KvError::InvalidCommand(e));
+        }
+        let store = &self.inner.store;
+        let _guard = self.inner.watch_lock.write().unwrap();
+        self.inner.expiry.before_execute(store, cmd)?;
+        let rx = self.broadcaster().subscribe(keyspace_topic(&param.table));
+        let current = match store.get(&param.table, &param.key)? {
+            Some(value) => value.into(),
+            None => CommandResponse::ok(),
+        };
+        Ok((rx, current))
+    }
+
+    // HgetIfNewer 和 HsetCas 需要 key 的版本号，其他命令返回 None
+    fn execute_versioned(&self, cmd: &CommandRequest) -> Option<CommandResponse> {
+        let name = match cmd.request_data {
+            Some(RequestData::HgetIfNewer(_)) => "HgetIfNewer",
+            Some(RequestData::HsetCas(_)) => "HsetCas",
+            _ => return None,
+        };
+        let Some(versions) = &self.inner.versions else {
+            let e = format!("{name} requires key versioning");
+            return Some(KvError::InvalidCommand(e).into());
+        };
+        versions.execute(&self.inner.store, cmd)
+    }
+
+    // 开启 key 版本号时，写命令从执行到更新完版本号都持有 Versions 的读锁。
+    // HsetCas 在 Versions::set_cas 中持有写锁，不能先持有读锁
+    fn versions_guard(&self, cmd: &CommandRequest) -> Option<RwLockReadGuard<'_, ()>> {
+        let versions = self.inner.versions.as_ref()?;
+        let is_cas = matches!(cmd.request_data, Some(RequestData::HsetCas(_)));
+        let is_write = !keyspace_event(cmd).is_empty();
+        (is_write && !is_cas).then(|| versions.write_guard())
+    }
+
+    // 开启 keyspace 通知时，写命令从执行到发布完通知都持有 watch_lock 的读锁，见 watch
+    fn write_guard(&self, is_write: bool) -> Option<RwLockReadGuard<'_, ()>> {
+        (is_write && self.inner.keyspace_notifications)
+            .then(|| self.inner.watch_lock.read().unwrap())
+    }
+
+    // 向 table 的 keyspace topic 发布通知，values 是事件名和被修改的 key
+    fn notify_keyspace(&self, table: &str, event: &str, keys: Vec<&str>) {
+        if !self.inner.keyspace_notifications {
+            return;
+        }
+        // 重复的 key 只通知一次，按第一次出现的顺序
+        let mut seen = HashSet::new();
+        let keys = keys.into_iter().filter(|k| seen.insert(*k));
+        let values: Vec<Value> = std::iter::once(event).chain(keys).map(Into::into).collect();
+        let res = Arc::new(values.into());
+        self.broadcaster().publish(keyspace_topic(table), res);
+    }
+
+    // 服务器的运行统计，key 的总数需要遍历所有 table
+    fn stats(&self) -> Result<Vec<Kvpair>, KvError> {
+        let store = &self.inner.store;
+        let stats = &self.inner.stats;
+        let mut pairs = vec![
+            Kvpair::new("uptime", stats.started.elapsed()),
+            Kvpair::new("commands", stats.commands.load(Ordering::Relaxed) as i64),
+            Kvpair::new(
+                "connections",
+                stats.connections.load(Ordering::Relaxed) as i64,
+            ),
+            Kvpair::new(
+                "subscriptions",
+                self.inner.broadcaster.subscription_count() as i64,
+            ),
+        ];
+        if let Some(max) = self.inner.max_connections {
+            pairs.push(Kvpair::new("max_connections", max as i64));
+        }
+        for (backend, count) in store.table_counts()? {
+            pairs.push(Kvpair::new(format!("tables.{backend}"), count as i64));
+        }
+        let mut keys = 0;
+        for table in store.tables()? {
+            keys += store.len(&table)?;
+        }
+        pairs.push(Kvpair::new("keys", keys as i64));
+        Ok(pairs)
+    }
+
+    // 排查问题用的诊断信息，只读取内存中的状态，不像 stats 一样遍历所有 table
+    fn diagnostics(&self) -> Result<Vec<Kvpair>, KvError> {
+        let stats = &self.inner.stats;
+        let broadcaster = &self.inner.broadcaster;
+        let mut pairs = vec![
+            Kvpair::new("version", env!("CARGO_PKG_VERSION")),
+            Kvpair::new("uptime", stats.started.elapsed()),
+            Kvpair::new("commands", stats.commands.load(Ordering::Relaxed) as i64),
+            Kvpair::new(
+                "connections",
+                stats.connections.load(Ordering::Relaxed) as i64,
+            ),
+            Kvpair::new("subscriptions", broadcaster.subscription_count() as i64),
+        ];
+        if let Some(max) = self.inner.max_connections {
+            pairs.push(Kvpair::new("max_connections", max as i64));
+        }
+        if let Some(config) = &self.inner.config {
+            let config = toml::to_string(config)
+                .map_err(|e| KvError::Internal(format!("failed to serialize config: {e}")))?;
+            pairs.push(Kvpair::new("config", config));
+        }
+        for (topic, count) in broadcaster.topic_subscriptions() {
+            let failed = broadcaster.failed_deliveries(&topic);
+            pairs.push(Kvpair::new(format!("topics.{topic}"), count as i64));
+            pairs.push(Kvpair::new(
+                format!("topics.{topic}.failed_deliveries"),
+                failed as i64,
+            ));
+        }
+        for (backend, count) in self.inner.store.table_counts()? {
+            pairs.push(Kvpair::new(format!("tables.{backend}"), count as i64));
+        }
+        for entry in stats.errors.iter() {
+            pairs.push(Kvpair::new(
+                format!("errors.{}", entry.key()),
+                *entry.value() as i64,
+            ));
+        }
+        Ok(pairs)
+    }
+
+    // 管理命令会影响整个服务器，需要在配置中显式开启
+    fn check_admin(&self, cmd: &CommandRequest) -> Result<(), KvError> {
+        if (cmd.is_admin() || uses_log_topic(cmd)) && !self.inner.admin_commands {
+            return Err(KvError::PermissionDenied(
+                "admin commands are disabled on this server".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    // 限制一个命令能操作的 key 的数量，避免一个请求长时间占用 worker
+    fn check_keys_count(&self, cmd: &CommandRequest) -> Result<(), KvError> {
+        let count = match &cmd.request_data {
+            Some(RequestData::Hmget(v)) => v.keys.len(),
+            Some(RequestData::MultiTableGet(v)) => v.requests.len(),
+            Some(RequestData::Hmset(v)) => v.pairs.len(),
+            Some(RequestData::Hmsetnx(v)) => v.pairs.len(),
+            Some(RequestData::Hmdel(v)) => v.keys.len(),
+            Some(RequestData::Hmexist(v)) => v.keys.len(),
+            _ => return Ok(()),
+        };
+        self.check_batch_size(count)
+    }
+
+    fn check_batch_size(&self, count: usize) -> Result<(), KvError> {
+        let Some(max) = self.inner.limits.max_keys_per_command else {
+            return Ok(());
+        };
+        if count > max {
+            return Err(KvError::InvalidCommand(format!(
+                "too many keys: {count}, max keys per command is {max}"
+            )));
+        }
+        Ok(())
+    }
+
+    // 不自动创建 table 时，读写的 table 必须已经存在
+    fn check_table(&self, table: &str) -> Result<(), KvError> {
+        if self.inner.auto_create_tables || self.inner.store.has_table(table)? {
+            return Ok(());
+        }
+        Err(KvError::NotFound(format!("no such table {table}")))
+    }
+
+    // 检查写入这些 key 是否会超出配额，更新已有的 key 总是允许的。
+    // 检查和写入不是原子的，并发写入时可能会略微超出配额
+    fn check_limits<'a>(
+        &self,
+        table: &str,
+        keys: impl Iterator<Item = &'a str>,
+    ) -> Result<(), KvError> {
+        let limits = &self.inner.limits;
+        if limits.max_tables.is_none() && limits.max_keys_per_table.is_none() {
+            return Ok(());
+        }
+
+        let store = &self.inner.store;
+        let mut new_keys = HashSet::new();
+        for key in keys {
+            if !store.contains(table, key)? {
+                new_keys.insert(key);
+            }
+        }
+        if new_keys.is_empty() {
+            return Ok(());
+        }
+
+        let len = store.len(table)?;
+        if let Some(max) = limits.max_tables {
+            // 只有创建新 table 时才需要列出所有 table
+            if len == 0 && store.tables()?.iter().filter(|t| *t != table).count() >= max {
+                return Err(KvError::InsufficientStorage(format!(
+                    "cannot create table {table}, max tables is {max}"
+                )));
+            }
+        }
+        if let Some(max) = limits.max_keys_per_table {
+            if len + new_keys.len() > max {
+                return Err(KvError::InsufficientStorage(format!(
+                    "table {table} cannot have more than {max} keys"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 服务器的运行统计，STATS 命令返回
+#[derive(Debug)]
+struct ServiceStats {
+    /// service 创建的时间
+    started: Instant,
+    /// 处理过的命令数
+    commands: AtomicU64,
+    /// 当前的连接数
+    connections: AtomicUsize,
+    /// 每个错误状态码出现的次数
+    errors: DashMap<u32, u64>,
+}
+
+impl Default for ServiceStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            commands: AtomicU64::new(0),
+            connections: AtomicUsize::new(0),
+            errors: DashMap::new(),
+        }
+    }
+}
+
 /// Service 内部数据结构
 pub struct ServiceInner<Store> {
     store: Store,
+    broadcaster: Arc<Broadcaster>,
+    expiry: Expiry,
+    stats: ServiceStats,
+    limits: LimitsConfig,
+    auto_create_tables: bool,
+    admin_commands: bool,
+    keyspace_notifications: bool,
+    max_connections: Option<usize>,
+    // 让 WatchKey 读取当前值和开始订阅之间没有写命令执行
+    watch_lock: RwLock<()>,
+    // key 的版本号，None 表示不记录
+    versions: Option<Versions>,
+    compression: CompressionConfig,
+    // 去掉敏感信息的配置，在诊断信息中返回
+    config: Option<ServerConfig>,
+    // 访问存储之前对 key 做的转换
+    key_transform: Option<Arc<dyn KeyTransform>>,
+    // 把服务器日志转发到日志 topic
+    log_bridge: Option<LogBridge>,
     on_received: Vec<fn(&CommandRequest)>,
     on_executed: Vec<fn(&CommandResponse)>,
     on_before_send: Vec<fn(&mut CommandResponse)>,
     on_after_send: Vec<fn()>,
+    on_connect: Vec<fn(&ConnectionInfo)>,
+    on_disconnect: Vec<fn(&ConnectionInfo)>,
 }
 
 impl<Store: Storage> ServiceInner<Store> {
     pub fn new(store: Store) -> Self {
         Self {
             store,
+            broadcaster: Default::default(),
+            expiry: Default::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            auto_create_tables: true,
+            admin_commands: false,
+            keyspace_notifications: false,
+            max_connections: None,
+            watch_lock: Default::default(),
+            versions: None,
+            compression: Default::default(),
+            config: None,
+            key_transform: None,
+            log_bridge: None,
             on_received: Vec::new(),
             on_executed: Vec::new(),
             on_before_send: Vec::new(),
             on_after_send: Vec::new(),
+            on_connect: Vec::new(),
+            on_disconnect: Vec::new(),
         }
     }
 
+    /// 使用指定的 Broadcaster 处理 pub/sub 命令
+    pub fn broadcaster(mut self, broadcaster: Broadcaster) -> Self {
+        self.broadcaster = Arc::new(broadcaster);
+        self
+    }
+
+    /// 设置存储配额
+    pub fn limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// 设置读写不存在的 table 时是否自动创建
+    pub fn auto_create_tables(mut self, auto_create_tables: bool) -> Self {
+        self.auto_create_tables = auto_create_tables;
+        self
+    }
+
+    /// 把 bridge 收到的日志发布到日志 topic，管理员可以用 SubscribeLogs 订阅
+    pub fn log_bridge(mut self, bridge: &LogBridge) -> Self {
+        self.log_bridge = Some(bridge.clone());
+        self
+    }
+
+    /// 设置是否允许执行管理命令（如 COMPACT）
+    pub fn admin_commands(mut self, admin_commands: bool) -> Self {
+        self.admin_commands = admin_commands;
+        self
+    }
+
+    /// 设置写命令成功后是否向 table 的 keyspace topic 发布通知
+    pub fn keyspace_notifications(mut self, keyspace_notifications: bool) -> Self {
+        self.keyspace_notifications = keyspace_notifications;
+        self
+    }
+
+    /// 设置是否在内存中记录每个 key 的版本号，HgetIfNewer 需要它
+    pub fn key_versioning(mut self, key_versioning: bool) -> Self {
+        self.versions = key_versioning.then(Versions::default);
+        self
+    }
+
+    /// 设置访问存储之前对 key 做的转换，所有命令和 BulkLoad 都会使用它
+    pub fn key_transform(mut self, transform: impl KeyTransform) -> Self {
+        self.key_transform = Some(Arc::new(transform));
+        self
+    }
+
+    /// 设置同时处理的连接数的上限，None 表示不限制
+    pub fn max_connections(mut self, max_connections: Option<usize>) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// 设置发送响应时的压缩算法和级别
+    pub fn compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// 设置服务器的配置，DIAGNOSTICS 命令会返回去掉私钥和口令之后的配置
+    pub fn server_config(mut self, config: &ServerConfig) -> Self {
+        self.config = Some(config.redacted());
+        self
+    }
+
     pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
         self.on_received.push(f);
         self
@@ -120,13 +851,23 @@ impl<Store: Storage> ServiceInner<Store> {
         self.on_after_send.push(f);
         self
     }
+    pub fn fn_connect(mut self, f: fn(&ConnectionInfo)) -> Self {
+        self.on_connect.push(f);
+        self
+    }
+    pub fn fn_disconnect(mut self, f: fn(&ConnectionInfo)) -> Self {
+        self.on_disconnect.push(f);
+        self
+    }
 }
 
 impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
     fn from(inner: ServiceInner<Store>) -> Self {
+        if let Some(bridge) = &inner.log_bridge {
+            bridge.attach(&inner.broadcaster);
+        }
         Service {
             inner: Arc::new(inner),
-            broadcaster: Default::default(),
         }
     }
 }
@@ -134,30 +875,214 @@ impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
 pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
     match cmd.request_data {
         Some(RequestData::Hget(param)) => param.execute(store),
+        Some(RequestData::Hgetfield(param)) => param.execute(store),
         Some(RequestData::Hset(param)) => param.execute(store),
+        Some(RequestData::Hgetset(param)) => param.execute(store),
         Some(RequestData::Hdel(param)) => param.execute(store),
+        Some(RequestData::Hgetdel(param)) => param.execute(store),
         Some(RequestData::Hexist(param)) => param.execute(store),
         Some(RequestData::Hmget(param)) => param.execute(store),
+        Some(RequestData::MultiTableGet(param)) => param.execute(store),
         Some(RequestData::Hmset(param)) => param.execute(store),
+        Some(RequestData::Hmsetnx(param)) => param.execute(store),
         Some(RequestData::Hmdel(param)) => param.execute(store),
         Some(RequestData::Hmexist(param)) => param.execute(store),
         Some(RequestData::Hgetall(param)) => param.execute(store),
+        Some(RequestData::CreateTable(param)) => param.execute(store),
+        Some(RequestData::DropTable(param)) => param.execute(store),
+        Some(RequestData::RenameTable(param)) => param.execute(store),
+        Some(RequestData::MoveNx(param)) => param.execute(store),
+        Some(RequestData::Hincrbyfloat(param)) => param.execute(store),
+        Some(RequestData::HincrInit(param)) => param.execute(store),
+        Some(RequestData::Sizeof(param)) => param.execute(store),
+        Some(RequestData::Compact(param)) => param.execute(store),
+        Some(RequestData::BulkLoad(_)) => {
+            KvError::InvalidCommand("BulkLoad must be followed by a Kvpair stream".into()).into()
+        }
+        Some(RequestData::BulkDel(_)) => {
+            KvError::InvalidCommand("BulkDel must be followed by a KeyBatch stream".into()).into()
+        }
+        // 订阅状态属于连接，由 ProstServerStream 处理
+        Some(RequestData::MySubscriptions(_)) => {
+            KvError::InvalidCommand("MySubscriptions must be sent over a connection".into()).into()
+        }
+        // 需要 Service 的 keyspace 通知
+        Some(RequestData::WatchKey(_)) => {
+            KvError::InvalidCommand("WatchKey must be executed by Service".into()).into()
+        }
+        // 需要 Service 记录的 key 的版本号
+        Some(RequestData::HgetIfNewer(_)) => {
+            KvError::InvalidCommand("HgetIfNewer must be executed by Service".into()).into()
+        }
+        Some(RequestData::HsetCas(_)) => {
+            KvError::InvalidCommand("HsetCas must be executed by Service".into()).into()
+        }
         None => KvError::InvalidCommand("Request has no data".into()).into(),
         // 处理不了的返回一个啥都不包括的 Response，这样后续可以用 dispatch_stream 处理
         _ => CommandResponse::default(),
     }
 }
 
-/// 从 Request 中得到 Response，目前处理所有 PUBLISH/SUBSCRIBE/UNSUBSCRIBE
+// 写入的 value 必须是合法的，见 Value::validate
+fn check_values<'a>(pairs: impl IntoIterator<Item = &'a Kvpair>) -> Result<(), KvError> {
+    pairs
+        .into_iter()
+        .filter_map(|p| p.value.as_ref())
+        .try_for_each(|v| v.validate())
+}
+
+// 日志 topic 和 SubscribeLogs 一样只有管理员可以订阅，也不能伪造日志
+fn uses_log_topic(cmd: &CommandRequest) -> bool {
+    let is_log = |topic: &str| topic.starts_with(LOG_TOPIC_PREFIX);
+    match &cmd.request_data {
+        Some(RequestData::Subscribe(v)) => is_log(&v.topic),
+        Some(RequestData::Publish(v)) => is_log(&v.topic),
+        Some(RequestData::Mpublish(v)) => v.topics.iter().any(|t| is_log(t)),
+        _ => false,
+    }
+}
+
+// 读写数据的命令所操作的 table，MultiTableGet 会读取多个 table，MoveNx 会修改两个 table
+fn data_tables(cmd: &CommandRequest) -> Vec<&str> {
+    let Some(data) = cmd.request_data.as_ref() else {
+        return vec![];
+    };
+    let table = match data {
+        RequestData::Hget(v) => &v.table,
+        RequestData::Hgetfield(v) => &v.table,
+        RequestData::HgetChunked(v) => &v.table,
+        RequestData::HgetIfNewer(v) => &v.table,
+        RequestData::HsetCas(v) => &v.table,
+        RequestData::Hgetall(v) => &v.table,
+        RequestData::ScanStream(v) => &v.table,
+        RequestData::Hmget(v) => &v.table,
+        RequestData::Hset(v) => &v.table,
+        RequestData::Hgetset(v) => &v.table,
+        RequestData::Hmset(v) => &v.table,
+        RequestData::Hmsetnx(v) => &v.table,
+        RequestData::Hdel(v) => &v.table,
+        RequestData::Hgetdel(v) => &v.table,
+        RequestData::Hmdel(v) => &v.table,
+        RequestData::Hexist(v) => &v.table,
+        RequestData::Hmexist(v) => &v.table,
+        RequestData::Hexpire(v) => &v.table,
+        RequestData::Hpersist(v) => &v.table,
+        RequestData::Httl(v) => &v.table,
+        RequestData::Hincrbyfloat(v) => &v.table,
+        RequestData::HincrInit(v) => &v.table,
+        RequestData::HincrEx(v) => &v.table,
+        RequestData::WatchKey(v) => &v.table,
+        RequestData::Sizeof(v) => &v.table,
+        RequestData::Compact(v) => return v.table.iter().map(|t| t.as_str()).collect(),
+        RequestData::MultiTableGet(v) => {
+            return v.requests.iter().map(|r| r.table.as_str()).collect()
+        }
+        RequestData::MoveNx(v) => return vec![v.src_table.as_str(), v.dst_table.as_str()],
+        _ => return vec![],
+    };
+    vec![table.as_str()]
+}
+
+/// table 的 keyspace 通知所在的 topic。
+///
+/// 开启 keyspace_notifications 后，写命令成功时会向这个 topic 发布一条消息，values 的第一个
+/// 是事件名（如 "hset"），之后是被修改的 key；没有 key 时表示整个 table 都被修改了（如
+/// DropTable）。key 因为过期被删除时不会发送通知
+pub fn keyspace_topic(table: &str) -> String {
+    format!("__keyspace@{table}")
+}
+
+// 写命令对应的 keyspace 事件：(table, 事件名, 被修改的 key)，RenameTable 和 MoveNx 会修改两个 table
+pub(crate) fn keyspace_event(cmd: &CommandRequest) -> Vec<(&str, &'static str, Vec<&str>)> {
+    let Some(data) = cmd.request_data.as_ref() else {
+        return vec![];
+    };
+    let (table, event, keys) = match data {
+        RequestData::Hset(v) => (&v.table, "hset", pair_keys(&v.pair)),
+        RequestData::HsetCas(v) => (&v.table, "hsetcas", vec![v.key.as_str()]),
+        RequestData::Hgetset(v) => (&v.table, "hgetset", pair_keys(&v.pair)),
+        RequestData::Hmset(v) => (&v.table, "hmset", pair_keys(&v.pairs)),
+        RequestData::Hmsetnx(v) => (&v.table, "hmsetnx", pair_keys(&v.pairs)),
+        RequestData::Hdel(v) => (&v.table, "hdel", vec![v.key.as_str()]),
+        RequestData::Hgetdel(v) => (&v.table, "hgetdel", vec![v.key.as_str()]),
+        RequestData::Hmdel(v) => (
+            &v.table,
+            "hmdel",
+            v.keys.iter().map(|k| k.as_str()).collect(),
+        ),
+        RequestData::Hincrbyfloat(v) => (&v.table, "hincrbyfloat", vec![v.key.as_str()]),
+        RequestData::HincrInit(v) => (&v.table, "hincrinit", vec![v.key.as_str()]),
+        RequestData::HincrEx(v) => (&v.table, "hincrex", vec![v.key.as_str()]),
+        RequestData::DropTable(v) => (&v.table, "drop_table", vec![]),
+        RequestData::RenameTable(v) => {
+            return vec![
+                (v.from.as_str(), "rename_from", vec![]),
+                (v.to.as_str(), "rename_to", vec![]),
+            ]
+        }
+        RequestData::MoveNx(v) => {
+            return vec![
+                (
+                    v.src_table.as_str(),
+                    "movenx_from",
+                    vec![v.src_key.as_str()],
+                ),
+                (v.dst_table.as_str(), "movenx_to", vec![v.dst_key.as_str()]),
+            ]
+        }
+        _ => return vec![],
+    };
+    vec![(table.as_str(), event, keys)]
+}
+
+fn pair_keys<'a>(pairs: impl IntoIterator<Item = &'a Kvpair>) -> Vec<&'a str> {
+    pairs.into_iter().map(|p| p.key.as_str()).collect()
+}
+
+/// 给流式响应中的每个 Response 带上命令的 request_id，没有 id 时不做任何事
+pub fn with_request_id(res: StreamingResponse, request_id: u64) -> StreamingResponse {
+    if request_id == 0 {
+        return res;
+    }
+    Box::pin(res.map(move |data| {
+        let mut data = Arc::unwrap_or_clone(data);
+        data.request_id = request_id;
+        Arc::new(data)
+    }))
+}
+
+/// 从 Request 中得到 Response，目前处理所有 PUBLISH/MPUBLISH/SUBSCRIBE/UNSUBSCRIBE
 pub fn dispatch_stream(cmd: CommandRequest, topic: impl Topic) -> StreamingResponse {
     match cmd.request_data {
         Some(RequestData::Subscribe(param)) => param.execute(topic),
         Some(RequestData::Unsubscribe(param)) => param.execute(topic),
         Some(RequestData::Publish(param)) => param.execute(topic),
+        Some(RequestData::Mpublish(param)) => param.execute(topic),
+        Some(RequestData::SubscribeLogs(param)) => param.execute(topic),
         _ => unreachable!(),
     }
 }
 
+// 测试成功的返回结果
+#[cfg(test)]
+pub fn assert_res_ok(res: &CommandResponse, values: &[Value], pairs: &[Kvpair]) {
+    let mut sorted_pairs = res.pairs.clone();
+    sorted_pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(res.status, 200);
+    assert_eq!(res.message, "");
+    assert_eq!(res.values, values);
+    assert_eq!(sorted_pairs, pairs);
+}
+
+// 测试失败的返回结果
+#[cfg(test)]
+pub fn assert_res_error(res: &CommandResponse, code: u32, msg: &str) {
+    assert_eq!(res.status, code);
+    assert!(res.message.contains(msg));
+    assert_eq!(res.values, &[]);
+    assert_eq!(res.pairs, &[]);
+}
+
 #[cfg(test)]
 mod tests {
     use http::StatusCode;
@@ -222,27 +1147,562 @@ mod tests {
         assert_eq!(data.message, "");
         assert_eq!(data.values, vec![Value::default()]);
     }
-}
 
-#[cfg(test)]
-use crate::{Kvpair, Value};
+    #[tokio::test]
+    async fn max_tables_should_be_enforced() {
+        let limits = LimitsConfig {
+            max_tables: Some(1),
+            ..Default::default()
+        };
+        let service: Service = ServiceInner::new(MemTable::new()).limits(limits).into();
 
-// 测试成功的返回结果
-#[cfg(test)]
-pub fn assert_res_ok(res: &CommandResponse, values: &[Value], pairs: &[Kvpair]) {
-    let mut sorted_pairs = res.pairs.clone();
-    sorted_pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    assert_eq!(res.status, 200);
-    assert_eq!(res.message, "");
-    assert_eq!(res.values, values);
-    assert_eq!(sorted_pairs, pairs);
-}
+        let res = execute(&service, CommandRequest::new_hset("t1", "k1", "v1")).await;
+        assert_res_ok(&res, &[Value::default()], &[]);
 
-// 测试失败的返回结果
-#[cfg(test)]
-pub fn assert_res_error(res: &CommandResponse, code: u32, msg: &str) {
-    assert_eq!(res.status, code);
-    assert!(res.message.contains(msg));
-    assert_eq!(res.values, &[]);
-    assert_eq!(res.pairs, &[]);
+        // 已有的 table 可以继续写入新的 key
+        let res = execute(&service, CommandRequest::new_hset("t1", "k2", "v2")).await;
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        // 不能创建新的 table
+        let res = execute(&service, CommandRequest::new_hset("t2", "k1", "v1")).await;
+        assert_res_error(&res, 507, "max tables is 1");
+        let pairs = vec![Kvpair::new("k1", "v1")];
+        assert!(service.bulk_load("t2", pairs).is_err());
+    }
+
+    #[tokio::test]
+    async fn non_finite_float_should_be_rejected() {
+        assert!(Value::try_from(f64::NAN).is_err());
+        assert!(Value::try_from(f64::INFINITY).is_err());
+        let v = Value::try_from(1.5).unwrap();
+
+        let service = Service::new(MemTable::new());
+        let res = execute(&service, CommandRequest::new_hset("t1", "k1", v.clone())).await;
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        // NaN/Infinity 绕过 TryFrom 直接构造也不能存入
+        for f in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let invalid = Value {
+                value: Some(crate::value::Value::Float(f)),
+            };
+            let cmd = CommandRequest::new_hset("t1", "k2", invalid.clone());
+            let res = execute(&service, cmd).await;
+            assert_res_error(&res, 400, "float value must be finite");
+
+            let pairs = vec![Kvpair::new("k2", v.clone()), Kvpair::new("k3", invalid)];
+            let res = execute(&service, CommandRequest::new_hmset("t1", pairs.clone())).await;
+            assert_res_error(&res, 400, "float value must be finite");
+            assert!(service.bulk_load("t1", pairs).is_err());
+        }
+
+        // 存储的数据总能排序
+        let res = execute(&service, CommandRequest::new_hgetall("t1")).await;
+        assert_res_ok(&res, &[], &[Kvpair::new("k1", v)]);
+    }
+
+    #[tokio::test]
+    async fn max_keys_per_command_should_be_enforced() {
+        let limits = LimitsConfig {
+            max_keys_per_command: Some(3),
+            ..Default::default()
+        };
+        let service: Service = ServiceInner::new(MemTable::new()).limits(limits).into();
+
+        // 刚好达到上限的命令可以执行
+        let keys = vec!["k1", "k2", "k3"];
+        let res = execute(&service, CommandRequest::new_hmget("t1", keys)).await;
+        assert_res_ok(&res, &vec![Value::default(); 3], &[]);
+
+        // 超过上限的命令被拒绝
+        let keys = vec!["k1", "k2", "k3", "k4"];
+        let res = execute(&service, CommandRequest::new_hmget("t1", keys)).await;
+        assert_res_error(&res, 400, "max keys per command is 3");
+
+        let pairs: Vec<_> = (0..4).map(|i| Kvpair::new(format!("k{i}"), i)).collect();
+        let res = execute(&service, CommandRequest::new_hmset("t1", pairs)).await;
+        assert_res_error(&res, 400, "max keys per command is 3");
+        let res = execute(&service, CommandRequest::new_hget("t1", "k0")).await;
+        assert_res_error(&res, 404, "Not found");
+    }
+
+    #[tokio::test]
+    async fn max_keys_per_table_should_be_enforced() {
+        let limits = LimitsConfig {
+            max_keys_per_table: Some(2),
+            ..Default::default()
+        };
+        let service: Service = ServiceInner::new(MemTable::new()).limits(limits).into();
+
+        let pairs = vec![Kvpair::new("k1", "v1"), Kvpair::new("k2", "v2")];
+        let res = execute(&service, CommandRequest::new_hmset("t1", pairs)).await;
+        assert_res_ok(&res, &[Value::default(), Value::default()], &[]);
+
+        // 新的 key 超出配额
+        let res = execute(&service, CommandRequest::new_hset("t1", "k3", "v3")).await;
+        assert_res_error(&res, 507, "cannot have more than 2 keys");
+        let pairs = vec![Kvpair::new("k1", "v1"), Kvpair::new("k3", "v3")];
+        let res = execute(&service, CommandRequest::new_hmset("t1", pairs)).await;
+        assert_res_error(&res, 507, "cannot have more than 2 keys");
+
+        // 更新已有的 key 总是允许的
+        let res = execute(&service, CommandRequest::new_hset("t1", "k1", "v")).await;
+        assert_res_ok(&res, &["v1".into()], &[]);
+
+        // 其他 table 不受影响
+        let res = execute(&service, CommandRequest::new_hset("t2", "k3", "v3")).await;
+        assert_res_ok(&res, &[Value::default()], &[]);
+    }
+
+    async fn execute(service: &Service, cmd: CommandRequest) -> Arc<CommandResponse> {
+        service.execute(cmd).next().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn case_insensitive_keys_should_collide() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .key_transform(LowercaseKeys)
+            .into();
+
+        let res = execute(&service, CommandRequest::new_hset("t1", "Key", "v1")).await;
+        assert_res_ok(&res, &[Value::default()], &[]);
+        // 写入 key 时返回 Key 写入的值
+        let res = execute(&service, CommandRequest::new_hset("t1", "key", "v2")).await;
+        assert_res_ok(&res, &["v1".into()], &[]);
+        let res = execute(&service, CommandRequest::new_hget("t1", "KEY")).await;
+        assert_res_ok(&res, &["v2".into()], &[]);
+
+        // BulkLoad 也使用同样的转换
+        service
+            .bulk_load("t1", vec![Kvpair::new("OTHER", "v3")])
+            .unwrap();
+        let res = execute(&service, CommandRequest::new_hgetall("t1")).await;
+        let pairs = [Kvpair::new("key", "v2"), Kvpair::new("other", "v3")];
+        assert_res_ok(&res, &[], &pairs);
+    }
+
+    #[tokio::test]
+    async fn tables_should_be_created_automatically_by_default() {
+        let service = Service::new(MemTable::new());
+
+        let res = execute(&service, CommandRequest::new_hset("t1", "k1", "v1")).await;
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        // 已存在的 table 再次创建什么也不做
+        let res = execute(&service, CommandRequest::new_create_table("t1")).await;
+        assert_res_ok(&res, &[], &[]);
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_ok(&res, &["v1".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn stats_should_count_commands() {
+        let service: Service = Service::new(MemTable::new());
+        fn stat(res: &CommandResponse, key: &str) -> i64 {
+            let pair = res.pairs.iter().find(|p| p.key == key).unwrap();
+            i64::try_from(pair.value.clone().unwrap()).unwrap()
+        }
+
+        let res = execute(&service, CommandRequest::new_stats()).await;
+        assert_eq!(res.status, 200);
+        let commands = stat(&res, "commands");
+        assert_eq!(stat(&res, "keys"), 0);
+        assert_eq!(stat(&res, "tables.memtable"), 0);
+
+        execute(&service, CommandRequest::new_hset("t1", "k1", "v1")).await;
+        execute(&service, CommandRequest::new_hset("t1", "k2", "v2")).await;
+        service.connected(&ConnectionInfo::default()).unwrap();
+
+        // STATS 本身也被统计
+        let res = execute(&service, CommandRequest::new_stats()).await;
+        assert_eq!(stat(&res, "commands"), commands + 3);
+        assert_eq!(stat(&res, "connections"), 1);
+        assert_eq!(stat(&res, "subscriptions"), 0);
+        assert_eq!(stat(&res, "tables.memtable"), 1);
+        assert_eq!(stat(&res, "keys"), 2);
+    }
+
+    #[tokio::test]
+    async fn admin_commands_should_require_permission() {
+        let service: Service = Service::new(MemTable::new());
+        let res = execute(&service, CommandRequest::new_compact(None)).await;
+        assert_res_error(&res, 403, "admin commands are disabled");
+
+        let service: Service = ServiceInner::new(MemTable::new())
+            .admin_commands(true)
+            .into();
+        let res = execute(&service, CommandRequest::new_compact(None)).await;
+        assert_res_ok(&res, &[0.into(), 0.into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn diagnostics_should_not_contain_secrets() {
+        let config: ServerConfig = toml::from_str(crate::TLS_SERVER_CONFIG).unwrap();
+        let crate::ServerSecurityProtocol::Tls(tls) = &config.security else {
+            panic!("expect tls config");
+        };
+        let service: Service = ServiceInner::new(MemTable::new())
+            .admin_commands(true)
+            .server_config(&config)
+            .into();
+        let _rx = service.broadcaster().subscribe("news");
+        execute(&service, CommandRequest::new_hget("t1", "k1")).await;
+
+        let res = execute(&service, CommandRequest::new_diagnostics()).await;
+        assert_eq!(res.status, 200);
+        let get = |key: &str| {
+            let pair = res.pairs.iter().find(|p| p.key == key).unwrap();
+            pair.value.clone().unwrap()
+        };
+        assert_eq!(get("version"), env!("CARGO_PKG_VERSION").into());
+        assert_eq!(get("topics.news"), 1.into());
+        assert_eq!(get("tables.memtable"), 1.into());
+        assert_eq!(get("errors.404"), 1.into());
+
+        // 配置中的私钥被去掉了，证书保留
+        let redacted: ServerConfig =
+            toml::from_str(&String::try_from(get("config")).unwrap()).unwrap();
+        let crate::ServerSecurityProtocol::Tls(redacted_tls) = &redacted.security else {
+            panic!("expect tls config");
+        };
+        assert_eq!(redacted_tls.cert, tls.cert);
+        assert_ne!(redacted_tls.key, tls.key);
+        for pair in &res.pairs {
+            assert!(!format!("{:?}", pair.value).contains("PRIVATE KEY"));
+        }
+    }
+
+    #[tokio::test]
+    async fn strict_mode_should_require_created_tables() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .auto_create_tables(false)
+            .into();
+
+        // 不存在的 table 不能读写
+        let res = execute(&service, CommandRequest::new_hset("t1", "k1", "v1")).await;
+        assert_res_error(&res, 404, "no such table t1");
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_error(&res, 404, "no such table t1");
+        assert!(service
+            .bulk_load("t1", vec![Kvpair::new("k1", "v1")])
+            .is_err());
+
+        // 创建后可以读写
+        let res = execute(&service, CommandRequest::new_create_table("t1")).await;
+        assert_res_ok(&res, &[], &[]);
+        let res = execute(&service, CommandRequest::new_hset("t1", "k1", "v1")).await;
+        assert_res_ok(&res, &[Value::default()], &[]);
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_ok(&res, &["v1".into()], &[]);
+
+        // 删除后不能再读写
+        let res = execute(&service, CommandRequest::new_drop_table("t1")).await;
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_error(&res, 404, "no such table t1");
+        let res = execute(&service, CommandRequest::new_drop_table("t1")).await;
+        assert_res_ok(&res, &[false.into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn rename_table_should_move_data_and_ttl() {
+        let service: Service = Service::new(MemTable::new());
+        execute(&service, CommandRequest::new_hset("users_new", "k1", "v1")).await;
+        execute(
+            &service,
+            CommandRequest::new_hexpire("users_new", "k1", 100),
+        )
+        .await;
+        execute(&service, CommandRequest::new_hset("users", "k1", "old")).await;
+
+        let cmd = CommandRequest::new_rename_table("users_new", "users", false);
+        let res = execute(&service, cmd).await;
+        assert_res_error(&res, 400, "table users already exists");
+
+        let cmd = CommandRequest::new_rename_table("users_new", "users", true);
+        assert_res_ok(&execute(&service, cmd).await, &[true.into()], &[]);
+        let res = execute(&service, CommandRequest::new_hget("users", "k1")).await;
+        assert_res_ok(&res, &["v1".into()], &[]);
+        // 过期时间跟着 key 移到新的 table
+        let res = execute(&service, CommandRequest::new_httl("users", "k1")).await;
+        assert!((99..=100).contains(&i64::try_from(res.as_ref()).unwrap()));
+
+        let cmd = CommandRequest::new_rename_table("users_new", "users", true);
+        assert_res_error(&execute(&service, cmd).await, 404, "table users_new");
+    }
+
+    #[tokio::test]
+    async fn expire_commands_should_work() {
+        let service = Service::new(MemTable::new());
+        execute(&service, CommandRequest::new_hset("t1", "k1", "v1")).await;
+
+        // 不存在的 key
+        let res = execute(&service, CommandRequest::new_hexpire("t1", "k2", 10)).await;
+        assert_res_ok(&res, &[false.into()], &[]);
+        let res = execute(&service, CommandRequest::new_httl("t1", "k2")).await;
+        assert_res_ok(&res, &[(-2).into()], &[]);
+
+        // 设置和删除过期时间不会改变 value
+        let res = execute(&service, CommandRequest::new_httl("t1", "k1")).await;
+        assert_res_ok(&res, &[(-1).into()], &[]);
+        let res = execute(&service, CommandRequest::new_hexpire("t1", "k1", 100)).await;
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = execute(&service, CommandRequest::new_httl("t1", "k1")).await;
+        assert!(res.values[0] == 100.into() || res.values[0] == 99.into());
+        let res = execute(&service, CommandRequest::new_hpersist("t1", "k1")).await;
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = execute(&service, CommandRequest::new_httl("t1", "k1")).await;
+        assert_res_ok(&res, &[(-1).into()], &[]);
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_ok(&res, &["v1".into()], &[]);
+
+        // HSET 会清除过期时间
+        execute(&service, CommandRequest::new_hexpire("t1", "k1", 100)).await;
+        execute(&service, CommandRequest::new_hset("t1", "k1", "v2")).await;
+        let res = execute(&service, CommandRequest::new_httl("t1", "k1")).await;
+        assert_res_ok(&res, &[(-1).into()], &[]);
+
+        // 过期后 key 被删除
+        execute(&service, CommandRequest::new_hexpire("t1", "k1", 0)).await;
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_error(&res, 404, "Not found");
+        let res = execute(&service, CommandRequest::new_hexist("t1", "k1")).await;
+        assert_res_ok(&res, &[false.into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn hincr_ex_should_set_ttl_on_creation() {
+        let service: Service = Service::new(MemTable::new());
+        let ttl = |res: Arc<CommandResponse>| i64::try_from(res.as_ref()).unwrap();
+
+        // 新建的 key 设置过期时间
+        let cmd = CommandRequest::new_hincr_ex("t1", "k1", 1, 100, false);
+        assert_res_ok(&execute(&service, cmd).await, &[1.into()], &[]);
+        let res = execute(&service, CommandRequest::new_httl("t1", "k1")).await;
+        assert!((99..=100).contains(&ttl(res)));
+
+        // 已经存在的 key 不刷新过期时间
+        let cmd = CommandRequest::new_hincr_ex("t1", "k1", 2, 10, false);
+        assert_res_ok(&execute(&service, cmd).await, &[3.into()], &[]);
+        let res = execute(&service, CommandRequest::new_httl("t1", "k1")).await;
+        assert!((99..=100).contains(&ttl(res)));
+
+        // refresh_ttl 时每次都刷新
+        let cmd = CommandRequest::new_hincr_ex("t1", "k1", 2, 10, true);
+        assert_res_ok(&execute(&service, cmd).await, &[5.into()], &[]);
+        let res = execute(&service, CommandRequest::new_httl("t1", "k1")).await;
+        assert!((9..=10).contains(&ttl(res)));
+
+        // 不是整数的值返回错误
+        execute(&service, CommandRequest::new_hset("t1", "k2", "v2")).await;
+        let cmd = CommandRequest::new_hincr_ex("t1", "k2", 1, 10, false);
+        assert_res_error(&execute(&service, cmd).await, 400, "Cannot convert");
+    }
+
+    #[tokio::test]
+    async fn cloned_service_should_share_broadcaster() {
+        let service: Service = Service::new(MemTable::new());
+        let cloned = service.clone();
+        assert!(Arc::ptr_eq(&service.broadcaster(), &cloned.broadcaster()));
+
+        // 一个 service 订阅，另一个 service 发布
+        let mut stream = service.execute(CommandRequest::new_subscribe("lobby"));
+        let id: i64 = stream.next().await.unwrap().as_ref().try_into().unwrap();
+        assert!(id > 0);
+
+        let v: Value = "hello".into();
+        let mut res = cloned.execute(CommandRequest::new_publish("lobby", vec![v.clone()]));
+        assert_res_ok(&res.next().await.unwrap(), &[], &[]);
+
+        let data = stream.next().await.unwrap();
+        assert_res_ok(&data, &[v], &[]);
+    }
+
+    #[tokio::test]
+    async fn writes_should_publish_keyspace_notifications() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .keyspace_notifications(true)
+            .into();
+        let mut stream = service.execute(CommandRequest::new_subscribe(keyspace_topic("t1")));
+        stream.next().await.unwrap();
+
+        // 读命令和其他 table 的写命令不会发到这个 topic
+        execute(&service, CommandRequest::new_hget("t1", "k1")).await;
+        execute(&service, CommandRequest::new_hset("t2", "k1", "v1")).await;
+        execute(&service, CommandRequest::new_hset("t1", "k1", "v1")).await;
+        let data = stream.next().await.unwrap();
+        assert_res_ok(&data, &["hset".into(), "k1".into()], &[]);
+
+        execute(&service, CommandRequest::new_hmdel("t1", vec!["k1", "k2"])).await;
+        let data = stream.next().await.unwrap();
+        assert_res_ok(&data, &["hmdel".into(), "k1".into(), "k2".into()], &[]);
+
+        // 重复的 key 只通知一次
+        let pairs = vec![
+            Kvpair::new("k2", 1),
+            Kvpair::new("k1", 2),
+            Kvpair::new("k2", 3),
+        ];
+        execute(&service, CommandRequest::new_hmset("t1", pairs)).await;
+        let data = stream.next().await.unwrap();
+        assert_res_ok(&data, &["hmset".into(), "k2".into(), "k1".into()], &[]);
+
+        execute(&service, CommandRequest::new_drop_table("t1")).await;
+        let data = stream.next().await.unwrap();
+        assert_res_ok(&data, &["drop_table".into()], &[]);
+
+        execute(&service, CommandRequest::new_hset("t0", "k1", "v1")).await;
+        execute(
+            &service,
+            CommandRequest::new_rename_table("t0", "t1", false),
+        )
+        .await;
+        let data = stream.next().await.unwrap();
+        assert_res_ok(&data, &["rename_to".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn hget_if_newer_should_return_value_only_when_changed() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .key_versioning(true)
+            .into();
+        execute(&service, CommandRequest::new_hset("t1", "k1", "v1")).await;
+
+        // 旧的版本号读到值和当前的版本号
+        let res = execute(&service, CommandRequest::new_hget_if_newer("t1", "k1", 0)).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(res.values[0], Value::from("v1"));
+        let version: i64 = res.values[1].clone().try_into().unwrap();
+
+        // 当前的版本号返回 304，不带 value
+        let cmd = CommandRequest::new_hget_if_newer("t1", "k1", version as u64);
+        let res = execute(&service, cmd.clone()).await;
+        assert_eq!(res.status, 304);
+        assert!(res.values.is_empty());
+
+        // 写入之后又能读到新的值
+        execute(&service, CommandRequest::new_hset("t1", "k1", "v2")).await;
+        let res = execute(&service, cmd).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(res.values[0], Value::from("v2"));
+        assert!(i64::try_from(res.values[1].clone()).unwrap() > version);
+
+        let res = execute(&service, CommandRequest::new_hget_if_newer("t1", "k2", 0)).await;
+        assert_res_error(&res, 404, "Not found");
+
+        let service: Service = Service::new(MemTable::new());
+        let res = execute(&service, CommandRequest::new_hget_if_newer("t1", "k1", 0)).await;
+        assert_res_error(&res, 400, "key versioning");
+    }
+
+    #[tokio::test]
+    async fn watch_key_should_send_current_value_then_changes() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .keyspace_notifications(true)
+            .into();
+        execute(&service, CommandRequest::new_hset("t1", "k1", "v1")).await;
+
+        let mut stream = service.execute(CommandRequest::new_watch_key("t1", "k1"));
+        let id: i64 = stream.next().await.unwrap().as_ref().try_into().unwrap();
+        assert!(id > 0);
+        assert_res_ok(&stream.next().await.unwrap(), &["v1".into()], &[]);
+
+        // 其他 key 的修改不会发送
+        execute(&service, CommandRequest::new_hset("t1", "k2", "v2")).await;
+        execute(&service, CommandRequest::new_hset("t1", "k1", "v3")).await;
+        let data = stream.next().await.unwrap();
+        assert_res_ok(&data, &["hset".into(), "k1".into()], &[]);
+        execute(&service, CommandRequest::new_hdel("t1", "k1")).await;
+        let data = stream.next().await.unwrap();
+        assert_res_ok(&data, &["hdel".into(), "k1".into()], &[]);
+
+        // 不存在的 key 的当前值为空
+        let mut stream = service.execute(CommandRequest::new_watch_key("t1", "k1"));
+        stream.next().await.unwrap();
+        assert_res_ok(&stream.next().await.unwrap(), &[], &[]);
+
+        let service: Service = Service::new(MemTable::new());
+        let mut stream = service.execute(CommandRequest::new_watch_key("t1", "k1"));
+        let res = stream.next().await.unwrap();
+        assert_res_error(&res, 400, "keyspace notifications");
+    }
+
+    #[tokio::test]
+    async fn watch_key_should_not_lose_or_duplicate_concurrent_writes() {
+        // 不超过订阅的 channel 容量，等写入结束再读取通知也不会被丢弃
+        const WRITES: i64 = 100;
+        let service: Service = ServiceInner::new(MemTable::new())
+            .keyspace_notifications(true)
+            .into();
+        let writer = service.clone();
+        let handle = task::spawn_blocking(move || {
+            for _ in 0..WRITES {
+                let cmd = CommandRequest::new_hincr_init("t1", "k1", 1, 1);
+                drop(writer.execute(cmd));
+            }
+        });
+
+        let mut stream = service.execute(CommandRequest::new_watch_key("t1", "k1"));
+        stream.next().await.unwrap();
+        let initial = match stream.next().await.unwrap().values.first() {
+            Some(v) => i64::try_from(v.clone()).unwrap(),
+            None => 0,
+        };
+        handle.await.unwrap();
+
+        // 当前值加上之后收到的通知数正好是写入的次数
+        for _ in initial..WRITES {
+            let data = stream.next().await.unwrap();
+            assert_res_ok(&data, &["hincrinit".into(), "k1".into()], &[]);
+        }
+        let extra = tokio::time::timeout(std::time::Duration::from_millis(50), stream.next()).await;
+        assert!(extra.is_err());
+    }
+
+    #[tokio::test]
+    async fn hset_cas_should_let_only_one_racing_writer_succeed() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .key_versioning(true)
+            .into();
+
+        // 版本号为 0 表示 key 必须不存在
+        let res = execute(&service, CommandRequest::new_hset_cas("t1", "k1", "v0", 0)).await;
+        assert_eq!(res.status, 200);
+        let version: i64 = res.values[0].clone().try_into().unwrap();
+        let res = execute(&service, CommandRequest::new_hset_cas("t1", "k1", "v0", 0)).await;
+        assert_eq!(res.status, 409);
+        assert_eq!(res.values, vec![Value::from(version)]);
+
+        // 两个写者基于同一个版本号同时写入
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let writers = ["v1", "v2"].map(|value| {
+            let (service, barrier) = (service.clone(), barrier.clone());
+            let cmd = CommandRequest::new_hset_cas("t1", "k1", value, version as u64);
+            task::spawn_blocking(move || {
+                barrier.wait();
+                service.execute(cmd)
+            })
+        });
+        let mut results = Vec::new();
+        for writer in writers {
+            let mut stream = writer.await.unwrap();
+            results.push(stream.next().await.unwrap());
+        }
+        results.sort_by_key(|res| res.status);
+
+        // 只有一个成功，另一个冲突，并且拿到成功的写入之后的版本号
+        let (ok, conflict) = (&results[0], &results[1]);
+        assert_eq!(ok.status, 200);
+        let new_version: i64 = ok.values[0].clone().try_into().unwrap();
+        assert!(new_version > version);
+        assert_eq!(conflict.status, StatusCode::CONFLICT.as_u16() as u32);
+        assert!(conflict.message.contains("Version conflict"));
+        assert_eq!(conflict.values, vec![Value::from(new_version)]);
+
+        // 用新的版本号可以继续写入
+        let cmd = CommandRequest::new_hset_cas("t1", "k1", "v3", new_version as u64);
+        let res = execute(&service, cmd).await;
+        assert_eq!(res.status, 200);
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_ok(&res, &["v3".into()], &[]);
+    }
 }
@@ -1,17 +1,37 @@
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+use std::{
+    collections::VecDeque,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
 use dashmap::{DashMap, DashSet};
-use tokio::sync::mpsc;
+use std::fmt::Write;
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{self, error::TrySendError},
+    },
+    task::JoinSet,
+    time::{self, Instant},
+};
 use tracing::{debug, info, instrument, warn};
 
-use crate::{CommandResponse, KvError, Value};
+use crate::{spawn_named_in, CommandResponse, KvError, Notify, Value};
 
 /// topic 里最大存放的数据
 const BROADCAST_CAPACITY: usize = 128;
 
+/// 每个主题最多缓存的数据条数。重放时 channel 里还要放 subscription id，所以比 channel 小 1
+pub const MAX_HISTORY_SIZE: usize = BROADCAST_CAPACITY - 1;
+
+/// 生命周期事件的 channel 大小，接收者落后太多时会丢失最早的事件
+const EVENT_CAPACITY: usize = 1024;
+
 /// 下一个 subscription id
 static NEXT_ID: AtomicU32 = AtomicU32::new(1);
 
@@ -23,10 +43,109 @@ fn get_next_subscription_id() -> u32 {
 pub trait Topic: Send + Sync + 'static {
     /// 订阅某个主题
     fn subscribe(self, name: impl Into<String>) -> mpsc::Receiver<Arc<CommandResponse>>;
-    /// 取消某个主题的订阅
+    /// 订阅某个主题，先重放缓存中序号不小于 from_seq 的数据，再接收新数据
+    fn subscribe_from(
+        self,
+        name: impl Into<String>,
+        from_seq: u64,
+    ) -> mpsc::Receiver<Arc<CommandResponse>>;
+    /// 订阅某个主题，按 options 限制推送的速率
+    fn subscribe_with(
+        self,
+        name: impl Into<String>,
+        from_seq: u64,
+        options: DeliveryOptions,
+    ) -> mpsc::Receiver<Arc<CommandResponse>>;
+    /// 取消某个主题的订阅，返回被删除的 subscription id
     fn unsubscribe(self, name: impl Into<String>, id: u32) -> Result<u32, KvError>;
-    /// 向对应主题发布数据
-    fn publish(self, name: impl Into<String>, value: Arc<CommandResponse>);
+    /// 向对应主题发布数据，返回发布时该主题的订阅数
+    fn publish(self, name: impl Into<String>, value: Arc<CommandResponse>) -> usize;
+    /// 和 publish 一样，但等到数据放入所有当前订阅的 channel（或者 drop_when_full 的订阅因为 channel
+    /// 已满丢弃了数据）之后才完成，不需要等待投递的 task。之前用 publish 发布的数据可能还在投递中
+    fn publish_sync(
+        self,
+        name: impl Into<String>,
+        value: Arc<CommandResponse>,
+    ) -> impl Future<Output = usize> + Send;
+    /// 向一组主题发布同样的数据，返回所有主题的订阅数之和
+    fn mpublish(self, names: Vec<String>, value: Arc<CommandResponse>) -> usize;
+}
+
+/// 发布失败的数据，用于通知调用者
+#[derive(Debug, Clone)]
+pub struct DeliveryFailure {
+    /// 发布的主题
+    pub topic: String,
+    /// 未能收到数据的 subscription id
+    pub id: u32,
+    /// 未送达的数据
+    pub value: Arc<CommandResponse>,
+}
+
+/// 订阅的统计数据，在每次 publish 时更新
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SubscriptionStats {
+    /// channel 中积压的消息数（gauge）
+    pub lag: usize,
+    /// 设置了 drop_when_full 的订阅因为 channel 已满而丢弃的消息数（counter）
+    pub dropped: u64,
+}
+
+/// 订阅的推送选项，用于降低推送给慢速 subscriber 的频率
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DeliveryOptions {
+    /// 两次推送之间的最小间隔，间隔内只保留最新的一条数据，在间隔结束时推送，0 表示不限制
+    pub throttle: Duration,
+    /// 每 N 条数据只推送第一条，0 和 1 表示全部推送
+    pub sample_every: u32,
+    /// channel 已满时丢弃数据（记为发布失败），而不是等待 subscriber 消费。
+    ///
+    /// 默认不丢弃数据，慢速 subscriber 会让投递的 task 等待；只关心最新数据的 subscriber
+    /// 可以打开这个选项，避免积压的投递占用内存
+    pub drop_when_full: bool,
+}
+
+// 设置了推送选项的订阅的状态
+#[derive(Default)]
+struct DeliveryState {
+    options: DeliveryOptions,
+    // 收到的数据条数，用于抽样
+    received: u64,
+    // 上一次推送的时间
+    last_sent: Option<Instant>,
+    // 间隔内等待推送的最新数据
+    pending: Option<Arc<CommandResponse>>,
+}
+
+/// 连接、stream 和订阅的生命周期事件，测试中可以等待某个事件代替 sleep
+#[derive(Debug, Clone, PartialEq)]
+pub enum LifecycleEvent {
+    /// 建立了一个新的连接（完成了握手）
+    ConnectionAccepted(Option<SocketAddr>),
+    /// 打开了一个新的 stream，完成了协议版本的协商
+    StreamOpened(Option<SocketAddr>),
+    /// 增加了一个订阅
+    SubscriptionAdded { topic: String, id: u32 },
+    /// 删除了一个订阅
+    SubscriptionRemoved { topic: String, id: u32 },
+}
+
+// broadcast::Sender 没有实现 Default
+struct EventSender(broadcast::Sender<LifecycleEvent>);
+
+impl Default for EventSender {
+    fn default() -> Self {
+        Self(broadcast::channel(EVENT_CAPACITY).0)
+    }
+}
+
+/// 每个主题最近发布的数据
+#[derive(Default)]
+struct TopicHistory {
+    /// 下一条数据的序号
+    next_seq: u64,
+    /// 最近发布的数据，最多保留 history_size 条
+    buffer: VecDeque<Arc<CommandResponse>>,
 }
 
 /// 用于主题发布和数据订阅的数据结构
@@ -36,13 +155,57 @@ pub struct Broadcaster {
     topics: DashMap<String, DashSet<u32>>,
     /// 所有的订阅列表
     subscriptions: DashMap<u32, mpsc::Sender<Arc<CommandResponse>>>,
+    /// 每个主题发布失败的次数
+    failures: DashMap<String, u64>,
+    /// 发布失败时的回调
+    on_delivery_failed: Vec<fn(&DeliveryFailure)>,
+    /// 死信主题，发布失败的数据会被转发到这个主题
+    dead_letter: Option<String>,
+    /// 每个订阅的统计数据
+    stats: DashMap<u32, SubscriptionStats>,
+    /// 设置了推送选项的订阅
+    delivery: DashMap<u32, DeliveryState>,
+    /// 每个主题的序号和最近发布的数据
+    history: DashMap<String, TopicHistory>,
+    /// 每个主题缓存的数据条数，0 表示不缓存
+    history_size: usize,
+    /// 正在投递数据的 task
+    tasks: Mutex<JoinSet<()>>,
+    /// 调用 shutdown 之后不再接受新的 publish
+    closed: AtomicBool,
+    /// 生命周期事件
+    events: EventSender,
 }
 
 impl Topic for Arc<Broadcaster> {
     #[instrument(name = "topic_subscribe", skip_all)]
     fn subscribe(self, name: impl Into<String>) -> mpsc::Receiver<Arc<CommandResponse>> {
+        self.subscribe_from(name, 0)
+    }
+
+    #[instrument(name = "topic_subscribe_from", skip_all)]
+    fn subscribe_from(
+        self,
+        name: impl Into<String>,
+        from_seq: u64,
+    ) -> mpsc::Receiver<Arc<CommandResponse>> {
+        self.subscribe_with(name, from_seq, DeliveryOptions::default())
+    }
+
+    #[instrument(name = "topic_subscribe_with", skip_all)]
+    fn subscribe_with(
+        self,
+        name: impl Into<String>,
+        from_seq: u64,
+        options: DeliveryOptions,
+    ) -> mpsc::Receiver<Arc<CommandResponse>> {
+        let name = name.into();
+
+        // 持有 history 的锁直到重放结束，避免同时 publish 的数据被重复或遗漏
+        let history = self.history.entry(name.clone()).or_default();
+
         let id = {
-            let entry = self.topics.entry(name.into()).or_default();
+            let entry = self.topics.entry(name.clone()).or_default();
             let id = get_next_subscription_id();
             entry.value().insert(id);
             id
@@ -51,20 +214,36 @@ impl Topic for Arc<Broadcaster> {
         // 生成一个 mpsc channel
         let (tx, rx) = mpsc::channel(BROADCAST_CAPACITY);
 
+        // 立刻发送 subscription id 到 rx，新建的 channel 不会满
         let v: Value = (id as i64).into();
+        if let Err(err) = tx.try_send(Arc::new(v.into())) {
+            warn!("Failed to send subscription id: {id}. Error: {err:?}");
+        }
 
-        // 立刻发送 subscription id 到 rx
-        let tx1 = tx.clone();
-        tokio::spawn(async move {
-            if let Err(err) = tx1.send(Arc::new(v.into())).await {
-                // TODO(Wiccy): 概率非常小，但是目前没善后
-                warn!("Failed to send subscription id: {id}. Error: {err:?}");
+        // 重放缓存的数据
+        if from_seq > 0 {
+            for value in history.buffer.iter().filter(|v| v.seq >= from_seq) {
+                if let Err(err) = tx.try_send(value.clone()) {
+                    warn!("Failed to replay seq {} to {id}. Error: {err:?}", value.seq);
+                    break;
+                }
             }
-        });
+        }
+
+        // 重放的数据不受推送选项的限制
+        if options != DeliveryOptions::default() {
+            let state = DeliveryState {
+                options,
+                ..Default::default()
+            };
+            self.delivery.insert(id, state);
+        }
 
         // 把 tx 存入 subscription table
+        self.stats.insert(id, SubscriptionStats::default());
         self.subscriptions.insert(id, tx);
         debug!("Subscription is added {id}");
+        self.emit(LifecycleEvent::SubscriptionAdded { topic: name, id });
 
         // 返回 rx 给网络处理的上下文
         rx
@@ -72,46 +251,188 @@ impl Topic for Arc<Broadcaster> {
 
     #[instrument(name = "topic_unsubscribe", skip_all)]
     fn unsubscribe(self, name: impl Into<String>, id: u32) -> Result<u32, KvError> {
-        match self.remove_subscription(name.into(), id) {
+        let name = name.into();
+
+        // id 必须确实订阅了这个主题，否则不能删除其他主题下的订阅
+        let matched = self
+            .topics
+            .get(&name)
+            .map(|topic| topic.contains(&id))
+            .unwrap_or(false);
+        if !matched {
+            return Err(KvError::NotFound(format!(
+                "subscription: {id} in topic: {name}"
+            )));
+        }
+
+        match self.remove_subscription(name.clone(), id) {
             Some(id) => Ok(id),
-            None => Err(KvError::NotFound(format!("subscription: {id}"))),
+            None => Err(KvError::NotFound(format!(
+                "subscription: {id} in topic: {name}"
+            ))),
         }
     }
 
     #[instrument(name = "topic_publish", skip_all)]
-    fn publish(self, name: impl Into<String>, value: Arc<CommandResponse>) {
+    fn publish(self, name: impl Into<String>, value: Arc<CommandResponse>) -> usize {
         let name = name.into();
-        tokio::spawn(async move {
-            let mut ids = vec![];
-            match self.topics.get(&name) {
-                Some(topic) => {
-                    // 复制整个 topic 下所有的 subscription id
-                    // 这里我们每个 id 是 u32，如果一个 topic 下有 10k 订阅，复制的成本
-                    // 也就是 40k 堆内存（外加一些控制结构），所以效率不算差
-                    // 这也是为什么我们用 NEXT_ID 来控制 subscription id 的生成
-                    let subscription = topic.value().clone();
-
-                    // 循环发送
-                    for id in subscription.into_iter() {
-                        if let Some(tx) = self.subscriptions.get(&id) {
-                            if let Err(e) = tx.send(value.clone()).await {
-                                warn!("Publish to {id} failed! error: {e:?}");
-                                // client 中断连接
-                                ids.push(id);
-                            }
-                        }
-                    }
-                }
-                None => {}
-            }
-            for id in ids {
-                self.remove_subscription(name.clone().into(), id);
-            }
+        let Some((subscription, value)) = self.record(&name, value) else {
+            return 0;
+        };
+        let count = subscription.len();
+
+        // 持有 tasks 的锁再检查 closed，保证 shutdown 之后不会有 task 被漏掉
+        let mut tasks = self.tasks.lock().unwrap();
+        if self.closed.load(Ordering::Acquire) {
+            return 0;
+        }
+        // 回收已经结束的 task，避免 JoinSet 无限增长
+        while tasks.try_join_next().is_some() {}
+        let this = self.clone();
+        spawn_named_in(&mut tasks, &format!("publish {name}"), async move {
+            this.deliver(name, subscription, value).await;
         });
+
+        count
+    }
+
+    #[instrument(name = "topic_publish_sync", skip_all)]
+    fn publish_sync(
+        self,
+        name: impl Into<String>,
+        value: Arc<CommandResponse>,
+    ) -> impl Future<Output = usize> + Send {
+        // 调用时就分配序号，和 publish 的顺序一致
+        let name = name.into();
+        let recorded = self.record(&name, value);
+        async move {
+            let Some((subscription, value)) = recorded else {
+                return 0;
+            };
+            let count = subscription.len();
+            self.deliver(name, subscription, value).await;
+            count
+        }
+    }
+
+    #[instrument(name = "topic_mpublish", skip_all)]
+    fn mpublish(self, names: Vec<String>, value: Arc<CommandResponse>) -> usize {
+        names
+            .into_iter()
+            .map(|name| self.clone().publish(name, value.clone()))
+            .sum()
     }
 }
 
 impl Broadcaster {
+    /// 注册发布失败时的回调
+    pub fn fn_delivery_failed(mut self, f: fn(&DeliveryFailure)) -> Self {
+        self.on_delivery_failed.push(f);
+        self
+    }
+
+    /// 设置每个主题缓存的数据条数，用于订阅时重放。超过 MAX_HISTORY_SIZE 时按 MAX_HISTORY_SIZE 处理，
+    /// 否则重放时 channel 放不下
+    pub fn history_size(mut self, size: usize) -> Self {
+        if size > MAX_HISTORY_SIZE {
+            warn!("history size {size} is larger than {MAX_HISTORY_SIZE}, clamped");
+        }
+        self.history_size = size.min(MAX_HISTORY_SIZE);
+        self
+    }
+
+    /// 设置死信主题，发布失败的数据会被转发到这个主题
+    pub fn dead_letter(mut self, topic: impl Into<String>) -> Self {
+        self.dead_letter = Some(topic.into());
+        self
+    }
+
+    /// 订阅生命周期事件，只能收到订阅之后发生的事件
+    pub fn events(&self) -> broadcast::Receiver<LifecycleEvent> {
+        self.events.0.subscribe()
+    }
+
+    /// 发送生命周期事件，没有接收者时直接丢弃
+    pub fn emit(&self, event: LifecycleEvent) {
+        let _ = self.events.0.send(event);
+    }
+
+    /// 获取某个主题发布失败的次数
+    pub fn failed_deliveries(&self, topic: &str) -> u64 {
+        self.failures.get(topic).map(|v| *v).unwrap_or(0)
+    }
+
+    /// 当前的订阅数
+    pub fn subscription_count(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    /// 主题当前是否有订阅
+    pub fn has_subscribers(&self, topic: &str) -> bool {
+        self.topics.get(topic).is_some_and(|t| !t.is_empty())
+    }
+
+    /// 每个主题当前的订阅数，没有订阅的主题不返回
+    pub fn topic_subscriptions(&self) -> Vec<(String, usize)> {
+        self.topics
+            .iter()
+            .filter(|entry| !entry.value().is_empty())
+            .map(|entry| (entry.key().clone(), entry.value().len()))
+            .collect()
+    }
+
+    /// 获取某个订阅的统计数据
+    pub fn subscription_stats(&self, id: u32) -> Option<SubscriptionStats> {
+        self.stats.get(&id).map(|v| *v)
+    }
+
+    /// 以 Prometheus 文本格式导出所有订阅的统计数据
+    pub fn metrics(&self) -> String {
+        let mut lag = String::from("# TYPE kv_subscription_lag gauge\n");
+        let mut dropped = String::from("# TYPE kv_subscription_dropped_total counter\n");
+        for entry in self.stats.iter() {
+            let (id, stats) = (entry.key(), entry.value());
+            let _ = writeln!(lag, "kv_subscription_lag{{id=\"{id}\"}} {}", stats.lag);
+            let _ = writeln!(
+                dropped,
+                "kv_subscription_dropped_total{{id=\"{id}\"}} {}",
+                stats.dropped
+            );
+        }
+        lag + &dropped
+    }
+
+    /// 停止接受新的 publish，等待正在投递的数据在 timeout 内发送完，然后关闭所有订阅的 channel，
+    /// subscriber 会先收到 channel 中剩余的数据再看到 stream 结束。
+    ///
+    /// 返回是否所有数据都在 timeout 内投递完成，超时的 task 会被取消
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        let mut tasks = {
+            let mut tasks = self.tasks.lock().unwrap();
+            self.closed.store(true, Ordering::Release);
+            std::mem::take(&mut *tasks)
+        };
+
+        // 投递失败时转发到死信主题的数据会因为 closed 被丢弃
+        let drained = time::timeout(timeout, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await
+        .is_ok();
+        if !drained {
+            warn!("{} publish tasks are aborted on shutdown", tasks.len());
+            tasks.shutdown().await;
+        }
+
+        // drop 所有的 sender，subscriber 的 stream 随之结束
+        self.subscriptions.clear();
+        self.topics.clear();
+        self.stats.clear();
+        self.delivery.clear();
+        info!("Broadcaster is shut down");
+        drained
+    }
+
     pub fn remove_subscription(&self, name: String, id: u32) -> Option<u32> {
         if let Some(v) = self.topics.get_mut(&name) {
             // 在 topics 表里找到 topic 的 subscription id 删除
@@ -122,17 +443,218 @@ impl Broadcaster {
                 info!("Topic: {:?} is deleted", &name);
                 drop(v);
                 self.topics.remove(&name);
+                // 不缓存数据时序号也没有用了，删除主题的状态，避免 publish 过的主题越来越多
+                if self.history_size == 0 {
+                    self.history
+                        .remove_if(&name, |_, _| !self.has_subscribers(&name));
+                }
             }
         }
 
         debug!("Subscription {id} is removed! ");
-        // 同样，删除在 subscription 的 id
-        self.subscriptions.remove(&id).map(|(id, _)| id)
+        // 同样，删除在 subscription 的 id。
+        // send 只更新已有的统计数据，删除之后不会再被创建
+        let removed = self.subscriptions.remove(&id);
+        self.stats.remove(&id);
+        self.delivery.remove(&id);
+        let (id, _) = removed?;
+        self.emit(LifecycleEvent::SubscriptionRemoved { topic: name, id });
+        Some(id)
+    }
+
+    // 分配序号并缓存数据，返回当前的订阅和带序号的数据，没有订阅或者已经 shutdown 时返回 None
+    fn record(
+        &self,
+        name: &str,
+        value: Arc<CommandResponse>,
+    ) -> Option<(DashSet<u32>, Arc<CommandResponse>)> {
+        if self.closed.load(Ordering::Acquire) {
+            return None;
+        }
+
+        // 没有订阅者时，只有需要缓存数据才记录，以便之后的订阅重放；否则不保留这个主题的状态
+        if self.history_size == 0 && !self.has_subscribers(name) {
+            return None;
+        }
+        let mut history = self.history.entry(name.to_string()).or_default();
+        history.next_seq += 1;
+        let mut data = value.as_ref().clone();
+        data.seq = history.next_seq;
+        let value = Arc::new(data);
+        if self.history_size > 0 {
+            if history.buffer.len() == self.history_size {
+                history.buffer.pop_front();
+            }
+            history.buffer.push_back(value.clone());
+        }
+
+        // 复制整个 topic 下所有的 subscription id
+        // 这里我们每个 id 是 u32，如果一个 topic 下有 10k 订阅，复制的成本
+        // 也就是 40k 堆内存（外加一些控制结构），所以效率不算差
+        // 这也是为什么我们用 NEXT_ID 来控制 subscription id 的生成
+        let subscription = self.topics.get(name)?.value().clone();
+        Some((subscription, value))
+    }
+
+    // 把数据发送给 subscription 中的每个订阅，删除已经断开的订阅
+    async fn deliver(
+        self: &Arc<Self>,
+        name: String,
+        subscription: DashSet<u32>,
+        value: Arc<CommandResponse>,
+    ) {
+        let mut failed = vec![];
+        // 循环发送
+        for id in subscription.into_iter() {
+            // 按推送选项跳过或者延后这条数据
+            let Some(value) = self.throttle(&name, id, value.clone()) else {
+                continue;
+            };
+            if let Err(e) = self.send(id, value).await {
+                failed.push((id, e));
+            }
+        }
+        for (id, e) in failed {
+            self.send_failed(name.clone(), id, e);
+        }
+    }
+
+    // 发送数据到订阅的 channel 并更新统计数据。默认等待 channel 有空位，
+    // 设置了 drop_when_full 的订阅在 channel 已满时返回 Full
+    async fn send(
+        &self,
+        id: u32,
+        value: Arc<CommandResponse>,
+    ) -> Result<(), TrySendError<Arc<CommandResponse>>> {
+        // 复制一份 sender，等待时不持有 DashMap 的锁
+        let Some(tx) = self.subscriptions.get(&id).map(|tx| tx.clone()) else {
+            return Ok(());
+        };
+        let drop_when_full = self
+            .delivery
+            .get(&id)
+            .is_some_and(|state| state.options.drop_when_full);
+        let res = if drop_when_full {
+            tx.try_send(value)
+        } else {
+            tx.send(value).await.map_err(|e| TrySendError::Closed(e.0))
+        };
+        if let Some(mut stats) = self.stats.get_mut(&id) {
+            if let Err(TrySendError::Full(_)) = res {
+                stats.dropped += 1;
+            }
+            stats.lag = tx.max_capacity() - tx.capacity();
+        }
+        res
+    }
+
+    // drop_when_full 的订阅 channel 已满时丢弃这条数据，subscriber 断开时删除订阅，两种情况都记录为发布失败
+    fn send_failed(self: &Arc<Self>, name: String, id: u32, e: TrySendError<Arc<CommandResponse>>) {
+        let value = match e {
+            // subscriber 消费太慢，丢弃这条消息
+            TrySendError::Full(value) => {
+                warn!("Subscription {id} is full, message dropped");
+                value
+            }
+            // client 中断连接
+            TrySendError::Closed(value) => {
+                warn!("Publish to {id} failed! subscription is closed");
+                self.remove_subscription(name.clone(), id);
+                value
+            }
+        };
+        self.clone().delivery_failed(name, id, value);
+    }
+
+    // 返回需要立刻推送的数据。抽样跳过的数据直接丢弃；间隔内的数据只保留最新的一条，
+    // 由间隔结束时的 task 推送
+    fn throttle(
+        self: &Arc<Self>,
+        name: &str,
+        id: u32,
+        value: Arc<CommandResponse>,
+    ) -> Option<Arc<CommandResponse>> {
+        let Some(mut state) = self.delivery.get_mut(&id) else {
+            return Some(value);
+        };
+        let options = state.options;
+        state.received += 1;
+        if options.sample_every > 1 && (state.received - 1) % options.sample_every as u64 != 0 {
+            return None;
+        }
+        if options.throttle.is_zero() {
+            return Some(value);
+        }
+
+        let now = Instant::now();
+        let Some(last) = state.last_sent else {
+            state.last_sent = Some(now);
+            return Some(value);
+        };
+        match last.checked_add(options.throttle) {
+            Some(deadline) if now < deadline => {
+                // 已经有等待推送的数据时，task 也已经创建了，只需要替换数据
+                if state.pending.replace(value).is_none() {
+                    drop(state);
+                    self.flush_at(name.to_string(), id, deadline);
+                }
+                None
+            }
+            // 间隔大到溢出时不再推送
+            None => None,
+            Some(_) => {
+                state.last_sent = Some(now);
+                Some(value)
+            }
+        }
+    }
+
+    // 在 deadline 时推送间隔内等待的数据，task 加入 tasks 中，shutdown 时同样会等待它结束
+    fn flush_at(self: &Arc<Self>, name: String, id: u32, deadline: Instant) {
+        let mut tasks = self.tasks.lock().unwrap();
+        if self.closed.load(Ordering::Acquire) {
+            return;
+        }
+        let this = self.clone();
+        spawn_named_in(&mut tasks, &format!("flush {id}"), async move {
+            time::sleep_until(deadline).await;
+            let value = this.delivery.get_mut(&id).and_then(|mut state| {
+                state.last_sent = Some(Instant::now());
+                state.pending.take()
+            });
+            if let Some(value) = value {
+                if let Err(e) = this.send(id, value).await {
+                    this.send_failed(name, id, e);
+                }
+            }
+        });
+    }
+
+    // 记录发布失败，通知回调，如果设置了死信主题则转发数据
+    fn delivery_failed(self: Arc<Self>, name: String, id: u32, value: Arc<CommandResponse>) {
+        *self.failures.entry(name.clone()).or_default() += 1;
+
+        let failure = DeliveryFailure {
+            topic: name,
+            id,
+            value,
+        };
+        self.on_delivery_failed.notify(&failure);
+
+        // 死信主题本身发布失败时不再转发，避免循环
+        if let Some(dead_letter) = &self.dead_letter {
+            if dead_letter != &failure.topic {
+                self.clone().publish(dead_letter.clone(), failure.value);
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+    use tokio::time;
+
     use crate::assert_res_ok;
 
     use super::*;
@@ -162,7 +684,7 @@ mod tests {
 
         assert_eq!(res1, res2);
 
-        assert_res_ok(&res1, &[v.clone()], &[]);
+        assert_res_ok(&res1, std::slice::from_ref(&v), &[]);
 
         // 如果 subscriber 取消订阅，则收不到数据
         let res = b.clone().unsubscribe(lobby.clone(), id1 as _).unwrap();
@@ -174,6 +696,340 @@ mod tests {
 
         assert!(stream1.recv().await.is_none());
         let res2 = stream2.recv().await.unwrap();
-        assert_res_ok(&res2, &[v.clone()], &[]);
+        assert_res_ok(&res2, std::slice::from_ref(&v), &[]);
+    }
+
+    #[tokio::test]
+    async fn subscription_events_should_be_emitted() {
+        let b = Arc::new(Broadcaster::default());
+        let mut events = b.events();
+
+        let mut stream = b.clone().subscribe("lobby");
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+        let added = LifecycleEvent::SubscriptionAdded {
+            topic: "lobby".into(),
+            id: id as _,
+        };
+        assert_eq!(events.recv().await.unwrap(), added);
+
+        // 取消订阅
+        b.clone().unsubscribe("lobby", id as _).unwrap();
+        let removed = LifecycleEvent::SubscriptionRemoved {
+            topic: "lobby".into(),
+            id: id as _,
+        };
+        assert_eq!(events.recv().await.unwrap(), removed);
+
+        // subscriber 断开后，下一次 publish 失败时删除订阅
+        let stream = b.clone().subscribe("lobby");
+        let LifecycleEvent::SubscriptionAdded { id, .. } = events.recv().await.unwrap() else {
+            panic!("expect SubscriptionAdded");
+        };
+        drop(stream);
+        b.clone()
+            .publish("lobby", Arc::new(Value::from("hello").into()));
+        let removed = LifecycleEvent::SubscriptionRemoved {
+            topic: "lobby".into(),
+            id,
+        };
+        assert_eq!(events.recv().await.unwrap(), removed);
+    }
+
+    #[tokio::test]
+    async fn publish_sync_should_deliver_before_return() {
+        let b = Arc::new(Broadcaster::default());
+        let mut stream1 = b.clone().subscribe("lobby");
+        let mut stream2 = b.clone().subscribe("lobby");
+        stream1.recv().await.unwrap();
+        stream2.recv().await.unwrap();
+
+        // 返回时数据已经在 channel 中，不需要等待
+        let v: Value = "hello".into();
+        let count = b
+            .clone()
+            .publish_sync("lobby", Arc::new(v.clone().into()))
+            .await;
+        assert_eq!(count, 2);
+        for stream in [&mut stream1, &mut stream2] {
+            let res = stream.try_recv().unwrap();
+            assert_res_ok(&res, std::slice::from_ref(&v), &[]);
+            assert_eq!(res.seq, 1);
+        }
+
+        // 断开的订阅在返回前被删除
+        drop(stream2);
+        assert_eq!(b.clone().publish_sync("lobby", Arc::new(v.into())).await, 2);
+        assert_eq!(b.subscription_count(), 1);
+        assert_eq!(
+            b.clone()
+                .publish_sync("nobody", Arc::new(Value::default().into()))
+                .await,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn subscription_lag_should_reflect_channel_depth() {
+        let b = Arc::new(Broadcaster::default());
+        let mut stream = b.clone().subscribe("lobby");
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+        let id = id as u32;
+
+        // subscriber 不消费，消息积压在 channel 中
+        let v: Arc<CommandResponse> = Arc::new(Value::from("hello").into());
+        for _ in 0..10 {
+            b.clone().publish_sync("lobby", v.clone()).await;
+        }
+        let stats = b.subscription_stats(id).unwrap();
+        assert_eq!(
+            stats,
+            SubscriptionStats {
+                lag: 10,
+                dropped: 0
+            }
+        );
+
+        // channel 满了之后等待 subscriber 消费，不丢弃消息
+        for _ in 10..BROADCAST_CAPACITY {
+            b.clone().publish_sync("lobby", v.clone()).await;
+        }
+        assert_eq!(b.subscription_stats(id).unwrap().lag, BROADCAST_CAPACITY);
+        let blocked = tokio::spawn(b.clone().publish_sync("lobby", v.clone()));
+        time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        stream.recv().await.unwrap();
+        assert_eq!(blocked.await.unwrap(), 1);
+        let stats = b.subscription_stats(id).unwrap();
+        assert_eq!(stats.lag, BROADCAST_CAPACITY);
+        assert_eq!(stats.dropped, 0);
+        assert_eq!(b.failed_deliveries("lobby"), 0);
+
+        let metrics = b.metrics();
+        assert!(metrics.contains(&format!("kv_subscription_lag{{id=\"{id}\"}} 128")));
+        assert!(metrics.contains(&format!("kv_subscription_dropped_total{{id=\"{id}\"}} 0")));
+
+        // subscriber 消费之后，下一次 publish 时 lag 会下降
+        for _ in 0..100 {
+            stream.recv().await.unwrap();
+        }
+        b.clone().publish_sync("lobby", v.clone()).await;
+        assert_eq!(b.subscription_stats(id).unwrap().lag, 29);
+
+        // 取消订阅后统计数据被删除
+        b.clone().unsubscribe("lobby", id).unwrap();
+        assert!(b.subscription_stats(id).is_none());
+    }
+
+    #[tokio::test]
+    async fn drop_when_full_subscription_should_drop_messages() {
+        let b = Arc::new(Broadcaster::default());
+        let options = DeliveryOptions {
+            drop_when_full: true,
+            ..Default::default()
+        };
+        let mut stream = b.clone().subscribe_with("lobby", 0, options);
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+        let id = id as u32;
+
+        // channel 满了之后的消息被丢弃，publish_sync 不会等待
+        let v: Arc<CommandResponse> = Arc::new(Value::from("hello").into());
+        for _ in 0..BROADCAST_CAPACITY + 10 {
+            b.clone().publish_sync("lobby", v.clone()).await;
+        }
+        let stats = b.subscription_stats(id).unwrap();
+        assert_eq!(stats.lag, BROADCAST_CAPACITY);
+        assert_eq!(stats.dropped, 10);
+        // 丢弃的消息同样记录为发布失败，订阅不会被删除
+        assert_eq!(b.failed_deliveries("lobby"), 10);
+        assert!(b.has_subscribers("lobby"));
+
+        let metrics = b.metrics();
+        assert!(metrics.contains(&format!("kv_subscription_dropped_total{{id=\"{id}\"}} 10")));
+
+        // 收到的是最早的 128 条
+        let first = stream.recv().await.unwrap();
+        assert_eq!(first.seq, 1);
+    }
+
+    #[tokio::test]
+    async fn subscribe_from_should_replay_buffered_messages() {
+        let b = Arc::new(Broadcaster::default().history_size(10));
+
+        // 没有订阅者时发布的数据也会被缓存
+        for i in 1..=3 {
+            let v: Value = i.into();
+            assert_eq!(b.clone().publish("lobby", Arc::new(v.into())), 0);
+        }
+
+        let mut stream = b.clone().subscribe_from("lobby", 2);
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+        assert!(id > 0);
+
+        // 先收到重放的数据
+        for i in 2..=3 {
+            let res = stream.recv().await.unwrap();
+            assert_eq!(res.seq, i as u64);
+            assert_res_ok(&res, &[i.into()], &[]);
+        }
+
+        // 然后收到新发布的数据
+        let v: Value = 4.into();
+        assert_eq!(b.clone().publish("lobby", Arc::new(v.clone().into())), 1);
+        let res = stream.recv().await.unwrap();
+        assert_eq!(res.seq, 4);
+        assert_res_ok(&res, &[v], &[]);
+    }
+
+    #[tokio::test]
+    async fn history_should_keep_latest_messages() {
+        let b = Arc::new(Broadcaster::default().history_size(2));
+        for i in 1..=3 {
+            let v: Value = i.into();
+            b.clone().publish("lobby", Arc::new(v.into()));
+        }
+
+        // 只保留最近的 2 条，更早的数据无法重放
+        let mut stream = b.clone().subscribe_from("lobby", 1);
+        stream.recv().await.unwrap();
+        assert_eq!(stream.recv().await.unwrap().seq, 2);
+        assert_eq!(stream.recv().await.unwrap().seq, 3);
+        assert!(stream.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn topic_state_should_be_dropped_without_subscribers() {
+        let b = Arc::new(Broadcaster::default());
+        let v: Arc<CommandResponse> = Arc::new(Value::from("hello").into());
+
+        // 不缓存数据时，向没有订阅者的主题 publish 不保留任何状态
+        for i in 0..10 {
+            assert_eq!(b.clone().publish(format!("topic{i}"), v.clone()), 0);
+        }
+        assert!(b.history.is_empty());
+
+        let mut stream = b.clone().subscribe("lobby");
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+        b.clone().publish_sync("lobby", v.clone()).await;
+        assert_eq!(stream.recv().await.unwrap().seq, 1);
+        assert_eq!(b.history.len(), 1);
+
+        // 最后一个订阅者离开后删除主题的状态
+        b.clone().unsubscribe("lobby", id as _).unwrap();
+        assert!(b.history.is_empty());
+    }
+
+    #[test]
+    fn history_size_should_be_clamped() {
+        let b = Broadcaster::default().history_size(BROADCAST_CAPACITY * 2);
+        assert_eq!(b.history_size, MAX_HISTORY_SIZE);
+    }
+
+    #[tokio::test]
+    async fn shutdown_should_drain_publishes_before_closing_streams() {
+        let b = Arc::new(Broadcaster::default());
+        let mut stream = b.clone().subscribe("lobby");
+        stream.recv().await.unwrap();
+
+        let v: Value = "hello".into();
+        assert_eq!(b.clone().publish("lobby", Arc::new(v.clone().into())), 1);
+        assert!(b.shutdown(Duration::from_secs(1)).await);
+
+        // 先收到 shutdown 之前发布的数据，然后 stream 结束
+        let res = stream.recv().await.unwrap();
+        assert_res_ok(&res, std::slice::from_ref(&v), &[]);
+        assert!(stream.recv().await.is_none());
+
+        // shutdown 之后不再接受新的 publish
+        assert_eq!(b.clone().publish("lobby", Arc::new(v.into())), 0);
+    }
+
+    #[tokio::test]
+    async fn delivery_failure_should_be_reported() {
+        static FAILED: AtomicU32 = AtomicU32::new(0);
+        fn on_failed(failure: &DeliveryFailure) {
+            assert_eq!(failure.topic, "lobby");
+            FAILED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let b = Arc::new(
+            Broadcaster::default()
+                .fn_delivery_failed(on_failed)
+                .dead_letter("dead"),
+        );
+
+        // 订阅后立刻断开
+        let mut stream = b.clone().subscribe("lobby");
+        stream.recv().await.unwrap();
+        drop(stream);
+
+        let mut dead = b.clone().subscribe("dead");
+        dead.recv().await.unwrap();
+
+        let v: Value = "hello".into();
+        assert_eq!(b.clone().publish("lobby", Arc::new(v.clone().into())), 1);
+
+        // 未送达的数据会被转发到死信主题
+        let res = dead.recv().await.unwrap();
+        assert_res_ok(&res, std::slice::from_ref(&v), &[]);
+        assert_eq!(FAILED.load(Ordering::SeqCst), 1);
+        assert_eq!(b.failed_deliveries("lobby"), 1);
+        assert_eq!(b.failed_deliveries("dead"), 0);
+    }
+
+    #[tokio::test]
+    async fn throttled_subscription_should_coalesce_messages() {
+        let b = Arc::new(Broadcaster::default());
+        let options = DeliveryOptions {
+            throttle: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let mut stream = b.clone().subscribe_with("lobby", 0, options);
+        stream.recv().await.unwrap();
+
+        // 200ms 内发布 20 条数据
+        for i in 1..=20 {
+            b.clone()
+                .publish("lobby", Arc::new(Value::from(i as i64).into()));
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        time::sleep(Duration::from_millis(100)).await;
+
+        let mut received = vec![];
+        while let Ok(res) = stream.try_recv() {
+            received.push(res.seq);
+        }
+        // 每 50ms 最多推送一条，间隔结束时推送最新的数据
+        assert!((3..=6).contains(&received.len()), "got {received:?}");
+        assert_eq!(received.last(), Some(&20));
+        assert!(received.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[tokio::test]
+    async fn sampled_subscription_should_receive_every_nth_message() {
+        let b = Arc::new(Broadcaster::default());
+        let options = DeliveryOptions {
+            sample_every: 5,
+            ..Default::default()
+        };
+        let mut sampled = b.clone().subscribe_with("lobby", 0, options);
+        sampled.recv().await.unwrap();
+        let mut all = b.clone().subscribe("lobby");
+        all.recv().await.unwrap();
+
+        for i in 0..20 {
+            b.clone()
+                .publish("lobby", Arc::new(Value::from(i as i64).into()));
+        }
+        for _ in 0..20 {
+            all.recv().await.unwrap();
+        }
+        time::sleep(Duration::from_millis(10)).await;
+
+        let mut count = 0;
+        while sampled.try_recv().is_ok() {
+            count += 1;
+        }
+        assert_eq!(count, 4);
     }
 }
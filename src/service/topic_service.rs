@@ -1,8 +1,11 @@
-use futures::stream::{self, BoxStream};
-use std::sync::Arc;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::{sync::Arc, time::Duration};
 use tokio_stream::wrappers::ReceiverStream;
 
-use crate::{CommandResponse, Publish, Subscribe, Topic, Unsubscribe};
+use crate::{
+    log_topic, CommandResponse, DeliveryOptions, Mpublish, Publish, Subscribe, SubscribeLogs,
+    Topic, Unsubscribe, Value,
+};
 
 pub type StreamingResponse = BoxStream<'static, Arc<CommandResponse>>;
 
@@ -13,15 +16,40 @@ pub trait TopicService {
 
 impl TopicService for Subscribe {
     fn execute(self, topic: impl Topic) -> StreamingResponse {
-        let rx = topic.subscribe(self.topic);
-        Box::pin(ReceiverStream::new(rx))
+        let options = DeliveryOptions {
+            throttle: Duration::from_millis(self.throttle_ms),
+            sample_every: self.sample_every,
+            ..Default::default()
+        };
+        let rx = topic.subscribe_with(self.topic, self.from_seq, options);
+        // 订阅被删除后发送结束标记，让客户端区分正常结束和连接断开
+        let end = stream::once(async { Arc::new(CommandResponse::stream_end()) });
+        Box::pin(ReceiverStream::new(rx).chain(end))
+    }
+}
+
+// 日志 topic 和普通的 topic 一样订阅，不能设置推送选项
+impl TopicService for SubscribeLogs {
+    fn execute(self, topic: impl Topic) -> StreamingResponse {
+        match log_topic(&self.level) {
+            Ok(name) => topic_subscribe(name).execute(topic),
+            Err(e) => Box::pin(stream::once(async { Arc::new(e.into()) })),
+        }
+    }
+}
+
+fn topic_subscribe(topic: String) -> Subscribe {
+    Subscribe {
+        topic,
+        ..Default::default()
     }
 }
 
 impl TopicService for Unsubscribe {
     fn execute(self, topic: impl Topic) -> StreamingResponse {
+        // 成功时返回被删除的 subscription id，subscriber 剩余的订阅数由所在的连接加上
         let res = match topic.unsubscribe(self.topic, self.id) {
-            Ok(_) => CommandResponse::ok(),
+            Ok(id) => Value::from(id as i64).into(),
             Err(e) => e.into(),
         };
         Box::pin(stream::once(async { Arc::new(res) }))
@@ -35,6 +63,15 @@ impl TopicService for Publish {
     }
 }
 
+impl TopicService for Mpublish {
+    fn execute(self, topic: impl Topic) -> StreamingResponse {
+        // 返回所有主题下收到数据的订阅数之和
+        let count = topic.mpublish(self.topics, Arc::new(self.data.into()));
+        let res = Value::from(count as i64).into();
+        Box::pin(stream::once(async { Arc::new(res) }))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
@@ -55,6 +92,43 @@ mod tests {
         assert_res_ok(&data, &[], &[]);
     }
 
+    #[tokio::test]
+    async fn dispatch_mpublish_should_work() {
+        let topic = Arc::new(Broadcaster::default());
+        let topics = ["lobby", "news", "sports"];
+
+        // 每个主题下各有一个订阅
+        let mut streams = vec![];
+        for name in topics {
+            let mut res = dispatch_stream(CommandRequest::new_subscribe(name), topic.clone());
+            get_id(&mut res).await;
+            streams.push(res);
+        }
+        // 其中一个主题再多一个订阅
+        let mut res = dispatch_stream(CommandRequest::new_subscribe("lobby"), topic.clone());
+        get_id(&mut res).await;
+        streams.push(res);
+
+        let v: Value = "hello".into();
+        let cmd = CommandRequest::new_mpublish(topics.to_vec(), vec![v.clone()]);
+        let data = dispatch_stream(cmd, topic).next().await.unwrap();
+        assert_res_ok(&data, &[4.into()], &[]);
+
+        // 所有订阅都应该收到数据
+        for res in streams.iter_mut() {
+            let data = res.next().await.unwrap();
+            assert_res_ok(&data, std::slice::from_ref(&v), &[]);
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatch_mpublish_to_unknown_topics_should_return_zero() {
+        let topic = Arc::new(Broadcaster::default());
+        let cmd = CommandRequest::new_mpublish(vec!["a", "b"], vec!["hello".into()]);
+        let data = dispatch_stream(cmd, topic).next().await.unwrap();
+        assert_res_ok(&data, &[0.into()], &[]);
+    }
+
     #[tokio::test]
     async fn dispatch_subscribe_should_work() {
         let topic = Arc::new(Broadcaster::default());
@@ -96,7 +170,52 @@ mod tests {
         let mut res = dispatch_stream(cmd, topic);
         let data = res.next().await.unwrap();
 
-        assert_res_ok(&data, &[], &[]);
+        // 返回被删除的 id
+        assert_res_ok(&data, &[(id as i64).into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn unsubscribed_stream_should_end_with_stream_end() {
+        let topic = Arc::new(Broadcaster::default());
+        let cmd = CommandRequest::new_subscribe("lobby");
+        let mut stream = dispatch_stream(cmd, topic.clone());
+        let id = get_id(&mut stream).await;
+
+        let cmd = CommandRequest::new_unsubscribe("lobby", id);
+        dispatch_stream(cmd, topic).next().await.unwrap();
+
+        // 取消订阅后，stream 以 204 结束
+        let data = stream.next().await.unwrap();
+        assert!(data.is_stream_end());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dispatch_unsubscribe_should_distinguish_valid_and_invalid_id() {
+        let topic = Arc::new(Broadcaster::default());
+        let mut res1 = dispatch_stream(CommandRequest::new_subscribe("lobby"), topic.clone());
+        let id1 = get_id(&mut res1).await;
+        let mut res2 = dispatch_stream(CommandRequest::new_subscribe("lobby"), topic.clone());
+        let id2 = get_id(&mut res2).await;
+
+        // 合法的 id，返回被删除的 id
+        let cmd = CommandRequest::new_unsubscribe("lobby", id1);
+        let data = dispatch_stream(cmd, topic.clone()).next().await.unwrap();
+        assert_res_ok(&data, &[(id1 as i64).into()], &[]);
+
+        // 同一个 id 再次取消订阅，返回 404
+        let cmd = CommandRequest::new_unsubscribe("lobby", id1);
+        let data = dispatch_stream(cmd, topic.clone()).next().await.unwrap();
+        assert_res_error(&data, 404, &format!("Not found subscription: {id1}"));
+
+        // id 存在但主题不匹配，返回 404，且不影响原有订阅
+        let cmd = CommandRequest::new_unsubscribe("other", id2);
+        let data = dispatch_stream(cmd, topic.clone()).next().await.unwrap();
+        assert_res_error(&data, 404, &format!("Not found subscription: {id2}"));
+
+        let cmd = CommandRequest::new_unsubscribe("lobby", id2);
+        let data = dispatch_stream(cmd, topic).next().await.unwrap();
+        assert_res_ok(&data, &[(id2 as i64).into()], &[]);
     }
 
     #[tokio::test]
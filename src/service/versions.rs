@@ -0,0 +1,234 @@
+use dashmap::DashMap;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock, RwLockReadGuard,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::keyspace_event;
+use crate::{
+    command_request::RequestData, CommandRequest, CommandResponse, KvError, Storage, Value,
+};
+
+/// key 的版本号，保存在内存中，只对当前进程有效。
+///
+/// 每次写入 key 都会分配一个新的版本号，所有 key 共用一个递增的计数器，key 被删除后重新写入时
+/// 版本号也比删除之前的大。计数器从进程启动时的时间（微秒）开始，重启后分配的版本号通常也比重启前的大。
+/// 进程启动前就存在的 key 在第一次读取版本号时分配
+#[derive(Debug)]
+pub struct Versions {
+    next: AtomicU64,
+    // table -> key -> 版本号
+    versions: DashMap<String, DashMap<String, u64>>,
+    // 写命令从写入到更新完版本号都持有读锁，set_cas 持有写锁
+    lock: RwLock<()>,
+}
+
+impl Default for Versions {
+    fn default() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            next: AtomicU64::new(now.as_micros() as u64),
+            versions: DashMap::new(),
+            lock: Default::default(),
+        }
+    }
+}
+
+impl Versions {
+    /// key 当前的版本号，key 不存在时返回 None
+    pub fn version(
+        &self,
+        store: &impl Storage,
+        table: &str,
+        key: &str,
+    ) -> Result<Option<u64>, KvError> {
+        if !store.contains(table, key)? {
+            // 因为过期被删除的 key 不经过 after_execute，在这里清除它的版本号
+            if let Some(versions) = self.versions.get(table) {
+                versions.remove(key);
+            }
+            return Ok(None);
+        }
+        let versions = self
+            .versions
+            .entry(table.to_string())
+            .or_default()
+            .downgrade();
+        let version = *versions
+            .entry(key.to_string())
+            .or_insert_with(|| self.next_version());
+        Ok(Some(version))
+    }
+
+    /// 给这些 key 分配新的版本号
+    pub fn bump<'a>(&self, table: &str, keys: impl IntoIterator<Item = &'a str>) {
+        let versions = self
+            .versions
+            .entry(table.to_string())
+            .or_default()
+            .downgrade();
+        for key in keys {
+            versions.insert(key.to_string(), self.next_version());
+        }
+    }
+
+    /// 删除这些 key 的版本号
+    pub fn remove<'a>(&self, table: &str, keys: impl IntoIterator<Item = &'a str>) {
+        if let Some(versions) = self.versions.get(table) {
+            for key in keys {
+                versions.remove(key);
+            }
+        }
+    }
+
+    /// 删除 table 中所有 key 的版本号，之后读取版本号时重新分配
+    pub fn clear_table(&self, table: &str) {
+        self.versions.remove(table);
+    }
+
+    /// 写命令执行前获取，持有到 after_execute 更新完版本号
+    pub fn write_guard(&self) -> RwLockReadGuard<'_, ()> {
+        self.lock.read().unwrap()
+    }
+
+    /// 写命令成功后更新被修改的 key 的版本号，被删除的 key 不再保留版本号
+    pub fn after_execute(&self, cmd: &CommandRequest) {
+        for (table, event, keys) in keyspace_event(cmd) {
+            match event {
+                // set_cas 已经分配了新的版本号
+                "hsetcas" => {}
+                "hdel" | "hgetdel" | "hmdel" | "movenx_from" => self.remove(table, keys),
+                // 没有 key 表示整个 table 都被修改了，如 DropTable 和 RenameTable
+                _ if keys.is_empty() => self.clear_table(table),
+                _ => self.bump(table, keys),
+            }
+        }
+    }
+
+    /// key 的版本号比 since_version 新时返回 value 和版本号，否则返回 304
+    pub fn get_if_newer(
+        &self,
+        store: &impl Storage,
+        table: &str,
+        key: &str,
+        since_version: u64,
+    ) -> Result<CommandResponse, KvError> {
+        // 先读版本号再读值，有并发写入时值可能比版本号新，下次读取会多返回一次，但不会漏掉修改
+        let version = self.version(store, table, key)?;
+        let value = store.get(table, key)?;
+        let (Some(version), Some(value)) = (version, value) else {
+            return Err(KvError::NotFound(format!("table {table}, key {key}")));
+        };
+        if version <= since_version {
+            return Ok(CommandResponse::not_modified());
+        }
+        Ok(vec![value, (version as i64).into()].into())
+    }
+
+    /// key 当前的版本号等于 expected 时写入 value，返回新的版本号；expected 为 0 表示 key 必须不存在。
+    ///
+    /// 检查版本号到分配新的版本号期间一直持有写锁，没有其他写命令在执行，所以并发的 set_cas
+    /// 中只有一个能成功，其他的返回 VersionConflict
+    pub fn set_cas(
+        &self,
+        store: &impl Storage,
+        table: &str,
+        key: &str,
+        value: Value,
+        expected: u64,
+    ) -> Result<u64, KvError> {
+        let _guard = self.lock.write().unwrap();
+        let current = self.version(store, table, key)?.unwrap_or(0);
+        if current != expected {
+            return Err(KvError::VersionConflict { expected, current });
+        }
+        store.set(table, key, value)?;
+        let version = self.next_version();
+        self.versions
+            .entry(table.to_string())
+            .or_default()
+            .insert(key.to_string(), version);
+        Ok(version)
+    }
+
+    /// 执行 HgetIfNewer/HsetCas 命令，其他命令返回 None
+    pub fn execute(&self, store: &impl Storage, cmd: &CommandRequest) -> Option<CommandResponse> {
+        let res = match &cmd.request_data {
+            Some(RequestData::HgetIfNewer(v)) => {
+                self.get_if_newer(store, &v.table, &v.key, v.since_version)
+            }
+            Some(RequestData::HsetCas(v)) => {
+                let Some(value) = v.value.clone() else {
+                    return Some(KvError::InvalidCommand("HsetCas has no value".into()).into());
+                };
+                match self.set_cas(store, &v.table, &v.key, value, v.expected_version) {
+                    Ok(version) => Ok(Value::from(version as i64).into()),
+                    // 冲突时带上当前的版本号，客户端不需要再读一次
+                    Err(e @ KvError::VersionConflict { current, .. }) => {
+                        let mut res = CommandResponse::from(e);
+                        res.values = vec![(current as i64).into()];
+                        Ok(res)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            _ => return None,
+        };
+        Some(res.unwrap_or_else(|e| e.into()))
+    }
+
+    fn next_version(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_res_error, MemTable};
+
+    #[test]
+    fn version_should_change_on_every_write() {
+        let store = MemTable::new();
+        let versions = Versions::default();
+        assert_eq!(versions.version(&store, "t1", "k1").unwrap(), None);
+
+        // 进程启动前就存在的 key 第一次读取时分配版本号，之后保持不变
+        store.set("t1", "k1", "v1").unwrap();
+        let v1 = versions.version(&store, "t1", "k1").unwrap().unwrap();
+        assert_eq!(versions.version(&store, "t1", "k1").unwrap(), Some(v1));
+
+        versions.after_execute(&CommandRequest::new_hset("t1", "k1", "v2"));
+        let v2 = versions.version(&store, "t1", "k1").unwrap().unwrap();
+        assert!(v2 > v1);
+
+        // 删除后重新写入，版本号也比之前的大
+        store.del("t1", "k1").unwrap();
+        versions.after_execute(&CommandRequest::new_hdel("t1", "k1"));
+        assert_eq!(versions.version(&store, "t1", "k1").unwrap(), None);
+        store.set("t1", "k1", "v3").unwrap();
+        versions.after_execute(&CommandRequest::new_hset("t1", "k1", "v3"));
+        assert!(versions.version(&store, "t1", "k1").unwrap().unwrap() > v2);
+    }
+
+    #[test]
+    fn set_cas_without_value_should_be_rejected() {
+        let store = MemTable::new();
+        let versions = Versions::default();
+        let mut cmd = CommandRequest::new_hset_cas("t1", "k1", "v1", 0);
+        if let Some(RequestData::HsetCas(v)) = &mut cmd.request_data {
+            v.value = None;
+        }
+        let res = versions.execute(&store, &cmd).unwrap();
+        assert_res_error(&res, 400, "HsetCas has no value");
+
+        // 没有写入，也没有分配版本号
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
+        assert_eq!(versions.version(&store, "t1", "k1").unwrap(), None);
+    }
+}
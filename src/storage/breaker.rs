@@ -0,0 +1,411 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::{CircuitBreakerConfig, Footprint, KvError, Kvpair, Storage, Value};
+
+/// 对磁盘存储的熔断：存储连续出错 failure_threshold 次后熔断，之后的调用直接返回
+/// ServiceUnavailable，不再访问存储。
+///
+/// 熔断 cooldown 之后进入半开状态，只放行一个调用试探存储是否恢复：
+/// 成功则恢复正常，失败则再熔断 cooldown。只有存储本身的错误（sled、RocksDB、I/O）会被计数，
+/// 命令错误（比如类型转换失败）不影响熔断
+pub struct CircuitBreakerStore<S: Storage> {
+    store: S,
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // 连续出错的次数
+    failures: u32,
+    // 熔断结束的时间，None 表示没有熔断
+    open_until: Option<Instant>,
+    // 半开状态下是否已经有一个调用在试探
+    probing: bool,
+}
+
+impl<S: Storage> CircuitBreakerStore<S> {
+    pub fn new(store: S, config: CircuitBreakerConfig) -> Self {
+        Self {
+            store,
+            threshold: config.failure_threshold.max(1),
+            cooldown: Duration::from_millis(config.cooldown),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// 是否处于熔断状态
+    pub fn is_open(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.open_until.is_some_and(|t| Instant::now() < t)
+    }
+
+    fn call<'a, T>(&'a self, f: impl FnOnce(&'a S) -> Result<T, KvError>) -> Result<T, KvError> {
+        self.acquire()?;
+        let result = f(&self.store);
+        self.record(result.as_ref().err());
+        result
+    }
+
+    // 熔断时直接返回错误，半开时只放行一个调用
+    fn acquire(&self) -> Result<(), KvError> {
+        let mut state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(t) if Instant::now() < t || state.probing => Err(KvError::ServiceUnavailable(
+                format!("{} storage is failing", self.store.backend()),
+            )),
+            Some(_) => {
+                state.probing = true;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn record(&self, err: Option<&KvError>) {
+        let mut state = self.state.lock().unwrap();
+        state.probing = false;
+        match err {
+            Some(e) if is_backend_error(e) => {
+                state.failures += 1;
+                if state.open_until.is_some() || state.failures >= self.threshold {
+                    warn!(
+                        "Storage failed {} times in a row, circuit open for {:?}: {e}",
+                        state.failures, self.cooldown
+                    );
+                    state.open_until = Some(Instant::now() + self.cooldown);
+                }
+            }
+            _ => {
+                state.failures = 0;
+                state.open_until = None;
+            }
+        }
+    }
+}
+
+// 存储本身的错误，命令本身的错误不算
+fn is_backend_error(e: &KvError) -> bool {
+    matches!(
+        e,
+        KvError::SeldError(_) | KvError::RocksDBError(_) | KvError::IoError(_)
+    )
+}
+
+impl<S: Storage> Storage for CircuitBreakerStore<S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.call(|s| s.get(table, key))
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        self.call(|s| s.set(table, key, value))
+    }
+
+    fn put(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        self.call(|s| s.put(table, key, value))
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+        self.call(|s| s.set_batch(table, pairs))
+    }
+
+    fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+        self.call(|s| s.set_batch_if_absent(table, pairs))
+    }
+
+    fn move_nx(
+        &self,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError> {
+        self.call(|s| s.move_nx(src_table, src_key, dst_table, dst_key))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.call(|s| s.contains(table, key))
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.call(|s| s.del(table, key))
+    }
+
+    fn remove(&self, table: &str, key: &str) -> Result<(), KvError> {
+        self.call(|s| s.remove(table, key))
+    }
+
+    fn del_batch(&self, table: &str, keys: &[String]) -> Result<usize, KvError> {
+        self.call(|s| s.del_batch(table, keys))
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+    ) -> Result<Value, KvError> {
+        self.call(|s| s.update(table, key, f))
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.call(|s| s.get_all(table))
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.call(|s| s.get_iter(table))
+    }
+
+    fn get_iter_sorted(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.call(|s| s.get_iter_sorted(table))
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+    ) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.call(|s| s.get_range(table, start, end))
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        self.call(|s| s.len(table))
+    }
+
+    fn size_of(&self, table: &str, key: Option<&str>) -> Result<Option<Footprint>, KvError> {
+        self.call(|s| s.size_of(table, key))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        self.call(|s| s.tables())
+    }
+
+    fn has_table(&self, table: &str) -> Result<bool, KvError> {
+        self.call(|s| s.has_table(table))
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        self.call(|s| s.create_table(table))
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        self.call(|s| s.drop_table(table))
+    }
+
+    fn rename_table(&self, from: &str, to: &str, overwrite: bool) -> Result<bool, KvError> {
+        self.call(|s| s.rename_table(from, to, overwrite))
+    }
+
+    fn compact(&self, table: Option<&str>) -> Result<(), KvError> {
+        self.call(|s| s.compact(table))
+    }
+
+    fn is_blocking(&self) -> bool {
+        self.store.is_blocking()
+    }
+
+    fn backend(&self) -> &'static str {
+        self.store.backend()
+    }
+
+    fn table_counts(&self) -> Result<Vec<(&'static str, usize)>, KvError> {
+        self.call(|s| s.table_counts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+    };
+
+    use super::*;
+    use crate::{CommandResponse, MemTable};
+
+    // 可以模拟磁盘故障的存储
+    #[derive(Default)]
+    struct FlakyStore {
+        inner: MemTable,
+        failing: AtomicBool,
+    }
+
+    impl FlakyStore {
+        fn check(&self) -> Result<(), KvError> {
+            match self.failing.load(Ordering::SeqCst) {
+                true => Err(std::io::Error::other("disk full").into()),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl Storage for FlakyStore {
+        fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            self.check()?;
+            self.inner.get(table, key)
+        }
+
+        fn set(
+            &self,
+            table: &str,
+            key: impl Into<String>,
+            value: impl Into<Value>,
+        ) -> Result<Option<Value>, KvError> {
+            self.check()?;
+            self.inner.set(table, key, value)
+        }
+
+        fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+            self.check()?;
+            self.inner.set_batch(table, pairs)
+        }
+
+        fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+            self.check()?;
+            self.inner.set_batch_if_absent(table, pairs)
+        }
+
+        fn move_nx(
+            &self,
+            src_table: &str,
+            src_key: &str,
+            dst_table: &str,
+            dst_key: &str,
+        ) -> Result<bool, KvError> {
+            self.check()?;
+            self.inner.move_nx(src_table, src_key, dst_table, dst_key)
+        }
+
+        fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+            self.check()?;
+            self.inner.contains(table, key)
+        }
+
+        fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            self.check()?;
+            self.inner.del(table, key)
+        }
+
+        fn update(
+            &self,
+            table: &str,
+            key: &str,
+            f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+        ) -> Result<Value, KvError> {
+            self.check()?;
+            self.inner.update(table, key, f)
+        }
+
+        fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+            self.check()?;
+            self.inner.get_all(table)
+        }
+
+        fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+            self.check()?;
+            self.inner.get_iter(table)
+        }
+
+        fn len(&self, table: &str) -> Result<usize, KvError> {
+            self.check()?;
+            self.inner.len(table)
+        }
+
+        fn tables(&self) -> Result<Vec<String>, KvError> {
+            self.check()?;
+            self.inner.tables()
+        }
+
+        fn has_table(&self, table: &str) -> Result<bool, KvError> {
+            self.check()?;
+            self.inner.has_table(table)
+        }
+
+        fn create_table(&self, table: &str) -> Result<bool, KvError> {
+            self.check()?;
+            self.inner.create_table(table)
+        }
+
+        fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+            self.check()?;
+            self.inner.drop_table(table)
+        }
+    }
+
+    fn breaker(failure_threshold: u32, cooldown: u64) -> CircuitBreakerStore<FlakyStore> {
+        let config = CircuitBreakerConfig {
+            failure_threshold,
+            cooldown,
+        };
+        CircuitBreakerStore::new(FlakyStore::default(), config)
+    }
+
+    #[test]
+    fn circuit_breaker_should_trip_and_recover() {
+        let store = breaker(3, 50);
+        store.set("t1", "k1", "v1").unwrap();
+
+        store.store.failing.store(true, Ordering::SeqCst);
+        for _ in 0..3 {
+            assert!(matches!(store.get("t1", "k1"), Err(KvError::IoError(_))));
+        }
+        assert!(store.is_open());
+
+        // 熔断后直接返回 503，不再访问存储
+        store.store.failing.store(false, Ordering::SeqCst);
+        let err = store.get("t1", "k1").unwrap_err();
+        assert!(matches!(err, KvError::ServiceUnavailable(_)));
+        assert_eq!(CommandResponse::from(err).status, 503);
+
+        // cooldown 之后试探成功，恢复正常
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert!(!store.is_open());
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+    }
+
+    #[test]
+    fn circuit_breaker_should_reopen_when_probe_fails() {
+        let store = breaker(1, 50);
+        store.store.failing.store(true, Ordering::SeqCst);
+        assert!(store.get("t1", "k1").is_err());
+        assert!(store.is_open());
+
+        // 试探失败时再熔断一个 cooldown
+        thread::sleep(Duration::from_millis(100));
+        assert!(matches!(store.get("t1", "k1"), Err(KvError::IoError(_))));
+        assert!(matches!(
+            store.get("t1", "k1"),
+            Err(KvError::ServiceUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn command_errors_should_not_trip_circuit_breaker() {
+        let store = breaker(1, 60_000);
+        store.set("t1", "k1", "v1").unwrap();
+        let result = store.update("t1", "k1", |_| {
+            Err(KvError::ConvertError("v1".into(), "Integer"))
+        });
+        assert!(result.is_err());
+        assert!(!store.is_open());
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+    }
+}
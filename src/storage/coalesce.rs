@@ -0,0 +1,505 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, Weak},
+    thread,
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::{CoalesceConfig, Footprint, KvError, Kvpair, Storage, Value};
+
+/// 对磁盘存储的写入做合并：set 先写入内存中的缓冲区，缓冲的写入达到 max_ops 个，
+/// 或者距离上次写入存储超过 window 时，作为一个 batch 一起写入存储。
+///
+/// get/contains 会先查缓冲区，所以总能读到自己之前的写入。
+/// 其他操作会先把缓冲区写入存储，再调用底层存储。
+/// 缓冲区中的数据在写入存储前进程退出会丢失，用少量的持久性换取更高的写入吞吐。
+/// 写入存储失败的数据会留在缓冲区里等待下次写入；缓冲区满了仍然写入失败时，拒绝新的写入。
+///
+/// set 需要返回旧值，缓冲区中没有这个 key 时会持有缓冲区的锁读取底层存储，保证 "先读旧值再写入"
+/// 是原子的。这次读取期间其他写入需要等待，写入的 key 大多已经在缓冲区中或者底层存储读取很快时
+/// 影响不大
+pub struct CoalescedStore<S: Storage> {
+    inner: Arc<Inner<S>>,
+}
+
+// table -> key -> value，同一个 key 的多次写入只保留最后一次
+type Buffer = HashMap<String, HashMap<String, Value>>;
+
+struct Inner<S: Storage> {
+    store: S,
+    max_ops: usize,
+    buffer: Mutex<Buffer>,
+}
+
+impl<S: Storage> CoalescedStore<S> {
+    pub fn new(store: S, config: CoalesceConfig) -> Self {
+        let inner = Arc::new(Inner {
+            store,
+            max_ops: config.max_ops.max(1),
+            buffer: Mutex::new(HashMap::new()),
+        });
+
+        // 后台线程定期写入缓冲区，store 被 drop 后退出
+        let weak = Arc::downgrade(&inner);
+        let window = Duration::from_millis(config.window);
+        thread::Builder::new()
+            .name("kv-coalesce".into())
+            .spawn(move || flush_periodically(weak, window))
+            .expect("failed to spawn coalesce thread");
+
+        Self { inner }
+    }
+
+    /// 立即把缓冲区中的写入写入存储
+    pub fn flush(&self) -> Result<(), KvError> {
+        self.inner.flush(&mut self.inner.lock())
+    }
+
+    // 先写入缓冲区再调用底层存储，保证底层存储看到的数据是最新的。
+    // 持有锁直到 f 返回，期间的 set 不会写入缓冲区，也就不会在之后覆盖 f 的写入
+    fn flushed<'a, T>(&'a self, f: impl FnOnce(&'a S) -> Result<T, KvError>) -> Result<T, KvError> {
+        let mut buffer = self.inner.lock();
+        self.inner.flush(&mut buffer)?;
+        f(&self.inner.store)
+    }
+}
+
+impl<S: Storage> Inner<S> {
+    fn lock(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap()
+    }
+
+    // 一个表写入成功后才从缓冲区中移除，失败的和还没写入的表留在缓冲区里等待下次写入
+    fn flush(&self, buffer: &mut Buffer) -> Result<(), KvError> {
+        while let Some(table) = buffer.keys().next().cloned() {
+            let pairs = buffer[&table]
+                .iter()
+                .map(|(k, v)| Kvpair::new(k.clone(), v.clone()))
+                .collect();
+            self.store.set_batch(&table, pairs)?;
+            buffer.remove(&table);
+        }
+        Ok(())
+    }
+
+    // 把一个写入放进缓冲区，缓冲的写入达到 max_ops 个时写入存储。
+    // 之前写入存储失败导致缓冲区已满时，先重试写入存储，仍然失败就拒绝这次写入
+    fn insert(
+        &self,
+        buffer: &mut Buffer,
+        table: &str,
+        key: String,
+        value: Value,
+    ) -> Result<(), KvError> {
+        if ops(buffer) >= self.max_ops {
+            self.flush(buffer)?;
+        }
+        buffer
+            .entry(table.to_string())
+            .or_default()
+            .insert(key, value);
+
+        // 这次写入已经在缓冲区里了，写入存储失败时留给下次重试
+        if ops(buffer) >= self.max_ops {
+            if let Err(e) = self.flush(buffer) {
+                warn!("Failed to flush coalesced writes: {e}");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn ops(buffer: &Buffer) -> usize {
+    buffer.values().map(|t| t.len()).sum()
+}
+
+impl<S: Storage> Drop for Inner<S> {
+    fn drop(&mut self) {
+        let buffer = std::mem::take(self.buffer.get_mut().unwrap());
+        for (table, pairs) in buffer {
+            let pairs = pairs.into_iter().map(|(k, v)| Kvpair::new(k, v)).collect();
+            if let Err(e) = self.store.set_batch(&table, pairs) {
+                warn!("Failed to flush coalesced writes of table {table}: {e}");
+            }
+        }
+    }
+}
+
+fn flush_periodically<S: Storage>(inner: Weak<Inner<S>>, window: Duration) {
+    loop {
+        thread::sleep(window);
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        if let Err(e) = inner.flush(&mut inner.lock()) {
+            warn!("Failed to flush coalesced writes: {e}");
+        }
+    }
+}
+
+impl<S: Storage> Storage for CoalescedStore<S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let buffer = self.inner.lock();
+        if let Some(v) = buffer.get(table).and_then(|t| t.get(key)) {
+            return Ok(Some(v.clone()));
+        }
+        drop(buffer);
+        self.inner.store.get(table, key)
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        let key = key.into();
+        // 持有锁直到写入缓冲区，保证 "先读旧值再写入" 是原子的
+        let mut buffer = self.inner.lock();
+        let old = match buffer.get(table).and_then(|t| t.get(&key)) {
+            Some(v) => Some(v.clone()),
+            None => self.inner.store.get(table, &key)?,
+        };
+        self.inner.insert(&mut buffer, table, key, value.into())?;
+        Ok(old)
+    }
+
+    // 不需要旧值时直接写入缓冲区，不读取底层存储
+    fn put(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        let mut buffer = self.inner.lock();
+        self.inner
+            .insert(&mut buffer, table, key.into(), value.into())
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+        self.flushed(|s| s.set_batch(table, pairs))
+    }
+
+    fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+        // 持有锁直到写入完成，期间的 set 不会写入缓冲区
+        let mut buffer = self.inner.lock();
+        self.inner.flush(&mut buffer)?;
+        self.inner.store.set_batch_if_absent(table, pairs)
+    }
+
+    fn move_nx(
+        &self,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError> {
+        let mut buffer = self.inner.lock();
+        self.inner.flush(&mut buffer)?;
+        self.inner
+            .store
+            .move_nx(src_table, src_key, dst_table, dst_key)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let buffer = self.inner.lock();
+        if buffer.get(table).is_some_and(|t| t.contains_key(key)) {
+            return Ok(true);
+        }
+        drop(buffer);
+        self.inner.store.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let mut buffer = self.inner.lock();
+        self.inner.flush(&mut buffer)?;
+        self.inner.store.del(table, key)
+    }
+
+    fn remove(&self, table: &str, key: &str) -> Result<(), KvError> {
+        let mut buffer = self.inner.lock();
+        self.inner.flush(&mut buffer)?;
+        self.inner.store.remove(table, key)
+    }
+
+    fn del_batch(&self, table: &str, keys: &[String]) -> Result<usize, KvError> {
+        let mut buffer = self.inner.lock();
+        self.inner.flush(&mut buffer)?;
+        self.inner.store.del_batch(table, keys)
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        mut f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+    ) -> Result<Value, KvError> {
+        // 和 set 一样，持有锁直到新值写入缓冲区
+        let mut buffer = self.inner.lock();
+        let old = match buffer.get(table).and_then(|t| t.get(key)) {
+            Some(v) => Some(v.clone()),
+            None => self.inner.store.get(table, key)?,
+        };
+        let value = f(old)?;
+        self.inner
+            .insert(&mut buffer, table, key.to_string(), value.clone())?;
+        Ok(value)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.flushed(|s| s.get_all(table))
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.flushed(|s| s.get_iter(table))
+    }
+
+    fn get_iter_sorted(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.flushed(|s| s.get_iter_sorted(table))
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+    ) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.flushed(|s| s.get_range(table, start, end))
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        self.flushed(|s| s.len(table))
+    }
+
+    fn size_of(&self, table: &str, key: Option<&str>) -> Result<Option<Footprint>, KvError> {
+        self.flushed(|s| s.size_of(table, key))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        self.flushed(|s| s.tables())
+    }
+
+    fn has_table(&self, table: &str) -> Result<bool, KvError> {
+        self.flushed(|s| s.has_table(table))
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        self.flushed(|s| s.create_table(table))
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let mut buffer = self.inner.lock();
+        self.inner.flush(&mut buffer)?;
+        self.inner.store.drop_table(table)
+    }
+
+    fn rename_table(&self, from: &str, to: &str, overwrite: bool) -> Result<bool, KvError> {
+        let mut buffer = self.inner.lock();
+        self.inner.flush(&mut buffer)?;
+        self.inner.store.rename_table(from, to, overwrite)
+    }
+
+    fn compact(&self, table: Option<&str>) -> Result<(), KvError> {
+        self.flushed(|s| s.compact(table))
+    }
+
+    fn is_blocking(&self) -> bool {
+        self.inner.store.is_blocking()
+    }
+
+    fn backend(&self) -> &'static str {
+        self.inner.store.backend()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Instant,
+    };
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{MemTable, SledDb};
+
+    // set_batch 可以模拟写入失败的存储
+    #[derive(Default)]
+    struct FailingStore {
+        inner: MemTable,
+        failing: AtomicBool,
+    }
+
+    impl Storage for FailingStore {
+        fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            self.inner.get(table, key)
+        }
+
+        fn set(
+            &self,
+            table: &str,
+            key: impl Into<String>,
+            value: impl Into<Value>,
+        ) -> Result<Option<Value>, KvError> {
+            self.inner.set(table, key, value)
+        }
+
+        fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(std::io::Error::other("disk full").into());
+            }
+            self.inner.set_batch(table, pairs)
+        }
+
+        fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+            self.inner.set_batch_if_absent(table, pairs)
+        }
+
+        fn move_nx(
+            &self,
+            src_table: &str,
+            src_key: &str,
+            dst_table: &str,
+            dst_key: &str,
+        ) -> Result<bool, KvError> {
+            self.inner.move_nx(src_table, src_key, dst_table, dst_key)
+        }
+
+        fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+            self.inner.contains(table, key)
+        }
+
+        fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            self.inner.del(table, key)
+        }
+
+        fn update(
+            &self,
+            table: &str,
+            key: &str,
+            f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+        ) -> Result<Value, KvError> {
+            self.inner.update(table, key, f)
+        }
+
+        fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+            self.inner.get_all(table)
+        }
+
+        fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+            self.inner.get_iter(table)
+        }
+
+        fn len(&self, table: &str) -> Result<usize, KvError> {
+            self.inner.len(table)
+        }
+
+        fn tables(&self) -> Result<Vec<String>, KvError> {
+            self.inner.tables()
+        }
+
+        fn has_table(&self, table: &str) -> Result<bool, KvError> {
+            self.inner.has_table(table)
+        }
+
+        fn create_table(&self, table: &str) -> Result<bool, KvError> {
+            self.inner.create_table(table)
+        }
+
+        fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+            self.inner.drop_table(table)
+        }
+    }
+
+    fn config(window: u64, max_ops: usize) -> CoalesceConfig {
+        CoalesceConfig { window, max_ops }
+    }
+
+    #[test]
+    fn coalesced_writes_should_be_readable_before_flush() {
+        let store = CoalescedStore::new(MemTable::new(), config(60_000, 100));
+        assert_eq!(store.set("t1", "k1", "v1").unwrap(), None);
+        assert_eq!(store.set("t1", "k1", "v2").unwrap(), Some("v1".into()));
+
+        // 还没有写入底层存储，但可以读到自己的写入
+        assert_eq!(store.inner.store.get("t1", "k1").unwrap(), None);
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v2".into()));
+        assert!(store.contains("t1", "k1").unwrap());
+
+        // 其他操作会先写入缓冲区
+        assert_eq!(store.len("t1").unwrap(), 1);
+        assert_eq!(
+            store.inner.store.get("t1", "k1").unwrap(),
+            Some("v2".into())
+        );
+        assert_eq!(store.del("t1", "k1").unwrap(), Some("v2".into()));
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
+    }
+
+    #[test]
+    fn coalesced_writes_should_flush_when_full() {
+        let store = CoalescedStore::new(MemTable::new(), config(60_000, 2));
+        store.set("t1", "k1", "v1").unwrap();
+        assert_eq!(store.inner.store.len("t1").unwrap(), 0);
+        store.set("t2", "k1", "v1").unwrap();
+        assert_eq!(store.inner.store.len("t1").unwrap(), 1);
+        assert_eq!(store.inner.store.len("t2").unwrap(), 1);
+    }
+
+    #[test]
+    fn coalesced_writes_should_flush_after_window() {
+        let store = CoalescedStore::new(MemTable::new(), config(10, 100));
+        store.set("t1", "k1", "v1").unwrap();
+
+        // 不依赖具体的调度时间，等到后台线程写入存储，最多等 5 秒
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while store.inner.store.get("t1", "k1").unwrap().is_none() {
+            assert!(
+                Instant::now() < deadline,
+                "coalesced writes were not flushed"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            store.inner.store.get("t1", "k1").unwrap(),
+            Some("v1".into())
+        );
+    }
+
+    #[test]
+    fn coalesced_writes_should_flush_on_drop() {
+        let dir = tempdir().unwrap();
+        let store = CoalescedStore::new(SledDb::new(dir.path()), config(60_000, 100));
+        store.set("t1", "k1", "v1").unwrap();
+        drop(store);
+
+        let store = SledDb::new(dir.path());
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+    }
+
+    #[test]
+    fn coalesced_writes_should_be_kept_when_flush_fails() {
+        let store = CoalescedStore::new(FailingStore::default(), config(60_000, 2));
+        store.set("t1", "k1", "v1").unwrap();
+
+        // 写入存储失败，已经缓冲的写入不会丢失
+        store.inner.store.failing.store(true, Ordering::SeqCst);
+        store.set("t2", "k1", "v1").unwrap();
+        assert!(store.flush().is_err());
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t2", "k1").unwrap(), Some("v1".into()));
+
+        // 缓冲区满了仍然写入失败，拒绝新的写入
+        assert!(store.set("t3", "k1", "v1").is_err());
+        assert_eq!(store.get("t3", "k1").unwrap(), None);
+
+        store.inner.store.failing.store(false, Ordering::SeqCst);
+        store.flush().unwrap();
+        assert_eq!(store.inner.store.len("t1").unwrap(), 1);
+        assert_eq!(store.inner.store.len("t2").unwrap(), 1);
+        assert_eq!(store.inner.store.len("t3").unwrap(), 0);
+    }
+}
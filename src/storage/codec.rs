@@ -0,0 +1,206 @@
+use clap::ValueEnum;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number};
+
+use super::compression::decompress_value;
+use crate::{value, KvError, Kvpair, Value};
+
+/// value 写入磁盘存储时的编码，读取时必须使用和写入时相同的编码
+pub trait ValueCodec {
+    fn encode(value: Value) -> Result<Vec<u8>, KvError>;
+    fn decode(data: &[u8]) -> Result<Value, KvError>;
+}
+
+/// protobuf 编码，可以无损地保存所有类型的 value
+pub struct ProstCodec;
+
+/// 只保存 String 和 Binary 的原始字节，其他进程可以直接读取数据；写入其他类型的 value 返回错误。
+///
+/// 读取时无法区分 String 和 Binary：合法的 UTF-8 作为 String 返回，否则作为 Binary 返回
+pub struct RawCodec;
+
+/// JSON 编码，便于其他语言的程序读取。String、Integer、Float、Bool 对应 JSON 中的同名类型，
+/// Map 对应 object，Binary 对应字节组成的 array，空的 value 对应 null。
+///
+/// Timestamp 和 Duration 保存为毫秒数，读取时变成 Integer
+pub struct JsonCodec;
+
+/// 磁盘存储使用的 value 编码
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueCodecType {
+    #[default]
+    Prost,
+    Raw,
+    Json,
+}
+
+impl ValueCodecType {
+    pub fn encode(self, value: Value) -> Result<Vec<u8>, KvError> {
+        match self {
+            ValueCodecType::Prost => ProstCodec::encode(value),
+            ValueCodecType::Raw => RawCodec::encode(value),
+            ValueCodecType::Json => JsonCodec::encode(value),
+        }
+    }
+
+    /// 压缩过的数据先按头部解压，见 ValueCompression
+    pub fn decode(self, data: &[u8]) -> Result<Value, KvError> {
+        let data = decompress_value(data)?;
+        match self {
+            ValueCodecType::Prost => ProstCodec::decode(&data),
+            ValueCodecType::Raw => RawCodec::decode(&data),
+            ValueCodecType::Json => JsonCodec::decode(&data),
+        }
+    }
+
+    /// 把存储中的 key 和编码后的 value 转换成 Kvpair
+    pub fn pair(self, key: &[u8], value: &[u8]) -> Result<Kvpair, KvError> {
+        let key = std::str::from_utf8(key)
+            .map_err(|_| KvError::ConvertError(format!("{key:?}"), "String"))?;
+        Ok(Kvpair::new(key, self.decode(value)?))
+    }
+}
+
+impl ValueCodec for ProstCodec {
+    fn encode(value: Value) -> Result<Vec<u8>, KvError> {
+        let mut buf = Vec::with_capacity(value.encoded_len());
+        value.encode(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn decode(data: &[u8]) -> Result<Value, KvError> {
+        Ok(Value::decode(data)?)
+    }
+}
+
+impl ValueCodec for RawCodec {
+    fn encode(value: Value) -> Result<Vec<u8>, KvError> {
+        match value.value {
+            Some(value::Value::String(s)) => Ok(s.into_bytes()),
+            Some(value::Value::Binary(b)) => Ok(b.to_vec()),
+            _ => Err(KvError::ConvertError(value.format(), "String or Binary")),
+        }
+    }
+
+    fn decode(data: &[u8]) -> Result<Value, KvError> {
+        Ok(match std::str::from_utf8(data) {
+            Ok(s) => s.into(),
+            Err(_) => bytes::Bytes::copy_from_slice(data).into(),
+        })
+    }
+}
+
+impl ValueCodec for JsonCodec {
+    fn encode(value: Value) -> Result<Vec<u8>, KvError> {
+        serde_json::to_vec(&to_json(value)?).map_err(|e| KvError::Internal(e.to_string()))
+    }
+
+    fn decode(data: &[u8]) -> Result<Value, KvError> {
+        let json = serde_json::from_slice(data).map_err(|e| KvError::Internal(e.to_string()))?;
+        from_json(json)
+    }
+}
+
+fn to_json(value: Value) -> Result<serde_json::Value, KvError> {
+    use serde_json::Value as Json;
+
+    Ok(match value.value {
+        None => Json::Null,
+        Some(value::Value::String(s)) => Json::String(s),
+        Some(value::Value::Binary(b)) => Json::Array(b.iter().map(|b| (*b).into()).collect()),
+        Some(value::Value::Integer(i)) => i.into(),
+        Some(value::Value::Float(f)) => Number::from_f64(f)
+            .map(Json::Number)
+            .ok_or_else(|| KvError::ConvertError(f.to_string(), "finite Float"))?,
+        Some(value::Value::Bool(b)) => b.into(),
+        Some(value::Value::Timestamp(t)) => t.into(),
+        Some(value::Value::Duration(d)) => d.into(),
+        Some(value::Value::Map(map)) => {
+            let mut object = Map::new();
+            for pair in map.pairs {
+                object.insert(pair.key, to_json(pair.value.unwrap_or_default())?);
+            }
+            Json::Object(object)
+        }
+    })
+}
+
+fn from_json(json: serde_json::Value) -> Result<Value, KvError> {
+    use serde_json::Value as Json;
+
+    Ok(match json {
+        Json::Null => Value::default(),
+        Json::Bool(b) => b.into(),
+        Json::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => i.into(),
+            (None, Some(f)) => Value::try_from(f)?,
+            _ => return Err(KvError::ConvertError(n.to_string(), "Integer")),
+        },
+        Json::String(s) => s.into(),
+        Json::Array(items) => {
+            let bytes = items
+                .iter()
+                .map(|v| v.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| KvError::ConvertError(format!("{items:?}"), "Binary"))?;
+            bytes::Bytes::from(bytes).into()
+        }
+        Json::Object(object) => {
+            let map = object
+                .into_iter()
+                .map(|(k, v)| Ok((k, from_json(v)?)))
+                .collect::<Result<std::collections::HashMap<_, _>, KvError>>()?;
+            map.into()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn raw_codec_should_store_plain_bytes() {
+        let codec = ValueCodecType::Raw;
+        assert_eq!(codec.encode("hello".into()).unwrap(), b"hello");
+        assert_eq!(codec.encode(b"\xff\x00".into()).unwrap(), b"\xff\x00");
+        assert!(codec.encode(1.into()).is_err());
+
+        assert_eq!(codec.decode(b"hello").unwrap(), "hello".into());
+        assert_eq!(codec.decode(b"\xff\x00").unwrap(), b"\xff\x00".into());
+    }
+
+    #[test]
+    fn json_codec_should_round_trip() {
+        let codec = ValueCodecType::Json;
+        let map: Value = HashMap::from([
+            ("name".to_string(), Value::from("alice")),
+            ("age".to_string(), Value::from(30)),
+        ])
+        .into();
+        let values = [
+            Value::from("hello"),
+            Value::from(b"\x01\x02"),
+            Value::from(-1),
+            Value::try_from(1.0).unwrap(),
+            Value::from(true),
+            Value::default(),
+            map,
+        ];
+        for value in values {
+            let data = codec.encode(value.clone()).unwrap();
+            assert_eq!(codec.decode(&data).unwrap(), value);
+        }
+
+        let data = codec.encode("hello".into()).unwrap();
+        assert_eq!(data, br#""hello""#);
+
+        // Duration 保存为毫秒数
+        let data = codec.encode(Duration::from_secs(1).into()).unwrap();
+        assert_eq!(codec.decode(&data).unwrap(), 1000.into());
+    }
+}
@@ -0,0 +1,140 @@
+use std::{borrow::Cow, sync::Arc};
+
+use bytes::BytesMut;
+
+use super::routing::matches;
+use crate::{compress, decompress, CompressorType, KvError, TableCompressionConfig};
+
+/// 压缩过的 value 以 MAGIC 开头，之后是 1 字节的算法和 4 字节（大端）的原始长度。
+/// protobuf 和 JSON 编码的数据不会以 0 开头
+const MAGIC: &[u8; 4] = b"\0kvz";
+const HEADER_LEN: usize = MAGIC.len() + 5;
+/// 解压前最多按头部中的原始长度预分配这么多字节，头部损坏时不会一次分配过多内存，
+/// 更大的 value 在解压时逐渐扩容
+const MAX_PREALLOC: usize = 1024 * 1024;
+
+/// 磁盘存储按 table 名压缩 value，table 按配置的顺序匹配第一个 pattern。
+///
+/// 压缩后的数据带有头部，读取时根据头部解压，和当前的配置无关；压缩后没有变小的 value 原样保存
+#[derive(Clone, Default)]
+pub struct ValueCompression(Arc<[TableCompressionConfig]>);
+
+impl ValueCompression {
+    pub fn new(configs: &[TableCompressionConfig]) -> Self {
+        Self(configs.into())
+    }
+
+    /// 压缩 table 中编码后的 value
+    pub fn compress(&self, table: &str, data: Vec<u8>) -> Result<Vec<u8>, KvError> {
+        let config = self.0.iter().find(|c| matches(&c.pattern, table));
+        let compressor = config.map_or(CompressorType::None, |c| c.compressor);
+        if compressor != CompressorType::None {
+            let mut buf = BytesMut::new();
+            compress(compressor, &data, &mut buf, config.and_then(|c| c.level))?;
+            if buf.len() + HEADER_LEN < data.len() {
+                return Ok(with_header(compressor, data.len(), &buf));
+            }
+        }
+        // 恰好以 MAGIC 开头的原始数据也加上头部，避免读取时被当成压缩过的数据
+        match data.starts_with(MAGIC) {
+            true => Ok(with_header(CompressorType::None, data.len(), &data)),
+            false => Ok(data),
+        }
+    }
+}
+
+/// 读取时根据头部解压，没有头部的数据原样返回
+pub fn decompress_value(data: &[u8]) -> Result<Cow<'_, [u8]>, KvError> {
+    if !data.starts_with(MAGIC) {
+        return Ok(Cow::Borrowed(data));
+    }
+    if data.len() < HEADER_LEN {
+        return Err(KvError::Internal("corrupted compressed value".into()));
+    }
+    let compressor = CompressorType::from(data[MAGIC.len()] as usize);
+    let len = u32::from_be_bytes(data[MAGIC.len() + 1..HEADER_LEN].try_into().unwrap()) as usize;
+    let payload = &data[HEADER_LEN..];
+    if compressor == CompressorType::None {
+        return Ok(Cow::Borrowed(payload));
+    }
+
+    // 解压时最多写入 len 字节，解压后的长度和 len 不一致说明数据已经损坏
+    let mut buf = Vec::with_capacity(len.min(MAX_PREALLOC));
+    decompress(compressor, payload, &mut buf, len)?;
+    if buf.len() != len {
+        return Err(KvError::Internal("corrupted compressed value".into()));
+    }
+    Ok(Cow::Owned(buf))
+}
+
+fn with_header(compressor: CompressorType, len: usize, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_LEN + payload.len());
+    data.extend_from_slice(MAGIC);
+    data.push(compressor as u8);
+    data.extend_from_slice(&(len as u32).to_be_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SledDb, Storage, Value};
+
+    fn config(pattern: &str, compressor: CompressorType) -> TableCompressionConfig {
+        TableCompressionConfig {
+            pattern: pattern.into(),
+            compressor,
+            level: None,
+        }
+    }
+
+    #[test]
+    fn compressed_table_should_store_fewer_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let compression = ValueCompression::new(&[
+            config("raw", CompressorType::None),
+            config("*", CompressorType::ZSTD),
+        ]);
+        let store = SledDb::new(dir.path()).with_compression(compression);
+
+        let value: Value = "compressible value ".repeat(100).into();
+        for table in ["logs", "raw"] {
+            store.set(table, "k1", value.clone()).unwrap();
+            assert_eq!(store.get(table, "k1").unwrap(), Some(value.clone()));
+        }
+        let compressed = store.size_of("logs", Some("k1")).unwrap().unwrap();
+        let raw = store.size_of("raw", Some("k1")).unwrap().unwrap();
+        assert!(compressed.bytes * 10 < raw.bytes);
+
+        // 关闭压缩后仍然可以读取之前压缩过的数据
+        drop(store);
+        let store = SledDb::new(dir.path());
+        assert_eq!(store.get("logs", "k1").unwrap(), Some(value.clone()));
+        let pairs = store.get_all("logs").unwrap();
+        assert_eq!(pairs[0].value, Some(value));
+    }
+
+    #[test]
+    fn raw_data_starting_with_magic_should_roundtrip() {
+        let compression = ValueCompression::new(&[config("*", CompressorType::LZ4)]);
+        let mut data = MAGIC.to_vec();
+        data.push(2);
+
+        let stored = compression.compress("t1", data.clone()).unwrap();
+        assert_ne!(stored, data);
+        assert_eq!(decompress_value(&stored).unwrap(), data.as_slice());
+        assert_eq!(decompress_value(b"plain").unwrap(), b"plain".as_slice());
+    }
+
+    #[test]
+    fn corrupted_length_should_be_rejected() {
+        let compression = ValueCompression::new(&[config("*", CompressorType::ZSTD)]);
+        let data = "compressible value ".repeat(100).into_bytes();
+        let mut stored = compression.compress("t1", data).unwrap();
+
+        // 头部中的原始长度被改成接近 4 GiB
+        stored[MAGIC.len() + 1..HEADER_LEN].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decompress_value(&stored).is_err());
+    }
+}
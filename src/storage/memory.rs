@@ -1,7 +1,14 @@
+use super::check_rename;
 use crate::{KvError, Kvpair, Storage, StorageIter, Value};
-use dashmap::{mapref::one::Ref, DashMap};
+use dashmap::{
+    mapref::{entry::Entry, one::Ref},
+    DashMap,
+};
 
 /// 使用 DashMap 构建的 MemTable，实现了 Storage trait
+///
+/// Value 的 binary 数据使用 Bytes 保存，get/get_all 返回的 Value 通过引用计数共享存储中的数据，
+/// 重复读取一个很大的 binary value 不会再拷贝数据（见 benches/large_value.rs）
 #[derive(Clone, Debug, Default)]
 pub struct MemTable {
     tables: DashMap<String, DashMap<String, Value>>,
@@ -14,7 +21,7 @@ impl MemTable {
     }
 
     // 如果名为 name 的 hash table不存在，则创建，否则返回
-    fn get_or_create_table(&self, name: &str) -> Ref<String, DashMap<String, Value>> {
+    fn get_or_create_table(&self, name: &str) -> Ref<'_, String, DashMap<String, Value>> {
         match self.tables.get(name) {
             Some(table) => table,
             None => {
@@ -44,6 +51,73 @@ impl Storage for MemTable {
         Ok(table.insert(key.into(), value.into()))
     }
 
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+        let table = self.get_or_create_table(table);
+        let count = pairs.len();
+        for pair in pairs {
+            table.insert(pair.key, pair.value.unwrap_or_default());
+        }
+        Ok(count)
+    }
+
+    fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+        // 持有 table 所在分片的写锁，检查和写入期间其他操作都无法访问这个 table
+        let table = self.tables.entry(table.to_string()).or_default();
+        if pairs.iter().any(|p| table.contains_key(&p.key)) {
+            return Ok(false);
+        }
+        for pair in pairs {
+            table.insert(pair.key, pair.value.unwrap_or_default());
+        }
+        Ok(true)
+    }
+
+    // 同一个 table 中移动时持有 table 所在分片的写锁，检查和移动是原子的。跨 table 移动时同时持有
+    // src 和 dst 两个 key 所在分片的写锁，其他命令在移动完成之前读写不到这两个 key
+    fn move_nx(
+        &self,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError> {
+        if src_table == dst_table {
+            let table = self.tables.entry(src_table.to_string()).or_default();
+            if table.contains_key(dst_key) {
+                return Ok(false);
+            }
+            let Some((_, value)) = table.remove(src_key) else {
+                return Ok(false);
+            };
+            table.insert(dst_key.to_string(), value);
+            return Ok(true);
+        }
+
+        // 先创建 table 再同时读取两个 table，持有一个 table 时创建另一个可能会等待同一个分片的写锁
+        self.get_or_create_table(src_table);
+        self.get_or_create_table(dst_table);
+        let (Some(src), Some(dst)) = (self.tables.get(src_table), self.tables.get(dst_table))
+        else {
+            // table 在这期间被删除了
+            return Ok(false);
+        };
+        // 按 table 名的顺序加锁，避免两个方向相反的移动互相等待
+        let (src_entry, dst_entry) = if src_table < dst_table {
+            let src_entry = src.entry(src_key.to_string());
+            (src_entry, dst.entry(dst_key.to_string()))
+        } else {
+            let dst_entry = dst.entry(dst_key.to_string());
+            (src.entry(src_key.to_string()), dst_entry)
+        };
+        match (src_entry, dst_entry) {
+            (Entry::Occupied(src_entry), Entry::Vacant(dst_entry)) => {
+                dst_entry.insert(src_entry.remove());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
         let table = self.get_or_create_table(table);
         Ok(table.contains_key(key))
@@ -54,6 +128,29 @@ impl Storage for MemTable {
         Ok(table.remove(key).map(|(_k, v)| v))
     }
 
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        mut f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+    ) -> Result<Value, KvError> {
+        let table = self.get_or_create_table(table);
+        // entry 持有 key 所在分片的写锁，读取和写入之间不会有其他写入
+        let value = match table.entry(key.to_string()) {
+            Entry::Occupied(mut entry) => {
+                let value = f(Some(entry.get().clone()))?;
+                entry.insert(value.clone());
+                value
+            }
+            Entry::Vacant(entry) => {
+                let value = f(None)?;
+                entry.insert(value.clone());
+                value
+            }
+        };
+        Ok(value)
+    }
+
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
         let table = self.get_or_create_table(table);
         Ok(table
@@ -66,10 +163,63 @@ impl Storage for MemTable {
         let table = self.get_or_create_table(table).clone();
         Ok(StorageIter::new(table.into_iter()))
     }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        Ok(self.tables.get(table).map(|t| t.len()).unwrap_or(0))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        // get 等操作会创建空的 table，这里跳过
+        Ok(self
+            .tables
+            .iter()
+            .filter(|t| !t.value().is_empty())
+            .map(|t| t.key().clone())
+            .collect())
+    }
+
+    fn has_table(&self, table: &str) -> Result<bool, KvError> {
+        Ok(self.tables.contains_key(table))
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        let mut created = false;
+        self.tables.entry(table.to_string()).or_insert_with(|| {
+            created = true;
+            DashMap::new()
+        });
+        Ok(created)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        Ok(self.tables.remove(table).is_some())
+    }
+
+    // 直接移动整个 table 的 DashMap，不复制数据。DashMap 不能同时锁住两个 table，
+    // 移除 from 之后、插入 to 之前的很短时间内两个 table 中都读不到这些数据
+    fn rename_table(&self, from: &str, to: &str, overwrite: bool) -> Result<bool, KvError> {
+        check_rename(self, from, to, overwrite)?;
+        let Some((_, table)) = self.tables.remove(from) else {
+            return Err(KvError::NotFound(format!("table {from}")));
+        };
+        Ok(self.tables.insert(to.to_string(), table).is_some())
+    }
+
+    // DashMap 的操作都在内存中完成，很快，直接在 async 任务里执行即可
+    fn is_blocking(&self) -> bool {
+        false
+    }
+
+    fn backend(&self) -> &'static str {
+        "memtable"
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use bytes::Bytes;
+    use std::thread;
+
     use super::*;
 
     #[test]
@@ -79,4 +229,48 @@ mod tests {
         store.get_or_create_table("table");
         assert!(store.tables.contains_key("table"));
     }
+
+    #[test]
+    fn move_nx_across_tables_should_not_deadlock() {
+        let store = MemTable::new();
+        store.set("t1", "key", "value").unwrap();
+
+        // 两个方向相反的移动并发执行，不会互相等待，key 最后只在其中一个 table 中
+        thread::scope(|s| {
+            for (src, dst) in [("t1", "t2"), ("t2", "t1")] {
+                let store = &store;
+                s.spawn(move || {
+                    for _ in 0..1000 {
+                        store.move_nx(src, "key", dst, "key").unwrap();
+                    }
+                });
+            }
+        });
+
+        let found = ["t1", "t2"].map(|t| store.get(t, "key").unwrap());
+        assert_eq!(found.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn get_binary_should_not_copy() {
+        let store = MemTable::new();
+        let data = Bytes::from(vec![1u8; 1024 * 1024]);
+        store.set("table", "key", data.clone()).unwrap();
+
+        // 读出来的数据和写入的数据共享同一块内存
+        let v1: Bytes = store
+            .get("table", "key")
+            .unwrap()
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let v2: Bytes = store.get_all("table").unwrap()[0]
+            .value
+            .clone()
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(v1.as_ptr(), data.as_ptr());
+        assert_eq!(v2.as_ptr(), data.as_ptr());
+    }
 }
@@ -1,12 +1,27 @@
+mod breaker;
+mod coalesce;
+mod codec;
+mod compression;
+mod consistency;
 mod memory;
+mod read_cache;
 mod rocksdb;
+mod routing;
 mod sleddb;
 
+pub use breaker::CircuitBreakerStore;
+pub use coalesce::CoalescedStore;
+pub use codec::*;
+pub use compression::ValueCompression;
+pub use consistency::{verify_consistency, Mismatch};
 pub use memory::MemTable;
+pub use read_cache::CachedStore;
 pub use rocksdb::RocksDB;
+pub use routing::{AnyStore, RoutingStore};
 pub use sleddb::SledDb;
 
 use crate::{KvError, Kvpair, Value};
+use prost::Message;
 
 /// 对存储的抽象，我们不关心数据存在哪儿，但需要定义外界如何和存储打交道
 pub trait Storage: Send + Sync + 'static {
@@ -19,14 +34,182 @@ pub trait Storage: Send + Sync + 'static {
         key: impl Into<String>,
         value: impl Into<Value>,
     ) -> Result<Option<Value>, KvError>;
+    /// 往一个 HashTable 里批量写入一组 kv pair，返回写入的数量
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError>;
+    /// 所有的 key 都不存在时原子地写入所有 kv pair 并返回 true，否则什么都不写入并返回 false
+    fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError>;
+    /// src_key 存在且 dst_key 不存在时，原子地把 src_key 的 value 移动到 dst_key 并返回 true，
+    /// 否则什么都不修改并返回 false
+    fn move_nx(
+        &self,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError>;
+    /// 和 set 一样写入一个 key，但不需要旧的 value。
+    /// 默认调用 set，读取旧值需要额外开销的存储（如 RocksDB）应该覆盖这个方法
+    fn put(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        self.set(table, key, value).map(|_| ())
+    }
     /// 查看 HashTable 中是否有 key
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError>;
-    /// 从 HashTable 中删除一个 key
+    /// 从 HashTable 中删除一个 key，原子地返回被删除的 value
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
+    /// 和 del 一样删除一个 key，但不需要被删除的 value，默认调用 del
+    fn remove(&self, table: &str, key: &str) -> Result<(), KvError> {
+        self.del(table, key).map(|_| ())
+    }
+    /// 删除一组 key，返回其中存在的 key 的数量，重复的 key 只计算一次。默认逐个调用 del
+    fn del_batch(&self, table: &str, keys: &[String]) -> Result<usize, KvError> {
+        let mut count = 0;
+        for key in keys {
+            if self.del(table, key)?.is_some() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+    /// 原子地读取 key 的 value，用 f 计算出新的 value 写入，返回新的 value
+    /// f 返回错误时不写入；有并发写入时 f 可能被调用多次
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+    ) -> Result<Value, KvError>;
     /// 遍历 HashTable，返回所有 kv pair（这个接口不好）
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
     /// 遍历 HashTable，返回 kv pair 的 Iterator
     fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError>;
+    /// 按 key 的字节序升序遍历 HashTable，默认实现读出所有数据后排序
+    fn get_iter_sorted(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        let mut pairs = self.get_all(table)?;
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(pairs.into_iter())
+    }
+    /// 按 key 的字节序升序返回 [start, end) 范围内的 kv pair，start >= end 时为空，
+    /// 默认实现遍历所有数据后过滤并排序
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+    ) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        let mut pairs: Vec<_> = self
+            .get_iter(table)?
+            .filter(|p| start <= p.key.as_str() && p.key.as_str() < end)
+            .collect();
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(pairs.into_iter())
+    }
+    /// HashTable 中 key 的数量
+    fn len(&self, table: &str) -> Result<usize, KvError>;
+    /// 指定 key 时返回 value 编码后的大小，key 不存在返回 None；
+    /// 不指定 key 时返回整个 HashTable 的大小。默认实现累加所有 key 和 value 编码后的长度
+    fn size_of(&self, table: &str, key: Option<&str>) -> Result<Option<Footprint>, KvError> {
+        let bytes = match key {
+            Some(key) => match self.get(table, key)? {
+                Some(v) => v.encoded_len(),
+                None => return Ok(None),
+            },
+            None => self
+                .get_iter(table)?
+                .map(|p| p.key.len() + p.value.map_or(0, |v| v.encoded_len()))
+                .sum(),
+        };
+        Ok(Some(Footprint::exact(bytes as u64)))
+    }
+    /// 所有非空的 HashTable 的名字
+    fn tables(&self) -> Result<Vec<String>, KvError>;
+    /// HashTable 是否存在
+    fn has_table(&self, table: &str) -> Result<bool, KvError>;
+    /// 创建 HashTable，返回是否是新创建的
+    fn create_table(&self, table: &str) -> Result<bool, KvError>;
+    /// 删除 HashTable 及其中所有的数据，返回它之前是否存在
+    fn drop_table(&self, table: &str) -> Result<bool, KvError>;
+    /// 把 HashTable from 改名为 to，返回 to 之前是否存在。to 存在时 overwrite 为 true 才会
+    /// 用 from 的数据替换 to，否则返回错误。默认实现把数据逐个复制过去，不是原子的，
+    /// 能原子地移动数据的存储应该覆盖这个方法
+    fn rename_table(&self, from: &str, to: &str, overwrite: bool) -> Result<bool, KvError> {
+        let existed = check_rename(self, from, to, overwrite)?;
+        let pairs = self.get_all(from)?;
+        self.drop_table(to)?;
+        self.create_table(to)?;
+        self.set_batch(to, pairs)?;
+        self.drop_table(from)?;
+        Ok(existed)
+    }
+    /// 压缩存储，回收删除和覆盖的数据占用的空间，table 为 None 时压缩所有 HashTable。
+    /// 压缩完成后才返回，默认实现什么都不做
+    fn compact(&self, _table: Option<&str>) -> Result<(), KvError> {
+        Ok(())
+    }
+    /// 调用是否可能阻塞线程（比如磁盘 IO），是的话服务端会放到 blocking 线程里执行
+    fn is_blocking(&self) -> bool {
+        true
+    }
+    /// 存储后端的名字，和配置中 storage 的名字一致
+    fn backend(&self) -> &'static str {
+        "custom"
+    }
+    /// 每个存储后端中非空 table 的数量，组合了多个后端的存储按后端分别统计
+    fn table_counts(&self) -> Result<Vec<(&'static str, usize)>, KvError> {
+        Ok(vec![(self.backend(), self.tables()?.len())])
+    }
+}
+
+// 检查 rename_table 的参数，返回 to 是否存在
+fn check_rename(
+    store: &(impl Storage + ?Sized),
+    from: &str,
+    to: &str,
+    overwrite: bool,
+) -> Result<bool, KvError> {
+    if from == to {
+        return Err(KvError::InvalidCommand(format!(
+            "cannot rename table {from} to itself"
+        )));
+    }
+    if !store.has_table(from)? {
+        return Err(KvError::NotFound(format!("table {from}")));
+    }
+    let existed = store.has_table(to)?;
+    if existed && !overwrite {
+        return Err(KvError::InvalidCommand(format!(
+            "table {to} already exists"
+        )));
+    }
+    Ok(existed)
+}
+
+/// 数据占用的空间（字节）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Footprint {
+    pub bytes: u64,
+    /// 是否是估算值，磁盘存储的 table 大小受压缩、未回收的空间等影响，只能估算
+    pub approximate: bool,
+}
+
+impl Footprint {
+    pub fn exact(bytes: u64) -> Self {
+        Self {
+            bytes,
+            approximate: false,
+        }
+    }
+
+    pub fn approximate(bytes: u64) -> Self {
+        Self {
+            bytes,
+            approximate: true,
+        }
+    }
 }
 
 //提供 Storage Iterator, 这样trait的实现者只需要把他们的Iterator, 提供给 StorageIter, 并且保证next()传出的类型实现了Into<Kvpair>
@@ -54,9 +237,13 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::thread;
+
+    use proptest::prelude::*;
     use tempfile::tempdir;
 
     use super::*;
+    use crate::test_support::*;
 
     #[test]
     fn memetable_basic_interface_should_work() {
@@ -76,6 +263,42 @@ mod tests {
         test_get_all(store);
     }
 
+    #[test]
+    fn memtable_set_batch_should_work() {
+        let store = MemTable::new();
+        test_set_batch(store);
+    }
+
+    #[test]
+    fn memtable_sorted_iter_and_range_should_work() {
+        let store = MemTable::new();
+        test_sorted_iter_and_range(store);
+    }
+
+    #[test]
+    fn memtable_len_and_tables_should_work() {
+        let store = MemTable::new();
+        test_len_and_tables(store);
+    }
+
+    #[test]
+    fn memtable_create_drop_table_should_work() {
+        let store = MemTable::new();
+        test_create_drop_table(store);
+    }
+
+    #[test]
+    fn memtable_rename_table_should_work() {
+        let store = MemTable::new();
+        test_rename_table(store);
+    }
+
+    #[test]
+    fn memtable_move_nx_should_work() {
+        let store = MemTable::new();
+        test_move_nx(store);
+    }
+
     #[test]
     fn selddb_basic_interface_should_work() {
         let dir = tempdir().unwrap();
@@ -97,6 +320,93 @@ mod tests {
         test_get_all(store);
     }
 
+    #[test]
+    fn selddb_set_batch_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_set_batch(store);
+    }
+
+    #[test]
+    fn selddb_sorted_iter_and_range_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_sorted_iter_and_range(store);
+    }
+
+    #[test]
+    fn selddb_len_and_tables_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_len_and_tables(store);
+    }
+
+    #[test]
+    fn selddb_create_drop_table_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_create_drop_table(store);
+    }
+
+    #[test]
+    fn selddb_move_nx_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_move_nx(store);
+    }
+
+    #[test]
+    fn selddb_rename_table_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_rename_table(store);
+    }
+
+    #[test]
+    fn selddb_should_persist_across_reopen() {
+        let dir = tempdir().unwrap();
+        {
+            let store = SledDb::new(dir.path());
+            store.set("t1", "k1", "v1").unwrap();
+            store.set("t1", "k2", "v2").unwrap();
+            store.del("t1", "k2").unwrap();
+        }
+
+        let store = SledDb::new(dir.path());
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert!(!store.contains("t1", "k2").unwrap());
+        assert_eq!(store.tables().unwrap(), vec!["t1"]);
+    }
+
+    #[test]
+    fn selddb_raw_codec_should_store_plain_bytes() {
+        let dir = tempdir().unwrap();
+        {
+            let store = SledDb::new(dir.path()).with_codec(ValueCodecType::Raw);
+            store.set("t1", "k1", "v1").unwrap();
+            assert!(store.set("t1", "k2", 1i64).is_err());
+            assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+            assert_eq!(store.get_all("t1").unwrap(), vec![Kvpair::new("k1", "v1")]);
+        }
+
+        // 其他程序直接打开数据库读到的是原始的字节
+        let db = sled::open(dir.path()).unwrap();
+        let value = db.open_tree("t1").unwrap().get("k1").unwrap().unwrap();
+        assert_eq!(value.as_ref(), b"v1");
+    }
+
+    #[test]
+    fn selddb_tables_should_not_overlap() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        // table 名和 key 中带 ":" 也不会和其他 table 混在一起
+        store.set("t1", "a:b", "v1").unwrap();
+        store.set("t1:a", "b", "v2").unwrap();
+        assert_eq!(store.len("t1").unwrap(), 1);
+        assert!(!store.contains("t1", "a").unwrap());
+        assert_eq!(store.get_all("t1").unwrap(), vec![Kvpair::new("a:b", "v1")]);
+    }
+
     #[test]
     fn rocksdb_basic_interface_should_work() {
         let dir = tempdir().unwrap();
@@ -118,6 +428,68 @@ mod tests {
         test_get_all(store);
     }
 
+    #[test]
+    fn rocksdb_set_batch_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDB::new(dir);
+        test_set_batch(store);
+    }
+
+    #[test]
+    fn rocksdb_sorted_iter_and_range_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDB::new(dir);
+        test_sorted_iter_and_range(store);
+    }
+
+    #[test]
+    fn rocksdb_len_and_tables_should_work() {
+        // tables 需要重新读取目录，所以 dir 不能提前被删除
+        let dir = tempdir().unwrap();
+        let store = RocksDB::new(dir.path());
+        test_len_and_tables(store);
+    }
+
+    #[test]
+    fn rocksdb_create_drop_table_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDB::new(dir);
+        test_create_drop_table(store);
+    }
+
+    #[test]
+    fn rocksdb_rename_table_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDB::new(dir);
+        test_rename_table(store);
+    }
+
+    #[test]
+    fn rocksdb_move_nx_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDB::new(dir);
+        test_move_nx(store);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn storages_should_agree(ops in proptest::collection::vec(arb_storage_op(), 1..64)) {
+            let sled_dir = tempdir().unwrap();
+            let rocksdb_dir = tempdir().unwrap();
+            let memtable = MemTable::new();
+            let sleddb = SledDb::new(sled_dir.path());
+            let rocksdb = RocksDB::new(rocksdb_dir.path());
+
+            for op in ops {
+                let expected = op.apply(&memtable).unwrap();
+                prop_assert_eq!(&op.apply(&sleddb).unwrap(), &expected, "sleddb: {:?}", op);
+                prop_assert_eq!(&op.apply(&rocksdb).unwrap(), &expected, "rocksdb: {:?}", op);
+            }
+        }
+    }
+
     fn test_basi_interface(store: impl Storage) {
         // 第一次set会创建table，插入key并返回None（之前没值）
         let v = store.set("table", "key", "value");
@@ -135,9 +507,9 @@ mod tests {
         assert!(store.get("not exist table", "key").unwrap().is_none());
 
         //contains 存在的key返回true，否则返回None
-        assert_eq!(true, store.contains("table", "key").unwrap());
-        assert_eq!(false, store.contains("table", "not exist key").unwrap());
-        assert_eq!(false, store.contains("not exist table", "key").unwrap());
+        assert!(store.contains("table", "key").unwrap());
+        assert!(!store.contains("table", "not exist key").unwrap());
+        assert!(!store.contains("not exist table", "key").unwrap());
 
         // del 存在的key返回之前的值
         let v = store.del("table", "key");
@@ -146,6 +518,13 @@ mod tests {
         // del 不存在的 key 或者table返回None
         assert_eq!(None, store.del("table", "not exist key").unwrap());
         assert_eq!(None, store.del("not exist table", "key").unwrap());
+
+        // put 和 remove 不返回旧值
+        store.put("table", "key", "value2").unwrap();
+        assert_eq!(store.get("table", "key").unwrap(), Some("value2".into()));
+        store.remove("table", "key").unwrap();
+        assert!(!store.contains("table", "key").unwrap());
+        store.remove("not exist table", "key").unwrap();
     }
 
     fn test_get_all(store: impl Storage) {
@@ -169,4 +548,173 @@ mod tests {
             vec![Kvpair::new("key1", "1"), Kvpair::new("key2", "2")]
         );
     }
+
+    fn test_sorted_iter_and_range(store: impl Storage) {
+        for key in ["k3", "k10", "a", "k1", "k2", "b"] {
+            store.set("table", key, key).unwrap();
+        }
+        store.set("other", "k0", "v").unwrap();
+
+        fn keys(iter: impl Iterator<Item = Kvpair>) -> Vec<String> {
+            iter.map(|p| p.key).collect()
+        }
+
+        // 按字节序排序，k10 在 k1 和 k2 之间
+        let sorted = keys(store.get_iter_sorted("table").unwrap());
+        assert_eq!(sorted, vec!["a", "b", "k1", "k10", "k2", "k3"]);
+
+        // 包含 start，不包含 end
+        let range = keys(store.get_range("table", "b", "k2").unwrap());
+        assert_eq!(range, vec!["b", "k1", "k10"]);
+        let range = keys(store.get_range("table", "k", "l").unwrap());
+        assert_eq!(range, vec!["k1", "k10", "k2", "k3"]);
+        let pairs: Vec<_> = store.get_range("table", "k3", "k4").unwrap().collect();
+        assert_eq!(pairs, vec![Kvpair::new("k3", "k3")]);
+
+        // 空的范围
+        assert_eq!(store.get_range("table", "k2", "k2").unwrap().count(), 0);
+        assert_eq!(store.get_range("table", "k3", "a").unwrap().count(), 0);
+        assert_eq!(store.get_range("not exist", "a", "z").unwrap().count(), 0);
+    }
+
+    fn test_len_and_tables(store: impl Storage) {
+        assert_eq!(store.len("t1").unwrap(), 0);
+        assert!(store.tables().unwrap().is_empty());
+
+        store.set("t1", "k1", "v1").unwrap();
+        store.set("t1", "k2", "v2").unwrap();
+        store.set("t2", "k1", "v1").unwrap();
+        // 读取不存在的 table 不会让它出现在 tables 里
+        store.get("t3", "k1").unwrap();
+
+        assert_eq!(store.len("t1").unwrap(), 2);
+        assert_eq!(store.len("t2").unwrap(), 1);
+        let mut tables = store.tables().unwrap();
+        tables.sort();
+        assert_eq!(tables, vec!["t1", "t2"]);
+
+        store.del("t2", "k1").unwrap();
+        assert_eq!(store.len("t2").unwrap(), 0);
+        assert_eq!(store.tables().unwrap(), vec!["t1"]);
+    }
+
+    fn test_create_drop_table(store: impl Storage) {
+        assert!(!store.has_table("t1").unwrap());
+
+        // 创建已存在的 table 什么也不做
+        assert!(store.create_table("t1").unwrap());
+        assert!(store.has_table("t1").unwrap());
+        assert!(!store.create_table("t1").unwrap());
+        assert_eq!(store.len("t1").unwrap(), 0);
+
+        store.set("t1", "k1", "v1").unwrap();
+        store.set("t2", "k1", "v1").unwrap();
+        assert!(!store.create_table("t1").unwrap());
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+
+        // 删除 table 会删除其中所有的数据，不影响其他 table
+        assert!(store.drop_table("t1").unwrap());
+        assert!(!store.has_table("t1").unwrap());
+        assert!(!store.drop_table("t1").unwrap());
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
+        assert_eq!(store.get("t2", "k1").unwrap(), Some("v1".into()));
+    }
+
+    fn test_rename_table(store: impl Storage) {
+        store.set("users_new", "k1", "v1").unwrap();
+        store.set("users_new", "k2", "v2").unwrap();
+
+        // 改名到不存在的 table
+        assert!(!store.rename_table("users_new", "users", false).unwrap());
+        assert!(!store.has_table("users_new").unwrap());
+        assert_eq!(store.get_all("users_new").unwrap(), vec![]);
+        let mut pairs = store.get_all("users").unwrap();
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            pairs,
+            vec![Kvpair::new("k1", "v1"), Kvpair::new("k2", "v2")]
+        );
+
+        // to 存在时不指定 overwrite 返回错误，什么都不改变
+        store.set("users_new", "k3", "v3").unwrap();
+        let err = store.rename_table("users_new", "users", false).unwrap_err();
+        assert!(matches!(err, KvError::InvalidCommand(_)));
+        assert_eq!(store.len("users").unwrap(), 2);
+        assert_eq!(store.len("users_new").unwrap(), 1);
+
+        // 指定 overwrite 时 to 原有的数据全部被替换
+        assert!(store.rename_table("users_new", "users", true).unwrap());
+        assert_eq!(
+            store.get_all("users").unwrap(),
+            vec![Kvpair::new("k3", "v3")]
+        );
+        assert_eq!(store.tables().unwrap(), vec!["users"]);
+
+        // from 不存在，或者 from 和 to 相同
+        let err = store.rename_table("not exist", "t1", false).unwrap_err();
+        assert!(matches!(err, KvError::NotFound(_)));
+        let err = store.rename_table("users", "users", true).unwrap_err();
+        assert!(matches!(err, KvError::InvalidCommand(_)));
+    }
+
+    fn test_move_nx(store: impl Storage) {
+        store.set("staging", "k1", "v1").unwrap();
+        store.set("prod", "k1", "old").unwrap();
+
+        // 目标已经存在时不移动，两个 key 都保持不变，并发执行时也一样
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| assert!(!store.move_nx("staging", "k1", "prod", "k1").unwrap()));
+            }
+        });
+        assert_eq!(store.get("staging", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("prod", "k1").unwrap(), Some("old".into()));
+
+        // 多个 key 并发地移动到同一个不存在的目标，只有一个成功
+        for i in 0..8 {
+            store.set("staging", format!("key{i}"), i as i64).unwrap();
+        }
+        let moved: Vec<bool> = thread::scope(|s| {
+            let store = &store;
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    s.spawn(move || store.move_nx("staging", &format!("key{i}"), "prod", "k2"))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap().unwrap())
+                .collect()
+        });
+        assert_eq!(moved.iter().filter(|m| **m).count(), 1);
+        let winner = moved.iter().position(|m| *m).unwrap();
+        assert_eq!(
+            store.get("prod", "k2").unwrap(),
+            Some((winner as i64).into())
+        );
+        for i in 0..8 {
+            let key = format!("key{i}");
+            assert_eq!(store.contains("staging", &key).unwrap(), i != winner);
+        }
+
+        // 同一个 table 中移动；源 key 不存在时返回 false
+        assert!(store.move_nx("staging", "k1", "staging", "k3").unwrap());
+        assert_eq!(store.get("staging", "k3").unwrap(), Some("v1".into()));
+        assert!(!store.move_nx("staging", "k1", "staging", "k4").unwrap());
+        assert!(!store.contains("staging", "k4").unwrap());
+    }
+
+    fn test_set_batch(store: impl Storage) {
+        store.set("table", "key1", "0").unwrap();
+        let pairs = vec![Kvpair::new("key1", "1"), Kvpair::new("key2", "2")];
+        assert_eq!(store.set_batch("table", pairs).unwrap(), 2);
+
+        // 已存在的 key 会被覆盖
+        let mut data = store.get_all("table").unwrap();
+        data.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            data,
+            vec![Kvpair::new("key1", "1"), Kvpair::new("key2", "2")]
+        );
+    }
 }
@@ -0,0 +1,412 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{Mutex, MutexGuard},
+};
+
+use lru::LruCache;
+
+use crate::{Footprint, KvError, Kvpair, ReadCacheConfig, Storage, Value};
+
+/// 存储的读缓存：get 的结果（包括 key 不存在）按 (table, key) 缓存在内存中，按 LRU 淘汰。
+///
+/// 所有写操作都经过这一层，写入底层存储之后、返回之前就会删除对应的缓存，不会读到旧值。
+/// 读取底层存储期间 table 有写入时，读到的结果可能已经过时，不写入缓存
+pub struct CachedStore<S: Storage> {
+    store: S,
+    state: Mutex<CacheState>,
+}
+
+struct CacheState {
+    entries: LruCache<(String, String), Option<Value>>,
+    // 每个 table 被写入的次数
+    generations: HashMap<String, u64>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<S: Storage> CachedStore<S> {
+    pub fn new(store: S, config: ReadCacheConfig) -> Self {
+        let capacity = NonZeroUsize::new(config.capacity).unwrap_or(NonZeroUsize::MIN);
+        let state = CacheState {
+            entries: LruCache::new(capacity),
+            generations: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        };
+        Self {
+            store,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// 缓存命中和未命中的次数
+    pub fn hit_stats(&self) -> (u64, u64) {
+        let state = self.lock();
+        (state.hits, state.misses)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap()
+    }
+
+    // 写入底层存储后删除缓存，写入失败时同样删除，不确定写入是否生效
+    fn write<'a, T>(
+        &'a self,
+        table: &str,
+        keys: &[&str],
+        f: impl FnOnce(&'a S) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let result = f(&self.store);
+        let mut state = self.lock();
+        state.invalidate(table);
+        for key in keys {
+            state.entries.pop(&(table.to_string(), key.to_string()));
+        }
+        result
+    }
+
+    // 修改整个 table 的操作删除 table 的所有缓存
+    fn write_table<'a, T>(
+        &'a self,
+        tables: &[&str],
+        f: impl FnOnce(&'a S) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let result = f(&self.store);
+        let mut state = self.lock();
+        for table in tables {
+            state.invalidate(table);
+            let keys: Vec<_> = state
+                .entries
+                .iter()
+                .filter(|((t, _), _)| t.as_str() == *table)
+                .map(|(k, _)| k.clone())
+                .collect();
+            for key in keys {
+                state.entries.pop(&key);
+            }
+        }
+        result
+    }
+}
+
+impl CacheState {
+    fn generation(&self, table: &str) -> u64 {
+        self.generations.get(table).copied().unwrap_or(0)
+    }
+
+    fn invalidate(&mut self, table: &str) {
+        *self.generations.entry(table.to_string()).or_default() += 1;
+    }
+}
+
+impl<S: Storage> Storage for CachedStore<S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let cache_key = (table.to_string(), key.to_string());
+        let generation = {
+            let mut state = self.lock();
+            if let Some(v) = state.entries.get(&cache_key).cloned() {
+                state.hits += 1;
+                return Ok(v);
+            }
+            state.misses += 1;
+            state.generation(table)
+        };
+
+        let value = self.store.get(table, key)?;
+        let mut state = self.lock();
+        if state.generation(table) == generation {
+            state.entries.put(cache_key, value.clone());
+        }
+        Ok(value)
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        let key = key.into();
+        self.write(table, &[key.as_str()], |s| {
+            s.set(table, key.as_str(), value)
+        })
+    }
+
+    fn put(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        let key = key.into();
+        self.write(table, &[key.as_str()], |s| {
+            s.put(table, key.as_str(), value)
+        })
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+        let keys: Vec<_> = pairs.iter().map(|p| p.key.clone()).collect();
+        let keys: Vec<_> = keys.iter().map(|k| k.as_str()).collect();
+        self.write(table, &keys, |s| s.set_batch(table, pairs))
+    }
+
+    fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+        let keys: Vec<_> = pairs.iter().map(|p| p.key.clone()).collect();
+        let keys: Vec<_> = keys.iter().map(|k| k.as_str()).collect();
+        self.write(table, &keys, |s| s.set_batch_if_absent(table, pairs))
+    }
+
+    // 修改了两个 table，写入后再删除 dst 的缓存
+    fn move_nx(
+        &self,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError> {
+        let moved = self.write(src_table, &[src_key], |s| {
+            s.move_nx(src_table, src_key, dst_table, dst_key)
+        });
+        self.write(dst_table, &[dst_key], |_| Ok(()))?;
+        moved
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let cached = self
+            .lock()
+            .entries
+            .get(&(table.to_string(), key.to_string()))
+            .map(|v| v.is_some());
+        match cached {
+            Some(v) => Ok(v),
+            None => self.store.contains(table, key),
+        }
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.write(table, &[key], |s| s.del(table, key))
+    }
+
+    fn remove(&self, table: &str, key: &str) -> Result<(), KvError> {
+        self.write(table, &[key], |s| s.remove(table, key))
+    }
+
+    fn del_batch(&self, table: &str, keys: &[String]) -> Result<usize, KvError> {
+        let refs: Vec<_> = keys.iter().map(|k| k.as_str()).collect();
+        self.write(table, &refs, |s| s.del_batch(table, keys))
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+    ) -> Result<Value, KvError> {
+        self.write(table, &[key], |s| s.update(table, key, f))
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.store.get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.store.get_iter(table)
+    }
+
+    fn get_iter_sorted(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.store.get_iter_sorted(table)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+    ) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.store.get_range(table, start, end)
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        self.store.len(table)
+    }
+
+    fn size_of(&self, table: &str, key: Option<&str>) -> Result<Option<Footprint>, KvError> {
+        self.store.size_of(table, key)
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        self.store.tables()
+    }
+
+    fn has_table(&self, table: &str) -> Result<bool, KvError> {
+        self.store.has_table(table)
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        self.store.create_table(table)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        self.write_table(&[table], |s| s.drop_table(table))
+    }
+
+    fn rename_table(&self, from: &str, to: &str, overwrite: bool) -> Result<bool, KvError> {
+        self.write_table(&[from, to], |s| s.rename_table(from, to, overwrite))
+    }
+
+    fn compact(&self, table: Option<&str>) -> Result<(), KvError> {
+        self.store.compact(table)
+    }
+
+    fn is_blocking(&self) -> bool {
+        self.store.is_blocking()
+    }
+
+    fn backend(&self) -> &'static str {
+        self.store.backend()
+    }
+
+    fn table_counts(&self) -> Result<Vec<(&'static str, usize)>, KvError> {
+        self.store.table_counts()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{dispatch, CommandRequest, MemTable};
+
+    // 记录底层存储被读取的次数
+    #[derive(Default)]
+    struct CountingStore {
+        inner: MemTable,
+        gets: AtomicUsize,
+    }
+
+    impl Storage for CountingStore {
+        fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            self.inner.get(table, key)
+        }
+
+        fn set(
+            &self,
+            table: &str,
+            key: impl Into<String>,
+            value: impl Into<Value>,
+        ) -> Result<Option<Value>, KvError> {
+            self.inner.set(table, key, value)
+        }
+
+        fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+            self.inner.set_batch(table, pairs)
+        }
+
+        fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+            self.inner.set_batch_if_absent(table, pairs)
+        }
+
+        fn move_nx(
+            &self,
+            src_table: &str,
+            src_key: &str,
+            dst_table: &str,
+            dst_key: &str,
+        ) -> Result<bool, KvError> {
+            self.inner.move_nx(src_table, src_key, dst_table, dst_key)
+        }
+
+        fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+            self.inner.contains(table, key)
+        }
+
+        fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            self.inner.del(table, key)
+        }
+
+        fn update(
+            &self,
+            table: &str,
+            key: &str,
+            f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+        ) -> Result<Value, KvError> {
+            self.inner.update(table, key, f)
+        }
+
+        fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+            self.inner.get_all(table)
+        }
+
+        fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+            self.inner.get_iter(table)
+        }
+
+        fn len(&self, table: &str) -> Result<usize, KvError> {
+            self.inner.len(table)
+        }
+
+        fn tables(&self) -> Result<Vec<String>, KvError> {
+            self.inner.tables()
+        }
+
+        fn has_table(&self, table: &str) -> Result<bool, KvError> {
+            self.inner.has_table(table)
+        }
+
+        fn create_table(&self, table: &str) -> Result<bool, KvError> {
+            self.inner.create_table(table)
+        }
+
+        fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+            self.inner.drop_table(table)
+        }
+    }
+
+    fn cached(capacity: usize) -> CachedStore<CountingStore> {
+        CachedStore::new(CountingStore::default(), ReadCacheConfig { capacity })
+    }
+
+    #[test]
+    fn cached_read_should_be_invalidated_by_write() {
+        let store = cached(16);
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1"), &store);
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        for _ in 0..3 {
+            let res = dispatch(cmd.clone(), &store);
+            assert_eq!(res.values, vec!["v1".into()]);
+        }
+        // 只有第一次读取访问了底层存储
+        assert_eq!(store.store.gets.load(Ordering::SeqCst), 1);
+        assert_eq!(store.hit_stats(), (2, 1));
+
+        dispatch(CommandRequest::new_hset("t1", "k1", "v2"), &store);
+        let res = dispatch(cmd.clone(), &store);
+        assert_eq!(res.values, vec!["v2".into()]);
+
+        dispatch(CommandRequest::new_hdel("t1", "k1"), &store);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.status, 404);
+        assert!(!store.contains("t1", "k1").unwrap());
+    }
+
+    #[test]
+    fn cache_should_evict_least_recently_used_keys() {
+        let store = cached(2);
+        for key in ["k1", "k2", "k3"] {
+            store.set("t1", key, key).unwrap();
+            store.get("t1", key).unwrap();
+        }
+        // k1 被淘汰，需要重新读取底层存储
+        store.get("t1", "k3").unwrap();
+        store.get("t1", "k1").unwrap();
+        assert_eq!(store.hit_stats(), (1, 4));
+
+        // drop table 后缓存中不再有这个 table 的数据
+        store.drop_table("t1").unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
+    }
+}
@@ -1,16 +1,47 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
-use crate::{KvError, Kvpair, Storage, StorageIter, Value};
-use rocksdb::{BoundColumnFamily, Options, DB};
+use super::check_rename;
+use crate::{
+    Footprint, KvError, Kvpair, Storage, StorageIter, Value, ValueCodecType, ValueCompression,
+};
+use rocksdb::{
+    BoundColumnFamily, Direction, IteratorMode, Options, WriteBatch, DB, DEFAULT_COLUMN_FAMILY_NAME,
+};
 
-pub struct RocksDB(DB);
+// 第二个字段用于让 set/del 中 "先读旧值再写入" 的操作成为原子操作，第三、四个字段是 value 的编码和压缩
+pub struct RocksDB(DB, Mutex<()>, ValueCodecType, ValueCompression);
 
 impl RocksDB {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(DB::open_default(path).unwrap())
+        Self(
+            DB::open_default(path).unwrap(),
+            Mutex::new(()),
+            ValueCodecType::default(),
+            ValueCompression::default(),
+        )
     }
 
-    pub fn get_or_create_table(&self, name: &str) -> Arc<BoundColumnFamily> {
+    /// 设置 value 的编码，打开已有的数据库时必须和写入时的编码一致
+    pub fn with_codec(mut self, codec: ValueCodecType) -> Self {
+        self.2 = codec;
+        self
+    }
+
+    /// 按 table 压缩写入的 value，读取时不需要这个配置
+    pub fn with_compression(mut self, compression: ValueCompression) -> Self {
+        self.3 = compression;
+        self
+    }
+
+    fn encode(&self, table: &str, value: Value) -> Result<Vec<u8>, KvError> {
+        self.3.compress(table, self.2.encode(value)?)
+    }
+
+    pub fn get_or_create_table(&self, name: &str) -> Arc<BoundColumnFamily<'_>> {
         if self.0.cf_handle(name).is_none() {
             let _ = self.0.create_cf(name, &Options::default());
         }
@@ -21,7 +52,7 @@ impl RocksDB {
 impl Storage for RocksDB {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
         let cf = self.get_or_create_table(table);
-        let result = self.0.get_cf(&cf, key)?.map(|v| v.as_slice().try_into());
+        let result = self.0.get_cf(&cf, key)?.map(|v| self.2.decode(&v));
         result.transpose()
     }
 
@@ -33,37 +64,285 @@ impl Storage for RocksDB {
     ) -> Result<Option<Value>, KvError> {
         let cf = self.get_or_create_table(table);
         let key = key.into();
-        let value: Vec<u8> = Into::<Value>::into(value).try_into()?;
-        let old = self.get(table, &key);
-        let _ = self.0.put_cf(&cf, key, value);
-        old
+        let value = self.encode(table, value.into())?;
+        let _guard = self.1.lock().unwrap();
+        let old = self.get(table, &key)?;
+        self.0.put_cf(&cf, key, value)?;
+        Ok(old)
+    }
+
+    // 不读取旧值，但仍然需要和 set_batch_if_absent、move_nx 互斥
+    fn put(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        let cf = self.get_or_create_table(table);
+        let value = self.encode(table, value.into())?;
+        let _guard = self.1.lock().unwrap();
+        self.0.put_cf(&cf, key.into(), value)?;
+        Ok(())
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+        let cf = self.get_or_create_table(table);
+        let count = pairs.len();
+        let mut batch = WriteBatch::default();
+        for pair in pairs {
+            let value = self.encode(table, pair.value.unwrap_or_default())?;
+            batch.put_cf(&cf, pair.key, value);
+        }
+        // 和 set_batch_if_absent 互斥，保证它检查之后不会有新的 key 写入
+        let _guard = self.1.lock().unwrap();
+        self.0.write(batch)?;
+        Ok(count)
+    }
+
+    fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+        let cf = self.get_or_create_table(table);
+        let mut batch = WriteBatch::default();
+        for pair in &pairs {
+            let value = self.encode(table, pair.value.clone().unwrap_or_default())?;
+            batch.put_cf(&cf, &pair.key, value);
+        }
+        // 持有锁检查所有 key，再用 WriteBatch 一次性原子地写入
+        let _guard = self.1.lock().unwrap();
+        for pair in &pairs {
+            if self.0.get_pinned_cf(&cf, &pair.key)?.is_some() {
+                return Ok(false);
+            }
+        }
+        self.0.write(batch)?;
+        Ok(true)
+    }
+
+    // 持有锁检查 dst 并读取 src，再用 WriteBatch 原子地删除 src、写入 dst。
+    // value 的头部记录了压缩算法，原样移动即可
+    fn move_nx(
+        &self,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError> {
+        let src = self.get_or_create_table(src_table);
+        let dst = self.get_or_create_table(dst_table);
+        let _guard = self.1.lock().unwrap();
+        if self.0.get_pinned_cf(&dst, dst_key)?.is_some() {
+            return Ok(false);
+        }
+        let Some(value) = self.0.get_pinned_cf(&src, src_key)? else {
+            return Ok(false);
+        };
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(&src, src_key);
+        batch.put_cf(&dst, dst_key, value);
+        self.0.write(batch)?;
+        Ok(true)
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
         let cf = self.get_or_create_table(table);
-        Ok(self.0.key_may_exist_cf(&cf, key))
+        // key_may_exist_cf 可能返回 false positive，这里需要准确的结果
+        Ok(self.0.get_pinned_cf(&cf, key)?.is_some())
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
         let cf = self.get_or_create_table(table);
+        let _guard = self.1.lock().unwrap();
         let old = self.get(table, key);
         self.0.delete_cf(&cf, key)?;
         old
     }
 
+    // 不读取旧值，但仍然需要和 set_batch_if_absent、move_nx 互斥
+    fn remove(&self, table: &str, key: &str) -> Result<(), KvError> {
+        let cf = self.get_or_create_table(table);
+        let _guard = self.1.lock().unwrap();
+        self.0.delete_cf(&cf, key)?;
+        Ok(())
+    }
+
+    // 持有锁统计存在的 key，再用 WriteBatch 一次性删除
+    fn del_batch(&self, table: &str, keys: &[String]) -> Result<usize, KvError> {
+        let cf = self.get_or_create_table(table);
+        let keys: HashSet<_> = keys.iter().collect();
+        let mut batch = WriteBatch::default();
+        for key in &keys {
+            batch.delete_cf(&cf, key);
+        }
+        let _guard = self.1.lock().unwrap();
+        let mut count = 0;
+        for key in &keys {
+            if self.0.get_pinned_cf(&cf, key)?.is_some() {
+                count += 1;
+            }
+        }
+        self.0.write(batch)?;
+        Ok(count)
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        mut f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+    ) -> Result<Value, KvError> {
+        let cf = self.get_or_create_table(table);
+        let _guard = self.1.lock().unwrap();
+        let value = f(self.get(table, key)?)?;
+        let data = self.encode(table, value.clone())?;
+        self.0.put_cf(&cf, key, data)?;
+        Ok(value)
+    }
+
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
         let cf = self.get_or_create_table(table);
         Ok(self
             .0
             .iterator_cf(&cf, rocksdb::IteratorMode::Start)
-            .map(|v| v.unwrap().into())
+            .map(|v| to_kvpair(self.2, v.unwrap()))
             .collect())
     }
 
     fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
         let cf = self.get_or_create_table(table);
         let iter = self.0.iterator_cf(&cf, rocksdb::IteratorMode::Start);
-        let iter = StorageIter::new(iter.map(|v| Into::<Kvpair>::into(v.unwrap())));
+        let codec = self.2;
+        let iter = StorageIter::new(iter.map(move |v| to_kvpair(codec, v.unwrap())));
         Ok(iter)
     }
+
+    // RocksDB 默认的 comparator 按 key 的字节序排序
+    fn get_iter_sorted(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.get_iter(table)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+    ) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        let (cf, codec) = (self.get_or_create_table(table), self.2);
+        // 直接 seek 到 start，读到 end 为止
+        let mode = IteratorMode::From(start.as_bytes(), Direction::Forward);
+        let iter = self
+            .0
+            .iterator_cf(&cf, mode)
+            .map(|v| v.unwrap())
+            .take_while(move |(k, _)| k.as_ref() < end.as_bytes())
+            .map(move |pair| to_kvpair(codec, pair));
+        Ok(StorageIter::new(iter))
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        let cf = self.get_or_create_table(table);
+        Ok(self.0.iterator_cf(&cf, IteratorMode::Start).count())
+    }
+
+    fn size_of(&self, table: &str, key: Option<&str>) -> Result<Option<Footprint>, KvError> {
+        let Some(cf) = self.0.cf_handle(table) else {
+            return Ok(key.is_none().then(|| Footprint::exact(0)));
+        };
+        if let Some(key) = key {
+            let value = self.0.get_pinned_cf(&cf, key)?;
+            return Ok(value.map(|v| Footprint::exact(v.len() as u64)));
+        }
+        // SST 文件的大小加上还没有写入 SST 的 memtable 的大小，都是压缩和编码后的估算值
+        let mut bytes = 0;
+        for property in [
+            "rocksdb.total-sst-files-size",
+            "rocksdb.cur-size-all-mem-tables",
+        ] {
+            bytes += self.0.property_int_value_cf(&cf, property)?.unwrap_or(0);
+        }
+        Ok(Some(Footprint::approximate(bytes)))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        // 每个 table 是一个 column family，跳过 default 和空的 column family
+        let names = DB::list_cf(&Options::default(), self.0.path())?;
+        Ok(names
+            .into_iter()
+            .filter(|name| name != DEFAULT_COLUMN_FAMILY_NAME)
+            .filter(|name| {
+                self.0.cf_handle(name).is_some_and(|cf| {
+                    self.0
+                        .iterator_cf(&cf, IteratorMode::Start)
+                        .next()
+                        .is_some()
+                })
+            })
+            .collect())
+    }
+
+    fn has_table(&self, table: &str) -> Result<bool, KvError> {
+        Ok(self.0.cf_handle(table).is_some())
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        if self.0.cf_handle(table).is_some() {
+            return Ok(false);
+        }
+        self.0.create_cf(table, &Options::default())?;
+        Ok(true)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        if self.0.cf_handle(table).is_none() {
+            return Ok(false);
+        }
+        self.0.drop_cf(table)?;
+        Ok(true)
+    }
+
+    // 用一个 WriteBatch 清空 to、写入 from 的数据并清空 from，WriteBatch 跨 column family
+    // 也是原子的，并发的读者不会看到写了一半的数据。之后再删除已经为空的 from
+    fn rename_table(&self, from: &str, to: &str, overwrite: bool) -> Result<bool, KvError> {
+        let _guard = self.1.lock().unwrap();
+        let existed = check_rename(self, from, to, overwrite)?;
+        let src = self.get_or_create_table(from);
+        let dst = self.get_or_create_table(to);
+        let mut batch = WriteBatch::default();
+        for item in self.0.iterator_cf(&dst, IteratorMode::Start) {
+            let (key, _) = item?;
+            batch.delete_cf(&dst, key);
+        }
+        for item in self.0.iterator_cf(&src, IteratorMode::Start) {
+            let (key, value) = item?;
+            batch.put_cf(&dst, &key, value);
+            batch.delete_cf(&src, key);
+        }
+        self.0.write(batch)?;
+        drop((src, dst));
+        self.0.drop_cf(from)?;
+        Ok(existed)
+    }
+
+    fn compact(&self, table: Option<&str>) -> Result<(), KvError> {
+        let names = match table {
+            Some(table) => vec![table.to_string()],
+            None => DB::list_cf(&Options::default(), self.0.path())?,
+        };
+        for name in names {
+            let Some(cf) = self.0.cf_handle(&name) else {
+                continue;
+            };
+            // 先把 memtable 写入 SST，再压缩整个 column family，tombstone 在压缩时被清除
+            self.0.flush_cf(&cf)?;
+            self.0.compact_range_cf(&cf, None::<&[u8]>, None::<&[u8]>);
+        }
+        Ok(())
+    }
+
+    fn backend(&self) -> &'static str {
+        "rocksdb"
+    }
+}
+
+// 无法解码的数据返回空的 Kvpair
+fn to_kvpair(codec: ValueCodecType, (k, v): (Box<[u8]>, Box<[u8]>)) -> Kvpair {
+    codec.pair(&k, &v).unwrap_or_default()
 }
@@ -0,0 +1,523 @@
+use crate::{
+    Footprint, KvError, Kvpair, MemTable, RocksDB, SledDb, Storage, StorageConfig,
+    TableStorageConfig, Value, ValueCodecType, ValueCompression,
+};
+
+/// 按 StorageConfig 创建的存储，用于在 RoutingStore 中放置不同类型的存储
+pub enum AnyStore {
+    MemTable(MemTable),
+    Sledb(SledDb),
+    Rocksdb(RocksDB),
+}
+
+// 对 AnyStore 的每种存储执行同样的表达式
+macro_rules! with_store {
+    ($store:expr, $s:ident => $e:expr) => {
+        match $store {
+            AnyStore::MemTable($s) => $e,
+            AnyStore::Sledb($s) => $e,
+            AnyStore::Rocksdb($s) => $e,
+        }
+    };
+}
+
+impl AnyStore {
+    pub fn new(config: &StorageConfig) -> Self {
+        match config {
+            StorageConfig::MemTable => Self::MemTable(MemTable::new()),
+            StorageConfig::Sledb(path) => Self::Sledb(SledDb::new(path)),
+            StorageConfig::Rocksdb(path) => Self::Rocksdb(RocksDB::new(path)),
+        }
+    }
+
+    /// 设置磁盘存储的 value 编码，MemTable 直接保存 Value，不需要编码
+    pub fn with_codec(self, codec: ValueCodecType) -> Self {
+        match self {
+            Self::MemTable(s) => Self::MemTable(s),
+            Self::Sledb(s) => Self::Sledb(s.with_codec(codec)),
+            Self::Rocksdb(s) => Self::Rocksdb(s.with_codec(codec)),
+        }
+    }
+
+    /// 设置磁盘存储的 value 压缩，MemTable 不压缩
+    pub fn with_compression(self, compression: ValueCompression) -> Self {
+        match self {
+            Self::MemTable(s) => Self::MemTable(s),
+            Self::Sledb(s) => Self::Sledb(s.with_compression(compression)),
+            Self::Rocksdb(s) => Self::Rocksdb(s.with_compression(compression)),
+        }
+    }
+}
+
+impl Storage for AnyStore {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        with_store!(self, s => s.get(table, key))
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        with_store!(self, s => s.set(table, key, value))
+    }
+
+    fn put(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        with_store!(self, s => s.put(table, key, value))
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+        with_store!(self, s => s.set_batch(table, pairs))
+    }
+
+    fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+        with_store!(self, s => s.set_batch_if_absent(table, pairs))
+    }
+
+    fn move_nx(
+        &self,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError> {
+        with_store!(self, s => s.move_nx(src_table, src_key, dst_table, dst_key))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        with_store!(self, s => s.contains(table, key))
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        with_store!(self, s => s.del(table, key))
+    }
+
+    fn remove(&self, table: &str, key: &str) -> Result<(), KvError> {
+        with_store!(self, s => s.remove(table, key))
+    }
+
+    fn del_batch(&self, table: &str, keys: &[String]) -> Result<usize, KvError> {
+        with_store!(self, s => s.del_batch(table, keys))
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+    ) -> Result<Value, KvError> {
+        with_store!(self, s => s.update(table, key, f))
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        with_store!(self, s => s.get_all(table))
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        Ok(match self {
+            AnyStore::MemTable(s) => AnyIter::MemTable(s.get_iter(table)?),
+            AnyStore::Sledb(s) => AnyIter::Sledb(s.get_iter(table)?),
+            AnyStore::Rocksdb(s) => AnyIter::Rocksdb(s.get_iter(table)?),
+        })
+    }
+
+    fn get_iter_sorted(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        Ok(match self {
+            AnyStore::MemTable(s) => AnyIter::MemTable(s.get_iter_sorted(table)?),
+            AnyStore::Sledb(s) => AnyIter::Sledb(s.get_iter_sorted(table)?),
+            AnyStore::Rocksdb(s) => AnyIter::Rocksdb(s.get_iter_sorted(table)?),
+        })
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+    ) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        Ok(match self {
+            AnyStore::MemTable(s) => AnyIter::MemTable(s.get_range(table, start, end)?),
+            AnyStore::Sledb(s) => AnyIter::Sledb(s.get_range(table, start, end)?),
+            AnyStore::Rocksdb(s) => AnyIter::Rocksdb(s.get_range(table, start, end)?),
+        })
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        with_store!(self, s => s.len(table))
+    }
+
+    fn size_of(&self, table: &str, key: Option<&str>) -> Result<Option<Footprint>, KvError> {
+        with_store!(self, s => s.size_of(table, key))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        with_store!(self, s => s.tables())
+    }
+
+    fn has_table(&self, table: &str) -> Result<bool, KvError> {
+        with_store!(self, s => s.has_table(table))
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        with_store!(self, s => s.create_table(table))
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        with_store!(self, s => s.drop_table(table))
+    }
+
+    fn rename_table(&self, from: &str, to: &str, overwrite: bool) -> Result<bool, KvError> {
+        with_store!(self, s => s.rename_table(from, to, overwrite))
+    }
+
+    fn compact(&self, table: Option<&str>) -> Result<(), KvError> {
+        with_store!(self, s => s.compact(table))
+    }
+
+    fn is_blocking(&self) -> bool {
+        with_store!(self, s => s.is_blocking())
+    }
+
+    fn backend(&self) -> &'static str {
+        with_store!(self, s => s.backend())
+    }
+}
+
+// 不同存储的 get_iter 返回的 Iterator 类型不同，用 enum 统一起来
+enum AnyIter<A, B, C> {
+    MemTable(A),
+    Sledb(B),
+    Rocksdb(C),
+}
+
+impl<A, B, C> Iterator for AnyIter<A, B, C>
+where
+    A: Iterator<Item = Kvpair>,
+    B: Iterator<Item = Kvpair>,
+    C: Iterator<Item = Kvpair>,
+{
+    type Item = Kvpair;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            AnyIter::MemTable(iter) => iter.next(),
+            AnyIter::Sledb(iter) => iter.next(),
+            AnyIter::Rocksdb(iter) => iter.next(),
+        }
+    }
+}
+
+/// 按 table 名把操作分发到不同的存储，比如热点 table 放在内存里，大的 table 放在磁盘上
+///
+/// table 按配置的顺序匹配第一个 pattern，都不匹配时使用 fallback 存储。
+/// 配置相同的 pattern 共享同一个存储
+pub struct RoutingStore {
+    // 第一个是 fallback 存储
+    stores: Vec<AnyStore>,
+    // pattern -> stores 中的下标
+    routes: Vec<(String, usize)>,
+}
+
+impl RoutingStore {
+    pub fn new(fallback: &StorageConfig, routes: &[TableStorageConfig]) -> Self {
+        let mut configs = vec![fallback];
+        let routes = routes
+            .iter()
+            .map(|route| {
+                let index = match configs.iter().position(|c| **c == route.storage) {
+                    Some(index) => index,
+                    None => {
+                        configs.push(&route.storage);
+                        configs.len() - 1
+                    }
+                };
+                (route.pattern.clone(), index)
+            })
+            .collect();
+        let stores = configs.into_iter().map(AnyStore::new).collect();
+
+        Self { stores, routes }
+    }
+
+    /// 所有磁盘存储使用同一种 value 编码
+    pub fn with_codec(mut self, codec: ValueCodecType) -> Self {
+        self.stores = self
+            .stores
+            .into_iter()
+            .map(|store| store.with_codec(codec))
+            .collect();
+        self
+    }
+
+    /// 所有磁盘存储使用同一组压缩配置
+    pub fn with_compression(mut self, compression: ValueCompression) -> Self {
+        self.stores = self
+            .stores
+            .into_iter()
+            .map(|store| store.with_compression(compression.clone()))
+            .collect();
+        self
+    }
+
+    // table 对应的存储
+    fn store(&self, table: &str) -> &AnyStore {
+        let index = self
+            .routes
+            .iter()
+            .find(|(pattern, _)| matches(pattern, table))
+            .map_or(0, |(_, index)| *index);
+        &self.stores[index]
+    }
+}
+
+// pattern 以 * 结尾时按前缀匹配，否则要求 table 名完全一致
+pub(super) fn matches(pattern: &str, table: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => table.starts_with(prefix),
+        None => pattern == table,
+    }
+}
+
+impl Storage for RoutingStore {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.store(table).get(table, key)
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        self.store(table).set(table, key, value)
+    }
+
+    fn put(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        self.store(table).put(table, key, value)
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+        self.store(table).set_batch(table, pairs)
+    }
+
+    fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+        self.store(table).set_batch_if_absent(table, pairs)
+    }
+
+    fn move_nx(
+        &self,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError> {
+        let store = self.store(src_table);
+        if !std::ptr::eq(store, self.store(dst_table)) {
+            return Err(KvError::InvalidCommand(format!(
+                "table {src_table} and {dst_table} are routed to different storages"
+            )));
+        }
+        store.move_nx(src_table, src_key, dst_table, dst_key)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.store(table).contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.store(table).del(table, key)
+    }
+
+    fn remove(&self, table: &str, key: &str) -> Result<(), KvError> {
+        self.store(table).remove(table, key)
+    }
+
+    fn del_batch(&self, table: &str, keys: &[String]) -> Result<usize, KvError> {
+        self.store(table).del_batch(table, keys)
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+    ) -> Result<Value, KvError> {
+        self.store(table).update(table, key, f)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.store(table).get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.store(table).get_iter(table)
+    }
+
+    fn get_iter_sorted(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.store(table).get_iter_sorted(table)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+    ) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.store(table).get_range(table, start, end)
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        self.store(table).len(table)
+    }
+
+    fn size_of(&self, table: &str, key: Option<&str>) -> Result<Option<Footprint>, KvError> {
+        self.store(table).size_of(table, key)
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        // 每个存储只列出路由到它的 table，修改配置前遗留在其他存储中的 table 不会被列出
+        let mut tables = vec![];
+        for store in &self.stores {
+            for table in store.tables()? {
+                if std::ptr::eq(self.store(&table), store) {
+                    tables.push(table);
+                }
+            }
+        }
+        Ok(tables)
+    }
+
+    fn has_table(&self, table: &str) -> Result<bool, KvError> {
+        self.store(table).has_table(table)
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        self.store(table).create_table(table)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        self.store(table).drop_table(table)
+    }
+
+    // 只能在同一个存储中改名，不在存储之间移动数据
+    fn rename_table(&self, from: &str, to: &str, overwrite: bool) -> Result<bool, KvError> {
+        let store = self.store(from);
+        if !std::ptr::eq(store, self.store(to)) {
+            return Err(KvError::InvalidCommand(format!(
+                "table {from} and {to} are routed to different storages"
+            )));
+        }
+        store.rename_table(from, to, overwrite)
+    }
+
+    fn compact(&self, table: Option<&str>) -> Result<(), KvError> {
+        match table {
+            Some(table) => self.store(table).compact(Some(table)),
+            None => self.stores.iter().try_for_each(|s| s.compact(None)),
+        }
+    }
+
+    fn is_blocking(&self) -> bool {
+        self.stores.iter().any(|s| s.is_blocking())
+    }
+
+    fn backend(&self) -> &'static str {
+        "routing"
+    }
+
+    fn table_counts(&self) -> Result<Vec<(&'static str, usize)>, KvError> {
+        // 和 tables 一样只统计路由到这个存储的 table，同一种后端的多个存储合并统计
+        let mut counts: Vec<(&'static str, usize)> = vec![];
+        for store in &self.stores {
+            let tables = store.tables()?;
+            let count = tables
+                .iter()
+                .filter(|table| std::ptr::eq(self.store(table), store))
+                .count();
+            match counts.iter_mut().find(|(name, _)| *name == store.backend()) {
+                Some((_, n)) => *n += count,
+                None => counts.push((store.backend(), count)),
+            }
+        }
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn route(pattern: &str, storage: StorageConfig) -> TableStorageConfig {
+        TableStorageConfig {
+            pattern: pattern.into(),
+            storage,
+        }
+    }
+
+    #[test]
+    fn routing_store_should_write_to_configured_store() {
+        let dir = tempdir().unwrap();
+        let sled = StorageConfig::Sledb(dir.path().to_string_lossy().into());
+        let routes = [route("disk_*", sled.clone()), route("logs", sled)];
+        let store = RoutingStore::new(&StorageConfig::MemTable, &routes);
+        // 相同配置的 pattern 共享一个存储
+        assert_eq!(store.stores.len(), 2);
+        assert!(store.is_blocking());
+
+        store.set("disk_users", "k1", "v1").unwrap();
+        store.set("logs", "k1", "v2").unwrap();
+        store.set("cache", "k1", "v3").unwrap();
+
+        let (memtable, sled) = (&store.stores[0], &store.stores[1]);
+        assert_eq!(sled.get("disk_users", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(sled.get("logs", "k1").unwrap(), Some("v2".into()));
+        assert_eq!(memtable.get("cache", "k1").unwrap(), Some("v3".into()));
+        assert_eq!(memtable.get("disk_users", "k1").unwrap(), None);
+        assert_eq!(sled.get("cache", "k1").unwrap(), None);
+
+        // logs 完全匹配，logs2 不匹配
+        store.set("logs2", "k1", "v4").unwrap();
+        assert_eq!(memtable.get("logs2", "k1").unwrap(), Some("v4".into()));
+        assert_eq!(store.get("logs2", "k1").unwrap(), Some("v4".into()));
+    }
+
+    #[test]
+    fn routing_store_should_list_tables_across_stores() {
+        let dir = tempdir().unwrap();
+        let sled = StorageConfig::Sledb(dir.path().to_string_lossy().into());
+        let store = RoutingStore::new(&StorageConfig::MemTable, &[route("disk_*", sled)]);
+
+        store.set("disk_t1", "k1", "v1").unwrap();
+        store.set("disk_t1", "k2", "v2").unwrap();
+        store.set("t2", "k1", "v1").unwrap();
+        let mut tables = store.tables().unwrap();
+        tables.sort();
+        assert_eq!(tables, vec!["disk_t1", "t2"]);
+
+        let mut data: Vec<_> = store.get_iter("disk_t1").unwrap().collect();
+        data.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(data, vec![Kvpair::new("k1", "v1"), Kvpair::new("k2", "v2")]);
+        assert_eq!(store.get_all("t2").unwrap(), vec![Kvpair::new("k1", "v1")]);
+
+        // 遗留在其他存储中的 table 不会被列出
+        store.stores[0].set("disk_t3", "k1", "v1").unwrap();
+        tables = store.tables().unwrap();
+        tables.sort();
+        assert_eq!(tables, vec!["disk_t1", "t2"]);
+        let counts = store.table_counts().unwrap();
+        assert_eq!(counts, vec![("memtable", 1), ("sledb", 1)]);
+    }
+}
@@ -1,27 +1,60 @@
-use crate::{KvError, Kvpair, Storage, StorageIter, Value};
-use sled::{Db, IVec};
-use std::{convert::TryInto, path::Path, str};
+use super::check_rename;
+use crate::{
+    Footprint, KvError, Kvpair, Storage, StorageIter, Value, ValueCodecType, ValueCompression,
+};
+use sled::{
+    transaction::{ConflictableTransactionResult, TransactionError, TransactionalTree},
+    Batch, Db, IVec, Transactional, Tree,
+};
+use std::{path::Path, str};
 
-pub struct SledDb(Db);
+/// 每个 table 对应一个 sled tree，table 名和 key 中可以包含任意字符
+pub struct SledDb(Db, ValueCodecType, ValueCompression);
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        Self(
+            sled::open(path).unwrap(),
+            ValueCodecType::default(),
+            ValueCompression::default(),
+        )
     }
 
-    fn get_full_key(table: &str, key: &str) -> String {
-        format!("{}:{}", table, key)
+    /// 设置 value 的编码，打开已有的数据库时必须和写入时的编码一致
+    pub fn with_codec(mut self, codec: ValueCodecType) -> Self {
+        self.1 = codec;
+        self
     }
 
-    fn get_table_prefix(table: &str) -> String {
-        format!("{}:", table)
+    /// 按 table 压缩写入的 value，读取时不需要这个配置
+    pub fn with_compression(mut self, compression: ValueCompression) -> Self {
+        self.2 = compression;
+        self
+    }
+
+    fn encode(&self, table: &str, value: Value) -> Result<Vec<u8>, KvError> {
+        self.2.compress(table, self.1.encode(value)?)
+    }
+
+    // 如果名为 name 的 tree 不存在，则创建，否则返回
+    fn get_or_create_table(&self, name: &str) -> Result<Tree, KvError> {
+        Ok(self.0.open_tree(name)?)
+    }
+
+    // sled 自带的 default tree 不是 table
+    fn table_names(&self) -> impl Iterator<Item = IVec> + '_ {
+        let default = self.0.name();
+        self.0
+            .tree_names()
+            .into_iter()
+            .filter(move |name| *name != default)
     }
 }
 
 impl Storage for SledDb {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let name = SledDb::get_full_key(table, key);
-        let result = self.0.get(name.as_bytes())?.map(|v| v.as_ref().try_into());
+        let table = self.get_or_create_table(table)?;
+        let result = table.get(key)?.map(|v| self.1.decode(&v));
         result.transpose()
     }
 
@@ -31,52 +64,270 @@ impl Storage for SledDb {
         key: impl Into<String>,
         value: impl Into<Value>,
     ) -> Result<Option<Value>, KvError> {
-        let key = key.into();
-        let name = SledDb::get_full_key(table, &key);
-        let data: Vec<u8> = value.into().try_into()?;
-        let result = self.0.insert(name, data)?.map(|v| v.as_ref().try_into());
+        let data = self.encode(table, value.into())?;
+        let table = self.get_or_create_table(table)?;
+        let key: String = key.into();
+        // sled 的 insert 原子地返回之前的值
+        let result = table.insert(key, data)?.map(|v| self.1.decode(&v));
         result.transpose()
     }
 
+    // sled 的 insert 总会返回旧值，这里只是省去 decode
+    fn put(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        let data = self.encode(table, value.into())?;
+        let table = self.get_or_create_table(table)?;
+        table.insert(key.into(), data)?;
+        Ok(())
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<usize, KvError> {
+        let count = pairs.len();
+        let mut batch = Batch::default();
+        for pair in pairs {
+            let data = self.encode(table, pair.value.unwrap_or_default())?;
+            batch.insert(pair.key.as_bytes(), data);
+        }
+        self.get_or_create_table(table)?.apply_batch(batch)?;
+        Ok(count)
+    }
+
+    fn set_batch_if_absent(&self, table: &str, pairs: Vec<Kvpair>) -> Result<bool, KvError> {
+        let mut data = Vec::with_capacity(pairs.len());
+        for pair in pairs {
+            let value = self.encode(table, pair.value.unwrap_or_default())?;
+            data.push((pair.key, value));
+        }
+        let table = self.get_or_create_table(table)?;
+        // sled 的事务在冲突时会自动重试闭包
+        let result = table.transaction(|tx| -> ConflictableTransactionResult<bool> {
+            for (key, _) in &data {
+                if tx.get(key.as_bytes())?.is_some() {
+                    return Ok(false);
+                }
+            }
+            for (key, value) in &data {
+                tx.insert(key.as_bytes(), value.as_slice())?;
+            }
+            Ok(true)
+        });
+        match result {
+            Ok(applied) => Ok(applied),
+            Err(TransactionError::Storage(e)) => Err(e.into()),
+            Err(TransactionError::Abort(())) => unreachable!("transaction never aborts"),
+        }
+    }
+
+    // 在一个事务中检查 dst 并移动 value，同一个 table 时只使用一个 tree
+    fn move_nx(
+        &self,
+        src_table: &str,
+        src_key: &str,
+        dst_table: &str,
+        dst_key: &str,
+    ) -> Result<bool, KvError> {
+        let src = self.get_or_create_table(src_table)?;
+        let result = if src_table == dst_table {
+            src.transaction(|tx| move_nx_in(tx, tx, src_key, dst_key))
+        } else {
+            let dst = self.get_or_create_table(dst_table)?;
+            (&src, &dst).transaction(|(src, dst)| move_nx_in(src, dst, src_key, dst_key))
+        };
+        match result {
+            Ok(moved) => Ok(moved),
+            Err(TransactionError::Storage(e)) => Err(e.into()),
+            Err(TransactionError::Abort(())) => unreachable!("transaction never aborts"),
+        }
+    }
+
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let name = SledDb::get_full_key(table, &key);
-        Ok(self.0.contains_key(name)?)
+        let table = self.get_or_create_table(table)?;
+        Ok(table.contains_key(key)?)
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let name = SledDb::get_full_key(table, &key);
-        let result = self.0.remove(name)?.map(|v| v.as_ref().try_into());
+        let table = self.get_or_create_table(table)?;
+        let result = table.remove(key)?.map(|v| self.1.decode(&v));
         result.transpose()
     }
 
+    fn remove(&self, table: &str, key: &str) -> Result<(), KvError> {
+        let table = self.get_or_create_table(table)?;
+        table.remove(key)?;
+        Ok(())
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        mut f: impl FnMut(Option<Value>) -> Result<Value, KvError>,
+    ) -> Result<Value, KvError> {
+        let tree = self.get_or_create_table(table)?;
+        // 用 compare_and_swap 做乐观并发控制，读取之后有其他写入时重试
+        loop {
+            let old = tree.get(key)?;
+            let value = f(old.as_ref().map(|v| self.1.decode(v)).transpose()?)?;
+            let data = self.encode(table, value.clone())?;
+            if tree.compare_and_swap(key, old, Some(data))?.is_ok() {
+                return Ok(value);
+            }
+        }
+    }
+
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
-        let prefix = SledDb::get_table_prefix(table);
-        let result = self.0.scan_prefix(prefix).map(|v| v.into()).collect();
+        let (table, codec) = (self.get_or_create_table(table)?, self.1);
+        let result = table.iter().map(move |v| to_kvpair(codec, v)).collect();
         Ok(result)
     }
 
     fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
-        let prefix = SledDb::get_table_prefix(table);
-        let iter = StorageIter::new(self.0.scan_prefix(prefix));
+        let (table, codec) = (self.get_or_create_table(table)?, self.1);
+        let iter = StorageIter::new(table.iter().map(move |v| to_kvpair(codec, v)));
         Ok(iter)
     }
-}
 
-impl From<Result<(IVec, IVec), sled::Error>> for Kvpair {
-    fn from(v: Result<(IVec, IVec), sled::Error>) -> Self {
-        match v {
-            Ok((k, v)) => match TryInto::<Value>::try_into(v.as_ref()) {
-                Ok(v) => Kvpair::new(ivec_to_key(k.as_ref()), v),
-                Err(_) => Kvpair::default(),
-            },
-            _ => Kvpair::default(),
+    // sled 的 tree 本身按 key 的字节序排序
+    fn get_iter_sorted(&self, table: &str) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        self.get_iter(table)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+    ) -> Result<impl Iterator<Item = Kvpair>, KvError> {
+        let (table, codec) = (self.get_or_create_table(table)?, self.1);
+        // start >= end 时使用空的范围
+        let end = end.max(start);
+        Ok(StorageIter::new(
+            table.range(start..end).map(move |v| to_kvpair(codec, v)),
+        ))
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        if !self.has_table(table)? {
+            return Ok(0);
+        }
+        Ok(self.get_or_create_table(table)?.len())
+    }
+
+    fn size_of(&self, table: &str, key: Option<&str>) -> Result<Option<Footprint>, KvError> {
+        if !self.has_table(table)? {
+            return Ok(key.is_none().then(|| Footprint::exact(0)));
+        }
+        let tree = self.get_or_create_table(table)?;
+        match key {
+            Some(key) => Ok(tree.get(key)?.map(|v| Footprint::exact(v.len() as u64))),
+            None => {
+                // sled 只统计整个数据库的磁盘占用，table 的大小用 key 和 value 的长度估算
+                let mut bytes = 0;
+                for pair in tree.iter() {
+                    let (k, v) = pair?;
+                    bytes += (k.len() + v.len()) as u64;
+                }
+                Ok(Some(Footprint::approximate(bytes)))
+            }
+        }
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        // get 等操作会创建空的 tree，这里跳过
+        let mut tables = vec![];
+        for name in self.table_names() {
+            if !self.0.open_tree(&name)?.is_empty() {
+                tables.push(ivec_to_key(&name).to_string());
+            }
+        }
+        Ok(tables)
+    }
+
+    fn has_table(&self, table: &str) -> Result<bool, KvError> {
+        Ok(self.table_names().any(|name| name == table.as_bytes()))
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        let existed = self.has_table(table)?;
+        self.get_or_create_table(table)?;
+        Ok(!existed)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        if !self.has_table(table)? {
+            return Ok(false);
+        }
+        Ok(self.0.drop_tree(table)?)
+    }
+
+    // sled 不能给 tree 改名：在一个跨两个 tree 的事务中清空 to、写入 from 的数据并清空 from，
+    // 并发的读者要么看到改名前的数据，要么看到改名后的数据。之后再删除已经为空的 from
+    fn rename_table(&self, from: &str, to: &str, overwrite: bool) -> Result<bool, KvError> {
+        let existed = check_rename(self, from, to, overwrite)?;
+        let src = self.get_or_create_table(from)?;
+        let dst = self.get_or_create_table(to)?;
+        let pairs = src.iter().collect::<Result<Vec<_>, _>>()?;
+        let old_keys = dst.iter().keys().collect::<Result<Vec<_>, _>>()?;
+
+        let result = (&src, &dst).transaction(|(src, dst)| -> ConflictableTransactionResult<()> {
+            for key in &old_keys {
+                dst.remove(key.clone())?;
+            }
+            for (key, value) in &pairs {
+                dst.insert(key.clone(), value.clone())?;
+                src.remove(key.clone())?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+            Err(TransactionError::Abort(())) => unreachable!("transaction never aborts"),
         }
+        self.0.drop_tree(from)?;
+        Ok(existed)
+    }
+
+    // sled 没有手动压缩的接口，所有的 tree 共享同一个日志，只能压缩整个数据库：
+    // flush 把脏页写入磁盘后，后台的 GC 会回收不再使用的 segment
+    fn compact(&self, _table: Option<&str>) -> Result<(), KvError> {
+        self.0.flush()?;
+        Ok(())
+    }
+
+    fn backend(&self) -> &'static str {
+        "sledb"
+    }
+}
+
+// value 的头部记录了压缩算法，读取时不依赖 table 的配置，所以原样移动，不需要重新编码
+fn move_nx_in(
+    src: &TransactionalTree,
+    dst: &TransactionalTree,
+    src_key: &str,
+    dst_key: &str,
+) -> ConflictableTransactionResult<bool> {
+    if dst.get(dst_key)?.is_some() {
+        return Ok(false);
     }
+    let Some(value) = src.remove(src_key)? else {
+        return Ok(false);
+    };
+    dst.insert(dst_key, value)?;
+    Ok(true)
+}
+
+// 迭代时无法读取或解码的数据返回空的 Kvpair
+fn to_kvpair(codec: ValueCodecType, item: Result<(IVec, IVec), sled::Error>) -> Kvpair {
+    item.map_err(KvError::from)
+        .and_then(|(k, v)| codec.pair(&k, &v))
+        .unwrap_or_default()
 }
 
 fn ivec_to_key(ivec: &[u8]) -> &str {
-    let s = str::from_utf8(ivec).unwrap();
-    let mut iter = s.split(":");
-    iter.next();
-    iter.next().unwrap()
+    str::from_utf8(ivec).unwrap()
 }
@@ -0,0 +1,277 @@
+//! 供测试使用的 proptest strategy，开启 `testing` feature 后下游 crate 也可以复用
+
+use bytes::Bytes;
+use proptest::{
+    collection::{btree_map, vec},
+    option,
+    prelude::*,
+};
+
+use crate::{command_request::RequestData, value, *};
+
+/// 生成 table / topic 的名字，取值范围较小，方便产生冲突
+pub fn arb_table() -> impl Strategy<Value = String> {
+    "t[0-3]"
+}
+
+/// 生成 key，取值范围较小，方便对同一个 key 做多次操作
+pub fn arb_key() -> impl Strategy<Value = String> {
+    "k[0-9]"
+}
+
+/// 生成覆盖所有类型的 Value，float 只取有限值（NaN 无法比较，也会被服务端拒绝）
+pub fn arb_value() -> impl Strategy<Value = Value> {
+    let scalar = || {
+        prop_oneof![
+            any::<String>().prop_map(value::Value::String),
+            vec(any::<u8>(), 0..64).prop_map(|v| value::Value::Binary(Bytes::from(v))),
+            any::<i64>().prop_map(value::Value::Integer),
+            (prop::num::f64::NORMAL | prop::num::f64::ZERO | prop::num::f64::SUBNORMAL)
+                .prop_map(value::Value::Float),
+            any::<bool>().prop_map(value::Value::Bool),
+            any::<i64>().prop_map(value::Value::Timestamp),
+            (0..=i64::MAX).prop_map(value::Value::Duration),
+        ]
+    };
+    // Map 只嵌套一层
+    let map = btree_map(arb_key(), option::of(scalar()), 0..4).prop_map(|fields| {
+        let pairs = fields
+            .into_iter()
+            .map(|(key, value)| Kvpair::new(key, Value { value }))
+            .collect();
+        value::Value::Map(ValueMap { pairs })
+    });
+    option::of(prop_oneof![scalar(), map]).prop_map(|value| Value { value })
+}
+
+/// 生成 Kvpair
+pub fn arb_kvpair() -> impl Strategy<Value = Kvpair> {
+    (arb_key(), arb_value()).prop_map(|(key, value)| Kvpair::new(key, value))
+}
+
+/// 生成覆盖所有命令的 CommandRequest
+pub fn arb_command_request() -> impl Strategy<Value = CommandRequest> {
+    let keys = || vec(arb_key(), 0..8);
+    let values = || vec(arb_value(), 0..8);
+    let request_data = prop_oneof![
+        (arb_table(), arb_key()).prop_map(|(table, key)| RequestData::Hget(Hget { table, key })),
+        (arb_table(), arb_key(), arb_key()).prop_map(|(table, key, field)| {
+            RequestData::Hgetfield(Hgetfield { table, key, field })
+        }),
+        (arb_table(), arb_key(), any::<u32>()).prop_map(|(table, key, chunk_size)| {
+            RequestData::HgetChunked(HgetChunked {
+                table,
+                key,
+                chunk_size,
+            })
+        }),
+        (arb_table(), arb_key(), any::<u64>()).prop_map(|(table, key, since_version)| {
+            RequestData::HgetIfNewer(HgetIfNewer {
+                table,
+                key,
+                since_version,
+            })
+        }),
+        arb_table().prop_map(|table| RequestData::Hgetall(Hgetall { table })),
+        (arb_table(), any::<u32>()).prop_map(|(table, chunk_size)| {
+            RequestData::ScanStream(ScanStream { table, chunk_size })
+        }),
+        (arb_table(), keys()).prop_map(|(table, keys)| RequestData::Hmget(Hmget { table, keys })),
+        vec((arb_table(), arb_key()), 0..8).prop_map(|requests| {
+            let requests = requests
+                .into_iter()
+                .map(|(table, key)| TableKey { table, key })
+                .collect();
+            RequestData::MultiTableGet(MultiTableGet { requests })
+        }),
+        (
+            arb_table(),
+            option::of(arb_kvpair()),
+            option::of(any::<bool>())
+        )
+            .prop_map(|(table, pair, get_old)| RequestData::Hset(Hset {
+                table,
+                pair,
+                get_old
+            })),
+        (
+            arb_table(),
+            arb_key(),
+            option::of(arb_value()),
+            any::<u64>()
+        )
+            .prop_map(
+                |(table, key, value, expected_version)| RequestData::HsetCas(HsetCas {
+                    table,
+                    key,
+                    value,
+                    expected_version,
+                })
+            ),
+        (arb_table(), vec(arb_kvpair(), 0..8))
+            .prop_map(|(table, pairs)| RequestData::Hmset(Hmset { table, pairs })),
+        (arb_table(), vec(arb_kvpair(), 0..8))
+            .prop_map(|(table, pairs)| RequestData::Hmsetnx(Hmsetnx { table, pairs })),
+        (arb_table(), arb_key(), option::of(any::<bool>())).prop_map(|(table, key, get_old)| {
+            RequestData::Hdel(Hdel {
+                table,
+                key,
+                get_old,
+            })
+        }),
+        (arb_table(), keys()).prop_map(|(table, keys)| RequestData::Hmdel(Hmdel { table, keys })),
+        (arb_table(), arb_key())
+            .prop_map(|(table, key)| RequestData::Hexist(Hexist { table, key })),
+        (arb_table(), keys())
+            .prop_map(|(table, keys)| RequestData::Hmexist(Hmexist { table, keys })),
+        (arb_table(), any::<u64>(), any::<u64>(), any::<u32>()).prop_map(
+            |(topic, from_seq, throttle_ms, sample_every)| RequestData::Subscribe(Subscribe {
+                topic,
+                from_seq,
+                throttle_ms,
+                sample_every,
+            })
+        ),
+        (arb_table(), any::<u32>())
+            .prop_map(|(topic, id)| RequestData::Unsubscribe(Unsubscribe { topic, id })),
+        (arb_table(), values())
+            .prop_map(|(topic, data)| RequestData::Publish(Publish { topic, data })),
+        (vec(arb_table(), 0..4), values())
+            .prop_map(|(topics, data)| RequestData::Mpublish(Mpublish { topics, data })),
+        arb_table().prop_map(|table| RequestData::BulkLoad(BulkLoad { table })),
+        arb_table().prop_map(|table| RequestData::BulkDel(BulkDel { table })),
+        (arb_table(), arb_key())
+            .prop_map(|(table, key)| RequestData::Hgetdel(Hgetdel { table, key })),
+        (arb_table(), option::of(arb_kvpair()))
+            .prop_map(|(table, pair)| RequestData::Hgetset(Hgetset { table, pair })),
+        arb_table().prop_map(|table| RequestData::CreateTable(CreateTable { table })),
+        arb_table().prop_map(|table| RequestData::DropTable(DropTable { table })),
+        (arb_table(), arb_table(), any::<bool>()).prop_map(|(from, to, overwrite)| {
+            RequestData::RenameTable(RenameTable {
+                from,
+                to,
+                overwrite,
+            })
+        }),
+        (arb_table(), arb_key(), arb_table(), arb_key()).prop_map(
+            |(src_table, src_key, dst_table, dst_key)| {
+                RequestData::MoveNx(MoveNx {
+                    src_table,
+                    src_key,
+                    dst_table,
+                    dst_key,
+                })
+            }
+        ),
+        (arb_table(), arb_key(), any::<u64>())
+            .prop_map(|(table, key, ttl)| RequestData::Hexpire(Hexpire { table, key, ttl })),
+        (arb_table(), arb_key())
+            .prop_map(|(table, key)| RequestData::Hpersist(Hpersist { table, key })),
+        (arb_table(), arb_key()).prop_map(|(table, key)| RequestData::Httl(Httl { table, key })),
+        (arb_table(), arb_key(), prop::num::f64::NORMAL).prop_map(|(table, key, delta)| {
+            RequestData::Hincrbyfloat(Hincrbyfloat { table, key, delta })
+        }),
+        (
+            arb_table(),
+            arb_key(),
+            any::<i64>(),
+            any::<u64>(),
+            any::<bool>()
+        )
+            .prop_map(|(table, key, delta, ttl, refresh_ttl)| {
+                RequestData::HincrEx(HincrEx {
+                    table,
+                    key,
+                    delta,
+                    ttl,
+                    refresh_ttl,
+                })
+            }),
+        (arb_table(), arb_key(), any::<i64>(), any::<i64>()).prop_map(
+            |(table, key, initial, delta)| {
+                RequestData::HincrInit(HincrInit {
+                    table,
+                    key,
+                    initial,
+                    delta,
+                })
+            }
+        ),
+        (arb_table(), option::of(arb_key()))
+            .prop_map(|(table, key)| RequestData::Sizeof(Sizeof { table, key })),
+        option::of(arb_table()).prop_map(|table| RequestData::Compact(Compact { table })),
+        Just(RequestData::Stats(Stats {})),
+        Just(RequestData::Diagnostics(Diagnostics {})),
+        Just(RequestData::MySubscriptions(MySubscriptions {})),
+        "(|error|warn|info|debug|trace)"
+            .prop_map(|level| RequestData::SubscribeLogs(SubscribeLogs { level })),
+        (arb_table(), arb_key())
+            .prop_map(|(table, key)| RequestData::WatchKey(WatchKey { table, key })),
+    ];
+    (option::of(request_data), any::<u64>(), any::<u32>()).prop_map(
+        |(request_data, request_id, priority)| CommandRequest {
+            request_data,
+            request_id,
+            priority,
+        },
+    )
+}
+
+/// 生成 CommandResponse
+pub fn arb_command_response() -> impl Strategy<Value = CommandResponse> {
+    (
+        any::<u32>(),
+        any::<String>(),
+        vec(arb_value(), 0..8),
+        vec(arb_kvpair(), 0..8),
+        any::<u32>(),
+        any::<u64>(),
+        any::<u64>(),
+    )
+        .prop_map(
+            |(status, message, values, pairs, error_code, seq, request_id)| CommandResponse {
+                status,
+                message,
+                values,
+                pairs,
+                error_code,
+                seq,
+                request_id,
+            },
+        )
+}
+
+/// 对 Storage 的一次操作，用来比较不同的存储实现行为是否一致
+#[derive(Debug, Clone)]
+pub enum StorageOp {
+    Get(String, String),
+    Set(String, String, Value),
+    Del(String, String),
+    DelBatch(String, Vec<String>),
+    Contains(String, String),
+}
+
+/// 生成对 Storage 的一次操作
+pub fn arb_storage_op() -> impl Strategy<Value = StorageOp> {
+    prop_oneof![
+        (arb_table(), arb_key()).prop_map(|(t, k)| StorageOp::Get(t, k)),
+        (arb_table(), arb_key(), arb_value()).prop_map(|(t, k, v)| StorageOp::Set(t, k, v)),
+        (arb_table(), arb_key()).prop_map(|(t, k)| StorageOp::Del(t, k)),
+        (arb_table(), vec(arb_key(), 0..8)).prop_map(|(t, k)| StorageOp::DelBatch(t, k)),
+        (arb_table(), arb_key()).prop_map(|(t, k)| StorageOp::Contains(t, k)),
+    ]
+}
+
+impl StorageOp {
+    /// 在 store 上执行这个操作，把结果统一转换成 Vec<Value> 方便比较
+    pub fn apply(&self, store: &impl Storage) -> Result<Vec<Value>, KvError> {
+        let res = match self {
+            StorageOp::Get(t, k) => store.get(t, k)?.into_iter().collect(),
+            StorageOp::Set(t, k, v) => store.set(t, k.clone(), v.clone())?.into_iter().collect(),
+            StorageOp::Del(t, k) => store.del(t, k)?.into_iter().collect(),
+            StorageOp::DelBatch(t, k) => vec![(store.del_batch(t, k)? as i64).into()],
+            StorageOp::Contains(t, k) => vec![store.contains(t, k)?.into()],
+        };
+        Ok(res)
+    }
+}
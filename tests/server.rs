@@ -1,14 +1,20 @@
 use anyhow::Result;
 use futures::StreamExt;
 use kv::{
-    start_quic_client_with_config, start_server_with_config, start_yamux_client_with_noise_config,
-    start_yamux_client_with_tls_config, AppStream, ClientConfig, CommandRequest, KvError,
-    ProstClientStream, NOISE_CLIENT_CONFIG, NOISE_SERVER_CONFIG, QUIC_CLIENT_CONFIG,
-    QUIC_SERVER_CONFIG, TLS_CLIENT_CONFIG, TLS_SERVER_CONFIG,
+    serve, serve_stdio, start_quic_client_with_config, start_server_with_config,
+    start_yamux_client_with_noise_config, start_yamux_client_with_tls_config,
+    start_yamux_server_with_listener, AppStream, ClientConfig, CommandRequest, ConnSettings,
+    KvError, LifecycleEvent, ListenerConfig, MemTable, NetworkType, ProstClientStream,
+    SecureStreamConnect, ServerConfig, ServerSecurityProtocol, Service, ServiceInner,
+    StorageConfig, TlsClientConnector, TlsServerAcceptor, TlsTransport, Transport, Value,
+    YamuxConn, NOISE_CLIENT_CONFIG, NOISE_SERVER_CONFIG, QUIC_CLIENT_CONFIG, QUIC_SERVER_CONFIG,
+    TLS_CA_CERT, TLS_CLIENT_CONFIG, TLS_SERVER_CERT, TLS_SERVER_CONFIG, TLS_SERVER_KEY,
 };
 use std::time::Duration;
 use tokio::{
-    io::{AsyncRead, AsyncWrite},
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite},
+    net::{TcpListener, TcpStream, UnixStream},
+    sync::broadcast,
     time,
 };
 use tracing::info;
@@ -43,6 +49,208 @@ async fn yamux_server_client_full_tests() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn stdio_server_should_work() -> Result<()> {
+    // 用两个 pipe 模拟子进程的 stdin 和 stdout
+    let (stdin_writer, stdin) = io::duplex(4096);
+    let (stdout, stdout_reader) = io::duplex(4096);
+    let service = Service::new(MemTable::new());
+    let server = tokio::spawn(serve_stdio(stdin, stdout, service));
+
+    // 向 stdin 写入命令的 frame，从 stdout 读取响应的 frame
+    let mut client = ProstClientStream::new(io::join(stdout_reader, stdin_writer));
+    let cmd = CommandRequest::new_hset("table", "key", "value");
+    let res = client.execute_unary(&cmd).await?;
+    assert_eq!(res.status, 200);
+
+    let cmd = CommandRequest::new_hget("table", "key");
+    let res = client.execute_unary(&cmd).await?;
+    assert_eq!(res.values, vec![Value::from("value")]);
+
+    // stdin 关闭后服务器正常退出
+    drop(client);
+    time::timeout(Duration::from_secs(1), server).await???;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn multiple_listeners_should_share_service() -> Result<()> {
+    // 启动同时监听 TCP 和 Unix socket 的服务器，不校验客户端证书
+    let mut server_config: ServerConfig = toml::from_str(TLS_SERVER_CONFIG)?;
+    if let ServerSecurityProtocol::Tls(tls) = &mut server_config.security {
+        tls.ca = None;
+    }
+    server_config.general.addr = "127.0.0.1:1974".into();
+    let path = std::env::temp_dir().join("kv-multiple-listeners.sock");
+    server_config.general.listeners.push(ListenerConfig {
+        addr: path.to_string_lossy().into(),
+        network: NetworkType::Unix,
+        security: server_config.security.clone(),
+    });
+    tokio::spawn(async move {
+        start_server_with_config(&server_config).await.unwrap();
+    });
+    time::sleep(Duration::from_millis(100)).await;
+
+    let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(TLS_CA_CERT))?;
+    let stream = TcpStream::connect("127.0.0.1:1974").await?;
+    let mut tcp_conn = YamuxConn::new_client(connector.connect(stream).await?, None);
+    let stream = UnixStream::connect(&path).await?;
+    let mut unix_conn = YamuxConn::new_client(connector.connect(stream).await?, None);
+
+    let mut tcp_client = tcp_conn.open_stream().await?;
+    let mut unix_client = unix_conn.open_stream().await?;
+
+    // 一个 listener 上写入的数据可以从另一个 listener 上读到
+    let cmd = CommandRequest::new_hset("table", "tcp", "hello");
+    tcp_client.execute_unary(&cmd).await?;
+    let cmd = CommandRequest::new_hset("table", "unix", "world");
+    unix_client.execute_unary(&cmd).await?;
+
+    let cmd = CommandRequest::new_hget("table", "tcp");
+    let data = unix_client.execute_unary(&cmd).await?;
+    assert_eq!(data.values, &["hello".into()]);
+
+    let cmd = CommandRequest::new_hget("table", "unix");
+    let data = tcp_client.execute_unary(&cmd).await?;
+    assert_eq!(data.values, &["world".into()]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_and_quic_should_share_port() -> Result<()> {
+    // TLS 和 QUIC 使用同一份证书，在同一个端口上监听
+    let mut server_config: ServerConfig = toml::from_str(QUIC_SERVER_CONFIG)?;
+    server_config.general.addr = "127.0.0.1:1984".into();
+    server_config.general.network = NetworkType::TcpQuic;
+    server_config.storage = StorageConfig::MemTable;
+    tokio::spawn(async move {
+        start_server_with_config(&server_config).await.unwrap();
+    });
+    time::sleep(Duration::from_millis(100)).await;
+
+    let mut config: ClientConfig = toml::from_str(QUIC_CLIENT_CONFIG)?;
+    config.general.addr = "127.0.0.1:1984".into();
+    let mut quic_conn = start_quic_client_with_config(&config).await?;
+    let mut tcp_conn = start_yamux_client_with_tls_config(&config).await?;
+    let mut quic_client = quic_conn.open_stream().await?;
+    let mut tcp_client = tcp_conn.open_stream().await?;
+
+    // QUIC 写入的数据可以通过 TCP 读到
+    let cmd = CommandRequest::new_hset("table", "quic", "hello");
+    quic_client.execute_unary(&cmd).await?;
+    let cmd = CommandRequest::new_hget("table", "quic");
+    let data = tcp_client.execute_unary(&cmd).await?;
+    assert_eq!(data.values, &["hello".into()]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn yamux_server_should_serve_on_given_listener() -> Result<()> {
+    // 由调用者 bind 端口，服务器直接在这个 listener 上 accept
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let acceptor = TlsServerAcceptor::new(TLS_SERVER_CERT, TLS_SERVER_KEY, None)?;
+    tokio::spawn(async move {
+        start_yamux_server_with_listener(listener, MemTable::new(), acceptor)
+            .await
+            .unwrap();
+    });
+
+    let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(TLS_CA_CERT))?;
+    let stream = TcpStream::connect(addr).await?;
+    let mut conn = YamuxConn::new_client(connector.connect(stream).await?, None);
+    let mut client = conn.open_stream().await?;
+
+    let cmd = CommandRequest::new_hset("table", "hello", "world");
+    client.execute_unary(&cmd).await?;
+    let cmd = CommandRequest::new_hget("table", "hello");
+    let data = client.execute_unary(&cmd).await?;
+    assert_eq!(data.values, &["world".into()]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn pub_sub_should_await_lifecycle_events() -> Result<()> {
+    let service: Service = ServiceInner::new(MemTable::new()).into();
+    let mut events = service.events();
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let acceptor = TlsServerAcceptor::new(TLS_SERVER_CERT, TLS_SERVER_KEY, None)?;
+    let listener = TlsTransport::<TcpListener>::server(acceptor).listen_on(listener)?;
+    tokio::spawn(serve(listener, service, ConnSettings::default()));
+
+    // 等待服务端完成握手，而不是 sleep
+    let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(TLS_CA_CERT))?;
+    let transport = TlsTransport::<TcpListener>::client(connector);
+    let mut conn = transport.connect(&addr.to_string()).await?;
+    wait_event(&mut events, |e| {
+        matches!(e, LifecycleEvent::ConnectionAccepted(_))
+    })
+    .await?;
+
+    let cmd = CommandRequest::new_subscribe("lobby");
+    let mut subscription = conn.open_stream().await?.execute_streaming(&cmd).await?;
+    let added = LifecycleEvent::SubscriptionAdded {
+        topic: "lobby".into(),
+        id: subscription.id,
+    };
+    wait_event(&mut events, |e| e == &added).await?;
+
+    // 订阅已经注册，publish 的数据一定能收到
+    let mut client = conn.open_stream().await?;
+    let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
+    client.execute_unary(&cmd).await?;
+    let data = subscription.next().await.unwrap()?;
+    assert_eq!(data.values, &["hello".into()]);
+
+    let cmd = CommandRequest::new_unsubscribe("lobby", subscription.id as _);
+    client.execute_unary(&cmd).await?;
+    let removed = LifecycleEvent::SubscriptionRemoved {
+        topic: "lobby".into(),
+        id: subscription.id,
+    };
+    wait_event(&mut events, |e| e == &removed).await?;
+
+    Ok(())
+}
+
+// 跳过不关心的事件，直到收到满足条件的事件
+async fn wait_event(
+    events: &mut broadcast::Receiver<LifecycleEvent>,
+    f: impl Fn(&LifecycleEvent) -> bool,
+) -> Result<LifecycleEvent> {
+    loop {
+        let event = time::timeout(Duration::from_secs(5), events.recv()).await??;
+        if f(&event) {
+            return Ok(event);
+        }
+    }
+}
+
+#[tokio::test]
+async fn server_should_close_connection_without_handshake() -> Result<()> {
+    let mut server_config: ServerConfig = toml::from_str(TLS_SERVER_CONFIG)?;
+    server_config.general.addr = "127.0.0.1:1977".into();
+    server_config.general.handshake_timeout = 1;
+    tokio::spawn(async move {
+        start_server_with_config(&server_config).await.unwrap();
+    });
+    time::sleep(Duration::from_millis(100)).await;
+
+    // 连接后不发送任何数据，握手超时后服务器关闭连接
+    let mut stream = TcpStream::connect("127.0.0.1:1977").await?;
+    let mut buf = [0u8; 1];
+    let n = time::timeout(Duration::from_secs(5), stream.read(&mut buf)).await??;
+    assert_eq!(n, 0);
+
+    Ok(())
+}
+
 // TODO(Wiccy): Currently noise can not work with yamux, so skip this
 // #[tokio::test]
 #[allow(dead_code)]
@@ -1,10 +1,12 @@
 use ::anyhow::{anyhow, Result};
 use clap::{Parser, ValueEnum};
 use kv::{
-    ClientConfig, ClientSecurityProtocol, ClientTlsConfig, GeneralConfig, LogConfig, NetworkType,
-    RotationConfig, ServerConfig, ServerSecurityProtocol, ServerTlsConfig, StorageConfig,
-    QUIC_CA_CERT, QUIC_CLIENT_CERT, QUIC_CLIENT_KEY, QUIC_SERVER_CERT, QUIC_SERVER_KEY,
-    TLS_CA_CERT, TLS_CLIENT_CERT, TLS_CLIENT_KEY, TLS_SERVER_CERT, TLS_SERVER_KEY,
+    AcceptRateConfig, BufferPoolConfig, CircuitBreakerConfig, ClientConfig, ClientSecurityProtocol,
+    ClientTlsConfig, CoalesceConfig, CommandTimeout, CompressionConfig, GeneralConfig,
+    LimitsConfig, LogConfig, NetworkType, ReadCacheConfig, RotationConfig, RuntimeConfig,
+    ServerConfig, ServerSecurityProtocol, ServerTlsConfig, StorageConfig, ValueCodecType,
+    DEFAULT_HANDSHAKE_TIMEOUT, QUIC_CA_CERT, QUIC_CLIENT_CERT, QUIC_CLIENT_KEY, QUIC_SERVER_CERT,
+    QUIC_SERVER_KEY, TLS_CA_CERT, TLS_CLIENT_CERT, TLS_CLIENT_KEY, TLS_SERVER_CERT, TLS_SERVER_KEY,
 };
 use std::{env, fs};
 
@@ -40,8 +42,141 @@ struct Args {
     #[clap(long, value_enum, default_value = "daily")]
     log_rotation: RotationConfig,
 
+    #[clap(
+        long,
+        help = "Enable tokio-console, requires the tokio-console feature"
+    )]
+    tokio_console: bool,
+
     #[clap(long, default_value = "memtable")]
     storage: StorageConfig,
+
+    #[clap(long, help = "Close idle connections after the given seconds")]
+    idle_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        default_value = "1",
+        help = "Maximum number of in-flight commands per stream"
+    )]
+    max_in_flight: usize,
+
+    #[clap(
+        long,
+        default_value = "0",
+        help = "Number of commands queued by priority per stream"
+    )]
+    priority_queue: usize,
+
+    #[clap(long, help = "Maximum number of active subscriptions per connection")]
+    max_subscriptions: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Maximum number of connections accepted per second on each listener"
+    )]
+    accept_rate: Option<u32>,
+
+    #[clap(long, help = "Maximum number of concurrent connections")]
+    max_connections: Option<usize>,
+
+    #[clap(long, help = "Timeout in milliseconds for read commands")]
+    read_timeout: Option<u64>,
+
+    #[clap(long, help = "Timeout in milliseconds for write commands")]
+    write_timeout: Option<u64>,
+
+    #[clap(long, help = "Timeout in milliseconds for scan commands")]
+    scan_timeout: Option<u64>,
+
+    #[clap(long, help = "Maximum number of tables")]
+    max_tables: Option<usize>,
+
+    #[clap(long, help = "Maximum number of keys per table")]
+    max_keys_per_table: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Maximum number of keys in a single HMGET/HMSET/HMDEL/HMEXIST"
+    )]
+    max_keys_per_command: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Require tables to be created with CreateTable before use"
+    )]
+    no_auto_create_tables: bool,
+
+    #[clap(long, help = "Allow clients to run admin commands such as COMPACT")]
+    admin_commands: bool,
+
+    #[clap(long, help = "Publish keyspace notifications after successful writes")]
+    keyspace_notifications: bool,
+
+    #[clap(long, help = "Track per-key versions in memory for HGETIFNEWER")]
+    key_versioning: bool,
+
+    #[clap(
+        long,
+        default_value = "0",
+        help = "Number of recent messages kept per topic for replay on SUBSCRIBE (at most 127)"
+    )]
+    topic_history_size: usize,
+
+    #[clap(
+        long,
+        help = "Advertise the server via mDNS with this instance name (requires the mdns feature)"
+    )]
+    mdns: Option<String>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "prost",
+        help = "Encoding of values in sled and rocksdb"
+    )]
+    value_codec: ValueCodecType,
+
+    #[clap(long, help = "Number of tokio worker threads of the server")]
+    worker_threads: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Maximum number of threads running blocking storage calls"
+    )]
+    max_blocking_threads: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Coalesce disk writes for the given milliseconds before writing them in a batch"
+    )]
+    coalesce_window: Option<u64>,
+
+    #[clap(
+        long,
+        default_value = "1024",
+        help = "Maximum number of coalesced writes"
+    )]
+    coalesce_max_ops: usize,
+
+    #[clap(
+        long,
+        help = "Fail fast after the disk storage fails the given times in a row"
+    )]
+    breaker_threshold: Option<u32>,
+
+    #[clap(
+        long,
+        default_value = "5000",
+        help = "Milliseconds to fail fast before retrying the disk storage"
+    )]
+    breaker_cooldown: u64,
+
+    #[clap(
+        long,
+        help = "Cache reads of the given number of keys in front of the disk storage"
+    )]
+    read_cache: Option<usize>,
 }
 
 #[derive(Debug, ValueEnum, Clone)]
@@ -71,6 +206,25 @@ fn main() -> Result<()> {
             }
             Protocol::Noise => NetworkType::Tcp,
         },
+        idle_timeout: args.idle_timeout,
+        command_timeout: CommandTimeout {
+            read: args.read_timeout,
+            write: args.write_timeout,
+            scan: args.scan_timeout,
+        },
+        listeners: vec![],
+        compression: CompressionConfig::default(),
+        max_in_flight: args.max_in_flight,
+        priority_queue: args.priority_queue,
+        max_subscriptions: args.max_subscriptions,
+        buffer_pool: BufferPoolConfig::default(),
+        listen_backlog: None,
+        handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        accept_rate: args.accept_rate.map(|per_second| AcceptRateConfig {
+            per_second,
+            burst: None,
+        }),
+        max_connections: args.max_connections,
     };
 
     let (s_security, c_security) = gen_security_protocol(&security_type);
@@ -107,7 +261,37 @@ fn main() -> Result<()> {
             log_level: args.log_level,
             path: args.log_path,
             rotation: args.log_rotation,
+            tokio_console: args.tokio_console,
+        },
+        limits: LimitsConfig {
+            max_tables: args.max_tables,
+            max_keys_per_table: args.max_keys_per_table,
+            max_keys_per_command: args.max_keys_per_command,
         },
+        auto_create_tables: !args.no_auto_create_tables,
+        runtime: RuntimeConfig {
+            worker_threads: args.worker_threads,
+            max_blocking_threads: args.max_blocking_threads,
+        },
+        write_coalescing: args.coalesce_window.map(|window| CoalesceConfig {
+            window,
+            max_ops: args.coalesce_max_ops,
+        }),
+        table_storage: Vec::new(),
+        admin_commands: args.admin_commands,
+        keyspace_notifications: args.keyspace_notifications,
+        key_versioning: args.key_versioning,
+        topic_history_size: args.topic_history_size,
+        mdns: args.mdns,
+        value_codec: args.value_codec,
+        circuit_breaker: args
+            .breaker_threshold
+            .map(|failure_threshold| CircuitBreakerConfig {
+                failure_threshold,
+                cooldown: args.breaker_cooldown,
+            }),
+        read_cache: args.read_cache.map(|capacity| ReadCacheConfig { capacity }),
+        table_compression: vec![],
     };
 
     fs::write(s_conf_path, toml::to_string_pretty(&server_config)?)?;
@@ -128,25 +312,36 @@ fn gen_security_protocol(s: &Protocol) -> (ServerSecurityProtocol, ClientSecurit
             ServerSecurityProtocol::Tls(ServerTlsConfig {
                 cert: TLS_SERVER_CERT.into(),
                 key: TLS_SERVER_KEY.into(),
+                key_passphrase: None,
                 ca: Some(TLS_CA_CERT.into()),
+                policy: Default::default(),
             }),
             ClientSecurityProtocol::Tls(ClientTlsConfig {
                 identity: Some((TLS_CLIENT_CERT.into(), TLS_CLIENT_KEY.into())),
+                key_passphrase: None,
                 ca: Some(TLS_CA_CERT.into()),
                 domain: "kvserver.acme.inc".into(),
+                policy: Default::default(),
             }),
         ),
-        Protocol::Noise => (ServerSecurityProtocol::Noise, ClientSecurityProtocol::Noise),
+        Protocol::Noise => (
+            ServerSecurityProtocol::Noise(Default::default()),
+            ClientSecurityProtocol::Noise(Default::default()),
+        ),
         Protocol::Quic => (
             ServerSecurityProtocol::Tls(ServerTlsConfig {
                 cert: QUIC_SERVER_CERT.into(),
                 key: QUIC_SERVER_KEY.into(),
+                key_passphrase: None,
                 ca: Some(QUIC_CA_CERT.into()),
+                policy: Default::default(),
             }),
             ClientSecurityProtocol::Tls(ClientTlsConfig {
                 identity: Some((QUIC_CLIENT_CERT.into(), QUIC_CLIENT_KEY.into())),
+                key_passphrase: None,
                 ca: Some(QUIC_CA_CERT.into()),
                 domain: "kvserver.acme.inc".into(),
+                policy: Default::default(),
             }),
         ),
     }
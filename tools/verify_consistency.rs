@@ -0,0 +1,64 @@
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use kv::{verify_consistency, RocksDB, SledDb, Storage};
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "Storage Consistency Verifier",
+    about = "compare a table in two disk storages, e.g. after a migration"
+)]
+struct Args {
+    #[clap(long, value_enum)]
+    a_type: Backend,
+
+    #[clap(long)]
+    a_path: String,
+
+    #[clap(long, value_enum)]
+    b_type: Backend,
+
+    #[clap(long)]
+    b_path: String,
+
+    #[clap(short, long, help = "Table to compare")]
+    table: String,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy)]
+enum Backend {
+    Sledb,
+    Rocksdb,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    match args.a_type {
+        Backend::Sledb => verify_with(&SledDb::new(&args.a_path), &args),
+        Backend::Rocksdb => verify_with(&RocksDB::new(&args.a_path), &args),
+    }
+}
+
+fn verify_with(a: &impl Storage, args: &Args) -> Result<()> {
+    match args.b_type {
+        Backend::Sledb => verify(a, &SledDb::new(&args.b_path), &args.table),
+        Backend::Rocksdb => verify(a, &RocksDB::new(&args.b_path), &args.table),
+    }
+}
+
+fn verify(a: &impl Storage, b: &impl Storage, table: &str) -> Result<()> {
+    let mismatches = verify_consistency(a, b, table)?;
+    for mismatch in &mismatches {
+        println!("{mismatch}");
+    }
+
+    if !mismatches.is_empty() {
+        anyhow::bail!(
+            "found {} mismatched keys in table {table}",
+            mismatches.len()
+        );
+    }
+    println!("Table {table} is consistent");
+
+    Ok(())
+}